@@ -0,0 +1,11 @@
+//! Feeds arbitrary cast files to the cast parser and terminal emulator
+//!
+//! Run with `cargo +nightly fuzz run terminal_frame_iter` from the root of the repository.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    cast2gif::fuzzing::terminal_frame_iter(data);
+});
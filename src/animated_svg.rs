@@ -0,0 +1,505 @@
+//! Animated SVG output
+//!
+//! The SVGs have the same structure as the ones made by [svg-term-cli], so that they can replace
+//! them in READMEs without changing how they are embedded. Every frame is drawn next to the one
+//! before it on a long strip, and a CSS animation moves the strip across the view one frame at a
+//! time. Lines that appear in more than one frame are only drawn once, as a `<symbol>`, and then
+//! placed in each frame with `<use>`.
+//!
+//! Everything is measured in units of the width of a terminal cell.
+//!
+//! [svg-term-cli]: https://github.com/marionebl/svg-term-cli
+
+use crate::color_profile::{ColorProfile, Rgb};
+use crate::types::{CropSettings, SvgSettings, TerminalFrame, Underline, UnderlineStyle};
+use crate::Error;
+
+use std::collections::HashMap;
+use std::fmt::{self, Write as _};
+use std::io::Write;
+
+/// The size of a unit in pixels
+const PX_PER_UNIT: f64 = 8.;
+/// The font size, which makes the characters of most monospace fonts one unit wide
+const FONT_SIZE: f64 = 1.67;
+/// The height of a line of the terminal
+const LINE_HEIGHT: f64 = 2.1;
+/// The distance from the top of a line to the baseline of its text
+const BASELINE: f64 = 1.55;
+/// The distance from the top of a line to its underline
+const UNDERLINE: f64 = 1.8;
+/// The thickness of underlines
+const UNDERLINE_WIDTH: f64 = 0.12;
+/// The height of the window bar with the buttons and title
+const WINDOW_BAR_HEIGHT: f64 = 3.;
+/// The padding around the terminal used when none is configured, in pixels
+pub(crate) const DEFAULT_PADDING: u16 = 10;
+/// The fonts that text is drawn with, which are the same as svg-term's
+const FONT_FAMILY: &str =
+    "Monaco,Consolas,Menlo,'Bitstream Vera Sans Mono','Powerline Symbols',monospace";
+
+/// Formats a number with at most 3 decimal places and without trailing zeros
+struct Num(f64);
+
+impl fmt::Display for Num {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = format!("{:.3}", self.0);
+        let s = s.trim_end_matches('0').trim_end_matches('.');
+        f.write_str(if s == "-0" { "0" } else { s })
+    }
+}
+
+/// Formats a color as `#rrggbb`
+struct Hex(Rgb);
+
+impl fmt::Display for Hex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (r, g, b) = self.0;
+        write!(f, "#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+/// Escape text for use in XML
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            // Control characters aren't allowed in XML
+            c if c.is_control() => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// An animated SVG that is built up one frame at a time
+pub(crate) struct AnimatedSvg {
+    settings: SvgSettings,
+    /// The region of the terminal that is drawn
+    crop: CropSettings,
+    /// The title to show in the window bar, if there is one
+    title: Option<String>,
+    /// How opaque the background is, from `0` for transparent to `1` for opaque
+    background_opacity: f64,
+    /// The classes of the colors used in the frames
+    color_classes: HashMap<Rgb, usize>,
+    /// The ids of the symbols for the lines used in the frames
+    line_ids: HashMap<String, usize>,
+    /// The symbols for the lines, in order of their ids
+    symbols: String,
+    /// The frames of the strip
+    frames: String,
+    /// The times of the frames, in centiseconds
+    times: Vec<f64>,
+}
+
+impl AnimatedSvg {
+    /// Create an SVG for a terminal of the given size
+    ///
+    /// If `title` is not `None`, the SVG has a window bar with the title, even if the settings
+    /// don't ask for a window. The background of the window and the terminal is as opaque as
+    /// `background_opacity`, from `0` for transparent to `1` for opaque.
+    pub fn new(
+        rows: u16,
+        cols: u16,
+        crop: Option<CropSettings>,
+        title: Option<String>,
+        background_opacity: f64,
+        settings: SvgSettings,
+    ) -> Self {
+        AnimatedSvg {
+            settings,
+            crop: crop.unwrap_or(CropSettings {
+                top: 0,
+                left: 0,
+                width: cols,
+                height: rows,
+            }),
+            title,
+            background_opacity,
+            color_classes: HashMap::new(),
+            line_ids: HashMap::new(),
+            symbols: String::new(),
+            frames: String::new(),
+            times: Vec::new(),
+        }
+    }
+
+    /// Get the width of the terminal
+    fn width(&self) -> f64 {
+        f64::from(self.crop.width)
+    }
+
+    /// Get the height of the terminal
+    fn height(&self) -> f64 {
+        f64::from(self.crop.height) * LINE_HEIGHT
+    }
+
+    /// Get the class of the text and backgrounds of a color
+    fn color_class(&mut self, color: Rgb) -> usize {
+        let next = self.color_classes.len();
+        *self.color_classes.entry(color).or_insert(next)
+    }
+
+    /// Get the class attribute for a terminal color, or nothing for the default color
+    fn class_attr(&mut self, color: vt100::Color) -> String {
+        match self.settings.profile.color(color) {
+            Some(rgb) => format!(" class=\"c{}\"", self.color_class(rgb)),
+            None => String::new(),
+        }
+    }
+
+    /// Get the id of the symbol for a line, adding the symbol if it is new
+    fn line_id(&mut self, line: String) -> usize {
+        if let Some(&id) = self.line_ids.get(&line) {
+            return id;
+        }
+
+        let id = self.line_ids.len();
+        write!(self.symbols, "<symbol id=\"l{}\">{}</symbol>", id, line).ok();
+        self.line_ids.insert(line, id);
+        id
+    }
+
+    /// Draw a line of the screen, returning the markup for it
+    fn draw_line(&mut self, frame: &TerminalFrame, row: u16) -> String {
+        let mut backgrounds = String::new();
+        let mut texts = String::new();
+        let mut underlines = String::new();
+
+        // The start column, color, and contents of the current runs of cells
+        let mut background: Option<(u16, vt100::Color)> = None;
+        let mut text: Option<(u16, vt100::Color, String)> = None;
+        let mut underline: Option<(u16, UnderlineStyle, Rgb)> = None;
+        let mut end_background = |svg: &mut Self, background: Option<(u16, vt100::Color)>, col| {
+            if let Some((start, color)) = background {
+                let class = svg.class_attr(color);
+                write!(
+                    backgrounds,
+                    "<rect x=\"{}\" width=\"{}\" height=\"{}\"{}/>",
+                    start,
+                    col - start,
+                    Num(LINE_HEIGHT),
+                    class
+                )
+                .ok();
+            }
+        };
+        let mut end_text = |svg: &mut Self, text: Option<(u16, vt100::Color, String)>| {
+            if let Some((start, color, contents)) = text {
+                let contents = contents.trim_end();
+                if !contents.is_empty() {
+                    let class = svg.class_attr(color);
+                    write!(
+                        texts,
+                        "<text x=\"{}\" y=\"{}\"{}>{}</text>",
+                        start,
+                        Num(BASELINE),
+                        class,
+                        escape(contents)
+                    )
+                    .ok();
+                }
+            }
+        };
+        let mut end_underline = |underline: Option<(u16, UnderlineStyle, Rgb)>, col| {
+            if let Some((start, style, color)) = underline {
+                let length = col - start;
+                let line = |y: f64| format!("M{},{}h{}", start, Num(y), length);
+                let (path, dashes) = match style {
+                    UnderlineStyle::Single => (line(UNDERLINE), ""),
+                    UnderlineStyle::Double => (
+                        line(UNDERLINE - UNDERLINE_WIDTH)
+                            + line(UNDERLINE + UNDERLINE_WIDTH).as_str(),
+                        "",
+                    ),
+                    UnderlineStyle::Dotted => (line(UNDERLINE), " stroke-dasharray=\"0.15\""),
+                    // One dash in the middle of each cell
+                    UnderlineStyle::Dashed => (
+                        line(UNDERLINE),
+                        " stroke-dasharray=\"0.5\" stroke-dashoffset=\"0.75\"",
+                    ),
+                    // A wave for each cell
+                    UnderlineStyle::Curly => (
+                        format!("M{},{}q0.25,-0.3 0.5,0", start, Num(UNDERLINE))
+                            + "t0.5,0".repeat(length as usize * 2 - 1).as_str(),
+                        "",
+                    ),
+                };
+                write!(
+                    underlines,
+                    "<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"{}/>",
+                    path,
+                    Hex(color),
+                    Num(UNDERLINE_WIDTH),
+                    dashes
+                )
+                .ok();
+            }
+        };
+
+        let row = self.crop.top + row;
+        for col in 0..self.crop.width {
+            let cell = frame
+                .screen
+                .cell(row, self.crop.left + col)
+                .copied()
+                .unwrap_or_default();
+
+            if background.map(|(_, color)| color) != Some(cell.bgcolor) {
+                end_background(self, background.take(), col);
+                if cell.bgcolor != vt100::Color::Default {
+                    background = Some((col, cell.bgcolor));
+                }
+            }
+
+            // Start new runs of text after the cells of wide characters, which have no contents, so
+            // that the text after them lines up with the cells
+            match cell.contents {
+                Some(contents) if text.as_ref().is_some_and(|t| t.1 == cell.fgcolor) => {
+                    text.as_mut().unwrap().2.push_str(&contents.to_string())
+                }
+                Some(contents) => {
+                    end_text(self, text.take());
+                    let contents = contents.to_string();
+                    if !contents.trim().is_empty() {
+                        text = Some((col, cell.fgcolor, contents));
+                    }
+                }
+                None => end_text(self, text.take()),
+            }
+
+            let cell_underline = cell.underline.map(|Underline { style, color }| {
+                let profile = &self.settings.profile;
+                let color = profile
+                    .color(color)
+                    .or_else(|| profile.color(cell.fgcolor))
+                    .unwrap_or(profile.foreground);
+                (style, color)
+            });
+            if underline.map(|(_, style, color)| (style, color)) != cell_underline {
+                end_underline(underline.take(), col);
+                underline = cell_underline.map(|(style, color)| (col, style, color));
+            }
+        }
+        end_background(self, background, self.crop.width);
+        end_text(self, text);
+        end_underline(underline, self.crop.width);
+
+        backgrounds + texts.as_str() + underlines.as_str()
+    }
+
+    /// Add a frame to the end of the animation
+    pub fn add_frame(&mut self, frame: &TerminalFrame) {
+        let index = self.times.len();
+        self.times.push(frame.time);
+
+        let mut markup = format!("<svg x=\"{}\">", Num(index as f64 * self.width()));
+        for row in 0..self.crop.height {
+            let line = self.draw_line(frame, row);
+            if line.is_empty() {
+                continue;
+            }
+            let id = self.line_id(line);
+            write!(
+                markup,
+                "<use xlink:href=\"#l{}\" y=\"{}\"/>",
+                id,
+                Num(f64::from(row) * LINE_HEIGHT)
+            )
+            .ok();
+        }
+
+        // Draw the cursors like the gif does, by swapping the colors of their cells
+        let crop = self.crop;
+        for &(row, col) in frame.screen.cursors() {
+            let cursor_is_visible = (crop.top..crop.top + crop.height).contains(&row)
+                && (crop.left..crop.left + crop.width).contains(&col);
+            if !self.settings.cursor || !cursor_is_visible {
+                continue;
+            }
+            let cell = frame.screen.cell(row, col).copied().unwrap_or_default();
+            let (x, y) = (col - crop.left, f64::from(row - crop.top) * LINE_HEIGHT);
+            let fill = match self.settings.profile.color(cell.fgcolor) {
+                Some(color) => Hex(color),
+                None => Hex(self.settings.profile.cursor()),
+            };
+            write!(
+                markup,
+                "<rect x=\"{}\" y=\"{}\" width=\"1\" height=\"{}\" fill=\"{}\"/>",
+                x,
+                Num(y),
+                Num(LINE_HEIGHT),
+                fill
+            )
+            .ok();
+            if let Some(contents) = cell.contents {
+                let fill = Hex(self
+                    .settings
+                    .profile
+                    .color(cell.bgcolor)
+                    .unwrap_or(self.settings.profile.background));
+                write!(
+                    markup,
+                    "<text x=\"{}\" y=\"{}\" fill=\"{}\">{}</text>",
+                    x,
+                    Num(y + BASELINE),
+                    fill,
+                    escape(&contents.to_string())
+                )
+                .ok();
+            }
+        }
+
+        markup.push_str("</svg>");
+        self.frames.push_str(&markup);
+    }
+
+    /// Get the number of frames in the animation
+    pub fn frame_count(&self) -> u64 {
+        self.times.len() as u64
+    }
+
+    /// Get the length of the animation in centiseconds, with the last frame held for `hold_last`
+    /// seconds
+    ///
+    /// If `hold_last` is `0`, the last frame is shown for as long as the frame before it.
+    pub fn duration(&self, hold_last: f64) -> f64 {
+        let last = self.times.last().copied().unwrap_or(0.);
+        if hold_last > 0. {
+            return last + hold_last * 100.;
+        }
+        match self.times.len() {
+            0 | 1 => last,
+            len => last + (last - self.times[len - 2]).max(1.),
+        }
+    }
+
+    /// Write the SVG, with the last frame held for `hold_last` seconds before it loops
+    pub fn write(&self, mut writer: impl Write, hold_last: f64) -> Result<(), Error> {
+        let profile: &ColorProfile = &self.settings.profile;
+        let padding = f64::from(self.settings.padding()) / PX_PER_UNIT;
+        let window = self.settings.window || self.title.is_some();
+        let bar_height = if window { WINDOW_BAR_HEIGHT } else { 0. };
+        let (width, height) = (self.width(), self.height());
+        let (full_width, full_height) = (width + padding * 2., height + padding * 2. + bar_height);
+
+        let mut svg = String::new();
+        write!(
+            svg,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+             width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" xml:space=\"preserve\">",
+            Num(full_width * PX_PER_UNIT),
+            Num(full_height * PX_PER_UNIT),
+            Num(full_width),
+            Num(full_height)
+        )
+        .ok();
+
+        // Add the styles of the colors and the animation
+        svg.push_str("<style>text{white-space:pre}");
+        let mut colors: Vec<_> = self.color_classes.iter().collect();
+        colors.sort_by_key(|(_, class)| **class);
+        for (color, class) in colors {
+            write!(svg, ".c{}{{fill:{}}}", class, Hex(*color)).ok();
+        }
+        let duration = self.duration(hold_last);
+        if self.times.len() > 1 && duration > 0. {
+            write!(
+                svg,
+                ".a{{animation:play {}s steps(1,end) infinite}}@keyframes play{{",
+                Num(duration / 100.)
+            )
+            .ok();
+            for (index, time) in self.times.iter().enumerate() {
+                let percent = if index == 0 {
+                    0.
+                } else {
+                    time / duration * 100.
+                };
+                write!(
+                    svg,
+                    "{}%{{transform:translateX({}px)}}",
+                    Num(percent),
+                    Num(-(index as f64) * width)
+                )
+                .ok();
+            }
+            write!(
+                svg,
+                "100%{{transform:translateX({}px)}}}}",
+                Num(-((self.times.len() - 1) as f64) * width)
+            )
+            .ok();
+        }
+        svg.push_str("</style>");
+
+        // Draw the window
+        write!(
+            svg,
+            "<rect width=\"{}\" height=\"{}\" rx=\"{}\" fill=\"{}\"",
+            Num(full_width),
+            Num(full_height),
+            if window { 0.5 } else { 0. },
+            Hex(profile.background)
+        )
+        .ok();
+        if self.background_opacity < 1. {
+            write!(svg, " fill-opacity=\"{}\"", Num(self.background_opacity)).ok();
+        }
+        svg.push_str("/>");
+        if window {
+            for (i, color) in ["#ff5f58", "#ffbd2e", "#18c132"].iter().enumerate() {
+                write!(
+                    svg,
+                    "<circle cx=\"{}\" cy=\"{}\" r=\"0.75\" fill=\"{}\"/>",
+                    Num(padding + 0.75 + i as f64 * 2.5),
+                    Num(padding + 0.75),
+                    color
+                )
+                .ok();
+            }
+            if let Some(title) = self.title.as_deref().filter(|t| !t.is_empty()) {
+                write!(
+                    svg,
+                    "<text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"1.4\" \
+                     text-anchor=\"middle\" fill=\"{}\" fill-opacity=\"0.6\">{}</text>",
+                    Num(full_width / 2.),
+                    Num(padding + 1.2),
+                    FONT_FAMILY,
+                    Hex(profile.foreground),
+                    escape(title)
+                )
+                .ok();
+            }
+        }
+
+        // Draw the terminal, clipping the strip of frames to it
+        write!(
+            svg,
+            "<svg x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\">\
+             <g font-family=\"{}\" font-size=\"{}\" fill=\"{}\">\
+             <defs>{}</defs><g class=\"a\">{}</g></g></svg></svg>",
+            Num(padding),
+            Num(padding + bar_height),
+            Num(width),
+            Num(height),
+            FONT_FAMILY,
+            Num(FONT_SIZE),
+            Hex(profile.foreground),
+            self.symbols,
+            self.frames
+        )
+        .ok();
+
+        writer
+            .write_all(svg.as_bytes())
+            .and_then(|_| writer.flush())
+            .map_err(|e| Error::Generic(format!("Could not write svg: {}", e)))
+    }
+}
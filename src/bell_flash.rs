@@ -0,0 +1,135 @@
+//! Flashing the screen when the terminal bell rings
+//!
+//! Programs ring the bell to get the user's attention, such as when tab completion has nothing to
+//! complete, which a recording would otherwise show no sign of. Like a terminal's visual bell, the
+//! screen is briefly lit up with the text color so that viewers notice it.
+
+use crate::cast_parser::AsciinemaError;
+use crate::highlight::mix;
+use crate::types::{ScreenSnapshot, TerminalFrame};
+use crate::ColorProfile;
+
+/// How long the screen flashes for, in centiseconds
+const FLASH_DURATION: f64 = 15.;
+
+/// How much of the text color is mixed into the background of the screen while it flashes
+const FLASH_OPACITY: f64 = 0.4;
+
+/// An iterator adapter that flashes the screen on the frames where the bell rang
+///
+/// The frames that come while the screen is flashing are flashed too, and the last frame is
+/// repeated without the flash when it ends.
+pub(crate) struct BellFlashFrames<I> {
+    /// The frames to flash
+    frames: I,
+    /// Whether or not to flash the screen
+    enabled: bool,
+    /// The colors that the frames are rendered with, which the flash is mixed with
+    profile: ColorProfile,
+    /// The time that the current flash ends at, in centiseconds, if the screen is flashing
+    flash_end: Option<f64>,
+    /// The last frame that was emitted while the screen is flashing, before it was flashed
+    last_frame: Option<TerminalFrame>,
+    /// A frame that has been read, but not yet emitted
+    pending_frame: Option<TerminalFrame>,
+    /// The index of the next frame
+    next_index: u64,
+}
+
+impl<I> BellFlashFrames<I> {
+    /// Flash the screen when the bell rings, mixing the colors of the `profile` that the frames
+    /// are rendered with
+    ///
+    /// Frames are passed through untouched if `enabled` is `false`.
+    pub fn new(frames: I, enabled: bool, profile: ColorProfile) -> Self {
+        BellFlashFrames {
+            frames,
+            enabled,
+            profile,
+            flash_end: None,
+            last_frame: None,
+            pending_frame: None,
+            next_index: 0,
+        }
+    }
+
+    /// Emit a frame with the next index
+    fn emit(&mut self, mut frame: TerminalFrame) -> TerminalFrame {
+        frame.index = self.next_index;
+        self.next_index += 1;
+
+        frame
+    }
+
+    /// Emit the last frame again without the flash, at the time that the flash ends
+    fn end_flash(&mut self) -> Option<TerminalFrame> {
+        let end = self.flash_end.take()?;
+        let mut frame = self.last_frame.take()?;
+        frame.time = end;
+        frame.bell = false;
+
+        Some(self.emit(frame))
+    }
+
+    /// Light up the background of every cell of a screen
+    fn flash(&self, screen: &mut ScreenSnapshot) {
+        let (rows, cols) = screen.size();
+        for row in 0..rows {
+            for col in 0..cols {
+                let cell = screen
+                    .cell_mut(row, col)
+                    .expect("The cell is on the screen");
+                let background = self
+                    .profile
+                    .color(cell.bgcolor)
+                    .unwrap_or(self.profile.background);
+                let (r, g, b) = mix(background, self.profile.foreground, FLASH_OPACITY);
+                cell.bgcolor = vt100::Color::Rgb(r, g, b);
+            }
+        }
+    }
+}
+
+impl<I> Iterator for BellFlashFrames<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = match self
+            .pending_frame
+            .take()
+            .map(Ok)
+            .or_else(|| self.frames.next())
+        {
+            Some(Ok(frame)) => frame,
+            Some(Err(e)) => return Some(Err(e)),
+            // Don't leave the last frame flashing while it is held
+            None => return self.end_flash().map(Ok),
+        };
+        if !self.enabled {
+            return Some(Ok(frame));
+        }
+
+        // End the flash before this frame if it ends first
+        match self.flash_end {
+            Some(end) if end < frame.time => {
+                self.pending_frame = Some(frame);
+                return self.end_flash().map(Ok);
+            }
+            Some(end) if end == frame.time => self.flash_end = None,
+            _ => (),
+        }
+
+        if frame.bell {
+            self.flash_end = Some(frame.time + FLASH_DURATION);
+        }
+        if self.flash_end.is_some() {
+            self.last_frame = Some(frame.clone());
+            self.flash(&mut frame.screen);
+        }
+
+        Some(Ok(self.emit(frame)))
+    }
+}
@@ -0,0 +1,312 @@
+//! Panning and zooming over the terminal
+//!
+//! A camera file gives the view of the terminal to show at times of the rendered gif, and the
+//! camera glides from one view to the next in between, so that demos of large terminals can
+//! follow the part of the screen that is being worked on.
+
+use std::str::FromStr;
+
+use crate::cast_parser::AsciinemaError;
+use crate::frame_renderer::View;
+use crate::types::{CropSettings, TerminalFrame};
+use crate::Error;
+
+/// The time between frames while the camera moves, in centiseconds
+const MOVE_INTERVAL: f64 = 4.;
+
+/// A path for the camera to follow over the rendered frames
+///
+/// Parsed from a file with one keyframe per line, as a time in seconds of the rendered gif,
+/// followed by the rectangle of terminal cells to show at that time, like a crop:
+///
+/// ```text
+/// # Start on the whole terminal, then move in on the prompt
+/// 0 top=0,left=0,width=120,height=40
+/// 2 top=0,left=0,width=120,height=40
+/// 4 top=30,left=0,width=60,height=10
+/// ```
+///
+/// Between keyframes the camera eases from one view to the next. It stays on the first view
+/// before the first keyframe, and on the last view after the last keyframe. Empty lines and lines
+/// starting with `#` are skipped.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CameraPath {
+    /// The keyframes, sorted by time
+    keyframes: Vec<Keyframe>,
+}
+
+/// A single keyframe of a [`CameraPath`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Keyframe {
+    /// The time of the keyframe, in seconds
+    time: f64,
+    /// The cells that the camera shows at the time of the keyframe
+    region: CropSettings,
+}
+
+impl CameraPath {
+    /// Whether or not the camera has no keyframes
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    /// Make sure that every keyframe fits inside of a terminal of the given size
+    pub fn validate(&self, rows: u16, cols: u16) -> Result<(), Error> {
+        for keyframe in &self.keyframes {
+            let region = keyframe.region;
+            let fits = region.width > 0
+                && region.height > 0
+                && region.left as u32 + region.width as u32 <= cols as u32
+                && region.top as u32 + region.height as u32 <= rows as u32;
+            if !fits {
+                return Err(Error::Generic(format!(
+                    "Camera keyframe at {} on {} does not fit inside of the terminal, which is {} \
+                     columns wide and {} rows tall",
+                    keyframe.time, region, cols, rows
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the view of the camera at the given time, in seconds, if it has any keyframes
+    pub(crate) fn view_at(&self, time: f64) -> Option<View> {
+        let first = self.keyframes.first()?;
+        let next = match self
+            .keyframes
+            .iter()
+            .position(|keyframe| keyframe.time > time)
+        {
+            Some(0) => return Some(first.region.into()),
+            Some(next) => next,
+            None => return self.keyframes.last().map(|keyframe| keyframe.region.into()),
+        };
+
+        let (from, to) = (self.keyframes[next - 1], self.keyframes[next]);
+        let progress = (time - from.time) / (to.time - from.time);
+        // Ease in and out, so that the camera doesn't start or stop moving with a jolt
+        let eased = progress * progress * (3. - 2. * progress);
+        let (from, to) = (View::from(from.region), View::from(to.region));
+        let between = |a: f64, b: f64| a + (b - a) * eased;
+
+        Some(View {
+            left: between(from.left, to.left),
+            top: between(from.top, to.top),
+            width: between(from.width, to.width),
+            height: between(from.height, to.height),
+        })
+    }
+
+    /// Get the (start, end) time ranges, in seconds, that the camera moves during
+    fn moves(&self) -> Vec<(f64, f64)> {
+        self.keyframes
+            .windows(2)
+            .filter(|pair| pair[0].region != pair[1].region)
+            .map(|pair| (pair[0].time, pair[1].time))
+            .collect()
+    }
+}
+
+impl FromStr for CameraPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keyframes = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                Error::Generic(format!(
+                    "Invalid camera keyframe on line {}: {}. Expected `time \
+                     top=T,left=L,width=W,height=H`",
+                    i + 1,
+                    reason
+                ))
+            };
+
+            let mut parts = line.split_whitespace();
+            let time = parts.next().ok_or_else(|| invalid("it is empty"))?;
+            let region = parts.next().ok_or_else(|| invalid("it has no rectangle"))?;
+            if parts.next().is_some() {
+                return Err(invalid("there is more after the rectangle"));
+            }
+
+            let time = match time.parse::<f64>() {
+                Ok(time) if time >= 0. && time.is_finite() => time,
+                _ => return Err(invalid("the time isn't a number of seconds")),
+            };
+            keyframes.push(Keyframe {
+                time,
+                region: region.parse().map_err(|e: Error| invalid(&e.to_string()))?,
+            });
+        }
+
+        // Make sure there is only one view for each time
+        keyframes.sort_by(|a, b| a.time.partial_cmp(&b.time).expect("Checked for NaN above"));
+        for pair in keyframes.windows(2) {
+            if pair[0].time == pair[1].time {
+                return Err(Error::Generic(format!(
+                    "There is more than one camera keyframe at {}",
+                    pair[0].time
+                )));
+            }
+        }
+
+        Ok(CameraPath { keyframes })
+    }
+}
+
+/// An iterator adapter that repeats the last frame while the camera moves
+///
+/// Frames are only emitted when the screen changes, so without the repeated frames the camera
+/// would jump from view to view on each change to the screen instead of gliding. Like spotlights,
+/// this comes after the frames are merged by the minimum frame delay, so the repeated frames are
+/// kept at least that far apart. The camera stops with the last frame.
+pub(crate) struct CameraFrames<I> {
+    /// The frames to move the camera over
+    frames: I,
+    /// The (start, end) time ranges that the camera moves during, in centiseconds
+    moves: Vec<(f64, f64)>,
+    /// The time between repeated frames, in centiseconds
+    interval: f64,
+    /// The minimum time between frames, in centiseconds
+    min_delay: f64,
+    /// The last frame that was emitted
+    last_frame: Option<TerminalFrame>,
+    /// A frame that has been read, but not yet emitted
+    pending_frame: Option<TerminalFrame>,
+    /// The index of the next frame
+    next_index: u64,
+}
+
+impl<I> CameraFrames<I> {
+    /// Move the camera along a path over frames that are at least `min_delay` seconds apart
+    pub fn new(frames: I, camera: &CameraPath, min_delay: f64) -> Self {
+        let moves = camera
+            .moves()
+            .iter()
+            .map(|(start, end)| (start * 100., end * 100.))
+            .collect();
+
+        CameraFrames {
+            frames,
+            moves,
+            interval: MOVE_INTERVAL.max(min_delay * 100.),
+            min_delay: min_delay * 100.,
+            last_frame: None,
+            pending_frame: None,
+            next_index: 0,
+        }
+    }
+
+    /// Emit a frame with the next index
+    fn emit(&mut self, mut frame: TerminalFrame) -> TerminalFrame {
+        frame.index = self.next_index;
+        self.next_index += 1;
+        self.last_frame = Some(frame.clone());
+
+        frame
+    }
+
+    /// Get the time to show the camera at next after a frame at `last_time`, if it is moving then
+    fn next_move_time(&self, last_time: f64) -> Option<f64> {
+        self.moves
+            .iter()
+            .find(|(_, end)| *end > last_time)
+            .map(|&(start, end)| (last_time + self.interval).max(start).min(end))
+    }
+}
+
+impl<I> Iterator for CameraFrames<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = match self
+            .pending_frame
+            .take()
+            .map(Ok)
+            .or_else(|| self.frames.next())?
+        {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+
+        // Repeat the last frame if the camera moves before this frame is shown
+        let last_time = self.last_frame.as_ref().map(|f| f.time);
+        if let Some(time) = last_time.and_then(|last_time| self.next_move_time(last_time)) {
+            if time <= frame.time - self.min_delay {
+                self.pending_frame = Some(frame);
+                let mut repeated = self.last_frame.clone().expect("Checked above");
+                repeated.time = time;
+                return Some(Ok(self.emit(repeated)));
+            }
+        }
+
+        Some(Ok(self.emit(frame)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Get the message of the error that parsing a camera file fails with
+    fn error(camera: &str) -> String {
+        camera
+            .parse::<CameraPath>()
+            .expect_err("The camera path doesn't parse")
+            .to_string()
+    }
+
+    #[test]
+    fn parses_keyframes() {
+        let camera: CameraPath = "# Move in on the prompt\n\
+                                  \n\
+                                  4 top=30,left=0,width=60,height=10\n\
+                                  0 top=0,left=0,width=120,height=40\n\
+                                  2 top=0,left=0,width=120,height=40"
+            .parse()
+            .expect("The camera path parses");
+
+        let view = camera.view_at(0.).expect("The camera has a view");
+        assert_eq!((view.top, view.width), (0., 120.));
+        let view = camera.view_at(3.).expect("The camera has a view");
+        assert_eq!((view.top, view.width), (15., 90.));
+        let view = camera.view_at(10.).expect("The camera has a view");
+        assert_eq!((view.top, view.width), (30., 60.));
+        assert_eq!(camera.moves(), vec![(2., 4.)]);
+    }
+
+    #[test]
+    fn rejects_invalid_keyframes() {
+        assert!(error("4").contains("line 1: it has no rectangle"));
+        assert!(error("# Comment\n4 top=0,left=0,width=4,height=1 zoom")
+            .contains("line 2: there is more after the rectangle"));
+        assert!(error("soon top=0,left=0,width=4,height=1")
+            .contains("the time isn't a number of seconds"));
+        assert!(error("-1 top=0,left=0,width=4,height=1")
+            .contains("the time isn't a number of seconds"));
+        assert!(error("4 top=0,right=0").contains("Invalid camera keyframe on line 1"));
+        assert_eq!(
+            error("4 top=0,left=0,width=4,height=1\n4 top=1,left=0,width=4,height=1"),
+            "There is more than one camera keyframe at 4"
+        );
+    }
+
+    #[test]
+    fn rejects_keyframes_outside_of_the_terminal() {
+        let camera: CameraPath = "4 top=0,left=20,width=60,height=10"
+            .parse()
+            .expect("The camera path parses");
+
+        assert!(camera.validate(10, 80).is_ok());
+        assert!(camera.validate(10, 79).is_err());
+        assert!(camera.validate(9, 80).is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Putting casts together in memory
+//!
+//! Tools that generate demos, and tests of renders, can build the events of a cast in code and
+//! render it without writing a cast file first:
+//!
+//! ```no_run
+//! use cast2gif::{convert_to_gif, CastBuilder};
+//!
+//! let cast = CastBuilder::new(80, 24)
+//!     .output_at(0.5, "$ ls\r\n")
+//!     .output_at(1.0, "Cargo.toml  README.md  src\r\n$ ");
+//! let gif = std::fs::File::create("ls.gif").unwrap();
+//! convert_to_gif(cast.reader(), gif, Default::default()).unwrap();
+//! ```
+//!
+//! Recorded casts can be read into a builder too, and edited before they are rendered by cutting
+//! parts out of them, pausing them, merging other casts into them, and changing their speed:
+//!
+//! ```no_run
+//! use cast2gif::{convert_to_gif, CastBuilder};
+//!
+//! let demo = CastBuilder::read(std::fs::File::open("demo.cast").unwrap()).unwrap();
+//! let outro = CastBuilder::read(std::fs::File::open("outro.cast").unwrap()).unwrap();
+//! let demo = demo
+//!     .cut(12., 19.)
+//!     .pause_at(4., 2.)
+//!     .retime(&"20-40:3".parse().unwrap());
+//! let end = demo.duration();
+//! let cast = demo.merge(outro, end);
+//! let gif = std::fs::File::create("demo.gif").unwrap();
+//! convert_to_gif(cast.reader(), gif, Default::default()).unwrap();
+//! ```
+
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read, Write};
+
+use crate::cast_parser::{AsciinemaCastMeta, AsciinemaFrameRaw, TerminalFrameIter};
+use crate::types::{InitialDelay, SpeedMap};
+use crate::Error;
+
+/// A cast that is built up in memory, one event at a time
+///
+/// Events can be added in any order. They are written out in the order of their times, and events
+/// at the same time keep the order that they were added in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CastBuilder {
+    /// The number of columns of the terminal
+    width: u16,
+    /// The number of rows of the terminal
+    height: u16,
+    /// The title of the recording
+    title: Option<String>,
+    /// The events of the cast, as time, event type, and data triples
+    events: Vec<(f64, String, String)>,
+}
+
+impl CastBuilder {
+    /// Start a cast of a terminal that is `width` columns by `height` rows, without any events
+    pub fn new(width: u16, height: u16) -> Self {
+        CastBuilder {
+            width,
+            height,
+            title: None,
+            events: Vec::new(),
+        }
+    }
+
+    /// Read a asciinema cast file into a builder, with its events at the times that they were
+    /// recorded at
+    pub fn read<R: Read>(reader: R) -> Result<Self, Error> {
+        Ok(TerminalFrameIter::new(reader)?
+            .with_initial_delay(InitialDelay::Keep)
+            .retimed()?)
+    }
+
+    /// Set the title of the recording, which is shown in the title bar of renders that have one
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Write `output` to the terminal `time` seconds into the cast
+    pub fn output_at(mut self, time: f64, output: impl Into<String>) -> Self {
+        self.events.push((time, "o".into(), output.into()));
+        self
+    }
+
+    /// Add a marker `time` seconds into the cast, with a label that may be empty
+    pub fn marker_at(mut self, time: f64, label: impl Into<String>) -> Self {
+        self.events.push((time, "m".into(), label.into()));
+        self
+    }
+
+    /// Get the time of the last event of the cast, in seconds
+    pub fn duration(&self) -> f64 {
+        self.events.iter().map(|event| event.0).fold(0., f64::max)
+    }
+
+    /// Cut the part of the cast from `start` to `end` seconds out of it, moving the events after
+    /// the cut earlier to close the gap
+    ///
+    /// The output in the cut is joined into one event at its start, so that the screen after the
+    /// cut is the same as it would have been without it. The markers in the cut are dropped.
+    pub fn cut(mut self, start: f64, end: f64) -> Self {
+        if end <= start {
+            return self;
+        }
+        self.events.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut events = Vec::with_capacity(self.events.len());
+        // The position of the cut in the events, and the output in it
+        let mut cut: Option<(usize, String)> = None;
+        for (time, event_type, data) in self.events {
+            if time < start {
+                events.push((time, event_type, data));
+            } else if time < end {
+                let (_, output) = cut.get_or_insert_with(|| (events.len(), String::new()));
+                if event_type == "o" {
+                    output.push_str(&data);
+                }
+            } else {
+                events.push((time - (end - start), event_type, data));
+            }
+        }
+        if let Some((index, output)) = cut.filter(|(_, output)| !output.is_empty()) {
+            events.insert(index, (start, "o".into(), output));
+        }
+        self.events = events;
+
+        self
+    }
+
+    /// Hold the screen for `duration` seconds at `time` seconds into the cast, moving the events
+    /// at and after that time later
+    pub fn pause_at(mut self, time: f64, duration: f64) -> Self {
+        for event in self.events.iter_mut().filter(|event| event.0 >= time) {
+            event.0 += duration;
+        }
+
+        self
+    }
+
+    /// Add the events of another cast to this one, starting `time` seconds into it
+    ///
+    /// The events of both casts are played on the terminal of this cast, which keeps its size and
+    /// title. Give the [`duration`](Self::duration) of this cast as the time to play the other one
+    /// after it.
+    pub fn merge(mut self, other: CastBuilder, time: f64) -> Self {
+        self.events.extend(
+            other
+                .events
+                .into_iter()
+                .map(|(event_time, event_type, data)| (time + event_time, event_type, data)),
+        );
+
+        self
+    }
+
+    /// Change the speed of parts of the cast, like the speed map setting of a render does
+    pub fn retime(mut self, speed_map: &SpeedMap) -> Self {
+        for event in &mut self.events {
+            event.0 = speed_map.map_time(event.0);
+        }
+
+        self
+    }
+
+    /// Write the cast in the asciinema v2 format
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let metadata = AsciinemaCastMeta {
+            version: 2,
+            width: self.width,
+            height: self.height,
+            timestamp: 0,
+            env: HashMap::new(),
+            title: self.title.clone(),
+        };
+        serde_json::to_writer(&mut writer, &metadata)?;
+        writeln!(writer)?;
+
+        let mut events: Vec<_> = self.events.iter().collect();
+        events.sort_by(|a, b| a.0.total_cmp(&b.0));
+        for (time, event_type, data) in events {
+            // Times are written to the microsecond, like asciinema does, instead of with the
+            // rounding errors of the arithmetic that they came from
+            let time = (time * 1e6).round() / 1e6;
+            let event = AsciinemaFrameRaw(time, event_type.clone(), data.clone());
+            serde_json::to_writer(&mut writer, &event)?;
+            writeln!(writer)?;
+        }
+
+        writer.flush()
+    }
+
+    /// Get a reader of the cast in the asciinema v2 format, to render it with any of the conversion
+    /// functions
+    pub fn reader(&self) -> Cursor<Vec<u8>> {
+        let mut cast = Vec::new();
+        self.write(&mut cast)
+            .expect("Writing to a Vec doesn't fail");
+
+        Cursor::new(cast)
+    }
+}
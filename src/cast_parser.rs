@@ -3,14 +3,28 @@
 //! This module contains the code that parses the asciinema cast into a set of terminal screen
 //! states.
 
+use memmap2::Mmap;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader, Read};
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read};
 use std::iter::Iterator;
+use std::sync::Arc;
 
-use crate::types::TerminalFrame;
+use crate::cast_builder::CastBuilder;
+use crate::escape_sequences::UnsupportedSequences;
+use crate::prompts::{PromptDetector, ShellCommand};
+use crate::types::{
+    CastSummary, ConvertSettings, InitialDelay, Marker, ScreenSnapshot, SpeedMap, TerminalFrame,
+    TimingMode, TrimSettings,
+};
+use crate::typing_speed::TypingSpeed;
+use crate::underline::Underlines;
 
 /// An asciinema error
 #[derive(Error, Debug)]
@@ -23,9 +37,48 @@ pub enum AsciinemaError {
     IoError(#[from] std::io::Error),
     #[error("Only asciinema file version 2 is supported, got version: {0}")]
     InvalidVersion(u16),
+    #[error(
+        "Could not parse line {line_number} (byte {byte_offset}) of Asciinema cast: {message}"
+    )]
+    InvalidLine {
+        /// The 1-based number of the line that could not be parsed
+        line_number: usize,
+        /// The 0-based byte offset in the file of the error, or of the start of the line if the
+        /// column isn't known
+        byte_offset: usize,
+        /// The line that could not be parsed
+        line: String,
+        /// The 1-based column that the error was found at, if known
+        column: Option<usize>,
+        /// What is wrong with the line
+        message: String,
+    },
+}
+
+impl AsciinemaError {
+    /// Create an error for a line that serde couldn't parse
+    fn invalid_json_line(line_number: usize, line: &CastLine, error: serde_json::Error) -> Self {
+        AsciinemaError::InvalidLine {
+            line_number,
+            // Serde counts columns in bytes
+            byte_offset: line.offset + error.column().saturating_sub(1),
+            line: line.text.to_string(),
+            column: Some(error.column()),
+            // The line is reported separately, and serde only ever sees one line at a time
+            message: error
+                .to_string()
+                .trim_end_matches(&format!(
+                    " at line {} column {}",
+                    error.line(),
+                    error.column()
+                ))
+                .to_string(),
+        }
+    }
 }
 
 /// An asciinema cast
+#[allow(dead_code)]
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct AsciinemaCast {
     /// Asciinema file metadata
@@ -36,18 +89,20 @@ struct AsciinemaCast {
 
 /// Asciinema cast file metadata
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct AsciinemaCastMeta {
-    version: u16,
-    width: u16,
-    height: u16,
-    timestamp: i32,
-    env: HashMap<String, String>,
+pub(crate) struct AsciinemaCastMeta {
+    pub version: u16,
+    pub width: u16,
+    pub height: u16,
+    pub timestamp: i32,
+    pub env: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
 }
 
 /// A frame from the asciinema recording
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct AsciinemaFrame {
-    time: f32,
+    time: f64,
     /// TODO: not actually sure what this field is for
     command: String,
     output: String,
@@ -56,7 +111,7 @@ struct AsciinemaFrame {
 /// A frame from the asciinema recording. This has unnamed fields to be compatible with the actual
 /// JSON representation of the frame.
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct AsciinemaFrameRaw(f32, String, String);
+pub(crate) struct AsciinemaFrameRaw(pub f64, pub String, pub String);
 
 /// An iterator over terminal frames in a asciinema cast file reader
 ///
@@ -67,45 +122,584 @@ pub(crate) struct TerminalFrameIter<R: Read> {
     next_index: u64,
     /// The parser instance used to emulate the terminal
     parser: vt100::Parser,
-    /// The buffered line reader over the Asciinema recording file
-    lines: std::io::Lines<BufReader<R>>,
+    /// The underline styles and colors of the emulated terminal, which `vt100` doesn't keep
+    underlines: Underlines,
+    /// The escape sequences in the output that don't make it into the frames, if they are being
+    /// warned about
+    unsupported_sequences: Option<UnsupportedSequences>,
+    /// The lines of the Asciinema recording file
+    lines: CastLines<R>,
+    /// The number of the last line read from the file, used for error messages
+    line_number: usize,
+    /// The title of the recording from the cast header
+    title: Option<String>,
+    /// The hash of the last screen that was emitted, used to skip duplicate screens
+    last_screen_hash: Option<u64>,
+    /// How frames are timed
+    timing: TimingMode,
+    /// The speed factors to apply to the event times
+    speed_map: SpeedMap,
+    /// The part of the recording to emit frames for
+    trim: TrimSettings,
+    /// Whether or not the first frame after the trim start has been emitted
+    trim_started: bool,
+    /// What to do with the time before the first output
+    initial_delay: InitialDelay,
+    /// The amount of time to move every event earlier by, once it is known from the first event
+    initial_offset: Option<f64>,
+    /// Moves typed characters to a steady typing speed, if typing is re-timed
+    typing_speed: Option<TypingSpeed>,
+    /// The time, in seconds, to hold the frame at each marker for
+    marker_pause: f64,
+    /// The amount of time to move events later by for the pauses at the markers before them
+    marker_pause_offset: f64,
+    /// The time of the next screen sample when using fixed timing
+    next_sample_time: f64,
+    /// An event that has been read, but not yet processed
+    pending_event: Option<AsciinemaFrame>,
+    /// Whether or not to put the labels of markers on frames
+    marker_labels: bool,
+    /// The label of a marker that hasn't been put on a frame yet
+    pending_marker: Option<Arc<str>>,
+    /// Whether or not to keep track of the last marker for every frame
+    track_markers: bool,
+    /// The last marker that was applied
+    marker: Option<Marker>,
+    /// The number of the marker of the last frame that was emitted
+    emitted_marker_number: u32,
+    /// Finds the commands run at shell prompts, if they are being looked for
+    prompts: Option<PromptDetector>,
+    /// Whether or not to add a marker for every command run at a shell prompt
+    command_markers: bool,
+    /// Whether or not to keep track of the window title for every frame
+    track_titles: bool,
+    /// The window title of the last frame that was emitted
+    emitted_title: Option<Arc<str>>,
+    /// Whether or not to keep track of when the bell rings
+    track_bells: bool,
+    /// The number of times that the bell rang before the last frame that was emitted, or before
+    /// the trim start
+    emitted_bell_count: usize,
 }
 
 impl<R: Read> TerminalFrameIter<R> {
     pub fn new(reader: R) -> Result<Self, AsciinemaError> {
-        // Buffer read
-        let buf_reader = BufReader::new(reader);
-        // Split file by lines
-        let mut lines = buf_reader.lines();
+        // Buffer read and split file by lines
+        Self::from_lines(CastLines::Reader {
+            reader: BufReader::new(reader),
+            pos: 0,
+        })
+    }
 
-        let metadata_line = lines.next().ok_or_else(|| {
+    fn from_lines(mut lines: CastLines<R>) -> Result<Self, AsciinemaError> {
+        let metadata_line = lines.next_line().ok_or_else(|| {
             AsciinemaError::GenericParserError("Missing cast metadata line".into())
         })??;
+        metadata_line.check_length(1)?;
 
         // Parse metadata
-        let metadata: AsciinemaCastMeta = serde_json::from_str(&metadata_line)?;
+        let metadata: AsciinemaCastMeta = serde_json::from_str(&metadata_line.text)
+            .map_err(|e| AsciinemaError::invalid_json_line(1, &metadata_line, e))?;
 
         // Validate metadata version
         if metadata.version != 2 {
             return Err(AsciinemaError::InvalidVersion(metadata.version));
         }
 
+        // The whole terminal is emulated and kept for every frame, so don't let a corrupt header
+        // make us allocate more than we can
+        let cells = u64::from(metadata.width) * u64::from(metadata.height);
+        if cells == 0 || cells > MAX_TERMINAL_CELLS {
+            return Err(AsciinemaError::InvalidLine {
+                line_number: 1,
+                byte_offset: metadata_line.offset,
+                line: metadata_line.text.into_owned(),
+                column: None,
+                message: format!(
+                    "The terminal is {}x{} cells, but only terminals with between 1 and {} cells \
+                     are supported",
+                    metadata.width, metadata.height, MAX_TERMINAL_CELLS
+                ),
+            });
+        }
+
         // Create iterator
         Ok(TerminalFrameIter {
             next_index: 0,
             parser: vt100::Parser::new(metadata.height, metadata.width, 0 /* scrollback */),
+            underlines: Underlines::new(metadata.height, metadata.width),
+            unsupported_sequences: Some(UnsupportedSequences::default()),
             lines,
+            line_number: 1,
+            title: metadata.title,
+            last_screen_hash: None,
+            timing: TimingMode::default(),
+            speed_map: SpeedMap::default(),
+            trim: TrimSettings::default(),
+            trim_started: false,
+            initial_delay: InitialDelay::default(),
+            initial_offset: None,
+            typing_speed: None,
+            marker_pause: 0.,
+            marker_pause_offset: 0.,
+            next_sample_time: 0.,
+            pending_event: None,
+            marker_labels: false,
+            pending_marker: None,
+            track_markers: false,
+            marker: None,
+            emitted_marker_number: 0,
+            prompts: None,
+            command_markers: false,
+            track_titles: false,
+            emitted_title: None,
+            track_bells: false,
+            emitted_bell_count: 0,
         })
     }
+
+    /// Set how frames are timed
+    pub fn with_timing(mut self, timing: TimingMode) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Set the speed factors to apply to the event times
+    pub fn with_speed_map(mut self, speed_map: SpeedMap) -> Self {
+        self.speed_map = speed_map;
+        self
+    }
+
+    /// Set the part of the recording to emit frames for
+    ///
+    /// Events before the start are still applied to the screen, so the first frame shows the
+    /// screen as it was at the start time.
+    pub fn with_trim(mut self, trim: TrimSettings) -> Self {
+        self.trim = trim;
+        self
+    }
+
+    /// Set what to do with the time between the start of the recording and its first output
+    pub fn with_initial_delay(mut self, initial_delay: InitialDelay) -> Self {
+        self.initial_delay = initial_delay;
+        self
+    }
+
+    /// Set the number of characters per second to re-time typing to, or `None` to keep the typing
+    /// as it was recorded
+    pub fn with_typing_speed(mut self, chars_per_second: Option<f64>) -> Self {
+        self.typing_speed = chars_per_second.map(TypingSpeed::new);
+        self
+    }
+
+    /// Set the time, in seconds, to hold the frame at each marker for
+    ///
+    /// The events after each marker are moved later by the pause. Markers before the trim start
+    /// don't pause.
+    pub fn with_marker_pause(mut self, marker_pause: f64) -> Self {
+        self.marker_pause = marker_pause;
+        self
+    }
+
+    /// Set whether or not to put the labels of markers on frames
+    ///
+    /// A frame is emitted for every marker with a label, even if the screen hasn't changed, with
+    /// the label as its caption. Otherwise markers are skipped.
+    pub fn with_marker_labels(mut self, marker_labels: bool) -> Self {
+        self.marker_labels = marker_labels;
+        self
+    }
+
+    /// Set whether or not to keep track of the last marker for every frame
+    ///
+    /// A frame is emitted for every marker, even if the screen hasn't changed, so that the marker
+    /// of a frame is the last marker before it.
+    pub fn with_marker_tracking(mut self, track_markers: bool) -> Self {
+        self.track_markers = track_markers;
+        self
+    }
+
+    /// Look for the commands run at shell prompts, which are listed by [`Self::commands`]
+    pub fn with_command_detection(mut self) -> Self {
+        self.prompts.get_or_insert_with(PromptDetector::default);
+        self
+    }
+
+    /// Set whether or not to add a marker for every command run at a shell prompt
+    ///
+    /// The markers are labeled with the commands, and are put on frames like the markers in the
+    /// cast.
+    pub fn with_command_markers(mut self, command_markers: bool) -> Self {
+        self.command_markers = command_markers;
+        if command_markers {
+            self = self.with_command_detection();
+        }
+        self
+    }
+
+    /// Set whether or not to keep track of the window title for every frame
+    ///
+    /// A frame is emitted whenever the cast changes the title with an OSC 0 or OSC 2 sequence,
+    /// even if the screen hasn't changed.
+    pub fn with_title_tracking(mut self, track_titles: bool) -> Self {
+        self.track_titles = track_titles;
+        self
+    }
+
+    /// Set whether or not to keep track of when the bell rings
+    ///
+    /// A frame is emitted whenever the bell rings, even if the screen hasn't changed, and the frames
+    /// after the bell rang are marked with it.
+    pub fn with_bell_tracking(mut self, track_bells: bool) -> Self {
+        self.track_bells = track_bells;
+        self
+    }
+
+    /// Stop warning about the escape sequences in the cast that don't make it into the frames
+    ///
+    /// Passes over a cast before it is rendered turn the warning off, so that it is only given once.
+    pub fn without_sequence_warnings(mut self) -> Self {
+        self.unsupported_sequences = None;
+        self
+    }
+
+    /// Set the timing of the frames from the settings of a conversion
+    pub fn with_settings(self, settings: &ConvertSettings) -> Self {
+        self.with_timing(settings.timing)
+            .with_speed_map(settings.speed_map.clone())
+            .with_trim(settings.trim)
+            .with_initial_delay(settings.initial_delay)
+            .with_typing_speed(settings.typing_speed)
+            .with_marker_pause(settings.pause_on_markers.unwrap_or(0.))
+            .with_marker_labels(settings.marker_captions.is_some())
+            .with_marker_tracking(settings.tracks_markers())
+            .with_command_markers(settings.command_markers)
+            .with_title_tracking(settings.tracks_titles())
+            .with_bell_tracking(settings.bell_flash)
+    }
+
+    /// Get the (rows, cols) size of the terminal
+    pub fn size(&self) -> (u16, u16) {
+        self.parser.screen().size()
+    }
+
+    /// Get the title of the recording from the cast header, if it has one
+    pub fn title(&self) -> Option<String> {
+        self.title.clone()
+    }
+
+    /// Get the commands run at shell prompts in the frames so far, if they are being looked for
+    pub fn commands(&self) -> &[ShellCommand] {
+        self.prompts
+            .as_ref()
+            .map_or(&[], |prompts| prompts.commands())
+    }
+
+    /// Read through the events of the cast without emulating the terminal, counting the events
+    /// that frames are rendered for and finding the time of the last one
+    pub fn scan(mut self) -> Result<CastSummary, AsciinemaError> {
+        let mut summary = CastSummary::default();
+        while let Some(event) = self.next_event() {
+            let event = event?;
+            // Events before the trim start only set up the screen
+            if event.time >= 0. {
+                summary.events += 1;
+                summary.duration = summary.duration.max(event.time * 100.);
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Read through the events of the cast without emulating the terminal, into a cast of the
+    /// events at the times that frames are rendered for them
+    ///
+    /// Output before the trim start is joined into one event at the start of the cast, so that it
+    /// sets up the screen as it was at the trim start all at once, and markers before it are
+    /// dropped.
+    pub fn retimed(mut self) -> Result<CastBuilder, AsciinemaError> {
+        // Markers are kept even if they aren't put on frames
+        self.track_markers = true;
+        let (rows, cols) = self.size();
+        let mut cast = CastBuilder::new(cols, rows);
+        if let Some(title) = &self.title {
+            cast = cast.title(title.as_str());
+        }
+
+        let mut setup = String::new();
+        let mut last_time = 0f64;
+        while let Some(event) = self.next_event() {
+            let event = event?;
+            if event.time < 0. {
+                if event.command != "m" {
+                    setup.push_str(&event.output);
+                }
+                continue;
+            }
+            if !setup.is_empty() {
+                cast = cast.output_at(0., std::mem::take(&mut setup));
+            }
+
+            // The events of a cast can't go back in time, which retiming the typing could make them
+            let time = event.time.max(last_time);
+            last_time = time;
+            cast = match event.command.as_str() {
+                "m" => cast.marker_at(time, event.output),
+                _ => cast.output_at(time, event.output),
+            };
+        }
+        if !setup.is_empty() {
+            cast = cast.output_at(0., setup);
+        }
+
+        Ok(cast)
+    }
 }
 
-impl<R: Read> Iterator for TerminalFrameIter<R> {
-    type Item = Result<TerminalFrame, AsciinemaError>;
+impl TerminalFrameIter<std::io::Empty> {
+    /// Create an iterator over the frames of a cast file by memory-mapping it
+    ///
+    /// Lines are parsed straight out of the mapped file instead of being copied into a new
+    /// `String` for every line.
+    pub fn from_file_mapped(file: &File) -> Result<Self, AsciinemaError> {
+        // Safety: the map is only ever read and the cast file is not expected to be modified while
+        // we are rendering it.
+        let map = unsafe { Mmap::map(file)? };
 
-    fn next(&mut self) -> Option<Self::Item> {
+        Self::from_lines(CastLines::Mapped { map, pos: 0 })
+    }
+}
+
+/// The longest line, in bytes, that is read from a cast file
+///
+/// Lines are read into memory whole, so this keeps a corrupt file without newlines from using up
+/// all of the memory.
+const MAX_LINE_LENGTH: usize = 64 * 1024 * 1024;
+
+/// The most bytes of a line that is too long to keep for the error message
+const MAX_TRUNCATED_LINE_LENGTH: usize = 1024;
+
+/// The byte order mark that some Windows programs put at the start of UTF-8 files
+const BYTE_ORDER_MARK: char = '\u{feff}';
+
+/// The largest terminal, in cells, that can be emulated
+const MAX_TERMINAL_CELLS: u64 = 4_000_000;
+
+/// A line of a cast file
+struct CastLine<'a> {
+    /// The byte offset of the start of the line in the file
+    offset: usize,
+    /// The line, without the trailing newline, or the start of it if it is too long
+    text: Cow<'a, str>,
+    /// Whether or not the line was longer than [`MAX_LINE_LENGTH`] and was cut off
+    truncated: bool,
+}
+
+impl<'a> CastLine<'a> {
+    /// Make a cast line out of the bytes of a line, which may include its line ending
+    fn new(mut offset: usize, line: Cow<'a, [u8]>, truncated: bool) -> io::Result<Self> {
+        let mut text = if truncated {
+            // Keep the start of the line for the error message, which needn't be valid UTF-8
+            let start = &line[..MAX_TRUNCATED_LINE_LENGTH];
+            Cow::Owned(String::from_utf8_lossy(start).into_owned())
+        } else {
+            // Strip the newline, and the carriage return of CRLF line endings
+            let mut len = line.len();
+            if line.ends_with(b"\n") {
+                len -= 1;
+            }
+            if line[..len].ends_with(b"\r") {
+                len -= 1;
+            }
+
+            match line {
+                Cow::Borrowed(line) => {
+                    Cow::Borrowed(std::str::from_utf8(&line[..len]).map_err(invalid_data)?)
+                }
+                Cow::Owned(mut line) => {
+                    line.truncate(len);
+                    Cow::Owned(String::from_utf8(line).map_err(invalid_data)?)
+                }
+            }
+        };
+
+        // Skip the byte order mark at the start of the file
+        if offset == 0 && text.starts_with(BYTE_ORDER_MARK) {
+            let len = BYTE_ORDER_MARK.len_utf8();
+            text = match text {
+                Cow::Borrowed(text) => Cow::Borrowed(&text[len..]),
+                Cow::Owned(mut text) => {
+                    text.drain(..len);
+                    Cow::Owned(text)
+                }
+            };
+            offset = len;
+        }
+
+        Ok(CastLine {
+            offset,
+            text,
+            truncated,
+        })
+    }
+
+    /// Make sure that the line wasn't cut off for being too long
+    fn check_length(&self, line_number: usize) -> Result<(), AsciinemaError> {
+        if self.truncated {
+            return Err(AsciinemaError::InvalidLine {
+                line_number,
+                byte_offset: self.offset,
+                line: self.text.to_string(),
+                column: None,
+                message: format!(
+                    "The line is longer than the limit of {} MB",
+                    MAX_LINE_LENGTH / 1024 / 1024
+                ),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// The source of the lines in a cast file
+enum CastLines<R: Read> {
+    /// Lines read through a buffered reader
+    Reader {
+        /// The reader of the file
+        reader: BufReader<R>,
+        /// The byte offset of the next line
+        pos: usize,
+    },
+    /// Lines sliced out of a memory-mapped file
+    Mapped {
+        /// The mapped file
+        map: Mmap,
+        /// The byte offset of the next line
+        pos: usize,
+    },
+}
+
+impl<R: Read> CastLines<R> {
+    /// Get the next line, without the trailing newline
+    fn next_line(&mut self) -> Option<io::Result<CastLine<'_>>> {
+        match self {
+            CastLines::Reader { reader, pos } => {
+                let offset = *pos;
+                let mut line = Vec::new();
+                let read = reader
+                    .by_ref()
+                    .take(MAX_LINE_LENGTH as u64 + 1)
+                    .read_until(b'\n', &mut line);
+                match read {
+                    Ok(0) => return None,
+                    Ok(read) => *pos += read,
+                    Err(e) => return Some(Err(e)),
+                }
+
+                // Skip the rest of lines that are too long
+                let truncated = line.len() > MAX_LINE_LENGTH;
+                if truncated {
+                    match skip_line(reader) {
+                        Ok(skipped) => *pos += skipped,
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+
+                Some(CastLine::new(offset, Cow::Owned(line), truncated))
+            }
+            CastLines::Mapped { map, pos } => {
+                if *pos >= map.len() {
+                    return None;
+                }
+
+                // Find the end of the line
+                let offset = *pos;
+                let rest = &map[*pos..];
+                let line = match rest.iter().position(|&b| b == b'\n') {
+                    Some(end) => &rest[..=end],
+                    None => rest,
+                };
+                *pos += line.len();
+
+                Some(CastLine::new(
+                    offset,
+                    Cow::Borrowed(line),
+                    line.len() > MAX_LINE_LENGTH,
+                ))
+            }
+        }
+    }
+}
+
+/// Create an error for a line that isn't valid UTF-8
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+/// Skip to the start of the next line, returning the number of bytes skipped
+fn skip_line<R: BufRead>(reader: &mut R) -> io::Result<usize> {
+    let mut skipped = 0;
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(skipped);
+        }
+
+        match buf.iter().position(|&b| b == b'\n') {
+            Some(end) => {
+                reader.consume(end + 1);
+                return Ok(skipped + end + 1);
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+                skipped += len;
+            }
+        }
+    }
+}
+
+/// Hash the visible state of a terminal screen and its underlines
+///
+/// Two screens with the same hash will rasterize to the same image.
+fn screen_hash(screen: &vt100::Screen, underlines: &Underlines) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    // The formatted contents include whether or not the cursor is hidden
+    screen.contents_formatted().hash(&mut hasher);
+    screen.cursor_position().hash(&mut hasher);
+    underlines.contents_formatted().hash(&mut hasher);
+    hasher.finish()
+}
+
+impl<R: Read> TerminalFrameIter<R> {
+    /// Get the amount of time to move the events earlier by to apply the initial delay setting,
+    /// given the time of the current event
+    fn initial_offset(&mut self, time: f64) -> f64 {
+        // Trimmed recordings start with the screen at the trim start instead
+        if self.trim.start.is_some() {
+            return 0.;
+        }
+
+        let initial_delay = self.initial_delay;
+        *self
+            .initial_offset
+            .get_or_insert_with(|| match initial_delay {
+                InitialDelay::Keep => 0.,
+                InitialDelay::Trim => time,
+                InitialDelay::Cap(max) => (time - max).max(0.),
+            })
+    }
+
+    /// Read the next event from the cast file
+    fn next_event(&mut self) -> Option<Result<AsciinemaFrame, AsciinemaError>> {
         loop {
             // Get the next line from our reader
-            let line = self.lines.next();
+            let line = self.lines.next_line();
+            self.line_number += 1;
 
             // If there was another line
             if let Some(line) = line {
@@ -115,56 +709,84 @@ impl<R: Read> Iterator for TerminalFrameIter<R> {
                     // Return IO error
                     Err(e) => break Some(Err(AsciinemaError::IoError(e))),
                 };
+                if let Err(e) = line.check_length(self.line_number) {
+                    break Some(Err(e));
+                }
 
                 // Skip this line if it is empty
-                if line == "" {
+                if line.text.is_empty() {
                     continue;
                 }
 
                 // Parse raw frame
-                let frame: AsciinemaFrameRaw = match serde_json::from_str(&line) {
+                let frame: AsciinemaFrameRaw = match serde_json::from_str(&line.text) {
                     // Extract frame
                     Ok(frame) => frame,
                     // Return parser error
-                    Err(_) => {
-                        break Some(Err(AsciinemaError::GenericParserError(format!(
-                            "Error parsing asciinema frame: {}",
-                            line
-                        ))))
+                    Err(e) => {
+                        break Some(Err(AsciinemaError::invalid_json_line(
+                            self.line_number,
+                            &line,
+                            e,
+                        )))
                     }
                 };
 
-                // Restructucuture frame for readability
-                let frame = AsciinemaFrame {
-                    time: frame.0,
+                // Stop at the end of the trim
+                if let Some(end) = self.trim.end {
+                    if frame.0 > end {
+                        break None;
+                    }
+                }
+
+                // Restructucuture frame for readability. Times are relative to the start of the
+                // trim, so events before it have negative times.
+                let trim_start = self.speed_map.map_time(self.trim.start.unwrap_or(0.));
+                let mut frame = AsciinemaFrame {
+                    time: self.speed_map.map_time(frame.0) - trim_start,
                     command: frame.1,
                     output: frame.2,
                 };
 
+                // Hold the frame at the marker by moving the events after it later
+                let marker_pause_offset = self.marker_pause_offset;
+                if frame.command == "m" && frame.time >= 0. {
+                    self.marker_pause_offset += self.marker_pause;
+                }
+
+                // Markers are only kept for their labels, unless they are being tracked
+                if frame.command == "m"
+                    && !self.track_markers
+                    && (!self.marker_labels || frame.output.is_empty())
+                {
+                    continue;
+                }
+
                 // TODO: I don't know what other items might be in the second item of the record array,
-                // but so far I've only seen "o".
-                if frame.command != "o" {
-                    let error_message = format!(
+                // but so far I've only seen "o" and "m".
+                if frame.command != "o" && frame.command != "m" {
+                    let message = format!(
                         "Cast2Gif doesn't yet understand asciinema files with \
-                        something other than `o` in the second item of the record \
-                        array. Please open an issue for this: {}",
-                        line
+                        something other than `o` or `m` in the second item of the record \
+                        array, got `{}`. Please open an issue for this.",
+                        frame.command
                     );
-                    return Some(Err(AsciinemaError::GenericParserError(error_message)));
+                    let index = line.text.find(&format!("\"{}\"", frame.command));
+                    break Some(Err(AsciinemaError::InvalidLine {
+                        line_number: self.line_number,
+                        byte_offset: line.offset + index.unwrap_or(0),
+                        column: index.map(|i| i + 1),
+                        line: line.text.into_owned(),
+                        message,
+                    }));
                 }
 
-                // Process the terminal input
-                self.parser.process(frame.output.as_bytes());
-
-                // Increment the frame index
-                let current_index = self.next_index;
-                self.next_index += 1;
-
-                break Some(Ok(TerminalFrame {
-                    index: current_index,
-                    time: frame.time * 100f32,
-                    screen: self.parser.screen().clone(),
-                }));
+                frame.time -= self.initial_offset(frame.time);
+                if let Some(typing_speed) = &mut self.typing_speed {
+                    frame.time = typing_speed.retime(frame.time, &frame.command, &frame.output);
+                }
+                frame.time += marker_pause_offset;
+                break Some(Ok(frame));
 
             // If there isn't another line
             } else {
@@ -172,4 +794,227 @@ impl<R: Read> Iterator for TerminalFrameIter<R> {
             }
         }
     }
+
+    /// Apply the output of an event at the given time to the terminal
+    fn process(&mut self, output: &str, time: f64) {
+        let output = output.as_bytes();
+        if let Some(unsupported_sequences) = &mut self.unsupported_sequences {
+            unsupported_sequences.process(output);
+        }
+        let mut prompts = match self.prompts.take() {
+            Some(prompts) => prompts,
+            None => return self.emulate(output),
+        };
+
+        // Apply the output up to each prompt mark before the mark, so that it sees the cursor
+        // where the shell put it
+        let mut commands = Vec::new();
+        prompts.before_output(self.parser.screen());
+        let mut start = 0;
+        for (end, mark) in prompts.scan(output) {
+            self.emulate(&output[start..end]);
+            commands.extend(prompts.mark(mark, self.parser.screen(), time));
+            start = end;
+        }
+        self.emulate(&output[start..]);
+        commands.extend(prompts.after_output(output, self.parser.screen(), time));
+        self.prompts = Some(prompts);
+
+        if self.command_markers {
+            for command in commands {
+                self.add_marker(command.command.as_str().into(), time);
+            }
+        }
+    }
+
+    /// Emulate some output on the terminal
+    fn emulate(&mut self, output: &[u8]) {
+        self.parser.process(&self.underlines.process(output));
+    }
+
+    /// Apply an event, keeping the label of markers for the next frame
+    ///
+    /// Labels of markers before the trim start are dropped, because their part of the recording
+    /// isn't shown, but they are still counted in the numbers of tracked markers.
+    fn apply(&mut self, event: &AsciinemaFrame) {
+        if event.command != "m" {
+            self.process(&event.output, event.time);
+            // Bells before the trim start aren't part of the recording
+            if event.time < 0. {
+                self.emitted_bell_count = self.bell_count();
+            }
+            return;
+        }
+
+        self.add_marker(event.output.as_str().into(), event.time);
+    }
+
+    /// Add a marker with the given label at the given time
+    fn add_marker(&mut self, label: Arc<str>, time: f64) {
+        if self.marker_labels && !label.is_empty() && time >= 0. {
+            self.pending_marker = Some(label.clone());
+        }
+        if self.track_markers {
+            let number = self.marker.as_ref().map_or(0, |marker| marker.number) + 1;
+            self.marker = Some(Marker { number, label });
+        }
+    }
+
+    /// Create a frame from the current screen at the given time
+    ///
+    /// Returns `None` if the screen hasn't changed since the last frame and there is no new marker
+    /// to put on it. Because the gif frames are timed by their timestamps, skipping the frame
+    /// extends the delay of the previous frame instead of rasterizing an identical image.
+    fn emit_frame(&mut self, time: f64) -> Option<TerminalFrame> {
+        let hash = screen_hash(self.parser.screen(), &self.underlines);
+        let marker_number = self.marker.as_ref().map_or(0, |marker| marker.number);
+        let title = self.window_title();
+        let bell_count = self.bell_count();
+        let bell = bell_count != self.emitted_bell_count;
+        if self.last_screen_hash == Some(hash)
+            && self.pending_marker.is_none()
+            && self.emitted_marker_number == marker_number
+            && self.emitted_title == title
+            && !bell
+        {
+            return None;
+        }
+        self.last_screen_hash = Some(hash);
+        self.emitted_marker_number = marker_number;
+        self.emitted_title = title.clone();
+        self.emitted_bell_count = bell_count;
+
+        // Increment the frame index
+        let current_index = self.next_index;
+        self.next_index += 1;
+
+        let mut screen = ScreenSnapshot::from_screen(self.parser.screen());
+        self.underlines.apply(&mut screen);
+
+        Some(TerminalFrame {
+            index: current_index,
+            time: time * 100f64,
+            screen,
+            caption: self.pending_marker.take(),
+            marker: self.marker.clone(),
+            title,
+            bell,
+            scroll: None,
+        })
+    }
+
+    /// Get the number of times that the bell has rung, if bells are being tracked, counting both
+    /// audible and visual bells
+    fn bell_count(&self) -> usize {
+        if !self.track_bells {
+            return 0;
+        }
+        let screen = self.parser.screen();
+
+        screen.audible_bell_count() + screen.visual_bell_count()
+    }
+
+    /// Get the title of the window, if titles are being tracked
+    ///
+    /// This is the last title that the cast set, or the title from the cast header until it sets
+    /// one.
+    fn window_title(&self) -> Option<Arc<str>> {
+        if !self.track_titles {
+            return None;
+        }
+        let title = Some(self.parser.screen().title())
+            .filter(|title| !title.is_empty())
+            .or(self.title.as_deref())
+            .filter(|title| !title.is_empty())?;
+
+        // Share the title with the last frame while it stays the same
+        match &self.emitted_title {
+            Some(emitted) if **emitted == *title => Some(emitted.clone()),
+            _ => Some(title.into()),
+        }
+    }
+}
+
+impl<R: Read> Iterator for TerminalFrameIter<R> {
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let event = match self
+                .pending_event
+                .take()
+                .map(Ok)
+                .or_else(|| self.next_event())
+            {
+                Some(Ok(event)) => event,
+                Some(Err(e)) => break Some(Err(e)),
+                None => {
+                    if let Some(unsupported_sequences) = &mut self.unsupported_sequences {
+                        unsupported_sequences.warn();
+                    }
+
+                    // Emit the changes since the last sample when the cast ends
+                    break match self.timing {
+                        // Emit the screen at the trim start if no events came after it
+                        TimingMode::Events if !self.trim_started && self.trim.start.is_some() => {
+                            self.trim_started = true;
+                            self.emit_frame(0.).map(Ok)
+                        }
+                        TimingMode::Events => None,
+                        TimingMode::Fixed(_) => self.emit_frame(self.next_sample_time).map(Ok),
+                    };
+                }
+            };
+
+            match self.timing {
+                // Emit a frame for every event
+                TimingMode::Events => {
+                    // Apply the events before the trim start without emitting frames for them
+                    if event.time < 0. {
+                        self.apply(&event);
+                        continue;
+                    }
+
+                    // Emit the screen as it was at the start before the first event after it, so
+                    // that the delay before the first event is part of the gif
+                    if !self.trim_started {
+                        self.trim_started = true;
+                        if self.trim.start.is_some() || event.time > 0. {
+                            self.pending_event = Some(event);
+                            if let Some(frame) = self.emit_frame(0.) {
+                                break Some(Ok(frame));
+                            }
+                            continue;
+                        }
+                    }
+
+                    self.apply(&event);
+
+                    if let Some(frame) = self.emit_frame(event.time) {
+                        break Some(Ok(frame));
+                    }
+                }
+                // Emit a frame for every sample interval
+                TimingMode::Fixed(interval) => {
+                    // Collect events up until the next sample
+                    if event.time <= self.next_sample_time {
+                        self.apply(&event);
+                        continue;
+                    }
+
+                    // This event happens after the next sample, so take the sample before
+                    // processing it and skip ahead to the first sample that will include it
+                    let sample_time = self.next_sample_time;
+                    // Rounding can put the sample just before the event, which would never include it
+                    self.next_sample_time =
+                        ((event.time / interval).ceil() * interval).max(event.time);
+                    self.pending_event = Some(event);
+
+                    if let Some(frame) = self.emit_frame(sample_time) {
+                        break Some(Ok(frame));
+                    }
+                }
+            }
+        }
+    }
 }
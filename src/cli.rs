@@ -1,9 +1,45 @@
 use anyhow::{format_err, Context};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 
-use std::path::Path;
+use std::collections::HashMap;
+use std::fmt;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
 
+use crate::color_profile::Rgb;
+use crate::{
+    AspectRatio, ColorProfile, ConvertSettings, CropSettings, InitialDelay, PngSequenceSettings,
+    SpeedMap, SvgSettings, TimingMode, TrimSettings, VideoSettings,
+};
+use atomic_file::AtomicFile;
+use exit_code::ArgumentError;
+use pipe_pane::TerminalSize;
+use temp_file::TempFile;
+
+mod atomic_file;
+mod bench;
+mod chapters;
+mod diagnostics;
+mod dry_run;
+mod edit;
+mod exit_code;
+#[cfg(feature = "fetch")]
+mod fetch;
+mod inspect;
 mod logging;
+mod palette;
+mod pipe_pane;
+mod preview;
+mod serve;
+#[cfg(feature = "server")]
+mod server;
+mod show;
+mod summary;
+mod temp_file;
 
 pub fn run() {
     // Enable colored backtraces
@@ -15,186 +51,1286 @@ pub fn run() {
         .format(logging::formatter)
         .init();
 
-    std::panic::catch_unwind(|| {
+    let result = std::panic::catch_unwind(|| {
         // run program and report any errors
         if let Err(e) = execute_cli() {
-            log::error!("{:?}", e);
-            std::process::exit(1);
+            match diagnostics::render(&e) {
+                Some((cause, diagnostic)) => {
+                    log::error!("{}", cause);
+                    eprintln!("\n{}", diagnostic);
+                }
+                None => log::error!("{:?}", e),
+            }
+            std::process::exit(exit_code::for_error(&e));
         }
-    })
+    });
+
     // Catch any panics and print an error message. This will appear after the message given by
     // better backtrace.
     // TODO: Replace all uses of the concat macro for wrapping strings with backslash escapes
-    .or_else(|_| -> Result<(), ()> {
+    if result.is_err() {
         log::error!(concat!(
             "The program has encountered a critical internal error and will now exit. ",
             "This is a bug. Please report it on our issue tracker:\n\n",
             "    https://github.com/katharostech/cast2gif/issues"
         ));
 
-        std::process::exit(1);
-    })
-    .expect("Panic while handling panic");
+        std::process::exit(exit_code::INTERNAL_ERROR);
+    }
+}
+
+/// How to report conversion progress
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum ProgressOutput {
+    /// Interactive progress bars
+    Bars,
+    /// Newline-delimited JSON events on stderr
+    Json,
+    /// No progress output
+    #[value(skip)]
+    Hidden,
+}
+
+/// The format of the file to render
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum InputFormat {
+    /// An asciinema .cast file
+    Cast,
+    /// The output of a tmux pane saved with `pipe-pane`, optionally with timestamps at the start
+    /// of each line from `ts`
+    PipePane,
 }
 
-#[derive(Debug)]
+/// The format of the file to render to
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
 enum OutputFormat {
+    /// An animated gif
     Gif,
+    /// An animated svg like the ones made by svg-term-cli
+    Svg,
+    /// An mp4 video, encoded by ffmpeg
+    Mp4,
+    /// An animation in the kitty graphics protocol, which plays when it is written to a terminal
+    /// that supports it, such as kitty or WezTerm. Give `-` as the output file to play it right
+    /// away.
+    Kitty,
+    /// A directory of png images, one for each frame, named by --frame-name. The directory is
+    /// created if it doesn't exist.
+    PngSequence,
     // TODO: Other image formats
     // Png,
-    // Svg,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct CropSettings {
-    pub top: u16,
-    pub left: u16,
-    pub width: u16,
-    pub height: u16,
+/// Parses the `CAST2GIF_<FLAG>` environment variables of flags, which may be set to `1`, `true`,
+/// `yes`, or `on`
+type FlagParser = clap::builder::BoolishValueParser;
+
+/// Help text shown after the options
+const AFTER_HELP: &str = "Every option can also be set with a `CAST2GIF_<OPTION>` environment \
+                          variable, such as `CAST2GIF_CROP`. Flags are enabled by setting their \
+                          variable, such as `CAST2GIF_FORCE`, to `1` or `true`.\n\n\
+                          Exit codes:\n    \
+                          0    Success\n    \
+                          1    Internal error\n    \
+                          2    Invalid arguments, or the output file already exists\n    \
+                          3    The cast file could not be parsed\n    \
+                          4    A file could not be read or written\n    \
+                          5    The cast could not be rendered";
+
+/// Renders Asciinema .cast files as gif, svg, mp4, or animated png. Only asciicast v2 files are
+/// supported.
+#[derive(Debug, Parser)]
+#[command(
+    name = "cast2gif",
+    version,
+    author,
+    arg_required_else_help = true,
+    subcommand_negates_reqs = true,
+    after_help = AFTER_HELP
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The asciinema .cast file, or other --input-format file, to render
+    #[cfg_attr(feature = "fetch", arg(required_unless_present = "from_asciinema"))]
+    #[cfg_attr(not(feature = "fetch"), arg(required = true))]
+    cast_file: Option<PathBuf>,
+
+    /// The file to render to, or `-` to write kitty output to stdout. Required unless --dry-run
+    /// or --emit-cast is given.
+    out_file: Option<PathBuf>,
+
+    /// Download a recording from asciinema.org and render it instead of a cast file. Specify the
+    /// ID or the URL of the recording. The output file is then the only file argument.
+    #[cfg(feature = "fetch")]
+    #[arg(long, env = "CAST2GIF_FROM_ASCIINEMA", value_name = "ID")]
+    from_asciinema: Option<String>,
+
+    #[cfg(feature = "fetch")]
+    #[command(flatten)]
+    asciinema: fetch::AsciinemaArgs,
+
+    /// The format to render to. Defaults to the format of the output file's extension.
+    #[arg(short = 'F', long, env = "CAST2GIF_FORMAT", value_enum)]
+    format: Option<OutputFormat>,
+
+    /// Render FRAMES frames, evenly spaced from the start of the recording to the end, side by
+    /// side into a single png instead of an animation, for blog posts and to see what a cast
+    /// contains at a glance
+    #[arg(long, env = "CAST2GIF_FILMSTRIP", value_name = "FRAMES")]
+    filmstrip: Option<NonZeroUsize>,
+
+    /// Render another cast next to the cast file, to compare them. Both casts start playing at
+    /// the same time, which is useful for before and after demos. A --crop is in cells of both
+    /// terminals, with the second one starting three columns after the end of the first.
+    #[arg(long, env = "CAST2GIF_COMPARE", value_name = "CAST")]
+    compare: Option<PathBuf>,
+
+    /// The format of the file to render
+    #[arg(long, env = "CAST2GIF_INPUT_FORMAT", value_enum, default_value_t = InputFormat::Cast)]
+    input_format: InputFormat,
+
+    /// The size of the terminal, as `COLSxROWS`, for input formats that don't record it. Defaults
+    /// to 80x24.
+    #[arg(long, env = "CAST2GIF_TERMINAL_SIZE", value_name = "COLSxROWS")]
+    terminal_size: Option<TerminalSize>,
+
+    /// Parse the cast and report the expected frame count, duration, dimensions, and a rough file
+    /// size estimate without rendering anything
+    #[arg(long, env = "CAST2GIF_DRY_RUN", value_parser = FlagParser::new())]
+    dry_run: bool,
+
+    /// Write the cast to FILE as an asciinema v2 cast, timed like the render by --trim,
+    /// --speed-map, --initial-delay, --typing-speed, and --pause-on-markers, to share or edit the
+    /// part of it that is rendered. The output file may be left out to only write the cast.
+    #[arg(long, env = "CAST2GIF_EMIT_CAST", value_name = "FILE")]
+    emit_cast: Option<PathBuf>,
+
+    /// Render the cast even if it shows what look like secrets, such as AWS access keys, GitHub
+    /// tokens, and private keys. Without this, the render is refused if any are found. Hide them
+    /// with --redact instead where possible.
+    #[arg(long, env = "CAST2GIF_ALLOW_SECRETS", value_parser = FlagParser::new())]
+    allow_secrets: bool,
+
+    /// Read the cast file through a memory map. This can be faster for very large cast files.
+    #[arg(long, env = "CAST2GIF_MMAP", value_parser = FlagParser::new())]
+    mmap: bool,
+
+    /// How to report progress. `json` prints newline-delimited JSON progress events to stderr for
+    /// other programs to read.
+    #[arg(long, env = "CAST2GIF_PROGRESS", value_enum, default_value_t = ProgressOutput::Bars)]
+    progress: ProgressOutput,
+
+    /// Read quickly through the cast before rendering it to find how long it is. The progress then
+    /// has a bar for parsing the cast, and the totals of the other bars are estimated from how far
+    /// into the cast the parsing is, so that their ETAs mean something. Only applies to gif output.
+    #[arg(long, env = "CAST2GIF_PRESCAN", value_parser = FlagParser::new())]
+    prescan: bool,
+
+    /// Print how long each stage of the render took once it is done, along with the average time
+    /// each frame took in it. This is printed even with --quiet.
+    #[arg(long, env = "CAST2GIF_TIMINGS", value_parser = FlagParser::new())]
+    timings: bool,
+
+    /// Don't show progress or log anything other than errors
+    #[arg(short, long, global = true, env = "CAST2GIF_QUIET", value_parser = FlagParser::new())]
+    quiet: bool,
+
+    /// Overwrite existing output file
+    #[arg(short, long, env = "CAST2GIF_FORCE", value_parser = FlagParser::new())]
+    force: bool,
+
+    /// Keep a journal of the rendered frames next to the output file, so that the render can be
+    /// resumed if it is interrupted. Run the same command again to resume it. Frames that haven't
+    /// changed are loaded from the journal instead of being rendered again, and the journal is
+    /// removed once the render succeeds. Only applies to gif and mp4 output.
+    #[arg(long, env = "CAST2GIF_RESUME", value_parser = FlagParser::new())]
+    resume: bool,
+
+    /// Show the gif in the terminal once it is rendered. iTerm2 and WezTerm play the animation, and
+    /// kitty and Ghostty show the first frame. The image protocol is detected from the
+    /// environment unless one is given, such as `--show=kitty`.
+    #[arg(
+        long,
+        env = "CAST2GIF_SHOW",
+        value_enum,
+        value_name = "PROTOCOL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "auto"
+    )]
+    show: Option<show::ImageProtocol>,
+
+    /// Show a PNG image after the last frame has been held, such as a card that asks viewers to star
+    /// the project. The image is scaled to fit the frames, and the space around it is filled with
+    /// the terminal background. Doesn't apply to svg output.
+    #[arg(long, env = "CAST2GIF_END_CARD", value_name = "PNG")]
+    end_card: Option<PathBuf>,
+
+    /// The time, in seconds, to show the --end-card for. Defaults to 3.
+    #[arg(
+        long,
+        env = "CAST2GIF_END_CARD_HOLD",
+        value_name = "SECONDS",
+        value_parser = parse_end_card_hold,
+        requires = "end_card"
+    )]
+    end_card_hold: Option<f64>,
+
+    /// Pass every rendered frame through a WebAssembly plugin, in .wasm or .wat format, such as to
+    /// draw an overlay over it. Give --plugin more than once to run several plugins, in order. Only
+    /// applies to gif and mp4 output.
+    #[cfg(feature = "plugins")]
+    #[arg(long = "plugin", env = "CAST2GIF_PLUGIN", value_name = "WASM")]
+    plugins: Vec<PathBuf>,
+
+    /// Draw text and boxes over every rendered frame with a Rhai script that defines an
+    /// `overlay(frame)` function, such as to show which step of a demo is playing by the markers
+    /// before it. Give --overlay-script more than once to run several scripts, in order. Only
+    /// applies to gif and mp4 output.
+    #[cfg(feature = "scripting")]
+    #[arg(
+        long = "overlay-script",
+        env = "CAST2GIF_OVERLAY_SCRIPT",
+        value_name = "SCRIPT"
+    )]
+    overlay_scripts: Vec<PathBuf>,
+
+    // Flattened last, because the help heading applies to every argument after it
+    #[command(flatten)]
+    render: RenderArgs,
+
+    #[command(flatten)]
+    svg: SvgArgs,
+
+    #[command(flatten)]
+    video: VideoArgs,
+
+    #[command(flatten)]
+    png_sequence: PngSequenceArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Render a cast with each available renderer and encoder configuration and report how fast
+    /// it is and how much memory it uses.
+    Bench {
+        /// The asciinema .cast file to render
+        cast_file: PathBuf,
+    },
+    /// Play a cast in the terminal, showing the timestamp of each frame, to review it before
+    /// rendering.
+    Preview {
+        /// The asciinema .cast file to play
+        cast_file: PathBuf,
+    },
+    /// Serve a live preview of the rendered gif in the browser, re-rendering it whenever the cast
+    /// file or the render settings change.
+    Serve {
+        /// The asciinema .cast file to render
+        cast_file: PathBuf,
+
+        /// The port to serve the preview on
+        #[arg(short, long, env = "CAST2GIF_PORT", default_value_t = 7878)]
+        port: u16,
+
+        #[command(flatten)]
+        render: RenderArgs,
+    },
+    /// Run an HTTP rendering service that renders casts uploaded to `POST /render` and streams
+    /// back the gifs.
+    #[cfg(feature = "server")]
+    Server {
+        /// The address to listen on
+        #[arg(long, env = "CAST2GIF_ADDRESS", default_value = "127.0.0.1")]
+        address: String,
+
+        /// The port to listen on
+        #[arg(short, long, env = "CAST2GIF_PORT", default_value_t = 7879)]
+        port: u16,
+
+        /// The largest cast upload to accept, in megabytes
+        #[arg(long, env = "CAST2GIF_MAX_UPLOAD", value_name = "MB", default_value_t = 64)]
+        max_upload: usize,
+
+        /// The most requests to handle at once. Requests that come in while that many are being
+        /// handled are turned away with `503 Service Unavailable`.
+        #[arg(
+            long,
+            env = "CAST2GIF_MAX_REQUESTS",
+            value_name = "N",
+            default_value_t = NonZeroUsize::new(4).unwrap()
+        )]
+        max_requests: NonZeroUsize,
+
+        /// The longest time, in seconds, that a render may take. Renders that take longer are cut
+        /// off.
+        #[arg(
+            long,
+            env = "CAST2GIF_RENDER_TIMEOUT",
+            value_name = "SECONDS",
+            default_value_t = 120
+        )]
+        render_timeout: u64,
+
+        /// The most pixels that the terminal of an uploaded cast may render to, before it is
+        /// cropped. Casts with larger terminals are turned away.
+        #[arg(
+            long,
+            env = "CAST2GIF_MAX_FRAME_PIXELS",
+            value_name = "PIXELS",
+            default_value_t = 4_000_000
+        )]
+        max_frame_pixels: usize,
+    },
+    /// Download a recording from asciinema.org to a cast file
+    #[cfg(feature = "fetch")]
+    Fetch {
+        /// The ID or the URL of the recording
+        id: String,
+
+        /// The file to save the recording to. Defaults to `<ID>.cast`.
+        file: Option<PathBuf>,
+
+        /// Overwrite an existing file
+        #[arg(short, long, env = "CAST2GIF_FORCE", value_parser = FlagParser::new())]
+        force: bool,
+
+        #[command(flatten)]
+        asciinema: fetch::AsciinemaArgs,
+    },
+    /// Report which terminal colors a cast uses and how much, to help pick colors that keep the
+    /// gif small.
+    Palette {
+        /// The asciinema .cast file to analyze
+        cast_file: PathBuf,
+    },
+    /// Print the settings that a gif was rendered with, the hash of the cast it was rendered from,
+    /// and the delay of each of its frames, to find out why two renders differ.
+    Inspect {
+        /// The gif rendered by cast2gif to inspect
+        gif_file: PathBuf,
+
+        /// Print the details as JSON
+        #[arg(long, env = "CAST2GIF_JSON", value_parser = FlagParser::new())]
+        json: bool,
+    },
+    /// List the commands run at shell prompts in a cast, with the --trim that renders each of them
+    /// as a chapter.
+    Chapters {
+        /// The asciinema .cast file to list the commands of
+        cast_file: PathBuf,
+
+        /// Print the commands as JSON, with the start and end of the chapter of each one
+        #[arg(long, env = "CAST2GIF_JSON", value_parser = FlagParser::new())]
+        json: bool,
+    },
+    /// Scrub through a cast in the terminal to pick the part of it to render and the region to
+    /// crop it to, then render it or print the settings.
+    Edit {
+        /// The asciinema .cast file to edit
+        cast_file: PathBuf,
+
+        /// The file to render to from the editor
+        out_file: Option<PathBuf>,
+
+        /// Overwrite an existing output file
+        #[arg(short, long, env = "CAST2GIF_FORCE", value_parser = FlagParser::new())]
+        force: bool,
+
+        /// Render the cast even if it shows what look like secrets
+        #[arg(long, env = "CAST2GIF_ALLOW_SECRETS", value_parser = FlagParser::new())]
+        allow_secrets: bool,
+
+        #[command(flatten)]
+        render: RenderArgs,
+    },
+    /// Print shell completions for cast2gif to stdout
+    #[command(hide = true)]
+    Completions {
+        /// The shell to generate completions for
+        shell: clap_complete::Shell,
+    },
 }
 
 fn execute_cli() -> anyhow::Result<()> {
-    use clap::{crate_authors, crate_version, App, AppSettings, Arg};
-
-    #[rustfmt::skip]
-    let args = App::new("cast2gif")
-        .version(crate_version!())
-        .author(crate_authors!())
-        .about("Renders Asciinema .cast files as gif, svg, or animated png.")
-        .setting(AppSettings::ColoredHelp)
-        .setting(AppSettings::ArgRequiredElseHelp)
-        .arg(Arg::with_name("cast_file")
-            .help("The asciinema .cast file to render")
-            .required(true))
-        .arg(Arg::with_name("out_file")
-            .help("The file to render to")
-            .required(true))
-        .arg(Arg::with_name("crop")
-            .long("crop")
-            .short("c")
-            .help("crop the recording while rendering. \
-                   Specify crop in terminal cells as \
-                   `top=[int],left=[int],width=[int],height=[int]`.")
-            .takes_value(true))
-        // TODO: Implement other file formats
-        // .arg(Arg::with_name("format")
-        //     .long("format")
-        //     .short("F")
-        //     .help("The file format to render to. This will be automatically determined from the \
-        //            file extension if not specified.")
-        //     .takes_value(true)
-        //     .possible_values(&["gif", "svg", "png"]))
-        .arg(Arg::with_name("force")
-            .long("force")
-            .short("f")
-            .help("Overwrite existing output file"))
-        .get_matches();
+    let args = match Cli::try_parse() {
+        Ok(args) => args,
+        // Help and version "errors" exit successfully
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            e.print().ok();
+            std::process::exit(exit_code::INVALID_ARGUMENTS);
+        }
+    };
+
+    // Only log errors in quiet mode
+    let quiet = args.quiet;
+    if quiet {
+        log::set_max_level(log::LevelFilter::Error);
+    }
+
+    // Run subcommands
+    match &args.command {
+        Some(Command::Bench { cast_file }) => return bench::run(cast_file),
+        Some(Command::Preview { cast_file }) => return preview::run(cast_file),
+        Some(Command::Palette { cast_file }) => return palette::run(cast_file),
+        Some(Command::Chapters { cast_file, json }) => return chapters::run(cast_file, *json),
+        Some(Command::Inspect { gif_file, json }) => return inspect::run(gif_file, *json),
+        Some(Command::Serve {
+            cast_file,
+            port,
+            render,
+        }) => return serve::run(cast_file, *port, render),
+        #[cfg(feature = "server")]
+        Some(Command::Server {
+            address,
+            port,
+            max_upload,
+            max_requests,
+            render_timeout,
+            max_frame_pixels,
+        }) => {
+            let limits = server::Limits {
+                max_upload: max_upload * 1024 * 1024,
+                max_requests: max_requests.get(),
+                render_timeout: std::time::Duration::from_secs(*render_timeout),
+                max_frame_pixels: *max_frame_pixels,
+            };
+            return server::run(address, *port, limits);
+        }
+        #[cfg(feature = "fetch")]
+        Some(Command::Fetch {
+            id,
+            file,
+            force,
+            asciinema,
+        }) => return fetch::run(id, file.as_deref(), *force, asciinema),
+        Some(Command::Edit {
+            cast_file,
+            out_file,
+            force,
+            allow_secrets,
+            render,
+        }) => {
+            return edit::run(
+                cast_file,
+                out_file.as_deref(),
+                *force,
+                *allow_secrets,
+                render,
+            )
+        }
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(
+                *shell,
+                &mut Cli::command(),
+                "cast2gif",
+                &mut std::io::stdout(),
+            );
+            return Ok(());
+        }
+        None => (),
+    }
+
+    // Download the recording. The file argument is then the output file.
+    #[cfg(feature = "fetch")]
+    let (downloaded_cast, out_file_path) = match &args.from_asciinema {
+        Some(_) if args.out_file.is_some() => {
+            return Err(ArgumentError(format_err!(
+                "A cast file can't be given along with --from-asciinema"
+            ))
+            .into())
+        }
+        Some(id) => (
+            Some(fetch::download_temp(id, &args.asciinema)?),
+            args.cast_file.as_deref(),
+        ),
+        None => (None, args.out_file.as_deref()),
+    };
+    #[cfg(not(feature = "fetch"))]
+    let out_file_path = args.out_file.as_deref();
 
     // Load cast file
+    #[cfg(feature = "fetch")]
+    let cast_file_path = match &downloaded_cast {
+        Some(cast) => cast.path(),
+        None => args
+            .cast_file
+            .as_deref()
+            .expect("Missing required argument: cast_file"),
+    };
+    #[cfg(not(feature = "fetch"))]
     let cast_file_path = args
-        .value_of("cast_file")
+        .cast_file
+        .as_deref()
         .expect("Missing required argument: cast_file");
-    let cast_file = std::fs::OpenOptions::new()
-        .read(true)
-        .open(cast_file_path)
-        .context(format!("Could not open cast file: {}", cast_file_path))?;
+
+    // The name of the cast in frame names, which is the ID of downloaded recordings
+    let file_stem = cast_file_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned());
+    #[cfg(feature = "fetch")]
+    let cast_name = match &args.from_asciinema {
+        Some(id) => Some(fetch::recording_id(id, &args.asciinema)?),
+        None => file_stem,
+    };
+    #[cfg(not(feature = "fetch"))]
+    let cast_name = file_stem;
+
+    // Convert other input formats to a cast. The gif records the hash of the file that was given.
+    let input_path = cast_file_path;
+    if args.input_format == InputFormat::Cast && args.terminal_size.is_some() {
+        return Err(ArgumentError(format_err!(
+            "--terminal-size can only be given for input formats that don't record it"
+        ))
+        .into());
+    }
+    let converted_cast = convert_input(cast_file_path, &args)?;
+    let cast_file_path = converted_cast
+        .as_ref()
+        .map_or(cast_file_path, TempFile::path);
+    let converted_compare = args
+        .compare
+        .as_deref()
+        .map(|path| convert_input(path, &args))
+        .transpose()?
+        .flatten();
+    let compare_path = converted_compare
+        .as_ref()
+        .map(TempFile::path)
+        .or(args.compare.as_deref());
+
+    let open_cast = |path: &Path| {
+        std::fs::OpenOptions::new()
+            .read(true)
+            .open(path)
+            .context(format!(
+                "Could not open cast file: {}",
+                path.to_string_lossy()
+            ))
+    };
+    let cast_file = open_cast(cast_file_path)?;
+    let (input, size) = match compare_path {
+        Some(_) if args.mmap => {
+            return Err(ArgumentError(format_err!("--mmap can't be used with --compare")).into())
+        }
+        Some(compare_path) => {
+            let size = crate::side_by_side::size(
+                terminal_size(cast_file_path)?,
+                terminal_size(compare_path)?,
+            )
+            .ok_or_else(|| format_err!("The casts are too wide to render side by side"))?;
+            (
+                CastInput::SideBySide(cast_file, open_cast(compare_path)?),
+                size,
+            )
+        }
+        None => (
+            CastInput::Single {
+                file: cast_file,
+                mmap: args.mmap,
+            },
+            terminal_size(cast_file_path)?,
+        ),
+    };
+
+    let mut settings = args.render.settings(size).map_err(ArgumentError)?;
+
+    // Write the cast as it is timed for the render, which may be all there is to do
+    if let Some(emit_cast_path) = &args.emit_cast {
+        if args.compare.is_some() {
+            return Err(
+                ArgumentError(format_err!("--emit-cast can't be given with --compare")).into(),
+            );
+        }
+        emit_cast(cast_file_path, emit_cast_path, &settings, args.force)?;
+        if out_file_path.is_none() && !args.dry_run {
+            return Ok(());
+        }
+    }
+
+    // Report what would be rendered without rendering it
+    if args.dry_run {
+        return dry_run::run(input, &settings);
+    }
 
     // Get output path
-    let out_file_path = Path::new(
-        args.value_of("out_file")
-            .expect("Missing required argument: out_file"),
-    );
+    let out_file_path = out_file_path.ok_or_else(|| {
+        ArgumentError(format_err!(
+            "Missing required argument: <out_file>. It may only be left out with --dry-run or \
+             --emit-cast."
+        ))
+    })?;
 
     // Make sure out path doesn't exist
-    if out_file_path.exists() && !args.is_present("force") {
-        return Err(format_err!(
+    let to_stdout = out_file_path == Path::new("-");
+    if out_file_path.exists() && !to_stdout && !args.force {
+        return Err(ArgumentError(format_err!(
             "Output file already exists: {}",
             out_file_path.to_string_lossy()
-        ));
+        ))
+        .into());
     }
 
-    // Open out file
-    let out_file = std::fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(out_file_path)
-        .context(format!(
+    // Filmstrips are a single png, so the options of animations don't apply to them
+    if args.filmstrip.is_some() {
+        let animation_options = [
+            ("--format", args.format.is_some()),
+            ("--compare", args.compare.is_some()),
+            ("--prescan", args.prescan),
+            ("--resume", args.resume),
+            ("--end-card", args.end_card.is_some()),
+            ("--quantizer", args.render.quantizer.is_some()),
+            ("--loop-crossfade", args.render.loop_crossfade.is_some()),
+            ("--fade-in", args.render.fade_in.is_some()),
+            ("--fade-out", args.render.fade_out.is_some()),
+        ];
+        if let Some((option, _)) = animation_options.iter().find(|(_, given)| *given) {
+            return Err(
+                ArgumentError(format_err!("{} can't be given with --filmstrip", option)).into(),
+            );
+        }
+        if to_stdout {
+            return Err(ArgumentError(format_err!("Filmstrips can't be written to stdout")).into());
+        }
+    }
+
+    // Get the output format
+    let format = match args.format {
+        Some(format) => format,
+        // Filmstrips are rendered from the same frames as gifs
+        None if args.filmstrip.is_some() => OutputFormat::Gif,
+        // Guess format from file extension
+        None => match out_file_path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .as_deref()
+        {
+            Some("gif") => OutputFormat::Gif,
+            Some("svg") => OutputFormat::Svg,
+            Some("mp4") => OutputFormat::Mp4,
+            Some("kitty") => OutputFormat::Kitty,
+            _ if to_stdout => OutputFormat::Kitty,
+            _ => {
+                log::warn!(
+                    "Could not detect output format from file extension, assuming gif format. Use \
+                     --format to specify otherwise."
+                );
+                OutputFormat::Gif
+            }
+        },
+    };
+    if to_stdout && format != OutputFormat::Kitty {
+        return Err(
+            ArgumentError(format_err!("Only kitty output can be written to stdout")).into(),
+        );
+    }
+    let svg_settings = match format {
+        OutputFormat::Svg => Some(args.svg.settings().map_err(ArgumentError)?),
+        _ if args.svg.is_set() => {
+            return Err(ArgumentError(format_err!(
+                "--window, --padding, --no-cursor, and --profile can only be given for svg output"
+            ))
+            .into())
+        }
+        _ => None,
+    };
+    let video_settings = match format {
+        OutputFormat::Mp4 => Some(args.video.settings()),
+        _ if args.video.is_set() => {
+            return Err(
+                ArgumentError(format_err!("--audio can only be given for mp4 output")).into(),
+            )
+        }
+        _ => None,
+    };
+    if args.prescan && format != OutputFormat::Gif {
+        return Err(
+            ArgumentError(format_err!("--prescan can only be given for gif output")).into(),
+        );
+    }
+    if args.render.quantizer.is_some() && format != OutputFormat::Gif {
+        return Err(
+            ArgumentError(format_err!("--quantizer can only be given for gif output")).into(),
+        );
+    }
+    if args.render.loop_crossfade.is_some() && format != OutputFormat::Gif {
+        return Err(ArgumentError(format_err!(
+            "--loop-crossfade can only be given for gif output"
+        ))
+        .into());
+    }
+    if args.render.loop_crossfade.is_some() && args.end_card.is_some() {
+        return Err(ArgumentError(format_err!(
+            "--loop-crossfade can't be given with --end-card, which the gif loops from instead"
+        ))
+        .into());
+    }
+    let png_sequence_settings = match format {
+        OutputFormat::PngSequence => Some(
+            args.png_sequence
+                .settings(cast_name)
+                .map_err(ArgumentError)?,
+        ),
+        _ if args.png_sequence.is_set() => {
+            return Err(ArgumentError(format_err!(
+                "--png-compression, --frame-name, and --manifest can only be given for png \
+                 sequence output"
+            ))
+            .into())
+        }
+        _ => None,
+    };
+    if args.resume && format == OutputFormat::Svg {
+        return Err(ArgumentError(format_err!(
+            "--resume can only be given for gif, mp4, kitty, and png sequence output"
+        ))
+        .into());
+    }
+    if args.render.aspect.is_some() && format == OutputFormat::Svg {
+        return Err(ArgumentError(format_err!(
+            "--aspect can only be given for gif, mp4, kitty, png sequence, and filmstrip output"
+        ))
+        .into());
+    }
+    if args.render.monochrome.is_some() && format == OutputFormat::Svg {
+        return Err(ArgumentError(format_err!(
+            "--monochrome can only be given for gif, mp4, kitty, png sequence, and filmstrip output"
+        ))
+        .into());
+    }
+    if args.render.smooth_scroll.is_some() && format == OutputFormat::Svg {
+        return Err(ArgumentError(format_err!(
+            "--smooth-scroll can only be given for gif, mp4, kitty, png sequence, and filmstrip \
+             output"
+        ))
+        .into());
+    }
+    if (args.render.fade_in.is_some() || args.render.fade_out.is_some())
+        && format == OutputFormat::Svg
+    {
+        return Err(ArgumentError(format_err!(
+            "--fade-in and --fade-out can only be given for gif, mp4, kitty, and png sequence \
+             output"
+        ))
+        .into());
+    }
+    // Leave out the options of the preset that don't apply to the output format
+    if format == OutputFormat::Svg {
+        settings.aspect = None;
+    }
+    if format != OutputFormat::Gif || args.filmstrip.is_some() {
+        settings.target_size = args.render.target_size;
+    }
+    let opaque_format = match format {
+        OutputFormat::Gif if args.filmstrip.is_none() => true,
+        OutputFormat::Mp4 => true,
+        _ => false,
+    };
+    if args.render.background_opacity.is_some() && opaque_format {
+        return Err(ArgumentError(format_err!(
+            "--background-opacity can only be given for svg, kitty, png sequence, and filmstrip \
+             output"
+        ))
+        .into());
+    }
+    if args.end_card.is_some() && format == OutputFormat::Svg {
+        return Err(ArgumentError(format_err!(
+            "--end-card can only be given for gif, mp4, kitty, and png sequence output"
+        ))
+        .into());
+    }
+    settings.end_card = args
+        .end_card
+        .as_ref()
+        .map(|path| {
+            let hold = args
+                .end_card_hold
+                .unwrap_or(crate::end_card::DEFAULT_END_CARD_HOLD);
+            crate::EndCard::from_file(path, hold)
+        })
+        .transpose()?;
+    #[cfg(feature = "plugins")]
+    {
+        if !args.plugins.is_empty() && format == OutputFormat::Svg {
+            return Err(ArgumentError(format_err!(
+                "--plugin can only be given for gif, mp4, kitty, and png sequence output"
+            ))
+            .into());
+        }
+        settings.plugins = args
+            .plugins
+            .iter()
+            .map(crate::Plugin::from_file)
+            .collect::<Result<_, _>>()?;
+    }
+    #[cfg(feature = "scripting")]
+    {
+        if !args.overlay_scripts.is_empty() && format == OutputFormat::Svg {
+            return Err(ArgumentError(format_err!(
+                "--overlay-script can only be given for gif, mp4, kitty, and png sequence output"
+            ))
+            .into());
+        }
+        settings.scripts = args
+            .overlay_scripts
+            .iter()
+            .map(crate::OverlayScript::from_file)
+            .collect::<Result<_, _>>()?;
+    }
+
+    // Check that the render doesn't give away any secrets
+    for path in std::iter::once(cast_file_path).chain(compare_path) {
+        check_secrets(open_cast(path)?, &settings, args.allow_secrets)?;
+    }
+
+    if let Some(frames) = args.filmstrip {
+        let out_file = AtomicFile::create(out_file_path).context(format!(
             "Could not open output file: {}",
             out_file_path.to_string_lossy()
         ))?;
+        return convert_filmstrip(input, out_file, settings, frames.get());
+    }
 
-    // TODO: Other image formats
-    let format = OutputFormat::Gif;
-    // let format = match args.value_of("format") {
-    //     // Guess format from file extension
-    //     None => {
-    //         let warn_message = "Could not detect output format from file extension, assuming gif \
-    //                             format. Use --format to specify otherwise.";
-    //         if let Some(ext) = out_file_path.extension() {
-    //             let ext = ext.to_string_lossy().to_lowercase();
-    //             match ext.as_str() {
-    //                 "gif" => OutputFormat::Gif,
-    //                 "svg" => OutputFormat::Svg,
-    //                 "png" => OutputFormat::Png,
-    //                 _ => {
-    //                     log::warn!("{}", warn_message);
-    //                     OutputFormat::Gif
-    //                 }
-    //             }
-    //         } else {
-    //             log::warn!("{}", warn_message);
-    //             OutputFormat::Gif
-    //         }
-    //     }
-    //     // Use seleted output format
-    //     Some("gif") => OutputFormat::Gif,
-    //     Some("svg") => OutputFormat::Svg,
-    //     Some("png") => OutputFormat::Png,
-    //     Some(other) => panic!("Invalid option to --format: {}", other),
-    // };
-    let crop = {
-        let mut top = None;
-        let mut left = None;
-        let mut width = None;
-        let mut height = None;
-
-        if let Some(crop_str) = args.value_of("crop") {
-            for pair in crop_str.split(",") {
-                let split: Vec<_> = pair.split("=").collect();
-                let key = split.get(0);
-                let value = split.get(1);
-
-                if let Some(value) = value {
-                    let value: u16 = value.parse().context("Could not parse crop value as int")?;
-
-                    if let Some(&key) = key {
-                        match key {
-                            "top" => top = Some(value),
-                            "left" => left = Some(value),
-                            "width" => width = Some(value),
-                            "height" => height = Some(value),
-                            _ => continue,
-                        }
+    // Record what the gif is rendered from, for the `inspect` subcommand
+    if format == OutputFormat::Gif {
+        settings.comment = Some(inspect::render_record(input_path, &args.render)?);
+    }
+
+    // Open out file. It is written to a temporary file that replaces the output path only once the
+    // render succeeds. Png sequences are written straight to their directory.
+    let out_file = if to_stdout || format == OutputFormat::PngSequence {
+        None
+    } else {
+        Some(AtomicFile::create(out_file_path).context(format!(
+            "Could not open output file: {}",
+            out_file_path.to_string_lossy()
+        ))?)
+    };
+    if args.resume {
+        settings.journal_dir = Some(journal_dir(out_file_path));
+    }
+
+    let progress_output = if quiet {
+        ProgressOutput::Hidden
+    } else {
+        args.progress
+    };
+    // The render is measured to sum it up once it is done
+    let started = Instant::now();
+    let stats = Arc::new(crate::RenderStats::new());
+    settings.stats = Some(stats.clone());
+
+    // There is no progress to measure against the totals when it is hidden
+    let summary = if args.prescan && progress_output != ProgressOutput::Hidden {
+        Some(prescan(&input, &settings)?)
+    } else {
+        None
+    };
+
+    match format {
+        OutputFormat::Gif => {
+            let out_file = out_file.expect(ONLY_KITTY_TO_STDOUT);
+            match progress_output {
+                ProgressOutput::Bars => {
+                    convert_gif_with_progress_bars(input, out_file, settings, summary)?
+                }
+                ProgressOutput::Json => {
+                    if let Some(summary) = summary {
+                        JsonProgressHandler::emit(serde_json::json!({
+                            "event": "scan",
+                            "events": summary.events,
+                            "duration": summary.duration / 100.,
+                        }));
                     }
+                    let result =
+                        convert_gif(input, out_file, JsonProgressHandler::default(), settings);
+                    JsonProgressHandler::emit(match &result {
+                        Ok(()) => serde_json::json!({ "event": "done" }),
+                        Err(e) => {
+                            serde_json::json!({ "event": "error", "message": e.to_string() })
+                        }
+                    });
+                    result?;
+                }
+                ProgressOutput::Hidden => {
+                    convert_gif(input, out_file, crate::types::NullProgressHandler, settings)?;
                 }
             }
-        };
+        }
+        // Svgs render too quickly to need progress bars, and ffmpeg doesn't report the progress of
+        // videos. Kitty animations and png sequences are rendered like videos.
+        OutputFormat::Svg | OutputFormat::Mp4 | OutputFormat::Kitty | OutputFormat::PngSequence => {
+            let result = match format {
+                OutputFormat::Svg => {
+                    let svg_settings = svg_settings.expect("Svg settings are read for svg output");
+                    convert_svg(
+                        input,
+                        out_file.expect(ONLY_KITTY_TO_STDOUT),
+                        settings,
+                        svg_settings,
+                    )
+                }
+                OutputFormat::Mp4 => {
+                    let video_settings =
+                        video_settings.expect("Video settings are read for mp4 output");
+                    convert_mp4(
+                        input,
+                        out_file.expect(ONLY_KITTY_TO_STDOUT),
+                        settings,
+                        video_settings,
+                    )
+                }
+                OutputFormat::PngSequence => {
+                    let png_sequence_settings = png_sequence_settings
+                        .expect("Png sequence settings are read for png sequence output");
+                    convert_png_sequence(input, out_file_path, settings, png_sequence_settings)
+                }
+                _ => convert_kitty(input, out_file, settings),
+            };
+            if progress_output == ProgressOutput::Json {
+                JsonProgressHandler::emit(match &result {
+                    Ok(()) => serde_json::json!({ "event": "done" }),
+                    Err(e) => serde_json::json!({ "event": "error", "message": e.to_string() }),
+                });
+            }
+            result?;
+        }
+    }
 
-        if top.is_none() || left.is_none() || width.is_none() || height.is_none() {
-            None
-        } else {
-            Some(CropSettings {
-                top: top.unwrap(),
-                left: left.unwrap(),
-                width: width.unwrap(),
-                height: height.unwrap(),
-            })
+    // Sum up the render, unless the output went to stdout
+    let output_size = if to_stdout {
+        None
+    } else {
+        summary::output_size(out_file_path)
+    };
+    match progress_output {
+        ProgressOutput::Bars => summary::print(&stats.summary(), output_size, started.elapsed()),
+        ProgressOutput::Json => JsonProgressHandler::emit(summary::json(
+            &stats.summary(),
+            output_size,
+            started.elapsed(),
+        )),
+        ProgressOutput::Hidden => (),
+    }
+    if args.timings {
+        match progress_output {
+            ProgressOutput::Json => JsonProgressHandler::emit(summary::timings_json(
+                &stats.summary(),
+                started.elapsed(),
+            )),
+            _ => summary::print_timings(&stats.summary(), started.elapsed()),
         }
+    }
+
+    // The gif has been rendered by now, so failing to show it doesn't fail the command
+    if let Some(protocol) = args.show {
+        if format != OutputFormat::Gif {
+            log::warn!("Only gifs can be shown in the terminal");
+        } else if let Err(e) = show::run(out_file_path, protocol) {
+            log::warn!("Could not show the gif: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// The message of the panic if a format other than kitty is written to stdout, which is checked
+/// when the format is picked
+const ONLY_KITTY_TO_STDOUT: &str = "Only kitty output is written to stdout";
+
+/// Get the directory to keep the frame journal of a resumable render to the given output file in
+fn journal_dir(out_file_path: &Path) -> PathBuf {
+    let file_name = out_file_path.file_name().unwrap_or_default();
+    out_file_path.with_file_name(format!(".{}.cast2gif-journal", file_name.to_string_lossy()))
+}
+
+/// Convert an input file in a format other than a cast to a temporary cast file
+///
+/// Returns `None` if the file is already a cast.
+fn convert_input(path: &Path, args: &Cli) -> anyhow::Result<Option<TempFile>> {
+    Ok(match args.input_format {
+        InputFormat::Cast => None,
+        InputFormat::PipePane => Some(pipe_pane::convert_file(
+            path,
+            args.terminal_size.unwrap_or_default(),
+        )?),
+    })
+}
+
+/// The cast files to render
+enum CastInput {
+    /// A cast file, which is read through a memory map if `mmap` is set
+    Single { file: std::fs::File, mmap: bool },
+    /// Two cast files to render side by side
+    SideBySide(std::fs::File, std::fs::File),
+}
+
+/// Warn about the secrets that a render of a cast would show, refusing to render it unless
+/// `allow_secrets` is set
+fn check_secrets(
+    cast: std::fs::File,
+    settings: &ConvertSettings,
+    allow_secrets: bool,
+) -> anyhow::Result<()> {
+    let secrets = crate::find_secrets(cast, settings)?;
+    for secret in &secrets {
+        log::warn!(
+            "Possible {} shown at {:.2}s: {}",
+            secret.kind,
+            secret.time,
+            secret.preview
+        );
+    }
+    if !secrets.is_empty() && !allow_secrets {
+        return Err(crate::Error::Generic(
+            "Refusing to render a cast that shows secrets. Hide them with --redact, or give \
+             --allow-secrets to render them anyway."
+                .into(),
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Read quickly through the cast files to find how much of them will be rendered
+///
+/// The files are read through memory maps, which leaves them to be read from the start again by
+/// the render.
+fn prescan(input: &CastInput, settings: &ConvertSettings) -> anyhow::Result<crate::CastSummary> {
+    Ok(match input {
+        CastInput::Single { file, .. } => crate::scan_cast_file(file, settings)?,
+        // Side by side casts are played at the same time
+        CastInput::SideBySide(left, right) => {
+            let left = crate::scan_cast_file(left, settings)?;
+            let right = crate::scan_cast_file(right, settings)?;
+            crate::CastSummary {
+                events: left.events + right.events,
+                duration: left.duration.max(right.duration),
+            }
+        }
+    })
+}
+
+/// Convert the cast file to a gif, reporting progress to the given handler, and move the gif to
+/// its destination once it is done
+fn convert_gif<C: crate::types::CastProgressHandler + 'static>(
+    input: CastInput,
+    out_file: AtomicFile,
+    progress_handler: C,
+    settings: ConvertSettings,
+) -> anyhow::Result<()> {
+    match input {
+        CastInput::Single { file, mmap: true } => crate::convert_file_to_gif_with_progress(
+            &file,
+            out_file.file(),
+            progress_handler,
+            settings,
+        )?,
+        CastInput::Single { file, mmap: false } => {
+            crate::convert_to_gif_with_progress(file, out_file.file(), progress_handler, settings)?
+        }
+        CastInput::SideBySide(left, right) => crate::convert_side_by_side_to_gif_with_progress(
+            left,
+            right,
+            out_file.file(),
+            progress_handler,
+            settings,
+        )?,
+    }
+    out_file.commit()?;
+
+    Ok(())
+}
+
+/// Convert the cast file to an svg and move it to its destination once it is done
+fn convert_svg(
+    input: CastInput,
+    out_file: AtomicFile,
+    settings: ConvertSettings,
+    svg_settings: SvgSettings,
+) -> anyhow::Result<()> {
+    let writer = std::io::BufWriter::new(out_file.file());
+    match input {
+        CastInput::Single { file, mmap: true } => {
+            crate::convert_file_to_svg(&file, writer, settings, svg_settings)?
+        }
+        CastInput::Single { file, mmap: false } => {
+            crate::convert_to_svg(file, writer, settings, svg_settings)?
+        }
+        CastInput::SideBySide(left, right) => {
+            crate::convert_side_by_side_to_svg(left, right, writer, settings, svg_settings)?
+        }
+    }
+    out_file.commit()?;
+
+    Ok(())
+}
+
+fn convert_mp4(
+    input: CastInput,
+    out_file: AtomicFile,
+    settings: ConvertSettings,
+    video_settings: VideoSettings,
+) -> anyhow::Result<()> {
+    let writer = std::io::BufWriter::new(out_file.file());
+    match input {
+        CastInput::Single { file, mmap: true } => {
+            crate::convert_file_to_mp4(&file, writer, settings, video_settings)?
+        }
+        CastInput::Single { file, mmap: false } => {
+            crate::convert_to_mp4(file, writer, settings, video_settings)?
+        }
+        CastInput::SideBySide(left, right) => {
+            crate::convert_side_by_side_to_mp4(left, right, writer, settings, video_settings)?
+        }
+    }
+    out_file.commit()?;
+
+    Ok(())
+}
+
+/// Render a filmstrip of the cast file to a png and move it to its destination once it is done
+fn convert_filmstrip(
+    input: CastInput,
+    out_file: AtomicFile,
+    settings: ConvertSettings,
+    frames: usize,
+) -> anyhow::Result<()> {
+    let writer = std::io::BufWriter::new(out_file.file());
+    match input {
+        CastInput::Single { file, .. } => {
+            crate::convert_file_to_filmstrip(&file, writer, settings, frames)?
+        }
+        CastInput::SideBySide(..) => unreachable!("Filmstrips can't be rendered with --compare"),
+    }
+    out_file.commit()?;
+
+    Ok(())
+}
+
+/// Write a cast file, timed like it is for the render, to a new cast file
+fn emit_cast(
+    cast_file_path: &Path,
+    emit_cast_path: &Path,
+    settings: &ConvertSettings,
+    force: bool,
+) -> anyhow::Result<()> {
+    if emit_cast_path.exists() && !force {
+        return Err(ArgumentError(format_err!(
+            "Output file already exists: {}",
+            emit_cast_path.to_string_lossy()
+        ))
+        .into());
+    }
+
+    let cast_file = std::fs::File::open(cast_file_path).context(format!(
+        "Could not open cast file: {}",
+        cast_file_path.to_string_lossy()
+    ))?;
+    let cast = crate::retime_cast(cast_file, settings)?;
+    let out_file = AtomicFile::create(emit_cast_path).context(format!(
+        "Could not open output file: {}",
+        emit_cast_path.to_string_lossy()
+    ))?;
+    cast.write(std::io::BufWriter::new(out_file.file()))
+        .context(format!(
+            "Could not write cast file: {}",
+            emit_cast_path.to_string_lossy()
+        ))?;
+    out_file.commit()?;
+
+    Ok(())
+}
+
+/// Convert the cast file to a kitty graphics protocol animation and move it to its destination
+/// once it is done, or write it to stdout if there is no output file
+fn convert_kitty(
+    input: CastInput,
+    out_file: Option<AtomicFile>,
+    settings: ConvertSettings,
+) -> anyhow::Result<()> {
+    let stdout = std::io::stdout();
+    let writer: Box<dyn std::io::Write + '_> = match &out_file {
+        Some(out_file) => Box::new(out_file.file()),
+        None => Box::new(stdout.lock()),
     };
+    match input {
+        CastInput::Single { file, mmap: true } => {
+            crate::convert_file_to_kitty(&file, writer, settings)?
+        }
+        CastInput::Single { file, mmap: false } => crate::convert_to_kitty(file, writer, settings)?,
+        CastInput::SideBySide(left, right) => {
+            crate::convert_side_by_side_to_kitty(left, right, writer, settings)?
+        }
+    }
+    if let Some(out_file) = out_file {
+        out_file.commit()?;
+    }
+
+    Ok(())
+}
+
+/// Convert the cast file to a sequence of png images in the output directory
+fn convert_png_sequence(
+    input: CastInput,
+    out_dir: &Path,
+    settings: ConvertSettings,
+    png_sequence_settings: PngSequenceSettings,
+) -> anyhow::Result<()> {
+    match input {
+        CastInput::Single { file, mmap: true } => {
+            crate::convert_file_to_png_sequence(&file, out_dir, settings, png_sequence_settings)?
+        }
+        CastInput::Single { file, mmap: false } => {
+            crate::convert_to_png_sequence(file, out_dir, settings, png_sequence_settings)?
+        }
+        CastInput::SideBySide(left, right) => crate::convert_side_by_side_to_png_sequence(
+            left,
+            right,
+            out_dir,
+            settings,
+            png_sequence_settings,
+        )?,
+    }
+
+    Ok(())
+}
+
+/// Get the (rows, cols) size of the terminal recorded in a cast file
+fn terminal_size(cast_file_path: &Path) -> anyhow::Result<(u16, u16)> {
+    let cast_file = std::fs::File::open(cast_file_path).context(format!(
+        "Could not open cast file: {}",
+        cast_file_path.to_string_lossy()
+    ))?;
+
+    Ok(crate::cast_parser::TerminalFrameIter::new(cast_file)?.size())
+}
 
+/// Convert the cast file to a gif like [`convert_gif`], showing progress bars while it renders
+///
+/// With a summary of the cast from [`prescan`], there is also a bar for parsing the cast.
+fn convert_gif_with_progress_bars(
+    input: CastInput,
+    out_file: AtomicFile,
+    settings: ConvertSettings,
+    summary: Option<crate::CastSummary>,
+) -> anyhow::Result<()> {
     // Create the progress bars
     let multi = MultiProgress::new();
+    let parse_progress = summary.map(|summary| {
+        let template =
+            "{prefix:12} [{elapsed_precise:.dim}]: {wide_bar:.green/white} {msg:>15} ( {eta_precise:.dim} )";
+        let bar = multi.add(
+            ProgressBar::new(summary.duration.ceil() as u64)
+                .with_style(ProgressStyle::default_bar().template(template)),
+        );
+        bar.enable_steady_tick(100);
+        bar
+    });
     let template =
         "{prefix:12} [{elapsed_precise:.dim}]: {wide_bar:.green/white} {pos:>7}/{len:7} ( {eta_precise:.dim} )";
     let raster_progress =
@@ -204,50 +1340,1164 @@ fn execute_cli() -> anyhow::Result<()> {
         multi.add(ProgressBar::new(0).with_style(ProgressStyle::default_bar().template(template)));
     sequence_progress.enable_steady_tick(100);
 
-    let progress_handler = ProgressHandler::new(raster_progress, sequence_progress);
+    let progress_handler = ProgressHandler::new(
+        raster_progress,
+        sequence_progress,
+        parse_progress.zip(summary),
+    );
 
-    match format {
-        OutputFormat::Gif => {
-            std::thread::spawn(move || {
-                crate::convert_to_gif_with_progress(
-                    cast_file,
-                    &out_file,
-                    progress_handler,
-                    crop
-                )
-                .expect("TODO");
-            });
-            multi.join_and_clear().expect("TODO");
-        }
-        // TODO: Other image formats
-        // _ => log::error!(
-        //     "File format not implemented yet. Open an issue to tell me you want this \
-        //                  feature sooner. :)"
-        // ),
+    let conversion =
+        std::thread::spawn(move || convert_gif(input, out_file, progress_handler, settings));
+    multi.join_and_clear().expect("TODO");
+    conversion.join().expect("Conversion thread panicked")
+}
+
+/// How to time the frames of the gif
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Timing {
+    /// Render a frame for every change to the screen
+    Events,
+    /// Sample the screen at the rate set by --fps
+    Fixed,
+}
+
+/// How to pick the colors of each frame of the gif
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Quantizer {
+    /// imagequant, which dithers and suits gradients and images
+    Imagequant,
+    /// NeuQuant, which is fast
+    Neuquant,
+    /// Median cut, which keeps the flat colors of text and TUIs
+    MedianCut,
+}
+
+/// How to collapse the colors of a monochrome render
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum MonochromeStyle {
+    /// Every shade between the paper and the ink
+    Grayscale,
+    /// Only the paper and the ink
+    TwoTone,
+}
+
+/// A bundle of render options that suits where the output will be posted
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Preset {
+    /// GitHub READMEs, issues, and pull requests, which don't take images over 10 MB
+    Github,
+    /// Twitter posts, which show 16:9 media best and don't take gifs over 15 MB
+    Twitter,
+    /// Presentation slides, which are 16:9
+    Slide,
+}
+
+impl Preset {
+    /// The aspect ratio that the preset pads the frames to, if any
+    fn aspect(self) -> Option<AspectRatio> {
+        match self {
+            Preset::Github => None,
+            Preset::Twitter | Preset::Slide => Some(AspectRatio {
+                width: 16,
+                height: 9,
+            }),
+        }
     }
 
-    Ok(())
+    /// The size in bytes that the preset fits gifs in, if any
+    fn target_size(self) -> Option<u64> {
+        match self {
+            Preset::Github => Some(10 * 1024 * 1024),
+            Preset::Twitter => Some(15 * 1024 * 1024),
+            Preset::Slide => None,
+        }
+    }
+}
+
+/// A crop given on the command line
+///
+/// Any of the sides may be left out. They are filled in from the size of the terminal by
+/// [`CropArg::resolve`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct CropArg {
+    top: Option<u16>,
+    left: Option<u16>,
+    width: Option<u16>,
+    height: Option<u16>,
+}
+
+impl CropArg {
+    /// Fill in the missing sides of the crop and check it against the (rows, cols) size of the
+    /// terminal
+    fn resolve(self, terminal_size: (u16, u16)) -> anyhow::Result<CropSettings> {
+        // Missing settings crop as little as possible
+        let (rows, cols) = terminal_size;
+        let top = self.top.unwrap_or(0);
+        let left = self.left.unwrap_or(0);
+        let crop = CropSettings {
+            top,
+            left,
+            width: self.width.unwrap_or_else(|| cols.saturating_sub(left)),
+            height: self.height.unwrap_or_else(|| rows.saturating_sub(top)),
+        };
+        crop.validate(rows, cols)?;
+
+        Ok(crop)
+    }
+}
+
+impl FromStr for CropArg {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut crop = CropArg::default();
+        for pair in s.split(',') {
+            let mut split = pair.splitn(2, '=');
+            let key = split.next().unwrap_or("").trim();
+            let value: u16 = split
+                .next()
+                .ok_or_else(|| format_err!("Expected `key=value` in crop, got: {}", pair))?
+                .trim()
+                .parse()
+                .context(format!("Could not parse crop value as int: {}", pair))?;
+
+            match key {
+                "top" => crop.top = Some(value),
+                "left" => crop.left = Some(value),
+                "width" => crop.width = Some(value),
+                "height" => crop.height = Some(value),
+                other => {
+                    return Err(format_err!(
+                        "Unknown crop setting `{}`, expected one of: top, left, width, height",
+                        other
+                    ))
+                }
+            }
+        }
+
+        Ok(crop)
+    }
+}
+
+impl fmt::Display for CropArg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sides = [
+            ("top", self.top),
+            ("left", self.left),
+            ("width", self.width),
+            ("height", self.height),
+        ];
+        let sides: Vec<String> = sides
+            .iter()
+            .filter_map(|(name, value)| Some(format!("{}={}", name, (*value)?)))
+            .collect();
+
+        write!(f, "{}", sides.join(","))
+    }
+}
+
+/// Parse the frame rate for `--timing fixed`
+fn parse_fps(fps: &str) -> anyhow::Result<f64> {
+    let fps: f64 = fps.parse().context("Could not parse --fps as a number")?;
+    if !fps.is_finite() || fps <= 0. {
+        return Err(format_err!("--fps must be greater than 0"));
+    }
+
+    Ok(fps)
+}
+
+/// Parse the minimum frame delay, in seconds
+fn parse_min_frame_delay(delay: &str) -> anyhow::Result<f64> {
+    let delay: f64 = delay
+        .parse()
+        .context("Could not parse --min-frame-delay as a number")?;
+    if !delay.is_finite() || delay < 0. {
+        return Err(format_err!("--min-frame-delay must be 0 or more"));
+    }
+
+    Ok(delay)
+}
+
+/// Parse the time to hold the last frame for, in seconds
+fn parse_hold_last(hold: &str) -> anyhow::Result<f64> {
+    let hold: f64 = hold
+        .parse()
+        .context("Could not parse --hold-last as a number")?;
+    if !hold.is_finite() || hold < 0. {
+        return Err(format_err!("--hold-last must be 0 or more"));
+    }
+
+    Ok(hold)
+}
+
+/// Parse the typing speed, in characters per second
+fn parse_typing_speed(speed: &str) -> anyhow::Result<f64> {
+    let speed: f64 = speed
+        .parse()
+        .context("Could not parse --typing-speed as a number")?;
+    if !speed.is_finite() || speed <= 0. {
+        return Err(format_err!("--typing-speed must be greater than 0"));
+    }
+
+    Ok(speed)
+}
+
+/// Parse the time to pause at each marker for, in seconds
+fn parse_pause_on_markers(pause: &str) -> anyhow::Result<f64> {
+    let pause: f64 = pause
+        .parse()
+        .context("Could not parse --pause-on-markers as a number")?;
+    if !pause.is_finite() || pause <= 0. {
+        return Err(format_err!("--pause-on-markers must be greater than 0"));
+    }
+
+    Ok(pause)
+}
+
+/// Parse the time to fade in over, in seconds
+fn parse_fade_in(fade: &str) -> anyhow::Result<f64> {
+    let fade: f64 = fade
+        .parse()
+        .context("Could not parse --fade-in as a number")?;
+    if !fade.is_finite() || fade <= 0. {
+        return Err(format_err!("--fade-in must be greater than 0"));
+    }
+
+    Ok(fade)
+}
+
+/// Parse the time to fade out over, in seconds
+fn parse_fade_out(fade: &str) -> anyhow::Result<f64> {
+    let fade: f64 = fade
+        .parse()
+        .context("Could not parse --fade-out as a number")?;
+    if !fade.is_finite() || fade <= 0. {
+        return Err(format_err!("--fade-out must be greater than 0"));
+    }
+
+    Ok(fade)
+}
+
+/// Parse the opacity of the terminal background
+fn parse_background_opacity(opacity: &str) -> anyhow::Result<f64> {
+    let opacity: f64 = opacity
+        .parse()
+        .context("Could not parse --background-opacity as a number")?;
+    if !(0. ..=1.).contains(&opacity) {
+        return Err(format_err!("--background-opacity must be between 0 and 1"));
+    }
+
+    Ok(opacity)
+}
+
+/// Parse the WCAG contrast ratio that text must have
+fn parse_min_contrast(ratio: &str) -> anyhow::Result<f64> {
+    let ratio: f64 = ratio
+        .parse()
+        .context("Could not parse --min-contrast as a number")?;
+    if !(1. ..=21.).contains(&ratio) {
+        return Err(format_err!("--min-contrast must be between 1 and 21"));
+    }
+
+    Ok(ratio)
+}
+
+/// Parse a color for --ink or --paper, such as `#fdf6e3`
+fn parse_monochrome_color(color: &str) -> anyhow::Result<Rgb> {
+    crate::color_profile::parse_x_color(color.trim())
+        .ok_or_else(|| format_err!("`{}` isn't a color like #fdf6e3", color))
+}
+
+/// Format a color like the ones that --ink and --paper take
+fn format_color((r, g, b): Rgb) -> String {
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Parse the time to show the end card for, in seconds
+fn parse_end_card_hold(hold: &str) -> anyhow::Result<f64> {
+    let hold: f64 = hold
+        .parse()
+        .context("Could not parse --end-card-hold as a number")?;
+    if !hold.is_finite() || hold <= 0. {
+        return Err(format_err!("--end-card-hold must be greater than 0"));
+    }
+
+    Ok(hold)
+}
+
+/// Parse the time to show marker captions for, in seconds
+fn parse_marker_captions(duration: &str) -> anyhow::Result<f64> {
+    let duration: f64 = duration
+        .parse()
+        .context("Could not parse --marker-captions as a number")?;
+    if !duration.is_finite() || duration <= 0. {
+        return Err(format_err!("--marker-captions must be greater than 0"));
+    }
+
+    Ok(duration)
+}
+
+/// Parse a file size such as `5MB`, `500KB`, or a plain number of bytes
+fn parse_size(size: &str) -> anyhow::Result<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 1.,
+        "K" | "KB" => 1024.,
+        "M" | "MB" => 1024. * 1024.,
+        "G" | "GB" => 1024. * 1024. * 1024.,
+        _ => return Err(format_err!("Unknown size unit `{}`", unit)),
+    };
+    let number: f64 = number
+        .trim()
+        .parse()
+        .context(format!("`{}` is not a number", number.trim()))?;
+    if !number.is_finite() || number <= 0. {
+        return Err(format_err!("Size must be greater than 0"));
+    }
+
+    Ok((number * multiplier) as u64)
+}
+
+/// The arguments that configure how a cast is rendered
+#[derive(Debug, Clone, Args)]
+#[command(next_help_heading = "Render Options")]
+struct RenderArgs {
+    /// crop the recording while rendering. Specify crop in terminal cells as
+    /// `top=[int],left=[int],width=[int],height=[int]`. Any of them may be left out: top and left
+    /// default to 0 and width and height default to the rest of the terminal.
+    #[arg(short, long, env = "CAST2GIF_CROP")]
+    crop: Option<CropArg>,
+
+    /// Set the options that suit where the output will be posted: `github` fits gifs in 10 MB,
+    /// `twitter` pads the frames to 16:9 and fits gifs in 15 MB, and `slide` pads the frames to
+    /// 16:9. Options that are given override the ones the preset sets, and options that don't
+    /// apply to the output format are left out.
+    #[arg(long, env = "CAST2GIF_PRESET", value_enum)]
+    preset: Option<Preset>,
+
+    /// Pad the frames with the terminal background to an aspect ratio, such as `16:9` or `4:3`,
+    /// keeping the terminal in the middle, so that the output fits slide decks and video platforms
+    /// without being stretched or cropped. Doesn't apply to svg output.
+    #[arg(long, env = "CAST2GIF_ASPECT", value_name = "WIDTH:HEIGHT")]
+    aspect: Option<AspectRatio>,
+
+    /// The approximate amount of memory, in megabytes, that rendered frames may take up while they
+    /// wait to be given to the gif encoder, which keeps a few frames of its own on top of this.
+    /// Lower values use less memory on long recordings at the cost of speed.
+    #[arg(long, env = "CAST2GIF_MAX_MEMORY", value_name = "MB")]
+    max_memory: Option<usize>,
+
+    /// The number of threads to render frames with. Defaults to the number of CPUs.
+    #[arg(short = 'j', long, env = "CAST2GIF_THREADS", value_name = "N")]
+    threads: Option<NonZeroUsize>,
+
+    /// Spill rendered frames to a temporary directory inside of DIR when they can't be written to
+    /// the gif fast enough, instead of slowing down rendering. Trades disk space for memory usage
+    /// and speed on long recordings.
+    #[arg(long, env = "CAST2GIF_SPILL_DIR", value_name = "DIR")]
+    spill_dir: Option<PathBuf>,
+
+    /// Cache rendered frames in DIR, and load the frames that are already in it instead of
+    /// rendering them again. Makes rendering a cast again with different encoder options, frame
+    /// rates, or output formats faster. The cache is never cleaned up, remove the directory to
+    /// clear it.
+    #[arg(long, env = "CAST2GIF_CACHE_DIR", value_name = "DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// How to time the frames of the gif
+    #[arg(long, env = "CAST2GIF_TIMING", value_enum, default_value_t = Timing::Events)]
+    timing: Timing,
+
+    /// The number of times per second to sample the screen with `--timing fixed`
+    #[arg(long, env = "CAST2GIF_FPS", default_value = "10", value_parser = parse_fps)]
+    fps: f64,
+
+    /// Change the playback speed of parts of the recording. Specify a comma separated list of
+    /// `start-end:speed` segments, with times in seconds, such as `0-10:1.0,10-60:4.0`. A speed of
+    /// 4.0 plays that part four times faster.
+    #[arg(long, env = "CAST2GIF_SPEED_MAP", value_name = "MAP")]
+    speed_map: Option<SpeedMap>,
+
+    /// Only render part of the recording. Specify `start-end` in seconds, such as `2.5-30`. Either
+    /// side may be left out, such as `2.5-` to skip the first 2.5 seconds.
+    #[arg(
+        long,
+        env = "CAST2GIF_TRIM",
+        value_name = "RANGE",
+        allow_hyphen_values = true
+    )]
+    trim: Option<TrimSettings>,
+
+    /// What to do with the time between the start of the recording and its first output: `keep`
+    /// it, `trim` it, or cap it at a number of seconds. Defaults to `trim`. Doesn't apply when
+    /// --trim has a start.
+    #[arg(long, env = "CAST2GIF_INITIAL_DELAY", value_name = "keep|trim|SECONDS")]
+    initial_delay: Option<InitialDelay>,
+
+    /// Re-time typing so that typed characters appear at a steady CPS characters per second,
+    /// however unevenly they were typed. The time between the output of commands, and the pause
+    /// before each run of typing, are kept as they were recorded.
+    #[arg(
+        long,
+        env = "CAST2GIF_TYPING_SPEED",
+        value_name = "CPS",
+        value_parser = parse_typing_speed
+    )]
+    typing_speed: Option<f64>,
+
+    /// Hold the frame at each marker in the cast for SECONDS, so that viewers can read the screen
+    /// at key checkpoints. The rest of the recording plays after the pause.
+    #[arg(
+        long,
+        env = "CAST2GIF_PAUSE_ON_MARKERS",
+        value_name = "SECONDS",
+        value_parser = parse_pause_on_markers
+    )]
+    pause_on_markers: Option<f64>,
+
+    /// Draw the cues of an SRT subtitle file as captions over the frames. Cue times are in the
+    /// timeline of the rendered gif.
+    #[arg(long, env = "CAST2GIF_SUBTITLES", value_name = "FILE")]
+    subtitles: Option<PathBuf>,
+
+    /// Direct attention to regions of the terminal over time ranges of the rendered gif, by
+    /// dimming everything around them or zooming into them. Each line of FILE is a spotlight, like
+    /// `2-5 top=0,left=0,width=40,height=1` or `5-9 top=1,left=0,width=40,height=6 zoom`. Svgs
+    /// dim instead of zooming.
+    #[arg(long, env = "CAST2GIF_SPOTLIGHTS", value_name = "FILE")]
+    spotlights: Option<PathBuf>,
+
+    /// Pan and zoom over the terminal along a path of views. Each line of FILE is a keyframe of a
+    /// time of the rendered gif and the cells to show then, like `4
+    /// top=30,left=0,width=60,height=10`, and the camera glides between them. Zoomed spotlights
+    /// take its place while they are on. Svgs leave the camera out.
+    #[arg(long, env = "CAST2GIF_CAMERA", value_name = "FILE")]
+    camera: Option<PathBuf>,
+
+    /// Black out text that matches a regular expression, such as access tokens, email addresses,
+    /// or host names, wherever the terminal, the window title, or a marker caption shows it. Give
+    /// --redact more than once to redact several patterns.
+    #[arg(long, env = "CAST2GIF_REDACT", value_name = "REGEX")]
+    redact: Vec<String>,
+
+    /// Draw a window title bar above the terminal, showing --title, or else the titles that the
+    /// cast sets for the window as they change, starting with the title from the cast file. Svgs
+    /// only show the title from the cast file
+    #[arg(long, env = "CAST2GIF_TITLE_BAR", value_parser = FlagParser::new())]
+    title_bar: bool,
+
+    /// The title to show in the title bar, instead of the ones from the cast. Implies
+    /// --title-bar.
+    #[arg(long, env = "CAST2GIF_TITLE")]
+    title: Option<String>,
+
+    /// How opaque the terminal background is, from 0 for transparent to 1 for opaque, such as
+    /// `0.8` for the look of a translucent terminal. Cells that don't set their own background
+    /// show whatever the image is shown over through them. Only applies to svg, kitty, png
+    /// sequence, and filmstrip output, because gifs and videos can't be translucent.
+    #[arg(
+        long,
+        env = "CAST2GIF_BACKGROUND_OPACITY",
+        value_name = "OPACITY",
+        value_parser = parse_background_opacity
+    )]
+    background_opacity: Option<f64>,
+
+    /// Lower the quality and frame rate of the gif until it fits in SIZE, such as `5MB` or
+    /// `500KB`. Every rendered frame is kept in memory while the gif is re-encoded.
+    #[arg(long, env = "CAST2GIF_TARGET_SIZE", value_name = "SIZE", value_parser = parse_size)]
+    target_size: Option<u64>,
+
+    /// How to pick the colors of each frame of the gif. Defaults to `imagequant`. Text-heavy
+    /// recordings often look crisper with `median-cut`.
+    #[arg(long, env = "CAST2GIF_QUANTIZER", value_enum)]
+    quantizer: Option<Quantizer>,
+
+    /// The shortest time, in seconds, to show a frame for. Frames that come sooner after the
+    /// previous one are merged, because browsers play gifs with very short delays much slower.
+    /// Defaults to 0.02. Set to 0 to keep every frame.
+    #[arg(
+        long,
+        env = "CAST2GIF_MIN_FRAME_DELAY",
+        value_name = "SECONDS",
+        value_parser = parse_min_frame_delay
+    )]
+    min_frame_delay: Option<f64>,
+
+    /// The time, in seconds, to show the last frame for before the gif loops back to the start.
+    /// Defaults to 2. Set to 0 to show it for as long as the frame before it.
+    #[arg(
+        long,
+        env = "CAST2GIF_HOLD_LAST",
+        value_name = "SECONDS",
+        value_parser = parse_hold_last
+    )]
+    hold_last: Option<f64>,
+
+    /// Cross-fade the last FRAMES frames of the gif into the first frame, or the last 8 if FRAMES
+    /// isn't given, such as `--loop-crossfade=12`, so that it loops back to the start without a
+    /// visible jump. The last frame looks just like the first.
+    #[arg(
+        long,
+        env = "CAST2GIF_LOOP_CROSSFADE",
+        value_name = "FRAMES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "8"
+    )]
+    loop_crossfade: Option<NonZeroU32>,
+
+    /// Fade in from the terminal background over SECONDS before the recording starts
+    #[arg(
+        long,
+        env = "CAST2GIF_FADE_IN",
+        value_name = "SECONDS",
+        value_parser = parse_fade_in
+    )]
+    fade_in: Option<f64>,
+
+    /// Fade out to the terminal background over SECONDS, once the last frame, or the end card, has
+    /// been held
+    #[arg(
+        long,
+        env = "CAST2GIF_FADE_OUT",
+        value_name = "SECONDS",
+        value_parser = parse_fade_out
+    )]
+    fade_out: Option<f64>,
+
+    /// Redraw text that is hard to read against the color behind it lighter or darker, just enough
+    /// to reach a WCAG contrast ratio of RATIO, or 4.5 if RATIO isn't given, such as
+    /// `--min-contrast=7`. Makes recordings with low-contrast themes readable for low-vision
+    /// viewers.
+    #[arg(
+        long,
+        env = "CAST2GIF_MIN_CONTRAST",
+        value_name = "RATIO",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "4.5",
+        value_parser = parse_min_contrast
+    )]
+    min_contrast: Option<f64>,
+
+    /// Render the terminal on a white background, for documentation sites with light themes.
+    /// Colors keep their hue and are made lighter or darker so that they stand out as much as they
+    /// did on the dark background, instead of being inverted.
+    #[arg(long, env = "CAST2GIF_LIGHT", value_parser = FlagParser::new())]
+    light: bool,
+
+    /// Redraw the frames in shades between the --paper color, for the darkest colors, and the
+    /// --ink color, for the lightest ones. `grayscale`, the default, keeps every shade, and
+    /// `two-tone` only keeps the two colors, which compresses extremely well. Doesn't apply to svg
+    /// output.
+    #[arg(
+        long,
+        env = "CAST2GIF_MONOCHROME",
+        value_name = "STYLE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "grayscale",
+        value_enum
+    )]
+    monochrome: Option<MonochromeStyle>,
+
+    /// The color of the lightest colors with --monochrome, such as `#222222`. Defaults to white.
+    #[arg(
+        long,
+        env = "CAST2GIF_INK",
+        value_name = "COLOR",
+        value_parser = parse_monochrome_color,
+        requires = "monochrome"
+    )]
+    ink: Option<Rgb>,
+
+    /// The color of the darkest colors with --monochrome, such as `#fdf6e3`. Defaults to black.
+    #[arg(
+        long,
+        env = "CAST2GIF_PAPER",
+        value_name = "COLOR",
+        value_parser = parse_monochrome_color,
+        requires = "monochrome"
+    )]
+    paper: Option<Rgb>,
+
+    /// Tint the cells that changed since the previous frame, to make it easy to spot what a
+    /// command changed. The tint fades out over the next FRAMES frames, or 3 if FRAMES isn't
+    /// given, such as `--highlight-changes=5`.
+    #[arg(
+        long,
+        env = "CAST2GIF_HIGHLIGHT_CHANGES",
+        value_name = "FRAMES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "3"
+    )]
+    highlight_changes: Option<NonZeroU32>,
+
+    /// Draw a subtle halo around the cursor and a trail behind it as it moves, to make fast cursor
+    /// movement easy to follow. The trail fades out over the next FRAMES frames, or 4 if FRAMES
+    /// isn't given, such as `--cursor-trail=6`.
+    #[arg(
+        long,
+        env = "CAST2GIF_CURSOR_TRAIL",
+        value_name = "FRAMES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "4"
+    )]
+    cursor_trail: Option<NonZeroU32>,
+
+    /// Slide the screen up over FRAMES frames when it scrolls, or 4 if FRAMES isn't given, instead
+    /// of jumping a whole row at a time, such as `--smooth-scroll=6`. Scrolls that are followed
+    /// too soon by the next frame slide over fewer frames. Doesn't apply to svg output.
+    #[arg(
+        long,
+        env = "CAST2GIF_SMOOTH_SCROLL",
+        value_name = "FRAMES",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "4"
+    )]
+    smooth_scroll: Option<NonZeroU32>,
+
+    /// Show the labels of the markers in the cast as captions, like chapter titles. Each label is
+    /// shown for SECONDS after its marker, or 3 seconds if SECONDS isn't given, such as
+    /// `--marker-captions=5`. Subtitles are shown instead while they have a cue.
+    #[arg(
+        long,
+        env = "CAST2GIF_MARKER_CAPTIONS",
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "3",
+        value_parser = parse_marker_captions
+    )]
+    marker_captions: Option<f64>,
+
+    /// Add a marker for every command run at a shell prompt, labeled with the command, so that
+    /// --marker-captions shows each command as it runs. Commands are found from the semantic
+    /// prompt marks of shells that send them, or guessed from lines that look like prompts.
+    #[arg(long, env = "CAST2GIF_COMMAND_MARKERS", value_parser = FlagParser::new())]
+    command_markers: bool,
+
+    /// Don't flash the screen when the cast rings the bell
+    #[arg(long, env = "CAST2GIF_NO_BELL_FLASH", value_parser = FlagParser::new())]
+    no_bell_flash: bool,
+}
+
+impl RenderArgs {
+    /// Get the render settings for a cast
+    ///
+    /// `terminal_size` is the (rows, cols) size of the cast's terminal, which the crop is checked
+    /// against.
+    fn settings(&self, terminal_size: (u16, u16)) -> anyhow::Result<ConvertSettings> {
+        let crop = self
+            .crop
+            .map(|crop| crop.resolve(terminal_size))
+            .transpose()?;
+
+        let timing = match self.timing {
+            Timing::Events => TimingMode::Events,
+            Timing::Fixed => TimingMode::Fixed(1. / self.fps),
+        };
+
+        let subtitles = self
+            .subtitles
+            .as_ref()
+            .map(|path| -> anyhow::Result<crate::Subtitles> {
+                let srt = std::fs::read_to_string(path).context(format!(
+                    "Could not read subtitles file: {}",
+                    path.to_string_lossy()
+                ))?;
+                Ok(srt.parse()?)
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        let spotlights = self
+            .spotlights
+            .as_ref()
+            .map(|path| -> anyhow::Result<crate::Spotlights> {
+                let spotlights = std::fs::read_to_string(path).context(format!(
+                    "Could not read spotlights file: {}",
+                    path.to_string_lossy()
+                ))?;
+                Ok(spotlights.parse()?)
+            })
+            .transpose()?
+            .unwrap_or_default();
+        spotlights.validate(terminal_size.0, terminal_size.1)?;
+
+        let camera = self
+            .camera
+            .as_ref()
+            .map(|path| -> anyhow::Result<crate::CameraPath> {
+                let camera = std::fs::read_to_string(path).context(format!(
+                    "Could not read camera file: {}",
+                    path.to_string_lossy()
+                ))?;
+                Ok(camera.parse()?)
+            })
+            .transpose()?
+            .unwrap_or_default();
+        camera.validate(terminal_size.0, terminal_size.1)?;
+
+        let settings = ConvertSettings {
+            crop,
+            aspect: self.aspect.or_else(|| self.preset.and_then(Preset::aspect)),
+            max_memory: self.max_memory.map(|mb| mb * 1024 * 1024),
+            threads: self.threads.map(NonZeroUsize::get),
+            spill_dir: self.spill_dir.clone(),
+            cache_dir: self.cache_dir.clone(),
+            timing,
+            speed_map: self.speed_map.clone().unwrap_or_default(),
+            trim: self.trim.unwrap_or_default(),
+            initial_delay: self.initial_delay.unwrap_or_default(),
+            typing_speed: self.typing_speed,
+            pause_on_markers: self.pause_on_markers,
+            subtitles,
+            spotlights,
+            camera,
+            redactions: crate::Redactions::new(&self.redact)?,
+            title_bar: self.title_bar,
+            title: self.title.clone(),
+            background_opacity: self.background_opacity,
+            target_size: self
+                .target_size
+                .or_else(|| self.preset.and_then(Preset::target_size)),
+            quantizer: match self.quantizer {
+                Some(Quantizer::Imagequant) | None => crate::Quantizer::Imagequant,
+                Some(Quantizer::Neuquant) => crate::Quantizer::NeuQuant,
+                Some(Quantizer::MedianCut) => crate::Quantizer::MedianCut,
+            },
+            min_frame_delay: self.min_frame_delay,
+            hold_last: self.hold_last,
+            loop_crossfade: self.loop_crossfade.map(NonZeroU32::get),
+            fade_in: self.fade_in,
+            fade_out: self.fade_out,
+            min_contrast: self.min_contrast,
+            light: self.light,
+            monochrome: self.monochrome.map(|style| {
+                let mut monochrome = crate::Monochrome::new(match style {
+                    MonochromeStyle::Grayscale => crate::MonochromeStyle::Grayscale,
+                    MonochromeStyle::TwoTone => crate::MonochromeStyle::TwoTone,
+                });
+                monochrome.ink = self.ink.unwrap_or(monochrome.ink);
+                monochrome.paper = self.paper.unwrap_or(monochrome.paper);
+                monochrome
+            }),
+            highlight_changes: self.highlight_changes.map(NonZeroU32::get),
+            cursor_trail: self.cursor_trail.map(NonZeroU32::get),
+            smooth_scroll: self.smooth_scroll.map(NonZeroU32::get),
+            marker_captions: self.marker_captions,
+            command_markers: self.command_markers,
+            bell_flash: !self.no_bell_flash,
+            ..Default::default()
+        };
+
+        Ok(settings)
+    }
+
+    /// Get the names of the render settings, such as `max_memory`
+    fn setting_names() -> Vec<String> {
+        Self::augment_args(clap::Command::new("cast2gif"))
+            .get_arguments()
+            .map(|arg| arg.get_id().to_string())
+            .collect()
+    }
+
+    /// Get the settings that are set, as setting name and command line value pairs
+    ///
+    /// Flags that are set have an empty value, and settings that are given more than once have
+    /// a line for each value.
+    fn setting_values(&self) -> HashMap<String, String> {
+        let mut values = HashMap::new();
+        let mut set = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                values.insert(name.to_string(), value);
+            }
+        };
+
+        set("crop", self.crop.map(|crop| crop.to_string()));
+        set(
+            "preset",
+            self.preset
+                .and_then(|preset| preset.to_possible_value())
+                .map(|value| value.get_name().to_string()),
+        );
+        set("aspect", self.aspect.map(|aspect| aspect.to_string()));
+        set("max_memory", self.max_memory.map(|mb| mb.to_string()));
+        set("threads", self.threads.map(|threads| threads.to_string()));
+        set(
+            "spill_dir",
+            self.spill_dir
+                .as_ref()
+                .map(|dir| dir.to_string_lossy().into_owned()),
+        );
+        set(
+            "cache_dir",
+            self.cache_dir
+                .as_ref()
+                .map(|dir| dir.to_string_lossy().into_owned()),
+        );
+        set(
+            "timing",
+            self.timing
+                .to_possible_value()
+                .map(|value| value.get_name().to_string()),
+        );
+        set("fps", Some(self.fps.to_string()));
+        set(
+            "speed_map",
+            self.speed_map.as_ref().map(SpeedMap::to_string),
+        );
+        set("trim", self.trim.map(|trim| trim.to_string()));
+        set(
+            "initial_delay",
+            self.initial_delay.map(|delay| delay.to_string()),
+        );
+        set(
+            "typing_speed",
+            self.typing_speed.map(|speed| speed.to_string()),
+        );
+        set(
+            "pause_on_markers",
+            self.pause_on_markers.map(|pause| pause.to_string()),
+        );
+        set(
+            "subtitles",
+            self.subtitles
+                .as_ref()
+                .map(|path| path.to_string_lossy().into_owned()),
+        );
+        set(
+            "spotlights",
+            self.spotlights
+                .as_ref()
+                .map(|path| path.to_string_lossy().into_owned()),
+        );
+        set(
+            "camera",
+            self.camera
+                .as_ref()
+                .map(|path| path.to_string_lossy().into_owned()),
+        );
+        set(
+            "redact",
+            Some(self.redact.join("\n")).filter(|_| !self.redact.is_empty()),
+        );
+        set("title_bar", Some(String::new()).filter(|_| self.title_bar));
+        set("title", self.title.clone());
+        set(
+            "background_opacity",
+            self.background_opacity.map(|opacity| opacity.to_string()),
+        );
+        set("target_size", self.target_size.map(|size| size.to_string()));
+        set(
+            "quantizer",
+            self.quantizer
+                .and_then(|quantizer| quantizer.to_possible_value())
+                .map(|value| value.get_name().to_string()),
+        );
+        set(
+            "min_frame_delay",
+            self.min_frame_delay.map(|delay| delay.to_string()),
+        );
+        set("hold_last", self.hold_last.map(|hold| hold.to_string()));
+        set(
+            "loop_crossfade",
+            self.loop_crossfade.map(|frames| frames.to_string()),
+        );
+        set("fade_in", self.fade_in.map(|fade| fade.to_string()));
+        set("fade_out", self.fade_out.map(|fade| fade.to_string()));
+        set(
+            "min_contrast",
+            self.min_contrast.map(|ratio| ratio.to_string()),
+        );
+        set("light", Some(String::new()).filter(|_| self.light));
+        set(
+            "monochrome",
+            self.monochrome
+                .and_then(|style| style.to_possible_value())
+                .map(|value| value.get_name().to_string()),
+        );
+        set("ink", self.ink.map(format_color));
+        set("paper", self.paper.map(format_color));
+        set(
+            "highlight_changes",
+            self.highlight_changes.map(|frames| frames.to_string()),
+        );
+        set(
+            "cursor_trail",
+            self.cursor_trail.map(|frames| frames.to_string()),
+        );
+        set(
+            "smooth_scroll",
+            self.smooth_scroll.map(|frames| frames.to_string()),
+        );
+        set(
+            "marker_captions",
+            self.marker_captions.map(|duration| duration.to_string()),
+        );
+        set(
+            "command_markers",
+            Some(String::new()).filter(|_| self.command_markers),
+        );
+        set(
+            "no_bell_flash",
+            Some(String::new()).filter(|_| self.no_bell_flash),
+        );
+
+        values
+    }
+
+    /// Parse render settings from setting name and command line value pairs, like the ones
+    /// returned by [`RenderArgs::setting_values`]
+    ///
+    /// Unlike the command line, settings that are left out are not read from the environment.
+    fn from_setting_values(values: &HashMap<String, String>) -> anyhow::Result<Self> {
+        let args = values.iter().flat_map(|(name, value)| {
+            let name = name.replace('_', "-");
+            if value.is_empty() {
+                vec![format!("--{}", name)]
+            } else {
+                value
+                    .lines()
+                    .map(|value| format!("--{}={}", name, value))
+                    .collect()
+            }
+        });
+        let command = clap::Command::new("cast2gif")
+            .no_binary_name(true)
+            .disable_help_flag(true);
+        let matches = Self::augment_args(command)
+            .mut_args(|arg| arg.env(None))
+            .try_get_matches_from(args)
+            // Only keep the message, the cause is already part of it
+            .map_err(|e| format_err!("{}", e.to_string().trim()))?;
+
+        Ok(Self::from_arg_matches(&matches)?)
+    }
+}
+
+/// The arguments that configure svg output
+#[derive(Debug, Clone, Args)]
+#[command(next_help_heading = "SVG Options")]
+struct SvgArgs {
+    /// Draw the terminal in a window with buttons, like svg-term's --window. The window is always
+    /// drawn with --title-bar.
+    #[arg(long, env = "CAST2GIF_WINDOW", value_parser = FlagParser::new())]
+    window: bool,
+
+    /// The space around the terminal, in pixels. Defaults to 10.
+    #[arg(long, env = "CAST2GIF_PADDING", value_name = "PX")]
+    padding: Option<u16>,
+
+    /// Don't draw the cursor
+    #[arg(long, env = "CAST2GIF_NO_CURSOR", value_parser = FlagParser::new())]
+    no_cursor: bool,
+
+    /// The colors to draw the terminal with. Specify an iTerm2 .itermcolors file or an X
+    /// resources file, such as ~/.Xresources.
+    #[arg(long, env = "CAST2GIF_PROFILE", value_name = "FILE")]
+    profile: Option<PathBuf>,
+}
+
+impl SvgArgs {
+    /// Whether or not any of the svg options are set
+    fn is_set(&self) -> bool {
+        self.window || self.padding.is_some() || self.no_cursor || self.profile.is_some()
+    }
+
+    /// Get the svg settings
+    fn settings(&self) -> anyhow::Result<SvgSettings> {
+        let profile = self
+            .profile
+            .as_ref()
+            .map(|path| -> anyhow::Result<ColorProfile> {
+                let profile = std::fs::read_to_string(path).context(format!(
+                    "Could not read color profile: {}",
+                    path.to_string_lossy()
+                ))?;
+                Ok(profile.parse()?)
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(SvgSettings {
+            window: self.window,
+            padding: self.padding,
+            cursor: !self.no_cursor,
+            profile,
+        })
+    }
+}
+
+/// The arguments that configure mp4 output
+#[derive(Debug, Clone, Args)]
+#[command(next_help_heading = "Video Options")]
+struct VideoArgs {
+    /// An audio file to add to the video, such as a narration of the demo. Any format that ffmpeg
+    /// can read may be given.
+    #[arg(long, env = "CAST2GIF_AUDIO", value_name = "FILE")]
+    audio: Option<PathBuf>,
+}
+
+impl VideoArgs {
+    /// Whether or not any of the video options are set
+    fn is_set(&self) -> bool {
+        self.audio.is_some()
+    }
+
+    /// Get the video settings
+    fn settings(&self) -> VideoSettings {
+        VideoSettings {
+            audio: self.audio.clone(),
+        }
+    }
+}
+
+/// How hard to compress the pngs of a png sequence
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Compression {
+    /// Compress quickly, making larger files
+    Fast,
+    /// Balance speed and size
+    Default,
+    /// Make the smallest files, compressing slowly
+    Best,
+}
+
+/// A manifest of the frames of a png sequence
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum Manifest {
+    /// A JSON file with the file name, index, time, and duration of every frame
+    Json,
+    /// A CMX 3600 edit decision list
+    Edl,
+}
+
+/// The arguments that configure png sequence output
+#[derive(Debug, Clone, Args)]
+#[command(next_help_heading = "PNG Sequence Options")]
+struct PngSequenceArgs {
+    /// How hard to compress the pngs
+    #[arg(
+        long,
+        env = "CAST2GIF_PNG_COMPRESSION",
+        value_name = "LEVEL",
+        value_enum
+    )]
+    png_compression: Option<Compression>,
+
+    /// The file name of each frame. `{index}` is replaced by the index of the frame, starting at
+    /// 0, `{time_ms}` by the time it is shown at, in milliseconds, and `{cast}` by the name of the
+    /// cast file without its extension. Give a width to pad a number with zeros, like
+    /// `{index:05}`. Defaults to `frame-{index:05}.png`.
+    #[arg(long, env = "CAST2GIF_FRAME_NAME", value_name = "TEMPLATE")]
+    frame_name: Option<String>,
+
+    /// Write a manifest of the frames to the output directory, with the time and duration of
+    /// every frame, so that video editors line them up with the recording: `manifest.json`, or
+    /// `manifest.edl`, an edit decision list at 30 frames per second.
+    #[arg(long, env = "CAST2GIF_MANIFEST", value_name = "FORMAT", value_enum)]
+    manifest: Option<Manifest>,
+}
+
+impl PngSequenceArgs {
+    /// Whether or not any of the png sequence options are set
+    fn is_set(&self) -> bool {
+        self.png_compression.is_some() || self.frame_name.is_some() || self.manifest.is_some()
+    }
+
+    /// Get the png sequence settings for a cast with the given name
+    fn settings(&self, cast_name: Option<String>) -> anyhow::Result<PngSequenceSettings> {
+        let compression = match self.png_compression {
+            Some(Compression::Fast) => crate::PngCompression::Fast,
+            Some(Compression::Default) | None => crate::PngCompression::Default,
+            Some(Compression::Best) => crate::PngCompression::Best,
+        };
+        let file_name = match &self.frame_name {
+            Some(template) => template.parse()?,
+            None => Default::default(),
+        };
+
+        Ok(PngSequenceSettings {
+            compression,
+            file_name,
+            cast_name,
+            manifest: self.manifest.map(|manifest| match manifest {
+                Manifest::Json => crate::ManifestFormat::Json,
+                Manifest::Edl => crate::ManifestFormat::Edl,
+            }),
+        })
+    }
 }
 
 struct ProgressHandler {
     raster_progress: ProgressBar,
     sequence_progress: ProgressBar,
+    /// The bar for parsing the cast, along with the summary of the cast that it is measured
+    /// against, if the cast was pre-scanned
+    parse_progress: Option<(ProgressBar, crate::CastSummary)>,
 }
 
 impl ProgressHandler {
-    fn new(raster_progress: ProgressBar, sequence_progress: ProgressBar) -> Self {
+    fn new(
+        raster_progress: ProgressBar,
+        sequence_progress: ProgressBar,
+        parse_progress: Option<(ProgressBar, crate::CastSummary)>,
+    ) -> Self {
         Self {
             raster_progress,
             sequence_progress,
+            parse_progress,
         }
     }
 }
 
 impl crate::types::CastProgressHandler for ProgressHandler {
     fn update_progress(&mut self, progress: &crate::CastRenderProgress) {
+        // Until all of the frames have been parsed, estimate how many there will be from how far
+        // into the cast the parsing is
+        let count = match &self.parse_progress {
+            Some((_, summary)) if !progress.parsed && progress.parse_progress > 0 => {
+                let estimate =
+                    progress.count as f64 * summary.duration / progress.parse_progress as f64;
+                progress.count.max(estimate.round() as u64)
+            }
+            _ => progress.count,
+        };
+
+        if let Some((bar, summary)) = &self.parse_progress {
+            let duration = summary.duration.ceil() as u64;
+            let position = if progress.parsed {
+                duration
+            } else {
+                progress.parse_progress.min(duration)
+            };
+            bar.set_position(position);
+            bar.set_message(&format!(
+                "{:.1}s/{:.1}s",
+                position as f64 / 100.,
+                summary.duration / 100.
+            ));
+            if progress.parsed {
+                bar.set_prefix("Done");
+                bar.finish();
+            } else {
+                bar.set_prefix("Parsing");
+            }
+        }
+
         macro_rules! handle_progress {
             ($x:expr, $p:expr, $message:expr) => {
-                $x.set_length(progress.count);
+                $x.set_length(count);
                 if $x.position() > 0 {
                     $x.set_prefix($message);
                 } else if $x.is_finished() {
@@ -261,7 +2511,7 @@ impl crate::types::CastProgressHandler for ProgressHandler {
                     $x.finish();
                 }
             };
-        };
+        }
 
         handle_progress!(
             self.raster_progress,
@@ -275,3 +2525,47 @@ impl crate::types::CastProgressHandler for ProgressHandler {
         );
     }
 }
+
+/// A progress handler that prints newline-delimited JSON progress events to stderr
+#[derive(Default)]
+struct JsonProgressHandler {
+    /// The last progress that was reported, used to skip duplicate events
+    last_progress: Option<(u64, u64, u64, u64, bool)>,
+}
+
+impl JsonProgressHandler {
+    /// Print an event to stderr as a single line of JSON
+    fn emit(event: serde_json::Value) {
+        use std::io::Write;
+
+        let stderr = std::io::stderr();
+        let mut stderr = stderr.lock();
+        writeln!(stderr, "{}", event).ok();
+        stderr.flush().ok();
+    }
+}
+
+impl crate::types::CastProgressHandler for JsonProgressHandler {
+    fn update_progress(&mut self, progress: &crate::CastRenderProgress) {
+        let current = (
+            progress.count,
+            progress.raster_progress,
+            progress.sequence_progress,
+            progress.parse_progress,
+            progress.parsed,
+        );
+        if self.last_progress == Some(current) {
+            return;
+        }
+        self.last_progress = Some(current);
+
+        Self::emit(serde_json::json!({
+            "event": "progress",
+            "count": progress.count,
+            "raster_progress": progress.raster_progress,
+            "sequence_progress": progress.sequence_progress,
+            "parse_progress": progress.parse_progress as f64 / 100.,
+            "parsed": progress.parsed,
+        }));
+    }
+}
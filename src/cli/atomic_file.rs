@@ -0,0 +1,74 @@
+//! Atomically written output files
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// An output file that is written to a temporary file next to its destination and only moved to
+/// the destination once it is complete
+///
+/// If it is dropped without being committed, the temporary file is removed, so a failed render
+/// never leaves a truncated file at the destination.
+pub(crate) struct AtomicFile {
+    /// The destination path
+    path: PathBuf,
+    /// The path of the temporary file
+    temp_path: PathBuf,
+    /// The temporary file
+    file: File,
+    /// Whether or not the file has been moved to its destination
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Create a temporary file for the given destination path
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file_name = path.file_name().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Output path is not a file")
+        })?;
+        let dir = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+
+        // Put the temporary file in the same directory so that it can be renamed into place
+        let temp_path = dir.join(format!(
+            ".{}.{}.tmp",
+            file_name.to_string_lossy(),
+            std::process::id()
+        ));
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)?;
+
+        Ok(AtomicFile {
+            path: path.to_owned(),
+            temp_path,
+            file,
+            committed: false,
+        })
+    }
+
+    /// Get the temporary file to write to
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Move the finished file to its destination, replacing anything already there
+    pub fn commit(mut self) -> io::Result<()> {
+        self.file.sync_all()?;
+        std::fs::rename(&self.temp_path, &self.path)?;
+        self.committed = true;
+
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            std::fs::remove_file(&self.temp_path).ok();
+        }
+    }
+}
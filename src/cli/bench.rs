@@ -0,0 +1,100 @@
+//! The `bench` subcommand
+
+use anyhow::Context;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering::SeqCst};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::{CastProgressHandler, CastRenderProgress, ConvertSettings};
+
+/// Progress handler that records how many frames were rendered
+#[derive(Clone, Default)]
+struct FrameCounter(Arc<AtomicU64>);
+
+impl CastProgressHandler for FrameCounter {
+    fn update_progress(&mut self, progress: &CastRenderProgress) {
+        self.0.store(progress.count, SeqCst);
+    }
+}
+
+/// Reset the peak resident memory of the process, returning whether or not it could be reset
+fn reset_peak_memory() -> bool {
+    // Writing `5` to `clear_refs` resets the peak RSS on Linux
+    std::fs::write("/proc/self/clear_refs", "5").is_ok()
+}
+
+/// Get the peak resident memory of the process in bytes
+fn peak_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kb: u64 = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+
+    Some(kb * 1024)
+}
+
+pub(crate) fn run(cast_file_path: &Path) -> anyhow::Result<()> {
+    // Load the cast into memory so that reading it isn't part of the benchmark
+    let cast = std::fs::read(cast_file_path).context(format!(
+        "Could not read cast file: {}",
+        cast_file_path.to_string_lossy()
+    ))?;
+
+    // The backend is selected at compile time, so the only thing we can vary is the encoder
+    let backend = crate::frame_renderer::BACKEND_NAME;
+    let configurations = [
+        (format!("{}/gifski", backend), ConvertSettings::default()),
+        (
+            format!("{}/gifski-fast", backend),
+            ConvertSettings {
+                fast: true,
+                ..Default::default()
+            },
+        ),
+    ];
+
+    println!(
+        "{:<24} {:>8} {:>10} {:>12} {:>12}",
+        "Configuration", "Frames", "Time", "Frames/sec", "Peak Memory"
+    );
+    for (name, settings) in configurations.iter() {
+        let counter = FrameCounter::default();
+        let can_measure_memory = reset_peak_memory();
+
+        let start = Instant::now();
+        crate::convert_to_gif_with_progress(
+            Cursor::new(cast.clone()),
+            std::io::sink(),
+            counter.clone(),
+            settings.clone(),
+        )?;
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let frames = counter.0.load(SeqCst);
+        let peak_memory = if can_measure_memory {
+            peak_memory()
+                .map(|bytes| format!("{:.1} MB", bytes as f64 / 1024. / 1024.))
+                .unwrap_or_else(|| "unknown".into())
+        } else {
+            "unknown".into()
+        };
+
+        println!(
+            "{:<24} {:>8} {:>9.2}s {:>12.1} {:>12}",
+            name,
+            frames,
+            elapsed,
+            frames as f64 / elapsed,
+            peak_memory
+        );
+    }
+
+    Ok(())
+}
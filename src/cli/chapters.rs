@@ -0,0 +1,84 @@
+//! The `chapters` subcommand
+
+use anyhow::Context;
+use std::path::Path;
+
+use crate::ShellCommand;
+
+/// Format a time in seconds for `--trim`
+fn trim_time(time: f64) -> String {
+    format!("{}", (time * 100.).round() / 100.)
+}
+
+pub(crate) fn run(cast_file_path: &Path, json: bool) -> anyhow::Result<()> {
+    let cast_file = std::fs::File::open(cast_file_path).context(format!(
+        "Could not open cast file: {}",
+        cast_file_path.to_string_lossy()
+    ))?;
+    let commands = crate::find_commands(cast_file)?;
+
+    // Each chapter starts at the prompt of its command and ends at the prompt of the next one
+    let ends = commands
+        .iter()
+        .skip(1)
+        .map(|command| Some(command.prompt_time))
+        .chain(Some(None));
+    let chapters: Vec<(&ShellCommand, Option<f64>)> = commands.iter().zip(ends).collect();
+
+    if json {
+        let chapters: Vec<_> = chapters
+            .iter()
+            .map(|(command, end)| {
+                serde_json::json!({
+                    "start": command.prompt_time,
+                    "end": end,
+                    "time": command.time,
+                    "command": command.command,
+                    "exit_status": command.exit_status,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&chapters)?);
+        return Ok(());
+    }
+
+    if chapters.is_empty() {
+        println!(
+            "{} doesn't run any commands at a shell prompt",
+            cast_file_path.to_string_lossy()
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{} runs {} commands:\n",
+        cast_file_path.to_string_lossy(),
+        chapters.len()
+    );
+    let trims: Vec<String> = chapters
+        .iter()
+        .map(|(command, end)| {
+            format!(
+                "--trim {}-{}",
+                trim_time(command.prompt_time),
+                end.map(trim_time).unwrap_or_default()
+            )
+        })
+        .collect();
+    let width = trims.iter().map(|trim| trim.len()).max().unwrap_or(0);
+    for ((command, _), trim) in chapters.iter().zip(&trims) {
+        let status = match command.exit_status {
+            Some(status) if status != 0 => format!("  (exit status {})", status),
+            _ => String::new(),
+        };
+        println!(
+            "    {:width$}    {}{}",
+            trim,
+            command.command,
+            status,
+            width = width
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,135 @@
+//! Friendly diagnostics for errors in cast files
+
+use colored::*;
+
+use crate::cast_parser::AsciinemaError;
+
+/// The most characters of a line to show around the error
+const MAX_SNIPPET_WIDTH: usize = 80;
+
+/// Get a hint on how to fix a line of the cast that couldn't be parsed
+fn hint(line_number: usize, line: &str) -> Option<&'static str> {
+    let line = line.trim();
+    if line_number == 1 {
+        if line == "{" || line.contains("\"version\": 1") || line.contains("\"version\":1") {
+            Some(
+                "this looks like an asciicast v1 file, but only asciicast v2 files are supported. \
+                 See --help for supported versions.",
+            )
+        } else if !line.starts_with('{') {
+            Some(
+                "the first line of a cast file should be a JSON object with the recording's \
+                 metadata. Is this an asciinema .cast file?",
+            )
+        } else {
+            None
+        }
+    } else if !line.starts_with('[') {
+        Some("events should be JSON arrays such as `[1.5, \"o\", \"output\"]`")
+    } else {
+        None
+    }
+}
+
+/// Convert the 1-based byte column of an error in a line to a 1-based column of characters
+///
+/// Columns in the middle of a character point at the character.
+fn char_column(line: &str, byte_column: usize) -> usize {
+    let mut end = byte_column.saturating_sub(1).min(line.len());
+    while !line.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    line[..end].chars().count() + 1
+}
+
+/// Cut a long line down to the part around `column`, returning the snippet and the column of the
+/// error inside of it
+fn snippet(line: &str, column: usize) -> (String, usize) {
+    let chars: Vec<char> = line.chars().collect();
+    let column = column.max(1).min(chars.len() + 1);
+    if chars.len() <= MAX_SNIPPET_WIDTH {
+        return (chars.into_iter().collect(), column);
+    }
+
+    // Center the window on the error
+    let start = column
+        .saturating_sub(MAX_SNIPPET_WIDTH / 2)
+        .min(chars.len() - MAX_SNIPPET_WIDTH);
+    let end = start + MAX_SNIPPET_WIDTH;
+    let mut snippet = String::new();
+    let mut column = column - start;
+    if start > 0 {
+        snippet.push_str("...");
+        column += 3;
+    }
+    snippet.extend(&chars[start..end]);
+    if end < chars.len() {
+        snippet.push_str("...");
+    }
+
+    (snippet, column)
+}
+
+/// Render a diagnostic pointing to the part of the cast file that caused an error, if the error
+/// came from a line of the cast
+///
+/// Returns the innermost error about the line along with the diagnostic, because the errors that
+/// wrap it only repeat it.
+pub(crate) fn render(error: &anyhow::Error) -> Option<(&AsciinemaError, String)> {
+    let (cause, line_number, line, column) = error
+        .chain()
+        .filter_map(|cause| match cause.downcast_ref::<AsciinemaError>()? {
+            cause @ AsciinemaError::InvalidLine {
+                line_number,
+                line,
+                column,
+                ..
+            } => Some((cause, *line_number, line, *column)),
+            _ => None,
+        })
+        .last()?;
+
+    let gutter = " ".repeat(line_number.to_string().len());
+    let bar = "|".blue().bold();
+    let mut out = String::new();
+    out.push_str(&format!("{} {}\n", gutter, bar));
+    match column {
+        Some(column) => {
+            let (snippet, column) = snippet(line, char_column(line, column));
+            out.push_str(&format!(
+                "{} {} {}\n",
+                line_number.to_string().blue().bold(),
+                bar,
+                snippet
+            ));
+            out.push_str(&format!(
+                "{} {} {}{}\n",
+                gutter,
+                bar,
+                " ".repeat(column - 1),
+                "^".red().bold()
+            ));
+        }
+        None => {
+            let (snippet, _) = snippet(line, 1);
+            out.push_str(&format!(
+                "{} {} {}\n",
+                line_number.to_string().blue().bold(),
+                bar,
+                snippet
+            ));
+        }
+    }
+    if let Some(hint) = hint(line_number, line) {
+        out.push_str(&format!(
+            "{} {} {} {}\n",
+            gutter,
+            "=".blue().bold(),
+            "hint:".bold(),
+            hint
+        ));
+    }
+
+    Some((cause, out))
+}
@@ -0,0 +1,105 @@
+//! The `--dry-run` estimation mode
+
+use super::CastInput;
+use crate::cast_parser::{AsciinemaError, TerminalFrameIter};
+use crate::min_delay::MinDelayFrames;
+use crate::types::{ScreenSnapshot, TerminalFrame};
+use crate::{ConvertSettings, CropSettings};
+
+/// The rough number of bytes that each changed pixel adds to the gif
+///
+/// This is a rough heuristic, not a measured figure. How well the changes compress depends on the
+/// content of the screen, so real gifs can be a few times smaller or larger than the estimate.
+const BYTES_PER_CHANGED_PIXEL: f64 = 0.18;
+
+/// Count the cells inside of the crop that differ between two screens
+fn changed_cells(
+    previous: Option<&ScreenSnapshot>,
+    current: &ScreenSnapshot,
+    crop: Option<CropSettings>,
+) -> usize {
+    let (rows, cols) = current.size();
+    let (top, left, height, width) = match crop {
+        Some(crop) => (crop.top, crop.left, crop.height, crop.width),
+        None => (0, 0, rows, cols),
+    };
+    let mut changed = 0;
+    for row in top..(top + height) {
+        for col in left..(left + width) {
+            let cell = current.cell(row, col);
+            if previous.map(|p| p.cell(row, col)) != Some(cell) {
+                changed += 1;
+            }
+        }
+    }
+
+    changed
+}
+
+/// Format a byte count for display
+pub(super) fn format_bytes(bytes: f64) -> String {
+    if bytes >= 1024. * 1024. {
+        format!("{:.1} MB", bytes / 1024. / 1024.)
+    } else {
+        format!("{:.1} KB", bytes / 1024.)
+    }
+}
+
+/// Parse the casts and report what rendering them would produce, without rendering anything
+pub(crate) fn run(input: CastInput, settings: &ConvertSettings) -> anyhow::Result<()> {
+    match input {
+        CastInput::Single { file, .. } => {
+            let frames = TerminalFrameIter::new(file)?.with_settings(settings);
+            let size = frames.size();
+            report(frames, size, settings)
+        }
+        CastInput::SideBySide(left, right) => {
+            let (frames, size, _) = crate::side_by_side_frames(left, right, settings)?;
+            report(frames, size, settings)
+        }
+    }
+}
+
+/// Report what rendering frames of a terminal with the given (rows, cols) size would produce
+fn report<I>(frames: I, (rows, cols): (u16, u16), settings: &ConvertSettings) -> anyhow::Result<()>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    let (width, height) = crate::frame_renderer::frame_size(
+        rows,
+        cols,
+        settings.crop,
+        settings.shows_title_bar(),
+        settings.aspect,
+    );
+    let (cell_width, cell_height) = crate::frame_renderer::cell_size();
+
+    let mut frame_count = 0u64;
+    let mut duration = 0f64;
+    let mut changed_pixels = 0usize;
+    let mut previous: Option<ScreenSnapshot> = None;
+    for frame in MinDelayFrames::new(frames, settings.min_frame_delay()) {
+        let frame = frame?;
+        frame_count += 1;
+        duration = frame.time / 100.;
+        changed_pixels += changed_cells(previous.as_ref(), &frame.screen, settings.crop)
+            * cell_width
+            * cell_height;
+        previous = Some(frame.screen);
+    }
+
+    // The last frame is held on screen before the gif loops
+    if frame_count > 0 {
+        duration += settings.hold_last();
+    }
+
+    println!("Frames:         {}", frame_count);
+    println!("Duration:       {:.2}s", duration);
+    println!("Dimensions:     {}x{} px", width, height);
+    println!(
+        "Estimated size: ~{}",
+        format_bytes(changed_pixels as f64 * BYTES_PER_CHANGED_PIXEL)
+    );
+
+    Ok(())
+}
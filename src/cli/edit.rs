@@ -0,0 +1,354 @@
+//! The `edit` subcommand
+//!
+//! An interactive editor for picking the part of a cast to render and the region of the terminal
+//! to crop it to.
+
+use anyhow::{format_err, Context};
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    terminal,
+};
+
+use std::io::Write;
+use std::path::Path;
+
+use super::atomic_file::AtomicFile;
+use super::preview::draw_screen;
+use super::{convert_gif_with_progress_bars, terminal_size, CastInput, RenderArgs};
+use crate::cast_parser::TerminalFrameIter;
+use crate::types::{CropSettings, TerminalFrame, TrimSettings};
+
+/// The key bindings, shown below the screen
+const HELP: &str = "←/→ frame  PgUp/PgDn ±10  Home/End  i/o set in/out  c crop mode  \
+                    r render  s print settings  q quit";
+/// The key bindings in crop mode, shown below the screen
+const CROP_HELP: &str = "arrows move crop  shift+arrows resize crop  c done";
+
+/// Puts the terminal into raw mode on the alternate screen, and restores it when dropped
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter() -> std::io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        crossterm::execute!(
+            std::io::stdout(),
+            terminal::EnterAlternateScreen,
+            cursor::Hide
+        )?;
+
+        Ok(RawTerminal)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        crossterm::execute!(
+            std::io::stdout(),
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        )
+        .ok();
+        terminal::disable_raw_mode().ok();
+    }
+}
+
+/// What to do when the editor exits
+enum EditorResult {
+    /// Render the cast with the picked settings
+    Render,
+    /// Print the picked settings as command line arguments
+    PrintSettings,
+    /// Do nothing
+    Quit,
+}
+
+/// The state of the editor
+struct Editor {
+    /// All of the frames in the cast
+    frames: Vec<TerminalFrame>,
+    /// The index of the frame being shown
+    current: usize,
+    /// The index of the first frame to render
+    in_point: usize,
+    /// The index of the last frame to render
+    out_point: usize,
+    /// The region of the terminal to render
+    crop: CropSettings,
+    /// Whether or not the arrow keys edit the crop
+    crop_mode: bool,
+}
+
+impl Editor {
+    /// The (rows, cols) size of the terminal
+    fn size(&self) -> (u16, u16) {
+        self.frames[0].screen.size()
+    }
+
+    /// Get the time of a frame in seconds
+    fn time(&self, index: usize) -> f64 {
+        self.frames[index].time / 100.
+    }
+
+    /// Get the trim for the in and out points
+    fn trim(&self) -> TrimSettings {
+        TrimSettings {
+            start: if self.in_point > 0 {
+                Some(self.time(self.in_point))
+            } else {
+                None
+            },
+            end: if self.out_point < self.frames.len() - 1 {
+                Some(self.time(self.out_point))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Get the crop, or `None` if it covers the whole terminal
+    fn crop(&self) -> Option<CropSettings> {
+        let (rows, cols) = self.size();
+        if self.crop.top == 0
+            && self.crop.left == 0
+            && self.crop.width == cols
+            && self.crop.height == rows
+        {
+            None
+        } else {
+            Some(self.crop)
+        }
+    }
+
+    /// Move to the frame `offset` frames away from the current one
+    fn seek(&mut self, offset: isize) {
+        let last = self.frames.len() as isize - 1;
+        self.current = (self.current as isize + offset).max(0).min(last) as usize;
+    }
+
+    /// Move the crop by the given number of cells, keeping it inside of the terminal
+    fn move_crop(&mut self, rows: i32, cols: i32) {
+        let (term_rows, term_cols) = self.size();
+        let crop = &mut self.crop;
+        crop.top = (crop.top as i32 + rows)
+            .max(0)
+            .min((term_rows - crop.height) as i32) as u16;
+        crop.left = (crop.left as i32 + cols)
+            .max(0)
+            .min((term_cols - crop.width) as i32) as u16;
+    }
+
+    /// Resize the crop by the given number of cells, keeping it inside of the terminal
+    fn resize_crop(&mut self, rows: i32, cols: i32) {
+        let (term_rows, term_cols) = self.size();
+        let crop = &mut self.crop;
+        crop.height = (crop.height as i32 + rows)
+            .max(1)
+            .min((term_rows - crop.top) as i32) as u16;
+        crop.width = (crop.width as i32 + cols)
+            .max(1)
+            .min((term_cols - crop.left) as i32) as u16;
+    }
+
+    /// Draw the current frame and the status lines
+    fn draw<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        draw_screen(out, &self.frames[self.current].screen, Some(self.crop))?;
+
+        let (rows, _) = self.size();
+        let crop = match self.crop() {
+            Some(crop) => crop.to_string(),
+            None => "none".into(),
+        };
+        write!(
+            out,
+            "\x1b[{};1H\x1b[2K{:>8.2}s  frame {}/{}  in {:.2}s  out {:.2}s  crop {}",
+            rows + 2,
+            self.time(self.current),
+            self.current + 1,
+            self.frames.len(),
+            self.time(self.in_point),
+            self.time(self.out_point),
+            crop,
+        )?;
+        write!(
+            out,
+            "\x1b[{};1H\x1b[2K\x1b[2m{}\x1b[0m",
+            rows + 3,
+            if self.crop_mode { CROP_HELP } else { HELP }
+        )?;
+
+        out.flush()
+    }
+
+    /// Handle a key press, returning the result if the editor should exit
+    fn handle_key(&mut self, key: KeyEvent) -> Option<EditorResult> {
+        let shift = key.modifiers.contains(KeyModifiers::SHIFT);
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Some(EditorResult::Quit)
+            }
+
+            // Crop mode
+            KeyCode::Left if self.crop_mode && shift => self.resize_crop(0, -1),
+            KeyCode::Right if self.crop_mode && shift => self.resize_crop(0, 1),
+            KeyCode::Up if self.crop_mode && shift => self.resize_crop(-1, 0),
+            KeyCode::Down if self.crop_mode && shift => self.resize_crop(1, 0),
+            KeyCode::Left if self.crop_mode => self.move_crop(0, -1),
+            KeyCode::Right if self.crop_mode => self.move_crop(0, 1),
+            KeyCode::Up if self.crop_mode => self.move_crop(-1, 0),
+            KeyCode::Down if self.crop_mode => self.move_crop(1, 0),
+            KeyCode::Char('c') | KeyCode::Enter if self.crop_mode => self.crop_mode = false,
+
+            // Scrubbing
+            KeyCode::Left => self.seek(-1),
+            KeyCode::Right => self.seek(1),
+            KeyCode::PageUp => self.seek(-10),
+            KeyCode::PageDown => self.seek(10),
+            KeyCode::Home => self.current = 0,
+            KeyCode::End => self.current = self.frames.len() - 1,
+
+            // Settings
+            KeyCode::Char('i') => {
+                self.in_point = self.current;
+                self.out_point = self.out_point.max(self.current);
+            }
+            KeyCode::Char('o') => {
+                self.out_point = self.current;
+                self.in_point = self.in_point.min(self.current);
+            }
+            KeyCode::Char('c') => self.crop_mode = true,
+
+            // Exiting
+            KeyCode::Char('r') => return Some(EditorResult::Render),
+            KeyCode::Char('s') => return Some(EditorResult::PrintSettings),
+            KeyCode::Char('q') | KeyCode::Esc => return Some(EditorResult::Quit),
+            _ => (),
+        }
+
+        None
+    }
+
+    /// Run the editor until the user exits it
+    fn run(&mut self) -> anyhow::Result<EditorResult> {
+        let _raw_terminal = RawTerminal::enter().context("Could not set up the terminal")?;
+        let stdout = std::io::stdout();
+        let mut out = stdout.lock();
+
+        loop {
+            self.draw(&mut out)?;
+
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Release {
+                    continue;
+                }
+                if let Some(result) = self.handle_key(key) {
+                    break Ok(result);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn run(
+    cast_file_path: &Path,
+    out_file_path: Option<&Path>,
+    force: bool,
+    allow_secrets: bool,
+    render: &RenderArgs,
+) -> anyhow::Result<()> {
+    // Make sure the out path doesn't exist before spending time editing
+    if let Some(out_file_path) = out_file_path {
+        if out_file_path.exists() && !force {
+            return Err(super::exit_code::ArgumentError(format_err!(
+                "Output file already exists: {}",
+                out_file_path.to_string_lossy()
+            ))
+            .into());
+        }
+    }
+
+    let (rows, cols) = terminal_size(cast_file_path)?;
+    let mut settings = render
+        .settings((rows, cols))
+        .map_err(super::exit_code::ArgumentError)?;
+
+    // Load all of the frames so that we can scrub through them
+    let cast_file = std::fs::File::open(cast_file_path).context(format!(
+        "Could not open cast file: {}",
+        cast_file_path.to_string_lossy()
+    ))?;
+    let frames = TerminalFrameIter::new(cast_file)?.collect::<Result<Vec<_>, _>>()?;
+    if frames.is_empty() {
+        return Err(format_err!("The cast doesn't have any frames to edit"));
+    }
+
+    // Start with the settings from the command line
+    let frame_at = |time: Option<f64>, default: usize| {
+        time.map(|time| {
+            frames
+                .iter()
+                .rposition(|f| f.time / 100. <= time)
+                .unwrap_or(0)
+        })
+        .unwrap_or(default)
+    };
+    let mut editor = Editor {
+        in_point: frame_at(settings.trim.start, 0),
+        out_point: frame_at(settings.trim.end, frames.len() - 1),
+        current: 0,
+        crop: settings.crop.unwrap_or(CropSettings {
+            top: 0,
+            left: 0,
+            width: cols,
+            height: rows,
+        }),
+        crop_mode: false,
+        frames,
+    };
+
+    let result = editor.run()?;
+    settings.crop = editor.crop();
+    settings.trim = editor.trim();
+
+    match result {
+        EditorResult::Render => {
+            let out_file_path = out_file_path.ok_or_else(|| {
+                super::exit_code::ArgumentError(format_err!(
+                    "An <out_file> is required to render from the editor"
+                ))
+            })?;
+            let open_cast = || {
+                std::fs::File::open(cast_file_path).context(format!(
+                    "Could not open cast file: {}",
+                    cast_file_path.to_string_lossy()
+                ))
+            };
+            super::check_secrets(open_cast()?, &settings, allow_secrets)?;
+
+            let out_file = AtomicFile::create(out_file_path).context(format!(
+                "Could not open output file: {}",
+                out_file_path.to_string_lossy()
+            ))?;
+            let cast_file = open_cast()?;
+
+            let input = CastInput::Single {
+                file: cast_file,
+                mmap: false,
+            };
+            convert_gif_with_progress_bars(input, out_file, settings, None)?;
+        }
+        EditorResult::PrintSettings => {
+            let mut settings_args = Vec::new();
+            if let Some(crop) = settings.crop {
+                settings_args.push(format!("--crop {}", crop));
+            }
+            if settings.trim != TrimSettings::default() {
+                settings_args.push(format!("--trim={}", settings.trim));
+            }
+            println!("{}", settings_args.join(" "));
+        }
+        EditorResult::Quit => (),
+    }
+
+    Ok(())
+}
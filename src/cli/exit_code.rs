@@ -0,0 +1,70 @@
+//! Process exit codes
+//!
+//! These are documented in the README so that scripts can tell failures apart. Don't change the
+//! existing values.
+
+use crate::cast_parser::AsciinemaError;
+
+/// The program encountered an unexpected internal error
+pub(crate) const INTERNAL_ERROR: i32 = 1;
+/// The command line arguments were invalid, or an output file that they name already exists
+/// without `--force`
+pub(crate) const INVALID_ARGUMENTS: i32 = 2;
+/// The cast file could not be parsed
+pub(crate) const PARSE_ERROR: i32 = 3;
+/// A file could not be read or written
+pub(crate) const IO_ERROR: i32 = 4;
+/// The cast could not be rendered
+pub(crate) const RENDER_ERROR: i32 = 5;
+
+/// An error caused by invalid command line arguments
+///
+/// Output files that already exist count as invalid arguments, because they are refused before
+/// anything is read or written.
+#[derive(Debug)]
+pub(crate) struct ArgumentError(pub anyhow::Error);
+
+impl std::fmt::Display for ArgumentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for ArgumentError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+/// Get the exit code for an error returned by the CLI
+pub(crate) fn for_error(error: &anyhow::Error) -> i32 {
+    for cause in error.chain() {
+        if cause.is::<ArgumentError>() {
+            return INVALID_ARGUMENTS;
+        }
+        if let Some(e) = cause.downcast_ref::<AsciinemaError>() {
+            return match e {
+                AsciinemaError::IoError(_) => IO_ERROR,
+                _ => PARSE_ERROR,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<gifski::Error>() {
+            return match e {
+                gifski::Error::Io(_) => IO_ERROR,
+                _ => RENDER_ERROR,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<crate::Error>() {
+            match e {
+                // Check the source of the error
+                crate::Error::AsciinemaError(_) | crate::Error::GifError(_) => continue,
+                crate::Error::Generic(_) => return RENDER_ERROR,
+            }
+        }
+        if cause.is::<std::io::Error>() {
+            return IO_ERROR;
+        }
+    }
+
+    INTERNAL_ERROR
+}
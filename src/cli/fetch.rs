@@ -0,0 +1,192 @@
+//! Downloading casts from asciinema.org
+//!
+//! Used by the `fetch` subcommand, which saves a recording to a file, and by `--from-asciinema`,
+//! which renders a recording without saving it.
+
+use anyhow::{format_err, Context};
+use base64::Engine;
+use clap::Args;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::atomic_file::AtomicFile;
+use super::exit_code::ArgumentError;
+use super::temp_file::TempFile;
+
+/// The arguments that configure the asciinema server to download recordings from
+#[derive(Debug, Clone, Args)]
+pub(super) struct AsciinemaArgs {
+    /// The asciinema server to download recordings from
+    #[arg(
+        long,
+        env = "CAST2GIF_ASCIINEMA_URL",
+        value_name = "URL",
+        default_value = "https://asciinema.org"
+    )]
+    asciinema_url: String,
+
+    /// The API token, or install ID, to download private recordings with. It is only sent to the
+    /// --asciinema-url server, and not to the servers of recordings given by URL on other servers.
+    #[arg(
+        long,
+        env = "CAST2GIF_ASCIINEMA_TOKEN",
+        value_name = "TOKEN",
+        hide_env_values = true
+    )]
+    asciinema_token: Option<String>,
+}
+
+/// A recording on an asciinema server
+struct Recording {
+    /// The URL of the server
+    server: String,
+    /// The ID of the recording
+    id: String,
+}
+
+impl Recording {
+    /// Get the recording from a recording ID or the URL of a recording
+    ///
+    /// Recordings given by ID are on the server given with `--asciinema-url`.
+    fn parse(recording: &str, args: &AsciinemaArgs) -> anyhow::Result<Self> {
+        let (server, id) = match recording.find("/a/") {
+            Some(start) => (&recording[..start], &recording[start + 3..]),
+            None => (args.asciinema_url.as_str(), recording),
+        };
+        // Take the ID out of URLs like `https://asciinema.org/a/123.cast?t=5`
+        let id = id.split(&['?', '#', '/'][..]).next().unwrap_or("");
+        let id = id
+            .strip_suffix(".cast")
+            .or_else(|| id.strip_suffix(".json"))
+            .unwrap_or(id);
+
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format_err!(
+                "`{}` is not an asciinema recording ID or URL, such as `335480` or \
+                 `https://asciinema.org/a/335480`",
+                recording
+            ));
+        }
+
+        Ok(Recording {
+            server: server.trim_end_matches('/').to_string(),
+            id: id.to_string(),
+        })
+    }
+
+    /// Get the token to download the recording with, if there is one and the recording is on the
+    /// `--asciinema-url` server
+    ///
+    /// Recordings given by URL can be on any server, which mustn't be handed the token for another
+    /// one.
+    fn token<'a>(&self, args: &'a AsciinemaArgs) -> Option<&'a str> {
+        let token = args.asciinema_token.as_deref()?;
+        let server = args.asciinema_url.trim_end_matches('/');
+        if !self.server.eq_ignore_ascii_case(server) {
+            log::warn!(
+                "Not sending the --asciinema-token to {}, which isn't the --asciinema-url server",
+                self.server
+            );
+            return None;
+        }
+
+        Some(token)
+    }
+
+    /// Download the cast file of the recording to a writer
+    fn download(&self, args: &AsciinemaArgs, writer: &mut impl Write) -> anyhow::Result<()> {
+        let url = format!("{}/a/{}.cast", self.server, self.id);
+        log::info!("Downloading {}", url);
+
+        let mut request = ureq::get(&url).set(
+            "User-Agent",
+            concat!("cast2gif/", env!("CARGO_PKG_VERSION")),
+        );
+        // Authenticate like the asciinema CLI does, with the token as the password
+        if let Some(token) = self.token(args) {
+            let credentials =
+                base64::engine::general_purpose::STANDARD.encode(format!(":{}", token));
+            request = request.set("Authorization", &format!("Basic {}", credentials));
+        }
+
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(404, _)) => {
+                return Err(format_err!(
+                    "Recording {} was not found on {}. Private recordings can be downloaded with \
+                     --asciinema-token.",
+                    self.id,
+                    self.server
+                ))
+            }
+            Err(ureq::Error::Status(status @ 401, _))
+            | Err(ureq::Error::Status(status @ 403, _)) => {
+                return Err(format_err!(
+                    "Not allowed to download recording {} (HTTP {}). Check the --asciinema-token.",
+                    self.id,
+                    status
+                ))
+            }
+            Err(e) => return Err(e).context(format!("Could not download {}", url)),
+        };
+
+        std::io::copy(&mut response.into_reader(), writer)
+            .context(format!("Could not download {}", url))?;
+
+        Ok(())
+    }
+}
+
+/// Download a recording to a file
+///
+/// The file defaults to `<id>.cast` in the current directory.
+pub(crate) fn run(
+    id: &str,
+    file: Option<&Path>,
+    force: bool,
+    args: &AsciinemaArgs,
+) -> anyhow::Result<()> {
+    let recording = Recording::parse(id, args).map_err(ArgumentError)?;
+    let path = file
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from(format!("{}.cast", recording.id)));
+
+    if path.exists() && !force {
+        return Err(ArgumentError(format_err!(
+            "Output file already exists: {}",
+            path.to_string_lossy()
+        ))
+        .into());
+    }
+
+    let out_file = AtomicFile::create(&path).context(format!(
+        "Could not open output file: {}",
+        path.to_string_lossy()
+    ))?;
+    recording.download(args, &mut out_file.file())?;
+    out_file.commit()?;
+
+    log::info!(
+        "Saved recording {} to {}",
+        recording.id,
+        path.to_string_lossy()
+    );
+
+    Ok(())
+}
+
+/// Get the ID of a recording given by ID or URL
+pub(super) fn recording_id(id: &str, args: &AsciinemaArgs) -> anyhow::Result<String> {
+    Ok(Recording::parse(id, args).map_err(ArgumentError)?.id)
+}
+
+/// Download a recording given by ID or URL to a temporary cast file
+pub(super) fn download_temp(id: &str, args: &AsciinemaArgs) -> anyhow::Result<TempFile> {
+    let recording = Recording::parse(id, args).map_err(ArgumentError)?;
+    let cast = TempFile::create(&format!("{}.cast", recording.id))
+        .context("Could not create a temporary file to download the recording to")?;
+    recording.download(args, &mut cast.file())?;
+
+    Ok(cast)
+}
@@ -0,0 +1,117 @@
+//! The `inspect` subcommand, and the record of what a gif was rendered from that it reads
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::RenderArgs;
+
+/// Settings that are left out of the record, because they don't change how the gif looks or, for
+/// `redact`, because they would give away the text that they hide
+const UNRECORDED_SETTINGS: &[&str] = &["max_memory", "threads", "spill_dir", "cache_dir", "redact"];
+
+/// What a gif was rendered from, which is embedded in a comment of the gif
+#[derive(Debug, Serialize, Deserialize)]
+struct RenderRecord {
+    /// The version of cast2gif that rendered the gif
+    cast2gif: String,
+    /// The hex BLAKE2b hash of the file that the gif was rendered from
+    cast_hash: String,
+    /// The render settings that were given, as setting name and command line value pairs
+    settings: BTreeMap<String, String>,
+}
+
+/// Hash a cast file for the record of a gif rendered from it
+fn cast_hash(cast_file_path: &Path) -> anyhow::Result<String> {
+    let mut cast_file = std::fs::File::open(cast_file_path).context(format!(
+        "Could not open cast file: {}",
+        cast_file_path.to_string_lossy()
+    ))?;
+    let mut state = blake2b_simd::Params::new().hash_length(32).to_state();
+    std::io::copy(&mut cast_file, &mut state).context(format!(
+        "Could not read cast file: {}",
+        cast_file_path.to_string_lossy()
+    ))?;
+
+    Ok(state.finalize().to_hex().to_string())
+}
+
+/// Get the comment that records what a gif is rendered from, for the `inspect` subcommand to read
+pub(super) fn render_record(cast_file_path: &Path, render: &RenderArgs) -> anyhow::Result<String> {
+    let record = RenderRecord {
+        cast2gif: env!("CARGO_PKG_VERSION").into(),
+        cast_hash: cast_hash(cast_file_path)?,
+        settings: render
+            .setting_values()
+            .into_iter()
+            .filter(|(name, _)| !UNRECORDED_SETTINGS.contains(&name.as_str()))
+            .collect(),
+    };
+
+    Ok(serde_json::to_string(&record)?)
+}
+
+pub(crate) fn run(gif_file_path: &Path, json: bool) -> anyhow::Result<()> {
+    let gif_file = std::fs::File::open(gif_file_path).context(format!(
+        "Could not open gif file: {}",
+        gif_file_path.to_string_lossy()
+    ))?;
+    let info = crate::read_gif_info(gif_file)?;
+    let record = info
+        .comments
+        .iter()
+        .find_map(|comment| serde_json::from_str::<RenderRecord>(comment).ok());
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&serde_json::json!({
+                "width": info.width,
+                "height": info.height,
+                "frames": info.delays.len(),
+                "duration": info.duration(),
+                "delays": info
+                    .delays
+                    .iter()
+                    .map(|&delay| f64::from(delay) / 100.)
+                    .collect::<Vec<_>>(),
+                "render": record,
+            }))?
+        );
+        return Ok(());
+    }
+
+    println!(
+        "{}: {}x{}, {} frames, {:.2}s",
+        gif_file_path.to_string_lossy(),
+        info.width,
+        info.height,
+        info.delays.len(),
+        info.duration()
+    );
+    match &record {
+        Some(record) => {
+            println!("\nRendered by cast2gif {}", record.cast2gif);
+            println!("Cast hash: {}", record.cast_hash);
+            println!("\nSettings:");
+            for (name, value) in &record.settings {
+                let flag = format!("--{}", name.replace('_', "-"));
+                if value.is_empty() {
+                    println!("    {}", flag);
+                }
+                for value in value.lines() {
+                    println!("    {}={}", flag, value);
+                }
+            }
+        }
+        None => println!("\nThe gif doesn't record the settings that it was rendered with"),
+    }
+
+    println!("\nFrame delays:");
+    for (index, &delay) in info.delays.iter().enumerate() {
+        println!("    {:>6}    {:.2}s", index, f64::from(delay) / 100.);
+    }
+
+    Ok(())
+}
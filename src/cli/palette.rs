@@ -0,0 +1,232 @@
+//! The `palette` subcommand
+
+use anyhow::Context;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use crate::cast_parser::TerminalFrameIter;
+use crate::color_profile::Rgb;
+use crate::frame_renderer::parse_color;
+use crate::types::ScreenSnapshot;
+
+/// The most colors that a gif frame can have
+const GIF_MAX_COLORS: usize = 256;
+
+/// The colors that the default text and background colors are drawn in
+const DEFAULT_FG_RGB: Rgb = (255, 255, 255);
+const DEFAULT_BG_RGB: Rgb = (0, 0, 0);
+
+/// The names of the 16 standard ANSI colors
+const ANSI_NAMES: [&str; 16] = [
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "bright black",
+    "bright red",
+    "bright green",
+    "bright yellow",
+    "bright blue",
+    "bright magenta",
+    "bright cyan",
+    "bright white",
+];
+
+/// A color that cells are drawn in
+///
+/// The default color is a different color for text and for backgrounds, so they are kept apart.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum CellColor {
+    DefaultText,
+    DefaultBackground,
+    Idx(u8),
+    Rgb(u8, u8, u8),
+}
+
+impl CellColor {
+    /// Get the color of a cell's text or background
+    fn new(color: vt100::Color, background: bool) -> Self {
+        match color {
+            vt100::Color::Default if background => CellColor::DefaultBackground,
+            vt100::Color::Default => CellColor::DefaultText,
+            vt100::Color::Idx(i) => CellColor::Idx(i),
+            vt100::Color::Rgb(r, g, b) => CellColor::Rgb(r, g, b),
+        }
+    }
+
+    /// Get the (r, g, b) color that the color is drawn in
+    fn rgb(self) -> Rgb {
+        match self {
+            CellColor::DefaultText => DEFAULT_FG_RGB,
+            CellColor::DefaultBackground => DEFAULT_BG_RGB,
+            CellColor::Idx(i) => parse_color(vt100::Color::Idx(i)).unwrap_or(DEFAULT_FG_RGB),
+            CellColor::Rgb(r, g, b) => (r, g, b),
+        }
+    }
+
+    /// Get a description of the color, like `color 1 (red)`
+    fn name(self) -> String {
+        match self {
+            CellColor::DefaultText => "default text".into(),
+            CellColor::DefaultBackground => "default background".into(),
+            CellColor::Idx(i) if (i as usize) < ANSI_NAMES.len() => {
+                format!("color {} ({})", i, ANSI_NAMES[i as usize])
+            }
+            CellColor::Idx(i) => format!("color {}", i),
+            CellColor::Rgb(..) => "true color".into(),
+        }
+    }
+}
+
+/// How much a color is used
+#[derive(Debug, Default)]
+struct Usage {
+    /// The number of cells with text in the color, over all frames
+    text_cells: u64,
+    /// The number of cells with a background in the color, over all frames
+    background_cells: u64,
+    /// The number of frames that the color is in
+    frames: u64,
+}
+
+/// The colors used by the frames of a cast
+#[derive(Debug, Default)]
+struct Palette {
+    /// How much each color is used
+    usage: HashMap<CellColor, Usage>,
+    /// The (text, background) pairs of RGB colors that text is drawn in
+    pairs: HashSet<(Rgb, Rgb)>,
+    /// The number of cells with text, over all frames
+    text_cells: u64,
+    /// The number of cells, over all frames
+    cells: u64,
+    /// The most RGB colors in one frame
+    max_frame_colors: usize,
+    /// The number of frames
+    frames: u64,
+}
+
+impl Palette {
+    /// Add the colors of a frame's screen
+    fn add_screen(&mut self, screen: &ScreenSnapshot) {
+        let mut frame_colors = HashSet::new();
+        let (rows, cols) = screen.size();
+        for row in 0..rows {
+            for col in 0..cols {
+                let cell = screen.cell(row, col).expect("Error indexing cell");
+                let mut text = CellColor::new(cell.fgcolor, false);
+                let mut background = CellColor::new(cell.bgcolor, true);
+                // The cursor is drawn by swapping the colors of the cell under it
+                if screen.is_cursor_at(row, col) {
+                    std::mem::swap(&mut text, &mut background);
+                }
+
+                self.cells += 1;
+                self.usage.entry(background).or_default().background_cells += 1;
+                frame_colors.insert(background);
+
+                let has_text = cell
+                    .contents
+                    .is_some_and(|contents| contents.chars().any(|c| !c.is_whitespace()));
+                if has_text {
+                    self.text_cells += 1;
+                    self.usage.entry(text).or_default().text_cells += 1;
+                    self.pairs.insert((text.rgb(), background.rgb()));
+                    frame_colors.insert(text);
+                }
+            }
+        }
+
+        for color in &frame_colors {
+            self.usage.entry(*color).or_default().frames += 1;
+        }
+        let frame_rgb = frame_colors
+            .iter()
+            .map(|color| color.rgb())
+            .collect::<HashSet<_>>();
+        self.max_frame_colors = self.max_frame_colors.max(frame_rgb.len());
+        self.frames += 1;
+    }
+
+    /// Print the colors from the most used to the least used, and a summary of them
+    fn print(&self, cast_file_path: &Path) {
+        println!("Colors in {}:", cast_file_path.to_string_lossy());
+        println!();
+        println!(
+            "{:<24} {:<8} {:>8} {:>11} {:>8}",
+            "Color", "RGB", "Text", "Background", "Frames"
+        );
+
+        let mut colors = self.usage.iter().collect::<Vec<_>>();
+        colors.sort_by_key(|(_, usage)| Reverse(usage.text_cells + usage.background_cells));
+        for (color, usage) in &colors {
+            let (r, g, b) = color.rgb();
+            println!(
+                "{:<24} #{:02x}{:02x}{:02x} {:>8} {:>11} {:>8}",
+                color.name(),
+                r,
+                g,
+                b,
+                percentage(usage.text_cells, self.text_cells),
+                percentage(usage.background_cells, self.cells),
+                usage.frames
+            );
+        }
+
+        let rgb_colors = colors
+            .iter()
+            .map(|(color, _)| color.rgb())
+            .collect::<HashSet<_>>();
+        println!();
+        println!("Frames:           {}", self.frames);
+        println!("Terminal colors:  {}", colors.len());
+        println!(
+            "RGB colors:       {} (up to {} in one frame)",
+            rgb_colors.len(),
+            self.max_frame_colors
+        );
+        println!("Text color pairs: {}", self.pairs.len());
+        println!();
+        println!(
+            "The edges of text blend its color with its background into more shades, and each gif \
+             frame can have at most {} colors, so fewer text color pairs make smaller and sharper \
+             gifs.",
+            GIF_MAX_COLORS
+        );
+    }
+}
+
+/// Format a count as a percentage of a total, or `-` if it is zero
+fn percentage(count: u64, total: u64) -> String {
+    if count == 0 {
+        "-".into()
+    } else {
+        format!("{:.1}%", count as f64 / total as f64 * 100.)
+    }
+}
+
+/// Report the colors that a cast uses and how much it uses them
+pub(crate) fn run(cast_file_path: &Path) -> anyhow::Result<()> {
+    let cast_file = std::fs::File::open(cast_file_path).context(format!(
+        "Could not open cast file: {}",
+        cast_file_path.to_string_lossy()
+    ))?;
+
+    let mut palette = Palette::default();
+    for frame in TerminalFrameIter::new(cast_file)? {
+        palette.add_screen(&frame?.screen);
+    }
+    if palette.frames == 0 {
+        println!("{} has no frames", cast_file_path.to_string_lossy());
+        return Ok(());
+    }
+
+    palette.print(cast_file_path);
+
+    Ok(())
+}
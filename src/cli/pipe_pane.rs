@@ -0,0 +1,217 @@
+//! Converting tmux `pipe-pane` logs to casts
+//!
+//! `tmux pipe-pane -o 'cat >> session.log'` saves the raw output of a pane, escape sequences and
+//! all, but without any timing. The log is often piped through `ts` from moreutils first, which
+//! puts a timestamp at the start of every line, such as `ts '%.s'` or the default
+//! `Oct 17 12:34:56`. Logs with timestamps play back with their recorded timing, and logs without
+//! them show one line every [`UNTIMED_LINE_DELAY`] seconds.
+
+use anyhow::{format_err, Context};
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::temp_file::TempFile;
+
+/// The time between lines of logs without timestamps, in seconds
+const UNTIMED_LINE_DELAY: f64 = 0.1;
+
+/// The seconds in a day, used when the time of day in timestamps wraps around at midnight
+const SECONDS_PER_DAY: f64 = 24. * 60. * 60.;
+
+/// The size of a terminal given on the command line as `COLSxROWS`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TerminalSize {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+impl Default for TerminalSize {
+    fn default() -> Self {
+        // The size that tmux gives detached sessions
+        TerminalSize { cols: 80, rows: 24 }
+    }
+}
+
+impl FromStr for TerminalSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, 'x');
+        let mut side = |name: &str| -> anyhow::Result<u16> {
+            let side: u16 = split
+                .next()
+                .unwrap_or("")
+                .trim()
+                .parse()
+                .map_err(|_| format_err!("Expected a size like `80x24`, got `{}`", s))?;
+            if side == 0 {
+                return Err(format_err!("The terminal {} must be greater than 0", name));
+            }
+            Ok(side)
+        };
+        let cols = side("width")?;
+        let rows = side("height")?;
+
+        Ok(TerminalSize { cols, rows })
+    }
+}
+
+/// The `ts` timestamp formats that are recognized at the start of lines
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TimestampFormat {
+    /// Seconds, such as the epoch time from `%s` or the elapsed time from `%.s`
+    Seconds,
+    /// A time of day, such as `12:34:56` from `%H:%M:%S` or `%.T`
+    TimeOfDay,
+    /// A date and time of day, such as `Oct 17 12:34:56` from the default `%b %d %H:%M:%S`
+    DateTime,
+}
+
+impl TimestampFormat {
+    /// Get the format of the timestamp at the start of a line, if there is one
+    fn detect(line: &str) -> Option<Self> {
+        [Self::DateTime, Self::TimeOfDay, Self::Seconds]
+            .iter()
+            .copied()
+            .find(|format| format.parse(line).is_some())
+    }
+
+    /// Parse the timestamp at the start of a line, returning its time in seconds and the rest of
+    /// the line after the space that follows the timestamp
+    fn parse(self, line: &str) -> Option<(f64, &str)> {
+        let (time, rest) = match self {
+            TimestampFormat::Seconds => {
+                let end = line.find(' ')?;
+                let seconds = &line[..end];
+                // Tell timestamps apart from output that starts with a number by only taking times
+                // with fractions or epoch times
+                if !seconds.contains('.') && seconds.len() < 9 {
+                    return None;
+                }
+                (parse_seconds(seconds)?, &line[end + 1..])
+            }
+            TimestampFormat::TimeOfDay => {
+                let end = line.find(' ')?;
+                (parse_time_of_day(&line[..end])?, &line[end + 1..])
+            }
+            TimestampFormat::DateTime => {
+                let mut fields = line.splitn(4, ' ');
+                let month = fields.next()?;
+                let day = fields.next()?;
+                let time = fields.next()?;
+                let rest = fields.next()?;
+                if month.len() != 3 || !month.chars().all(|c| c.is_ascii_alphabetic()) {
+                    return None;
+                }
+                let day: u8 = day.parse().ok()?;
+                let time = parse_time_of_day(time)?;
+                (f64::from(day) * SECONDS_PER_DAY + time, rest)
+            }
+        };
+
+        Some((time, rest))
+    }
+}
+
+/// Parse a number of seconds with an optional fraction, such as `12.5`
+fn parse_seconds(s: &str) -> Option<f64> {
+    if s.is_empty() || !s.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// Parse a time of day, such as `12:34:56.5`, to seconds since midnight
+fn parse_time_of_day(s: &str) -> Option<f64> {
+    let mut fields = s.splitn(3, ':');
+    let hours: u8 = fields.next()?.parse().ok()?;
+    let minutes: u8 = fields.next()?.parse().ok()?;
+    let seconds = parse_seconds(fields.next()?)?;
+    if fields.next().is_some() || hours > 23 || minutes > 59 || seconds >= 61. {
+        return None;
+    }
+
+    Some(f64::from(hours) * 3600. + f64::from(minutes) * 60. + seconds)
+}
+
+/// Convert a tmux `pipe-pane` log to an asciicast v2 file
+pub(crate) fn convert(
+    mut log: impl BufRead,
+    size: TerminalSize,
+    mut cast: impl Write,
+) -> anyhow::Result<()> {
+    let header = serde_json::json!({
+        "version": 2,
+        "width": size.cols,
+        "height": size.rows,
+        "timestamp": 0,
+        "env": {},
+    });
+    writeln!(cast, "{}", header)?;
+
+    let mut format = None;
+    let mut start_time = None;
+    let mut previous_time = None;
+    let mut day_offset = 0.;
+    let mut last_time = 0.;
+    let mut buf = Vec::new();
+    for index in 0u32.. {
+        buf.clear();
+        if log.read_until(b'\n', &mut buf)? == 0 {
+            break;
+        }
+        // The output doesn't have to be UTF-8, but multi-byte characters are never split by a line
+        // break, so invalid lines can be fixed up on their own
+        let line = String::from_utf8_lossy(&buf);
+
+        // Logs have timestamps if their first line does
+        if index == 0 {
+            format = TimestampFormat::detect(&line);
+        }
+
+        let (time, output) = match format.and_then(|format| format.parse(&line)) {
+            Some((time, output)) => {
+                let mut time = time + day_offset;
+                // Times of day start over at midnight
+                if format == Some(TimestampFormat::TimeOfDay)
+                    && previous_time.is_some_and(|previous| time < previous)
+                {
+                    day_offset += SECONDS_PER_DAY;
+                    time += SECONDS_PER_DAY;
+                }
+                previous_time = Some(time);
+
+                let start_time = *start_time.get_or_insert(time);
+                ((time - start_time).max(last_time), output)
+            }
+            // Continue lines that are missing their timestamp at the time of the line before them
+            None if format.is_some() => (last_time, &line[..]),
+            None => (f64::from(index) * UNTIMED_LINE_DELAY, &line[..]),
+        };
+        last_time = time;
+
+        writeln!(cast, "{}", serde_json::json!([time, "o", output]))?;
+    }
+    cast.flush()?;
+
+    Ok(())
+}
+
+/// Convert a tmux `pipe-pane` log file to a temporary cast file
+pub(crate) fn convert_file(log_path: &Path, size: TerminalSize) -> anyhow::Result<TempFile> {
+    let log = std::fs::File::open(log_path).context(format!(
+        "Could not open pipe-pane log: {}",
+        log_path.to_string_lossy()
+    ))?;
+    let cast = TempFile::create("pipe-pane.cast")
+        .context("Could not create a temporary file to convert the pipe-pane log to")?;
+    convert(
+        BufReader::new(log),
+        size,
+        std::io::BufWriter::new(cast.file()),
+    )?;
+
+    Ok(cast)
+}
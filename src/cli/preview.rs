@@ -0,0 +1,117 @@
+//! The `preview` subcommand
+
+use anyhow::Context;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::cast_parser::TerminalFrameIter;
+use crate::types::{CropSettings, ScreenSnapshot, TerminalFrame};
+
+/// Get the SGR parameters that select a terminal color
+fn color_sgr(color: vt100::Color, background: bool) -> String {
+    let base = if background { 40 } else { 30 };
+    match color {
+        vt100::Color::Default => format!("{}", base + 9),
+        vt100::Color::Idx(i) => format!("{};5;{}", base + 8, i),
+        vt100::Color::Rgb(r, g, b) => format!("{};2;{};{};{}", base + 8, r, g, b),
+    }
+}
+
+/// Draw a terminal screen to the top left corner of the terminal
+///
+/// If a crop is given, the cells outside of it are dimmed.
+pub(crate) fn draw_screen<W: Write>(
+    out: &mut W,
+    screen: &ScreenSnapshot,
+    crop: Option<CropSettings>,
+) -> std::io::Result<()> {
+    let (rows, cols) = screen.size();
+    for row in 0..rows {
+        // Move to the start of the row
+        write!(out, "\x1b[{};1H", row + 1)?;
+        let mut last_style = None;
+        for col in 0..cols {
+            let cell = screen.cell(row, col).expect("Error indexing cell");
+
+            // Draw the cursor by inverting the colors of the cell under it, like the renderers do
+            let inverse = screen.is_cursor_at(row, col);
+            let dim = crop
+                .map(|c| {
+                    row < c.top
+                        || row >= c.top + c.height
+                        || col < c.left
+                        || col >= c.left + c.width
+                })
+                .unwrap_or(false);
+
+            // Only change the style when it differs from the previous cell
+            let style = (cell.fgcolor, cell.bgcolor, inverse, dim);
+            if last_style != Some(style) {
+                write!(
+                    out,
+                    "\x1b[0;{};{}{}{}m",
+                    color_sgr(cell.fgcolor, false),
+                    color_sgr(cell.bgcolor, true),
+                    if inverse { ";7" } else { "" },
+                    if dim { ";2" } else { "" },
+                )?;
+                last_style = Some(style);
+            }
+            match &cell.contents {
+                Some(contents) => write!(out, "{}", contents)?,
+                None => write!(out, " ")?,
+            }
+        }
+    }
+    write!(out, "\x1b[0m")
+}
+
+/// Draw the frame and a status line with its timestamp below it
+fn draw_frame<W: Write>(out: &mut W, frame: &TerminalFrame) -> std::io::Result<()> {
+    draw_screen(out, &frame.screen, None)?;
+
+    let (rows, _) = frame.screen.size();
+    write!(
+        out,
+        "\x1b[{};1H\x1b[2K\x1b[2m{:>9.2}s  frame {}\x1b[0m",
+        rows + 2,
+        frame.time / 100.,
+        frame.index
+    )?;
+    out.flush()
+}
+
+pub(crate) fn run(cast_file_path: &Path) -> anyhow::Result<()> {
+    let cast_file = std::fs::File::open(cast_file_path).context(format!(
+        "Could not open cast file: {}",
+        cast_file_path.to_string_lossy()
+    ))?;
+    let frames = TerminalFrameIter::new(cast_file)?;
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+
+    // Clear the screen
+    write!(out, "\x1b[2J")?;
+
+    // Play back the frames at the time they occur in the recording
+    let start = Instant::now();
+    let mut rows = 0;
+    for frame in frames {
+        let frame = frame?;
+        rows = frame.screen.size().0;
+
+        let frame_time = Duration::from_secs_f64((frame.time / 100.).max(0.));
+        if let Some(wait) = frame_time.checked_sub(start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+
+        draw_frame(&mut out, &frame)?;
+    }
+
+    // Leave the cursor below the status line
+    writeln!(out, "\x1b[{};1H", rows + 3)?;
+
+    Ok(())
+}
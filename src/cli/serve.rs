@@ -0,0 +1,412 @@
+//! The `serve` subcommand
+//!
+//! Runs a small HTTP server that shows the rendered gif in a browser and re-renders it whenever
+//! the cast file or the render settings change.
+//!
+//! Only pages served from the preview itself may change the settings, so that other sites open in
+//! the browser can't, and the settings that use the filesystem can only be given on the command
+//! line.
+
+use anyhow::Context;
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+use super::exit_code::ArgumentError;
+use super::{terminal_size, RenderArgs};
+
+/// How often to check the cast file for changes
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The page that displays the rendered gif
+const INDEX_HTML: &str = include_str!("serve/index.html");
+
+/// The largest request head, in bytes
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// The largest body of a request to change the settings, in bytes
+const MAX_SETTINGS_SIZE: usize = 64 * 1024;
+
+/// The render settings that can't be changed from the browser, because they read from or write to
+/// the filesystem
+///
+/// The values given on the command line are kept when the other settings change.
+const FILE_SETTINGS: &[&str] = &[
+    "spill_dir",
+    "cache_dir",
+    "subtitles",
+    "spotlights",
+    "camera",
+];
+
+/// The state shared between the render thread and the HTTP server
+#[derive(Default)]
+struct ServeState {
+    /// The latest render of the cast
+    gif: Vec<u8>,
+    /// The error from the latest render, if it failed
+    error: Option<String>,
+    /// Incremented every time a render finishes
+    version: u64,
+    /// The render settings, as argument name and value pairs
+    settings: HashMap<String, String>,
+    /// Whether or not the settings have changed since the last render
+    settings_changed: bool,
+}
+
+type SharedState = Arc<(Mutex<ServeState>, Condvar)>;
+
+pub(crate) fn run(cast_file_path: &Path, port: u16, render: &RenderArgs) -> anyhow::Result<()> {
+    let cast_file_path = cast_file_path.to_path_buf();
+
+    // Start with the settings given on the command line
+    let settings = render.setting_values();
+    // Make sure they are valid before we start
+    render
+        .settings(terminal_size(&cast_file_path)?)
+        .map_err(ArgumentError)?;
+
+    let state: SharedState = Arc::new((
+        Mutex::new(ServeState {
+            settings,
+            settings_changed: true,
+            ..Default::default()
+        }),
+        Condvar::new(),
+    ));
+
+    // Render in the background whenever something changes
+    let render_state = state.clone();
+    let render_path = cast_file_path.clone();
+    std::thread::spawn(move || render_thread(render_path, render_state));
+
+    let listener = TcpListener::bind(("127.0.0.1", port))
+        .context(format!("Could not listen on port {}", port))?;
+    log::info!("Serving live preview on http://127.0.0.1:{}", port);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Could not accept connection: {}", e);
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        let cast_file_path = cast_file_path.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, port, &cast_file_path, &state) {
+                log::debug!("Error handling connection: {}", e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Re-render the cast whenever it or the settings change
+fn render_thread(cast_file_path: PathBuf, state: SharedState) {
+    let (lock, cvar) = &*state;
+    let mut last_modified = None;
+
+    loop {
+        // Wait for the cast file or the settings to change
+        let settings = {
+            let mut state = lock.lock().unwrap();
+            loop {
+                let modified = std::fs::metadata(&cast_file_path)
+                    .and_then(|m| m.modified())
+                    .ok();
+                if state.settings_changed || modified != last_modified {
+                    last_modified = modified;
+                    state.settings_changed = false;
+                    break state.settings.clone();
+                }
+
+                state = cvar.wait_timeout(state, WATCH_INTERVAL).unwrap().0;
+            }
+        };
+
+        log::info!("Rendering {}", cast_file_path.to_string_lossy());
+        let result = render(&cast_file_path, &settings);
+
+        let mut state = lock.lock().unwrap();
+        match result {
+            Ok(gif) => {
+                state.gif = gif;
+                state.error = None;
+            }
+            Err(e) => {
+                log::error!("{:?}", e);
+                state.error = Some(format!("{:?}", e));
+            }
+        }
+        state.version += 1;
+        cvar.notify_all();
+    }
+}
+
+/// Render the cast to a gif in memory
+fn render(cast_file_path: &Path, settings: &HashMap<String, String>) -> anyhow::Result<Vec<u8>> {
+    let settings =
+        RenderArgs::from_setting_values(settings)?.settings(terminal_size(cast_file_path)?)?;
+    let cast = std::fs::read(cast_file_path).context(format!(
+        "Could not read cast file: {}",
+        cast_file_path.to_string_lossy()
+    ))?;
+
+    let mut gif = Vec::new();
+    crate::convert_to_gif(Cursor::new(cast), &mut gif, settings)?;
+
+    Ok(gif)
+}
+
+/// Decode a percent-encoded URL query component
+fn percent_decode(s: &str) -> String {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut input = s.bytes();
+    while let Some(b) = input.next() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex: Vec<u8> = input.by_ref().take(2).collect();
+                match std::str::from_utf8(&hex)
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(b) => bytes.push(b),
+                    None => {
+                        bytes.push(b'%');
+                        bytes.extend(hex);
+                    }
+                }
+            }
+            b => bytes.push(b),
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Parse a URL query string
+fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let mut split = pair.splitn(2, '=');
+            let key = percent_decode(split.next().unwrap_or(""));
+            let value = percent_decode(split.next().unwrap_or(""));
+            (key, value)
+        })
+        .collect()
+}
+
+/// The parts of an HTTP request that the server looks at
+pub(super) struct Request {
+    pub method: String,
+    /// The path of the request, with the query string if it has one
+    pub path: String,
+    /// The headers, with lowercase names
+    pub headers: HashMap<String, String>,
+}
+
+/// Read the request line and headers of an HTTP request
+pub(super) fn read_request_head(reader: &mut impl BufRead) -> std::io::Result<Option<Request>> {
+    let mut head = reader.take(MAX_HEAD_SIZE as u64);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let path = parts.next().unwrap_or("/").to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if head.read_line(&mut line)? == 0 {
+            // The head was too big or the connection was closed before it ended
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(colon) = line.find(':') {
+            headers.insert(
+                line[..colon].trim().to_ascii_lowercase(),
+                line[colon + 1..].trim().to_string(),
+            );
+        }
+    }
+
+    Ok(Some(Request {
+        method,
+        path,
+        headers,
+    }))
+}
+
+/// Write an HTTP response
+pub(super) fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Cache-Control: no-store\r\n\
+         Connection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// Check that a request was sent to the preview from one of its own pages
+///
+/// The host is checked so that other sites can't read the preview by pointing their own domain at
+/// it, and the origin, which browsers send with requests that change things, is checked so that
+/// they can't change the settings.
+fn is_same_origin(request: &Request, port: u16) -> bool {
+    let hosts = [format!("127.0.0.1:{}", port), format!("localhost:{}", port)];
+    let host_ok = request.headers.get("host").is_some_and(|host| {
+        hosts
+            .iter()
+            .any(|allowed| host.eq_ignore_ascii_case(allowed))
+    });
+    let origin_ok = request.headers.get("origin").is_none_or(|origin| {
+        hosts
+            .iter()
+            .any(|allowed| origin.eq_ignore_ascii_case(&format!("http://{}", allowed)))
+    });
+
+    host_ok && origin_ok
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    port: u16,
+    cast_file_path: &Path,
+    state: &SharedState,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+    let request = match read_request_head(&mut reader)? {
+        Some(request) => request,
+        None => return respond(&mut stream, "400 Bad Request", "text/plain", b"Bad request"),
+    };
+    if !is_same_origin(&request, port) {
+        return respond(&mut stream, "403 Forbidden", "text/plain", b"Forbidden");
+    }
+    let path = request.path.split('?').next().unwrap_or("/");
+
+    let method = match path {
+        "/settings" => "POST",
+        _ => "GET",
+    };
+    if request.method != method {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+    }
+
+    let (lock, cvar) = &**state;
+    match path {
+        "/" => respond(&mut stream, "200 OK", "text/html", INDEX_HTML.as_bytes()),
+        "/render.gif" => {
+            let gif = lock.lock().unwrap().gif.clone();
+            respond(&mut stream, "200 OK", "image/gif", &gif)
+        }
+        "/status" => {
+            let state = lock.lock().unwrap();
+            let status = serde_json::json!({
+                "version": state.version,
+                "error": state.error,
+                "settings": state.settings,
+            });
+            respond(
+                &mut stream,
+                "200 OK",
+                "application/json",
+                status.to_string().as_bytes(),
+            )
+        }
+        "/settings" => {
+            // The settings are sent as a form
+            let content_length = match request
+                .headers
+                .get("content-length")
+                .and_then(|length| length.parse::<usize>().ok())
+            {
+                Some(length) if length <= MAX_SETTINGS_SIZE => length,
+                Some(_) => return respond(&mut stream, "413 Payload Too Large", "text/plain", b""),
+                None => return respond(&mut stream, "411 Length Required", "text/plain", b""),
+            };
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body)?;
+            let form = parse_query(&String::from_utf8_lossy(&body));
+
+            if let Some(name) = form
+                .iter()
+                .find(|(name, value)| FILE_SETTINGS.contains(&name.as_str()) && !value.is_empty())
+                .map(|(name, _)| name)
+            {
+                return respond(
+                    &mut stream,
+                    "400 Bad Request",
+                    "text/plain",
+                    format!(
+                        "The `{}` setting can only be given on the command line",
+                        name
+                    )
+                    .as_bytes(),
+                );
+            }
+
+            // Only keep the settings that were filled in, and the file settings from the command
+            // line
+            let setting_names = RenderArgs::setting_names();
+            let mut settings: HashMap<String, String> = form
+                .into_iter()
+                .filter(|(name, value)| setting_names.contains(name) && !value.is_empty())
+                .collect();
+            settings.extend(
+                lock.lock()
+                    .unwrap()
+                    .settings
+                    .iter()
+                    .filter(|(name, _)| FILE_SETTINGS.contains(&name.as_str()))
+                    .map(|(name, value)| (name.clone(), value.clone())),
+            );
+
+            // Validate the settings before applying them
+            let valid = RenderArgs::from_setting_values(&settings)
+                .and_then(|render| render.settings(terminal_size(cast_file_path)?));
+            if let Err(e) = valid {
+                return respond(
+                    &mut stream,
+                    "400 Bad Request",
+                    "text/plain",
+                    format!("{:?}", e).as_bytes(),
+                );
+            }
+
+            let mut state = lock.lock().unwrap();
+            state.settings = settings;
+            state.settings_changed = true;
+            cvar.notify_all();
+
+            respond(&mut stream, "204 No Content", "text/plain", b"")
+        }
+        _ => respond(&mut stream, "404 Not Found", "text/plain", b"Not found"),
+    }
+}
@@ -0,0 +1,575 @@
+//! The `server` subcommand
+//!
+//! Runs an HTTP rendering service. Casts are uploaded to `POST /render` as a
+//! `multipart/form-data` request with a `cast` part holding the cast file and an optional `options`
+//! part holding a JSON object of render settings, such as `{"fps": 15, "title_bar": true}`. The
+//! gif is streamed back in the response as it is rendered.
+//!
+//! Clients can only set the settings that don't use the server's filesystem or change how much of
+//! the server a render may use, and settings that add frames or work are capped. Requests over
+//! `--max-requests` are turned away, as are casts of terminals that render larger than
+//! `--max-frame-pixels`, and renders that take longer than `--render-timeout` are cut off.
+
+use anyhow::{format_err, Context};
+
+use std::collections::HashMap;
+use std::io::{BufReader, Cursor, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use super::exit_code::{self, ArgumentError};
+use super::serve::{read_request_head, respond, Request};
+use super::RenderArgs;
+use crate::{ConvertSettings, Converter, SpeedMap};
+
+/// How long the client may take to send the whole request
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for the client to take more of the response before giving up
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The render settings that clients can set
+///
+/// Everything else is left out, because it reads from or writes to the server's filesystem, or
+/// changes how much of the server a render may use.
+const CLIENT_SETTINGS: &[&str] = &[
+    "crop",
+    "preset",
+    "aspect",
+    "timing",
+    "fps",
+    "speed_map",
+    "trim",
+    "initial_delay",
+    "typing_speed",
+    "pause_on_markers",
+    "redact",
+    "title_bar",
+    "title",
+    "background_opacity",
+    "quantizer",
+    "min_frame_delay",
+    "hold_last",
+    "loop_crossfade",
+    "fade_in",
+    "fade_out",
+    "min_contrast",
+    "light",
+    "monochrome",
+    "ink",
+    "paper",
+    "highlight_changes",
+    "cursor_trail",
+    "smooth_scroll",
+    "marker_captions",
+    "command_markers",
+    "no_bell_flash",
+];
+
+/// The range that clients may set each numeric setting in, because values outside of it add
+/// frames to the gif or work to each frame, with the smallest and largest value of each
+const CAPPED_SETTINGS: &[(&str, f64, f64)] = &[
+    ("fps", 0., 60.),
+    ("typing_speed", 5., f64::INFINITY),
+    ("pause_on_markers", 0., 30.),
+    ("background_opacity", 0., 1.),
+    ("min_frame_delay", 0.02, f64::INFINITY),
+    ("hold_last", 0., 30.),
+    ("loop_crossfade", 0., 60.),
+    ("fade_in", 0., 30.),
+    ("fade_out", 0., 30.),
+    ("min_contrast", 1., 21.),
+    ("highlight_changes", 0., 30.),
+    ("cursor_trail", 0., 30.),
+    ("smooth_scroll", 0., 16.),
+    ("marker_captions", 0., 30.),
+];
+
+/// The slowest speed that clients may give in a `speed_map`, because slower parts of the
+/// recording take more frames to play
+const MIN_SPEED: f64 = 0.25;
+
+/// The limits on the requests that the server handles
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limits {
+    /// The largest cast upload to accept, in bytes
+    pub max_upload: usize,
+    /// The most requests to handle at once
+    pub max_requests: usize,
+    /// The longest time that a render may take before it is cut off
+    pub render_timeout: Duration,
+    /// The most pixels that the terminal of a cast may render to
+    pub max_frame_pixels: usize,
+}
+
+pub(crate) fn run(address: &str, port: u16, limits: Limits) -> anyhow::Result<()> {
+    let listener = TcpListener::bind((address, port))
+        .context(format!("Could not listen on {}:{}", address, port))?;
+    // Every request renders on the same warmed up thread pool, with its own settings
+    let converter = Converter::new(ConvertSettings::default())?;
+    log::info!(
+        "Listening for render requests on http://{}",
+        listener.local_addr()?
+    );
+
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Could not accept connection: {}", e);
+                continue;
+            }
+        };
+
+        let converter = converter.clone();
+        let active = active.clone();
+        std::thread::spawn(move || {
+            let peer = stream.peer_addr().ok();
+            if let Err(e) = handle_connection(stream, &converter, &active, limits) {
+                log::debug!("Error handling connection from {:?}: {}", peer, e);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// One of the requests that the server may handle at once, which is given back when it is dropped
+struct RequestSlot(Arc<AtomicUsize>);
+
+impl RequestSlot {
+    /// Take a slot, if fewer than `max` requests are being handled
+    fn take(active: &Arc<AtomicUsize>, max: usize) -> Option<Self> {
+        active
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < max).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| RequestSlot(active.clone()))
+    }
+}
+
+impl Drop for RequestSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    converter: &Converter,
+    active: &Arc<AtomicUsize>,
+    limits: Limits,
+) -> std::io::Result<()> {
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let peer = stream.peer_addr()?;
+    let mut reader = BufReader::new(DeadlineReader {
+        stream: stream.try_clone()?,
+        deadline: Instant::now() + READ_TIMEOUT,
+    });
+    let mut stream = stream;
+
+    let request = match read_request_head(&mut reader)? {
+        Some(request) => request,
+        None => return respond(&mut stream, "400 Bad Request", "text/plain", b"Bad request"),
+    };
+
+    if request.path != "/render" {
+        return respond(&mut stream, "404 Not Found", "text/plain", b"Not found");
+    }
+    if request.method != "POST" {
+        return respond(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+    }
+
+    // Read the whole upload, because the terminal size is needed to check the settings before
+    // rendering starts
+    let content_length = match request
+        .headers
+        .get("content-length")
+        .and_then(|length| length.parse::<usize>().ok())
+    {
+        Some(length) => length,
+        None => return respond(&mut stream, "411 Length Required", "text/plain", b""),
+    };
+    if content_length > limits.max_upload {
+        return respond(
+            &mut stream,
+            "413 Payload Too Large",
+            "text/plain",
+            format!("Uploads may be at most {} bytes", limits.max_upload).as_bytes(),
+        );
+    }
+    if request
+        .headers
+        .get("expect")
+        .is_some_and(|expect| expect.eq_ignore_ascii_case("100-continue"))
+    {
+        stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+    }
+    // The buffer grows as the upload arrives, so that clients can't make the server allocate more
+    // than they send
+    let mut body = Vec::new();
+    (&mut reader)
+        .take(content_length as u64)
+        .read_to_end(&mut body)?;
+    if body.len() < content_length {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+
+    // Only uploads that have fully arrived take up one of the requests that may be handled at once
+    let _slot = match RequestSlot::take(active, limits.max_requests) {
+        Some(slot) => slot,
+        None => {
+            log::debug!("Turned away a request, because the server is busy");
+            return respond(
+                &mut stream,
+                "503 Service Unavailable",
+                "text/plain",
+                b"The server is busy, try again later",
+            );
+        }
+    };
+
+    let started = Instant::now();
+    let mut response = GifResponse {
+        stream: &mut stream,
+        started: false,
+        deadline: started + limits.render_timeout,
+    };
+    let result = render(&request, &body, converter, limits, &mut response);
+    let started_response = response.started;
+
+    match result {
+        Ok(()) => {
+            log::info!(
+                "Rendered a cast for {} in {:.2}s",
+                peer,
+                started.elapsed().as_secs_f64()
+            );
+            Ok(())
+        }
+        // The gif has already been partly sent, so just cut the response off to show that it failed
+        Err(e) if started_response => {
+            log::warn!("Could not render a cast for {}: {:#}", peer, e);
+            Ok(())
+        }
+        Err(e) => {
+            let status = match exit_code::for_error(&e) {
+                exit_code::INVALID_ARGUMENTS | exit_code::PARSE_ERROR => "400 Bad Request",
+                _ => "500 Internal Server Error",
+            };
+            log::debug!("Rejected a render request from {}: {:#}", peer, e);
+            respond(
+                &mut stream,
+                status,
+                "text/plain",
+                format!("{:#}", e).as_bytes(),
+            )
+        }
+    }
+}
+
+/// Reads from a client until a deadline, however slowly it sends
+struct DeadlineReader {
+    stream: TcpStream,
+    /// When the whole request has to be read by
+    deadline: Instant,
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let time_left = self.deadline.saturating_duration_since(Instant::now());
+        if time_left == Duration::ZERO {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "The client took too long to send the request",
+            ));
+        }
+        self.stream.set_read_timeout(Some(time_left))?;
+        self.stream.read(buf)
+    }
+}
+
+/// Render the cast uploaded in a request, streaming the gif to the response
+fn render(
+    request: &Request,
+    body: &[u8],
+    converter: &Converter,
+    limits: Limits,
+    response: &mut GifResponse,
+) -> anyhow::Result<()> {
+    let content_type = request
+        .headers
+        .get("content-type")
+        .map(String::as_str)
+        .unwrap_or("");
+    let parts = parse_multipart(content_type, body).map_err(ArgumentError)?;
+
+    let cast = parts
+        .get("cast")
+        .ok_or_else(|| ArgumentError(format_err!("Missing the `cast` part of the upload")))?;
+    let settings = match parts.get("options") {
+        Some(options) => parse_options(options).map_err(ArgumentError)?,
+        None => HashMap::new(),
+    };
+
+    let terminal_size = crate::cast_parser::TerminalFrameIter::new(Cursor::new(*cast))?.size();
+    let mut settings = RenderArgs::from_setting_values(&settings)
+        .and_then(|render| render.settings(terminal_size))
+        .map_err(ArgumentError)?;
+    // Presets can set a target size too
+    settings.target_size = None;
+    settings.deadline = Some(response.deadline);
+
+    // The frames are rendered at the size of the terminal, so a cast of a huge terminal would take
+    // a huge amount of memory and work to render. The whole terminal is emulated for every frame,
+    // so cropping it doesn't make it any cheaper.
+    let (width, height) = crate::frame_renderer::frame_size(
+        terminal_size.0,
+        terminal_size.1,
+        None,
+        settings.shows_title_bar(),
+        settings.aspect,
+    );
+    if width * height > limits.max_frame_pixels {
+        return Err(ArgumentError(format_err!(
+            "The terminal of this cast renders to {}x{} pixels, but the server only renders \
+             terminals of up to {} pixels",
+            width,
+            height,
+            limits.max_frame_pixels
+        ))
+        .into());
+    }
+
+    converter
+        .with_settings(settings)
+        .convert_to_gif(Cursor::new(cast.to_vec()), &mut *response)?;
+    response.finish()?;
+
+    Ok(())
+}
+
+/// Parse the render settings from a JSON object of setting names and values
+///
+/// Flags are set with `true`, and settings with `false` or `null` values are left out.
+fn parse_options(options: &[u8]) -> anyhow::Result<HashMap<String, String>> {
+    let options: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(options).context("The `options` part must be a JSON object")?;
+
+    let mut settings = HashMap::new();
+    for (name, value) in options {
+        let name = name.replace('-', "_");
+        if !CLIENT_SETTINGS.contains(&name.as_str()) {
+            return Err(format_err!(
+                "The `{}` option can't be set on the server",
+                name
+            ));
+        }
+
+        let value = match value {
+            serde_json::Value::Null | serde_json::Value::Bool(false) => continue,
+            serde_json::Value::Bool(true) => String::new(),
+            serde_json::Value::Number(number) => number.to_string(),
+            serde_json::Value::String(string) => string,
+            _ => {
+                return Err(format_err!(
+                    "The `{}` option must be a string or number",
+                    name
+                ))
+            }
+        };
+        // Values that don't parse are left for the setting to reject
+        let cap = CAPPED_SETTINGS
+            .iter()
+            .find(|(capped, _, _)| *capped == name);
+        if let (Some((_, min, max)), Ok(number)) = (cap, value.trim().parse::<f64>()) {
+            if number < *min {
+                return Err(format_err!(
+                    "The `{}` option must be at least {} on the server",
+                    name,
+                    min
+                ));
+            }
+            if number > *max {
+                return Err(format_err!(
+                    "The `{}` option may be at most {} on the server",
+                    name,
+                    max
+                ));
+            }
+        }
+        if name == "speed_map" {
+            let min_speed = value
+                .parse::<SpeedMap>()
+                .ok()
+                .and_then(|speed_map| speed_map.min_speed());
+            if min_speed.is_some_and(|speed| speed < MIN_SPEED) {
+                return Err(format_err!(
+                    "The speeds in the `speed_map` option must be at least {} on the server",
+                    MIN_SPEED
+                ));
+            }
+        }
+        settings.insert(name, value);
+    }
+
+    Ok(settings)
+}
+
+/// Split a `multipart/form-data` body into its parts, by name
+fn parse_multipart<'a>(
+    content_type: &str,
+    body: &'a [u8],
+) -> anyhow::Result<HashMap<String, &'a [u8]>> {
+    let boundary = content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| {
+            let mut split = param.trim().splitn(2, '=');
+            match (split.next(), split.next()) {
+                (Some(name), Some(value)) if name.eq_ignore_ascii_case("boundary") => {
+                    Some(value.trim_matches('"'))
+                }
+                _ => None,
+            }
+        })
+        .next()
+        .filter(|_| {
+            content_type
+                .trim_start()
+                .to_ascii_lowercase()
+                .starts_with("multipart/form-data")
+        })
+        .ok_or_else(|| format_err!("The request must be multipart/form-data"))?;
+    let delimiter = format!("--{}", boundary).into_bytes();
+
+    let mut parts = HashMap::new();
+    // Everything before the first delimiter is a preamble to skip
+    let mut sections = split_bytes(body, &delimiter).into_iter().skip(1);
+    for section in &mut sections {
+        // The closing delimiter is followed by `--`
+        if section.starts_with(b"--") {
+            return Ok(parts);
+        }
+
+        let section = section.strip_prefix(b"\r\n").unwrap_or(section);
+        let head_end = find_bytes(section, b"\r\n\r\n")
+            .ok_or_else(|| format_err!("A part of the upload doesn't have any headers"))?;
+        let head = String::from_utf8_lossy(&section[..head_end]);
+        let content = &section[head_end + 4..];
+        // The line break before the next delimiter belongs to the delimiter
+        let content = content.strip_suffix(b"\r\n").unwrap_or(content);
+
+        let name = head
+            .lines()
+            .filter(|line| {
+                line.to_ascii_lowercase()
+                    .starts_with("content-disposition:")
+            })
+            .flat_map(|line| line.split(';').skip(1))
+            .filter_map(|param| {
+                let mut split = param.trim().splitn(2, '=');
+                match (split.next(), split.next()) {
+                    (Some("name"), Some(value)) => Some(value.trim_matches('"').to_string()),
+                    _ => None,
+                }
+            })
+            .next();
+        if let Some(name) = name {
+            parts.insert(name, content);
+        }
+    }
+
+    Err(format_err!("The upload ended before its closing boundary"))
+}
+
+/// Find the first position of `needle` in `haystack`
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Split `bytes` on every occurrence of `delimiter`
+fn split_bytes<'a>(mut bytes: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut sections = Vec::new();
+    while let Some(position) = find_bytes(bytes, delimiter) {
+        sections.push(&bytes[..position]);
+        bytes = &bytes[position + delimiter.len()..];
+    }
+    sections.push(bytes);
+
+    sections
+}
+
+/// Streams a gif to the client with chunked transfer encoding
+///
+/// The response head is only sent once the first part of the gif is written, so that an error
+/// response can still be sent if the render fails before it starts. The render stops itself at the
+/// deadline, and writing to the response fails once it has passed, in case the gif encoder is still
+/// writing out the frames that it had.
+struct GifResponse<'a> {
+    stream: &'a mut TcpStream,
+    /// Whether or not the response head has been sent
+    started: bool,
+    /// When the render has to be done by
+    deadline: Instant,
+}
+
+impl GifResponse<'_> {
+    /// Fail if the render has gone past its deadline
+    fn check_deadline(&self) -> std::io::Result<()> {
+        if Instant::now() > self.deadline {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "The render took longer than the server's --render-timeout",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// End the response
+    fn finish(&mut self) -> std::io::Result<()> {
+        if !self.started {
+            // The gif was empty
+            return respond(self.stream, "200 OK", "image/gif", b"");
+        }
+        self.stream.write_all(b"0\r\n\r\n")?;
+        self.stream.flush()
+    }
+}
+
+impl Write for GifResponse<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.check_deadline()?;
+        if !self.started {
+            self.started = true;
+            write!(
+                self.stream,
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: image/gif\r\n\
+                 Transfer-Encoding: chunked\r\n\
+                 Cache-Control: no-store\r\n\
+                 Connection: close\r\n\r\n"
+            )?;
+        }
+        write!(self.stream, "{:x}\r\n", buf.len())?;
+        self.stream.write_all(buf)?;
+        self.stream.write_all(b"\r\n")?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.check_deadline()?;
+        self.stream.flush()
+    }
+}
@@ -0,0 +1,117 @@
+//! Showing rendered gifs in the terminal
+//!
+//! Terminals that support the iTerm2 inline image protocol, such as iTerm2 and WezTerm, play the
+//! whole gif. Terminals that support the kitty graphics protocol, such as kitty and Ghostty, show
+//! its first frame.
+
+use anyhow::{format_err, Context};
+use base64::Engine;
+use crossterm::tty::IsTty;
+
+use std::io::Write;
+use std::path::Path;
+
+/// The largest amount of base64 data that may be sent in one kitty graphics command
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// The image protocol to show the gif with
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub(super) enum ImageProtocol {
+    /// Detect the protocol from the environment
+    Auto,
+    /// The kitty graphics protocol, which shows the first frame
+    Kitty,
+    /// The iTerm2 inline image protocol, which plays the animation
+    Iterm,
+}
+
+impl ImageProtocol {
+    /// Detect the image protocol that the terminal supports from its environment variables
+    fn detect() -> Option<Self> {
+        let var = |name| std::env::var(name).unwrap_or_default();
+        match var("TERM_PROGRAM").as_str() {
+            "iTerm.app" | "WezTerm" => return Some(ImageProtocol::Iterm),
+            "ghostty" => return Some(ImageProtocol::Kitty),
+            _ => (),
+        }
+        if std::env::var_os("KITTY_WINDOW_ID").is_some() || var("TERM").contains("kitty") {
+            return Some(ImageProtocol::Kitty);
+        }
+
+        None
+    }
+}
+
+/// Show a gif in the terminal with the given image protocol
+pub(super) fn run(path: &Path, protocol: ImageProtocol) -> anyhow::Result<()> {
+    let stdout = std::io::stdout();
+    if !stdout.is_tty() {
+        return Err(format_err!("The output is not a terminal"));
+    }
+    let protocol = match protocol {
+        ImageProtocol::Auto => ImageProtocol::detect().ok_or_else(|| {
+            format_err!(
+                "The terminal doesn't seem to support the kitty or iTerm2 image protocols. Pick \
+                 one with --show=kitty or --show=iterm if it does."
+            )
+        })?,
+        protocol => protocol,
+    };
+
+    let gif =
+        std::fs::read(path).context(format!("Could not read gif: {}", path.to_string_lossy()))?;
+    let base64 = base64::engine::general_purpose::STANDARD;
+    let mut out = stdout.lock();
+    match protocol {
+        ImageProtocol::Iterm => write!(
+            out,
+            "\x1b]1337;File=inline=1;size={};preserveAspectRatio=1:{}\x07",
+            gif.len(),
+            base64.encode(&gif)
+        )?,
+        _ => {
+            let (width, height, rgba) = first_frame(&gif)?;
+            let data = base64.encode(rgba);
+            let chunks: Vec<_> = data.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+            for (i, chunk) in chunks.iter().enumerate() {
+                // Only the first chunk has the image format, and every chunk but the last one says
+                // that more are coming
+                let more = (i + 1 < chunks.len()) as u8;
+                if i == 0 {
+                    write!(out, "\x1b_Ga=T,f=32,s={},v={},m={};", width, height, more)?;
+                } else {
+                    write!(out, "\x1b_Gm={};", more)?;
+                }
+                out.write_all(chunk)?;
+                write!(out, "\x1b\\")?;
+            }
+        }
+    }
+    writeln!(out)?;
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Decode the first frame of a gif to its (width, height, RGBA pixels)
+fn first_frame(gif: &[u8]) -> anyhow::Result<(u16, u16, Vec<u8>)> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::RGBA);
+    let mut decoder = options.read_info(gif).context("Could not read gif")?;
+    let (width, height) = (decoder.width(), decoder.height());
+    let frame = decoder
+        .read_next_frame()
+        .context("Could not read gif")?
+        .ok_or_else(|| format_err!("The gif has no frames"))?;
+
+    // Draw the frame at its position on the gif, in case it doesn't cover all of it
+    let mut rgba = vec![0; width as usize * height as usize * 4];
+    for y in 0..frame.height.min(height.saturating_sub(frame.top)) {
+        let src = y as usize * frame.width as usize * 4;
+        let dst = ((frame.top + y) as usize * width as usize + frame.left as usize) * 4;
+        let len = frame.width.min(width.saturating_sub(frame.left)) as usize * 4;
+        rgba[dst..dst + len].copy_from_slice(&frame.buffer[src..src + len]);
+    }
+
+    Ok((width, height, rgba))
+}
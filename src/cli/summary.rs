@@ -0,0 +1,139 @@
+//! The summary that is printed once a render is done
+
+use std::path::Path;
+use std::time::Duration;
+
+use super::dry_run::format_bytes;
+use crate::RenderSummary;
+
+/// Get the number of bytes in an output file, or in the files of an output directory
+///
+/// Returns `None` if the size can't be read.
+pub(crate) fn output_size(path: &Path) -> Option<u64> {
+    let metadata = std::fs::metadata(path).ok()?;
+    if !metadata.is_dir() {
+        return Some(metadata.len());
+    }
+
+    let mut size = 0;
+    for entry in std::fs::read_dir(path).ok()? {
+        let metadata = entry.ok()?.metadata().ok()?;
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+
+    Some(size)
+}
+
+/// Get the number of frames that the encoder merged into the frames before them
+fn merged_frames(summary: &RenderSummary) -> u64 {
+    summary
+        .sequenced_frames
+        .saturating_sub(summary.encoded_frames)
+}
+
+/// Get the number of frames shown per second of the output
+fn frame_rate(summary: &RenderSummary) -> f64 {
+    if summary.duration > 0. {
+        summary.encoded_frames as f64 / summary.duration
+    } else {
+        0.
+    }
+}
+
+/// Print the summary of a render to stderr
+///
+/// `output_size` is the size of the output in bytes, if it was written to a file, and `total_time`
+/// is the wall-clock time that the whole render took.
+pub(crate) fn print(summary: &RenderSummary, output_size: Option<u64>, total_time: Duration) {
+    eprintln!(
+        "Frames:         {} encoded, {} duplicates merged",
+        summary.encoded_frames,
+        merged_frames(summary)
+    );
+    eprintln!("Duration:       {:.2}s", summary.duration);
+    eprintln!("Frame rate:     {:.1} fps", frame_rate(summary));
+    if let Some(size) = output_size {
+        eprintln!("Output size:    {}", format_bytes(size as f64));
+    }
+    eprintln!("Time:           {:.2}s", total_time.as_secs_f64());
+    eprintln!("  Parsing:      {:.2}s", summary.parse_time.as_secs_f64());
+    eprintln!("  Rasterizing:  {:.2}s", summary.raster_time.as_secs_f64());
+    eprintln!("  Encoding:     {:.2}s", summary.encode_time.as_secs_f64());
+}
+
+/// Get the summary of a render as a JSON progress event
+pub(crate) fn json(
+    summary: &RenderSummary,
+    output_size: Option<u64>,
+    total_time: Duration,
+) -> serde_json::Value {
+    serde_json::json!({
+        "event": "summary",
+        "rendered": summary.rendered_frames,
+        "frames": summary.encoded_frames,
+        "merged": merged_frames(summary),
+        "duration": summary.duration,
+        "fps": frame_rate(summary),
+        "bytes": output_size,
+        "time": total_time.as_secs_f64(),
+        "parse_time": summary.parse_time.as_secs_f64(),
+        "raster_time": summary.raster_time.as_secs_f64(),
+        "encode_time": summary.encode_time.as_secs_f64(),
+    })
+}
+
+/// The stages of a render, with their names, their times, and the number of frames that went
+/// through them, followed by the whole render
+fn stages(summary: &RenderSummary, total_time: Duration) -> [(&'static str, Duration, u64); 4] {
+    [
+        ("parse", summary.parse_time, summary.parsed_frames),
+        ("rasterize", summary.raster_time, summary.rendered_frames),
+        ("encode", summary.encode_time, summary.sequenced_frames),
+        ("total", total_time, summary.encoded_frames),
+    ]
+}
+
+/// Get the average time, in milliseconds, that each frame took in a stage
+fn ms_per_frame(time: Duration, frames: u64) -> f64 {
+    if frames > 0 {
+        time.as_secs_f64() * 1000. / frames as f64
+    } else {
+        0.
+    }
+}
+
+/// Print the time that each stage of a render took to stderr, with the average time per frame
+pub(crate) fn print_timings(summary: &RenderSummary, total_time: Duration) {
+    eprintln!(
+        "{:<12} {:>9} {:>8} {:>10}",
+        "Stage", "Time", "Frames", "ms/frame"
+    );
+    for (stage, time, frames) in stages(summary, total_time).iter() {
+        eprintln!(
+            "{:<12} {:>8.2}s {:>8} {:>10.2}",
+            stage,
+            time.as_secs_f64(),
+            frames,
+            ms_per_frame(*time, *frames)
+        );
+    }
+}
+
+/// Get the time that each stage of a render took as a JSON progress event
+pub(crate) fn timings_json(summary: &RenderSummary, total_time: Duration) -> serde_json::Value {
+    let stages: Vec<_> = stages(summary, total_time)
+        .iter()
+        .map(|(stage, time, frames)| {
+            serde_json::json!({
+                "stage": stage,
+                "time": time.as_secs_f64(),
+                "frames": frames,
+                "ms_per_frame": ms_per_frame(*time, *frames),
+            })
+        })
+        .collect();
+
+    serde_json::json!({ "event": "timings", "stages": stages })
+}
@@ -0,0 +1,50 @@
+//! Temporary files
+
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+/// Counter used to give each temporary file in this process a unique name
+static NEXT_TEMP_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A file in the system temporary directory that is removed when this is dropped
+pub(crate) struct TempFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl TempFile {
+    /// Create a temporary file with a name ending in `suffix`
+    pub fn create(suffix: &str) -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "cast2gif-{}-{}-{}",
+            std::process::id(),
+            NEXT_TEMP_ID.fetch_add(1, SeqCst),
+            suffix
+        ));
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+
+        Ok(TempFile { path, file })
+    }
+
+    /// Get the path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the file to write to
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
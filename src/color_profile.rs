@@ -0,0 +1,251 @@
+//! Terminal color profiles
+//!
+//! A profile sets the default foreground and background colors of the terminal and the 16 colors
+//! of its palette. Profiles are read from iTerm2 `.itermcolors` files and from X resources files,
+//! such as `~/.Xresources`, which are the formats that most terminal color schemes are published
+//! in.
+//!
+//! Every color is an sRGB color, and colors are mixed as their sRGB values, the same way that
+//! terminals draw them, so that the outputs look like the terminal that was recorded.
+
+use crate::Error;
+
+use rgb::RGBA8;
+
+use std::str::FromStr;
+
+/// An (r, g, b) color
+pub type Rgb = (u8, u8, u8);
+
+/// Mix a color toward another one by `amount`, from `0` for none of the other color to `1` for all
+/// of it
+pub(crate) fn mix(color: RGBA8, other: RGBA8, amount: f64) -> RGBA8 {
+    let channel =
+        |a: u8, b: u8| (f64::from(a) * (1. - amount) + f64::from(b) * amount).round() as u8;
+    RGBA8::new(
+        channel(color.r, other.r),
+        channel(color.g, other.g),
+        channel(color.b, other.b),
+        channel(color.a, other.a),
+    )
+}
+
+/// The colors of a terminal
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorProfile {
+    /// The color of text that doesn't set a color
+    pub foreground: Rgb,
+    /// The color of the terminal background
+    pub background: Rgb,
+    /// The color of the cursor, or `None` to draw it in the foreground color
+    pub cursor: Option<Rgb>,
+    /// The 16 colors of the palette, which are the standard colors followed by the bright ones
+    pub palette: [Rgb; 16],
+}
+
+impl Default for ColorProfile {
+    /// The colors that gifs are rendered with
+    fn default() -> Self {
+        let mut palette = [(0, 0, 0); 16];
+        for (i, color) in palette.iter_mut().enumerate() {
+            *color = crate::frame_renderer::parse_color(vt100::Color::Idx(i as u8))
+                .expect("Palette colors are never the default color");
+        }
+
+        ColorProfile {
+            foreground: (255, 255, 255),
+            background: (0, 0, 0),
+            cursor: None,
+            palette,
+        }
+    }
+}
+
+impl ColorProfile {
+    /// Get the color of a terminal color, or `None` if it is the default color
+    pub(crate) fn color(&self, color: vt100::Color) -> Option<Rgb> {
+        match color {
+            vt100::Color::Default => None,
+            vt100::Color::Idx(i) if i < 16 => Some(self.palette[i as usize]),
+            vt100::Color::Idx(i) => Some(ansi_colours::rgb_from_ansi256(i)),
+            vt100::Color::Rgb(r, g, b) => Some((r, g, b)),
+        }
+    }
+
+    /// Get the color of the cursor
+    pub(crate) fn cursor(&self) -> Rgb {
+        self.cursor.unwrap_or(self.foreground)
+    }
+
+    /// Get the color that a profile key, such as `Ansi 1 Color` or `color1`, sets
+    fn slot_mut(&mut self, key: &str) -> Option<&mut Rgb> {
+        let key = key.trim().to_ascii_lowercase();
+        match key.as_str() {
+            "foreground" | "foreground color" => return Some(&mut self.foreground),
+            "background" | "background color" => return Some(&mut self.background),
+            "cursorcolor" | "cursor color" => return Some(self.cursor.get_or_insert((0, 0, 0))),
+            _ => (),
+        }
+        let index = key
+            .strip_prefix("color")
+            .or_else(|| {
+                key.strip_prefix("ansi ")
+                    .and_then(|key| key.strip_suffix(" color"))
+            })?
+            .parse::<usize>()
+            .ok()?;
+
+        self.palette.get_mut(index)
+    }
+
+    /// Parse an iTerm2 `.itermcolors` property list
+    fn from_itermcolors(plist: &str) -> Result<Self, Error> {
+        let mut profile = ColorProfile::default();
+        let mut found = false;
+        let mut rest = plist;
+        while let Some((key, after)) = next_element(rest, "key") {
+            rest = after;
+            let slot = match profile.slot_mut(key) {
+                Some(slot) => slot,
+                None => continue,
+            };
+
+            // The components of the color are in the dictionary that follows its key
+            let dict = after
+                .find("</dict>")
+                .map(|end| &after[..end])
+                .ok_or_else(|| invalid_profile(&format!("`{}` has no color", key)))?;
+            let component = |name: &str| -> Result<u8, Error> {
+                let mut rest = dict;
+                while let Some((key, after)) = next_element(rest, "key") {
+                    rest = after;
+                    if key == name {
+                        let value = after
+                            .find('>')
+                            .and_then(|start| {
+                                let value = &after[start + 1..];
+                                value.find('<').map(|end| &value[..end])
+                            })
+                            .and_then(|value| value.trim().parse::<f64>().ok())
+                            .ok_or_else(|| {
+                                invalid_profile(&format!("`{}` of `{}` is not a number", name, key))
+                            })?;
+                        return Ok((value.clamp(0., 1.) * 255.).round() as u8);
+                    }
+                }
+                // iTerm2 leaves out components that are 0
+                Ok(0)
+            };
+            *slot = (
+                component("Red Component")?,
+                component("Green Component")?,
+                component("Blue Component")?,
+            );
+            found = true;
+            rest = &after[dict.len()..];
+        }
+
+        if !found {
+            return Err(invalid_profile("it doesn't set any colors"));
+        }
+
+        Ok(profile)
+    }
+
+    /// Parse X resources, such as `*.color1: #ab4642`
+    fn from_xresources(resources: &str) -> Result<Self, Error> {
+        let mut profile = ColorProfile::default();
+        let mut found = false;
+        for line in resources.lines().map(str::trim) {
+            // Skip comments and preprocessor directives
+            if line.is_empty() || line.starts_with('!') || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = match line.split_once(':') {
+                Some(resource) => resource,
+                None => continue,
+            };
+            // Take the resource name out of names like `URxvt*color1` or `*.foreground`
+            let name = name.rsplit(&['*', '.'][..]).next().unwrap_or(name);
+            if let Some(slot) = profile.slot_mut(name) {
+                *slot = parse_x_color(value.trim()).ok_or_else(|| {
+                    invalid_profile(&format!("`{}` is not a color", value.trim()))
+                })?;
+                found = true;
+            }
+        }
+
+        if !found {
+            return Err(invalid_profile(
+                "expected an iTerm2 .itermcolors file or X resources that set colors like \
+                 `*.color1: #ab4642`",
+            ));
+        }
+
+        Ok(profile)
+    }
+}
+
+impl FromStr for ColorProfile {
+    type Err = Error;
+
+    /// Parse the contents of an iTerm2 `.itermcolors` file or an X resources file
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains("<plist") {
+            ColorProfile::from_itermcolors(s)
+        } else {
+            ColorProfile::from_xresources(s)
+        }
+    }
+}
+
+/// Get the error for a profile that can't be read
+fn invalid_profile(reason: &str) -> Error {
+    Error::Generic(format!("Invalid color profile: {}", reason))
+}
+
+/// Find the next `<tag>` element, returning its text and the rest of the document after it
+fn next_element<'a>(document: &'a str, tag: &str) -> Option<(&'a str, &'a str)> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = document.find(&open)? + open.len();
+    let end = start + document[start..].find(&close)?;
+
+    Some((&document[start..end], &document[end + close.len()..]))
+}
+
+/// Parse an X color, such as `#ab4642`, `#a44`, or `rgb:ab/46/42`
+pub(crate) fn parse_x_color(color: &str) -> Option<Rgb> {
+    // Scale a hex channel of 1 to 4 digits to 8 bits
+    let channel = |hex: &str| -> Option<u8> {
+        if hex.is_empty() || hex.len() > 4 {
+            return None;
+        }
+        let value = u32::from_str_radix(hex, 16).ok()?;
+        let max = (1u32 << (hex.len() * 4)) - 1;
+        Some((value * 255 / max) as u8)
+    };
+
+    if let Some(hex) = color.strip_prefix('#') {
+        if hex.len() % 3 != 0 || !hex.is_ascii() {
+            return None;
+        }
+        let len = hex.len() / 3;
+        Some((
+            channel(&hex[..len])?,
+            channel(&hex[len..len * 2])?,
+            channel(&hex[len * 2..])?,
+        ))
+    } else {
+        let mut channels = color.strip_prefix("rgb:")?.split('/');
+        let rgb = (
+            channel(channels.next()?)?,
+            channel(channels.next()?)?,
+            channel(channels.next()?)?,
+        );
+        match channels.next() {
+            Some(_) => None,
+            None => Some(rgb),
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Raising the contrast of text that is hard to read
+//!
+//! Text whose color is too close to the color behind it is redrawn lighter or darker, just enough
+//! to reach a minimum [WCAG contrast ratio], so that recordings made with low-contrast themes stay
+//! readable for low-vision viewers. Text that already has enough contrast keeps its color.
+//!
+//! [WCAG contrast ratio]: https://www.w3.org/TR/WCAG21/#dfn-contrast-ratio
+
+use crate::cast_parser::AsciinemaError;
+use crate::color_profile::Rgb;
+use crate::highlight::mix;
+use crate::types::{ScreenSnapshot, TerminalFrame};
+use crate::ColorProfile;
+
+/// The number of steps to search for the least change of a color that reaches the contrast
+const SEARCH_STEPS: u32 = 12;
+
+/// Get the linear light of an sRGB channel, from `0` to `1`
+pub(crate) fn linear(channel: u8) -> f64 {
+    let channel = f64::from(channel) / 255.;
+    if channel <= 0.039_28 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Get the relative luminance of an sRGB color, from `0` for black to `1` for white
+pub(crate) fn luminance((r, g, b): Rgb) -> f64 {
+    0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+}
+
+/// Get the contrast ratio of two colors, from `1` for none to `21` for black on white
+pub(crate) fn contrast_ratio(a: Rgb, b: Rgb) -> f64 {
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// Get the color closest to `color` that has at least `min_contrast` with `background`
+///
+/// The color is moved toward white or black, whichever stands out more from the background. If
+/// even that can't reach the contrast, it is the closest the color can get.
+fn with_contrast(color: Rgb, background: Rgb, min_contrast: f64) -> Rgb {
+    if contrast_ratio(color, background) >= min_contrast {
+        return color;
+    }
+
+    let (white, black) = ((255, 255, 255), (0, 0, 0));
+    let target = if contrast_ratio(white, background) >= contrast_ratio(black, background) {
+        white
+    } else {
+        black
+    };
+    if contrast_ratio(target, background) <= min_contrast {
+        return target;
+    }
+
+    // The contrast only grows as the color moves toward the target, so the least amount that
+    // reaches it can be searched for
+    let (mut low, mut high) = (0., 1.);
+    for _ in 0..SEARCH_STEPS {
+        let amount = (low + high) / 2.;
+        if contrast_ratio(mix(color, target, amount), background) >= min_contrast {
+            high = amount;
+        } else {
+            low = amount;
+        }
+    }
+
+    mix(color, target, high)
+}
+
+/// An iterator adapter that raises the contrast of text that is too close to the color behind it
+pub(crate) struct ContrastFrames<I> {
+    /// The frames to raise the contrast of
+    frames: I,
+    /// The contrast ratio that text must have, or `None` to leave the frames as they are
+    min_contrast: Option<f64>,
+    /// The colors that the frames are rendered with
+    profile: ColorProfile,
+}
+
+impl<I> ContrastFrames<I> {
+    /// Make the text of the frames have at least `min_contrast` with the color behind it, as
+    /// rendered with the colors of the `profile`
+    ///
+    /// Frames are passed through untouched if `min_contrast` is `None`.
+    pub fn new(frames: I, min_contrast: Option<f64>, profile: ColorProfile) -> Self {
+        ContrastFrames {
+            frames,
+            min_contrast,
+            profile,
+        }
+    }
+
+    /// Change the colors of the text on a screen that doesn't have enough contrast
+    fn raise_contrast(&self, screen: &mut ScreenSnapshot, min_contrast: f64) {
+        let (rows, cols) = screen.size();
+        for row in 0..rows {
+            for col in 0..cols {
+                let cell = screen
+                    .cell_mut(row, col)
+                    .expect("The cell is on the screen");
+                // The colors of empty cells can't be seen
+                if cell.contents.is_none() {
+                    continue;
+                }
+
+                let foreground = self
+                    .profile
+                    .color(cell.fgcolor)
+                    .unwrap_or(self.profile.foreground);
+                let background = self
+                    .profile
+                    .color(cell.bgcolor)
+                    .unwrap_or(self.profile.background);
+                let color = with_contrast(foreground, background, min_contrast);
+                if color != foreground {
+                    let (r, g, b) = color;
+                    cell.fgcolor = vt100::Color::Rgb(r, g, b);
+                }
+            }
+        }
+    }
+}
+
+impl<I> Iterator for ContrastFrames<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = match self.frames.next()? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        if let Some(min_contrast) = self.min_contrast {
+            self.raise_contrast(&mut frame.screen, min_contrast);
+        }
+
+        Some(Ok(frame))
+    }
+}
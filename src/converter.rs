@@ -0,0 +1,302 @@
+//! A conversion pipeline that is set up once and reused
+//!
+//! Every conversion function sets up what it needs the first time it is used: the thread pool that
+//! frames are rendered on, and the font and glyph canvas of each thread in it. Servers and batch
+//! jobs that render lots of casts can make a [`Converter`] up front instead, so that none of the
+//! renders pay for that setup.
+//!
+//! The conversion functions are run through a converter too, one that sets the thread pool up
+//! when it renders, so that every kind of input and output goes through the same pipeline.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::cast_parser::{AsciinemaError, TerminalFrameIter};
+use crate::side_by_side::SideBySideCasts;
+use crate::types::{
+    CastProgressHandler, ConvertSettings, NullProgressHandler, PngSequenceSettings, SvgSettings,
+    TerminalFrame, VideoSettings,
+};
+use crate::Error;
+
+/// The frames of a cast, with the (rows, cols) size of the terminal that they are on and the title
+/// of the cast
+pub(crate) type CastFrames<Fi> = (Fi, (u16, u16), Option<String>);
+
+/// Where the frames of a conversion are read from
+pub(crate) trait CastSource {
+    type Frames: Iterator<Item = Result<TerminalFrame, AsciinemaError>>;
+
+    /// Start reading the frames of the cast, timed by the settings
+    fn frames(self, settings: &ConvertSettings) -> Result<CastFrames<Self::Frames>, Error>;
+}
+
+/// A cast file that is read from a reader
+pub(crate) struct CastReader<R>(pub(crate) R);
+
+impl<R: Read> CastSource for CastReader<R> {
+    type Frames = TerminalFrameIter<R>;
+
+    fn frames(self, settings: &ConvertSettings) -> Result<CastFrames<Self::Frames>, Error> {
+        with_settings(TerminalFrameIter::new(self.0)?, settings)
+    }
+}
+
+/// A cast file that is read through a memory map
+///
+/// The file must not be modified while it is being read.
+pub(crate) struct MappedCast<'a>(pub(crate) &'a File);
+
+impl CastSource for MappedCast<'_> {
+    type Frames = TerminalFrameIter<std::io::Empty>;
+
+    fn frames(self, settings: &ConvertSettings) -> Result<CastFrames<Self::Frames>, Error> {
+        with_settings(TerminalFrameIter::from_file_mapped(self.0)?, settings)
+    }
+}
+
+/// Two cast files that are rendered side by side
+pub(crate) struct SideBySide<L, R>(pub(crate) L, pub(crate) R);
+
+impl<L: Read, R: Read> CastSource for SideBySide<L, R> {
+    type Frames = SideBySideCasts<L, R>;
+
+    fn frames(self, settings: &ConvertSettings) -> Result<CastFrames<Self::Frames>, Error> {
+        crate::side_by_side_frames(self.0, self.1, settings)
+    }
+}
+
+/// Time the frames of a cast by the settings
+fn with_settings<R: Read>(
+    term_frames: TerminalFrameIter<R>,
+    settings: &ConvertSettings,
+) -> Result<CastFrames<TerminalFrameIter<R>>, Error> {
+    let term_frames = term_frames.with_settings(settings);
+    let size = term_frames.size();
+    let cast_title = term_frames.title();
+
+    Ok((term_frames, size, cast_title))
+}
+
+/// A conversion pipeline for rendering any number of casts with the same settings
+///
+/// The thread pool is attached and the renderer is warmed up on every thread of it when the
+/// converter is made. Casts may be converted one after the other or at the same time from
+/// different threads, and clones of the converter share the same pool.
+#[derive(Clone)]
+pub struct Converter {
+    /// The settings of the conversions, with the thread pool filled in
+    settings: ConvertSettings,
+}
+
+impl Converter {
+    /// Make a converter with the given settings
+    ///
+    /// If the settings don't have a thread pool, one is built from `threads`, or the default pool
+    /// is used.
+    pub fn new(mut settings: ConvertSettings) -> Result<Self, Error> {
+        let thread_pool = crate::thread_pool(&settings)?;
+        warm_up(&thread_pool);
+        settings.thread_pool = Some(thread_pool);
+
+        Ok(Converter { settings })
+    }
+
+    /// Make a converter with other settings that renders on the thread pool of this one
+    ///
+    /// This is for jobs that each have their own settings, such as the requests of a server. The
+    /// `threads` and `thread_pool` of the new settings are ignored.
+    pub fn with_settings(&self, mut settings: ConvertSettings) -> Self {
+        settings.thread_pool = self.settings.thread_pool.clone();
+
+        Converter { settings }
+    }
+
+    /// Make a converter that sets its thread pool up when it renders, for the conversion functions
+    ///
+    /// The renderer isn't warmed up, and the converter must not be asked for its thread pool.
+    pub(crate) fn lazy(settings: ConvertSettings) -> Self {
+        Converter { settings }
+    }
+
+    /// Get the settings that casts are converted with
+    pub fn settings(&self) -> &ConvertSettings {
+        &self.settings
+    }
+
+    /// Get the thread pool that frames are rendered on
+    pub fn thread_pool(&self) -> &Arc<rayon::ThreadPool> {
+        self.settings
+            .thread_pool
+            .as_ref()
+            .expect("The converter has a thread pool")
+    }
+
+    /// Convert a cast to a gif, like [`convert_to_gif`](crate::convert_to_gif)
+    pub fn convert_to_gif<R, W>(&self, reader: R, writer: W) -> Result<(), Error>
+    where
+        R: Read + Send + 'static,
+        W: Write + Send,
+    {
+        self.clone()
+            .render_gif(CastReader(reader), writer, NullProgressHandler)
+    }
+
+    /// Convert a cast to a gif, reporting the progress of the conversion, like
+    /// [`convert_to_gif_with_progress`](crate::convert_to_gif_with_progress)
+    pub fn convert_to_gif_with_progress<R, W, C>(
+        &self,
+        reader: R,
+        writer: W,
+        update_progress: C,
+    ) -> Result<(), Error>
+    where
+        R: Read + Send + 'static,
+        W: Write + Send,
+        C: CastProgressHandler + 'static,
+    {
+        self.clone()
+            .render_gif(CastReader(reader), writer, update_progress)
+    }
+
+    /// Convert a cast to an animated SVG, like [`convert_to_svg`](crate::convert_to_svg)
+    pub fn convert_to_svg<R, W>(
+        &self,
+        reader: R,
+        writer: W,
+        svg_settings: SvgSettings,
+    ) -> Result<(), Error>
+    where
+        R: Read,
+        W: Write,
+    {
+        self.clone()
+            .render_svg(CastReader(reader), writer, svg_settings)
+    }
+
+    /// Convert a cast to an mp4 video, like [`convert_to_mp4`](crate::convert_to_mp4)
+    pub fn convert_to_mp4<R, W>(
+        &self,
+        reader: R,
+        writer: W,
+        video_settings: VideoSettings,
+    ) -> Result<(), Error>
+    where
+        R: Read,
+        W: Write + Send,
+    {
+        self.clone()
+            .render_mp4(CastReader(reader), writer, video_settings)
+    }
+
+    /// Convert a cast to a kitty animation, like [`convert_to_kitty`](crate::convert_to_kitty)
+    pub fn convert_to_kitty<R: Read, W: Write>(&self, reader: R, writer: W) -> Result<(), Error> {
+        self.clone().render_kitty(CastReader(reader), writer)
+    }
+
+    /// Convert a cast to a sequence of png images in a directory, like
+    /// [`convert_to_png_sequence`](crate::convert_to_png_sequence)
+    pub fn convert_to_png_sequence<R: Read>(
+        &self,
+        reader: R,
+        dir: &Path,
+        png_settings: PngSequenceSettings,
+    ) -> Result<(), Error> {
+        self.clone()
+            .render_png_sequence(CastReader(reader), dir, png_settings)
+    }
+
+    /// Render a cast to a gif
+    pub(crate) fn render_gif<S, W, C>(
+        self,
+        cast: S,
+        writer: W,
+        update_progress: C,
+    ) -> Result<(), Error>
+    where
+        S: CastSource,
+        S::Frames: Send + 'static,
+        W: Write + Send,
+        C: CastProgressHandler + 'static,
+    {
+        let (frames, size, cast_title) = cast.frames(&self.settings)?;
+
+        crate::render_gif(
+            frames,
+            size,
+            cast_title,
+            writer,
+            update_progress,
+            self.settings,
+        )
+    }
+
+    /// Render a cast to an animated SVG
+    pub(crate) fn render_svg<S: CastSource, W: Write>(
+        self,
+        cast: S,
+        writer: W,
+        svg_settings: SvgSettings,
+    ) -> Result<(), Error> {
+        let (frames, size, cast_title) = cast.frames(&self.settings)?;
+
+        crate::render_svg(
+            frames,
+            size,
+            cast_title,
+            writer,
+            self.settings,
+            svg_settings,
+        )
+    }
+
+    /// Render a cast to an mp4 video
+    pub(crate) fn render_mp4<S: CastSource, W: Write + Send>(
+        self,
+        cast: S,
+        writer: W,
+        video_settings: VideoSettings,
+    ) -> Result<(), Error> {
+        let (frames, size, cast_title) = cast.frames(&self.settings)?;
+
+        crate::render_mp4(
+            frames,
+            size,
+            cast_title,
+            writer,
+            self.settings,
+            video_settings,
+        )
+    }
+
+    /// Render a cast to a kitty animation
+    pub(crate) fn render_kitty<S: CastSource, W: Write>(
+        self,
+        cast: S,
+        writer: W,
+    ) -> Result<(), Error> {
+        let (frames, size, cast_title) = cast.frames(&self.settings)?;
+
+        crate::render_kitty(frames, size, cast_title, writer, self.settings)
+    }
+
+    /// Render a cast to a sequence of png images in a directory
+    pub(crate) fn render_png_sequence<S: CastSource>(
+        self,
+        cast: S,
+        dir: &Path,
+        png_settings: PngSequenceSettings,
+    ) -> Result<(), Error> {
+        let (frames, size, cast_title) = cast.frames(&self.settings)?;
+
+        crate::render_png_sequence(frames, size, cast_title, dir, self.settings, png_settings)
+    }
+}
+
+/// Warm up the renderer on every thread of the pool, so that the first frames rendered on them
+/// don't have to load the font
+fn warm_up(thread_pool: &rayon::ThreadPool) {
+    thread_pool.broadcast(|_| crate::frame_renderer::warm_up());
+}
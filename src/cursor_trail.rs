@@ -0,0 +1,168 @@
+//! Drawing a halo around the cursor and a trail behind it
+//!
+//! The cells around the cursor are drawn with a tinted background, and so are the cells that the
+//! cursor passed over on its way from where it was in the previous frame. The trail fades back to
+//! the background of the cells over the frames after it, so that viewers can follow the cursor
+//! when it jumps around the screen, such as in editor demos.
+
+use std::collections::HashMap;
+
+use crate::cast_parser::AsciinemaError;
+use crate::color_profile::Rgb;
+use crate::highlight::mix;
+use crate::types::{ScreenSnapshot, TerminalFrame};
+use crate::ColorProfile;
+
+/// The color that the halo and the trail are tinted with
+const CURSOR_TRAIL_COLOR: Rgb = (100, 170, 255);
+
+/// How much of the tint is mixed into the background of the cell under the cursor
+const HALO_OPACITY: f64 = 0.35;
+
+/// How much of the tint is mixed into the background of the cells that the cursor just left
+const TRAIL_OPACITY: f64 = 0.3;
+
+/// The number of rows above and below the cursor that the halo covers. It covers twice as many
+/// columns, because cells are about twice as tall as they are wide.
+const HALO_RADIUS: i32 = 1;
+
+/// An iterator adapter that draws a halo around the cursor and a fading trail behind it
+pub(crate) struct CursorTrailFrames<I> {
+    /// The frames to draw the cursor trail on
+    frames: I,
+    /// The number of frames that the trail takes to fade out
+    fade_frames: u32,
+    /// The colors that the frames are rendered with, which the tint is mixed with
+    profile: ColorProfile,
+    /// The size of the screen of the previous frame
+    size: (u16, u16),
+    /// The positions of the cursors in the previous frame
+    previous_cursors: Vec<(u16, u16)>,
+    /// The number of frames since the cursor passed over each cell of the trail
+    trail: HashMap<(u16, u16), u32>,
+}
+
+impl<I> CursorTrailFrames<I> {
+    /// Draw a halo around the cursor and a trail behind it that fades out over `fade_frames`
+    /// frames
+    ///
+    /// The tint is mixed with the background colors of the `profile` that the frames are rendered
+    /// with. Frames are passed through untouched if `fade_frames` is `0`.
+    pub fn new(frames: I, fade_frames: u32, profile: ColorProfile) -> Self {
+        CursorTrailFrames {
+            frames,
+            fade_frames,
+            profile,
+            size: (0, 0),
+            previous_cursors: Vec::new(),
+            trail: HashMap::new(),
+        }
+    }
+
+    /// Add the cells that the cursors passed over since the previous frame to the trail, and age
+    /// the rest of it
+    fn update_trail(&mut self, screen: &ScreenSnapshot) {
+        // The trail doesn't line up with the screen anymore if its size changed
+        if screen.size() != self.size {
+            self.size = screen.size();
+            self.trail.clear();
+            self.previous_cursors.clear();
+        }
+
+        let fade_frames = self.fade_frames;
+        self.trail.retain(|_, age| {
+            *age += 1;
+            *age < fade_frames
+        });
+
+        // Screens that are put side by side keep their cursors in the same order
+        for (&from, &to) in self.previous_cursors.iter().zip(screen.cursors()) {
+            for cell in path(from, to) {
+                self.trail.insert(cell, 0);
+            }
+        }
+        self.previous_cursors = screen.cursors().to_vec();
+    }
+
+    /// Tint the cells around the cursors and along the trail
+    fn draw(&mut self, screen: &mut ScreenSnapshot) {
+        if self.fade_frames == 0 {
+            return;
+        }
+        self.update_trail(screen);
+
+        // The opacity of the tint of each cell, keeping the strongest where the halo and the trail
+        // overlap
+        let mut opacities: HashMap<(u16, u16), f64> = HashMap::new();
+        let mut tint = |cell: (u16, u16), opacity: f64| {
+            let current = opacities.entry(cell).or_insert(0.);
+            *current = current.max(opacity);
+        };
+        for (&cell, &age) in &self.trail {
+            tint(
+                cell,
+                TRAIL_OPACITY * f64::from(self.fade_frames - age) / f64::from(self.fade_frames),
+            );
+        }
+        for &(row, col) in screen.cursors() {
+            for row_offset in -HALO_RADIUS..=HALO_RADIUS {
+                for col_offset in -HALO_RADIUS * 2..=HALO_RADIUS * 2 {
+                    let distance = (row_offset.abs() * 2).max(col_offset.abs());
+                    let row = i32::from(row) + row_offset;
+                    let col = i32::from(col) + col_offset;
+                    if row < 0 || col < 0 {
+                        continue;
+                    }
+                    let opacity = HALO_OPACITY * f64::from(HALO_RADIUS * 2 + 1 - distance)
+                        / f64::from(HALO_RADIUS * 2 + 1);
+                    tint((row as u16, col as u16), opacity);
+                }
+            }
+        }
+
+        for ((row, col), opacity) in opacities {
+            let cell = match screen.cell_mut(row, col) {
+                Some(cell) => cell,
+                None => continue,
+            };
+            let background = self
+                .profile
+                .color(cell.bgcolor)
+                .unwrap_or(self.profile.background);
+            let (r, g, b) = mix(background, CURSOR_TRAIL_COLOR, opacity);
+            cell.bgcolor = vt100::Color::Rgb(r, g, b);
+        }
+    }
+}
+
+/// Get the cells on the straight line from one cell to another, leaving out the last one
+fn path(from: (u16, u16), to: (u16, u16)) -> impl Iterator<Item = (u16, u16)> {
+    let row_distance = i32::from(to.0) - i32::from(from.0);
+    let col_distance = i32::from(to.1) - i32::from(from.1);
+    let steps = row_distance.abs().max(col_distance.abs());
+
+    (0..steps).map(move |step| {
+        let along = |start: u16, distance: i32| {
+            let offset = (f64::from(distance) * f64::from(step) / f64::from(steps)).round();
+            (f64::from(start) + offset) as u16
+        };
+        (along(from.0, row_distance), along(from.1, col_distance))
+    })
+}
+
+impl<I> Iterator for CursorTrailFrames<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = match self.frames.next()? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        self.draw(&mut frame.screen);
+
+        Some(Ok(frame))
+    }
+}
@@ -0,0 +1,263 @@
+//! Showing an image at the end of the animation
+//!
+//! An end card, such as one that asks viewers to star a project, is shown after the last frame of
+//! the recording has been held. The image is scaled to fit the frames, keeping its aspect ratio,
+//! and the space around it is filled with the terminal background.
+
+use std::fmt;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+use crate::color_profile::Rgb;
+use crate::types::RgbaFrame;
+use crate::{ColorProfile, Error, Monochrome};
+
+/// The time an end card is shown for when none is given, in seconds
+#[cfg_attr(not(feature = "cli"), allow(dead_code))]
+pub(crate) const DEFAULT_END_CARD_HOLD: f64 = 3.;
+
+/// An image to show after the last frame of the animation
+///
+/// Cloning an end card is cheap, and the clones share their image.
+#[derive(Clone)]
+pub struct EndCard {
+    /// The image to show
+    image: Arc<ImgVec<RGBA8>>,
+    /// The time to show the image for, in seconds
+    hold: f64,
+    /// The color to fill the frame around the image with
+    background: Rgb,
+}
+
+impl EndCard {
+    /// Make an end card from an image that is shown for `hold` seconds
+    pub fn new(image: ImgVec<RGBA8>, hold: f64) -> Self {
+        EndCard {
+            image: Arc::new(image),
+            hold,
+            background: ColorProfile::default().background,
+        }
+    }
+
+    /// Read an end card from a PNG image that is shown for `hold` seconds
+    pub fn from_png<R: Read>(reader: R, hold: f64) -> Result<Self, Error> {
+        let error = |e: &dyn fmt::Display| Error::Generic(format!("Could not read PNG: {}", e));
+
+        let mut decoder = png::Decoder::new(BufReader::new(reader));
+        // Expand palettes and low bit depths to 8 bit channels
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| error(&e))?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| error(&e))?;
+        buf.truncate(info.buffer_size());
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => buf
+                .chunks_exact(4)
+                .map(|rgba| RGBA8::new(rgba[0], rgba[1], rgba[2], rgba[3]))
+                .collect(),
+            png::ColorType::Rgb => buf
+                .chunks_exact(3)
+                .map(|rgb| RGBA8::new(rgb[0], rgb[1], rgb[2], 255))
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .map(|ga| RGBA8::new(ga[0], ga[0], ga[0], ga[1]))
+                .collect(),
+            png::ColorType::Grayscale => buf.iter().map(|&g| RGBA8::new(g, g, g, 255)).collect(),
+            png::ColorType::Indexed => {
+                return Err(error(&"Palettes should have been expanded"));
+            }
+        };
+
+        Ok(Self::new(
+            ImgVec::new(pixels, info.width as usize, info.height as usize),
+            hold,
+        ))
+    }
+
+    /// Load an end card from a PNG file that is shown for `hold` seconds
+    pub fn from_file<P: AsRef<Path>>(path: P, hold: f64) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let error = |e: &dyn fmt::Display| {
+            Error::Generic(format!(
+                "Could not read end card {}: {}",
+                path.to_string_lossy(),
+                e
+            ))
+        };
+
+        let file = std::fs::File::open(path).map_err(|e| error(&e))?;
+        Self::from_png(file, hold).map_err(|e| error(&e))
+    }
+
+    /// Get the time that the end card is shown for, in seconds
+    pub fn hold(&self) -> f64 {
+        self.hold
+    }
+
+    /// Get the end card with the space around its image filled with `background`
+    pub(crate) fn with_background(&self, background: Rgb) -> Self {
+        EndCard {
+            background,
+            ..self.clone()
+        }
+    }
+
+    /// Get the end card redrawn in the colors of a monochrome render
+    pub(crate) fn monochrome(&self, monochrome: &Monochrome) -> Self {
+        let mut image = (*self.image).clone();
+        monochrome.apply(&mut image);
+        EndCard {
+            image: Arc::new(image),
+            hold: self.hold,
+            background: monochrome.rgb(self.background),
+        }
+    }
+
+    /// Get the frame that shows the end card after the last frame of the animation, once the last
+    /// frame has been held for `hold_last` seconds
+    ///
+    /// The last frame is shown for at least the shortest delay that gifs play reliably, even if
+    /// it isn't held.
+    pub(crate) fn frame_after(&self, last: LastFrame, hold_last: f64) -> RgbaFrame {
+        let hold_last = hold_last.max(crate::min_delay::DEFAULT_MIN_FRAME_DELAY);
+        RgbaFrame {
+            index: last.index + 1,
+            time: last.time + hold_last * 100.,
+            image: self.fit(last.width, last.height),
+        }
+    }
+
+    /// Scale the image to fit in a frame of the given size, keeping its aspect ratio, and fill the
+    /// rest of the frame with the terminal background
+    fn fit(&self, width: usize, height: usize) -> ImgVec<RGBA8> {
+        let (r, g, b) = self.background;
+        let background = RGBA8::new(r, g, b, 255);
+        let image = &*self.image;
+        if image.width() == 0 || image.height() == 0 {
+            return ImgVec::new(vec![background; width * height], width, height);
+        }
+
+        let scale =
+            (width as f64 / image.width() as f64).min(height as f64 / image.height() as f64);
+        let scaled_width = ((image.width() as f64 * scale).round() as usize).min(width);
+        let scaled_height = ((image.height() as f64 * scale).round() as usize).min(height);
+        let left = (width - scaled_width) / 2;
+        let top = (height - scaled_height) / 2;
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                if x < left || x >= left + scaled_width || y < top || y >= top + scaled_height {
+                    pixels.push(background);
+                    continue;
+                }
+
+                let source_x = ((x - left) as f64 + 0.5) / scale - 0.5;
+                let source_y = ((y - top) as f64 + 0.5) / scale - 0.5;
+                let color = crate::frame_renderer::sample(image, source_x, source_y);
+                pixels.push(over(color, background));
+            }
+        }
+
+        ImgVec::new(pixels, width, height)
+    }
+}
+
+impl fmt::Debug for EndCard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EndCard")
+            .field("width", &self.image.width())
+            .field("height", &self.image.height())
+            .field("hold", &self.hold)
+            .finish()
+    }
+}
+
+/// The index, time, and size of the last frame of an animation, which the end card follows
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LastFrame {
+    index: u64,
+    /// The time of the frame, in hundredths of a second
+    time: f64,
+    width: usize,
+    height: usize,
+}
+
+impl LastFrame {
+    pub fn of(frame: &RgbaFrame) -> Self {
+        LastFrame {
+            index: frame.index,
+            time: frame.time,
+            width: frame.image.width(),
+            height: frame.image.height(),
+        }
+    }
+}
+
+/// Draw a color over an opaque background
+fn over(color: RGBA8, background: RGBA8) -> RGBA8 {
+    let alpha = f64::from(color.a) / 255.;
+    let channel = |a: u8, b: u8| (f64::from(a) * alpha + f64::from(b) * (1. - alpha)).round() as u8;
+
+    RGBA8::new(
+        channel(color.r, background.r),
+        channel(color.g, background.g),
+        channel(color.b, background.b),
+        255,
+    )
+}
+
+/// An iterator adapter that adds the frame of an end card after the last frame
+///
+/// The frames must be in order. The end card is shown once the last frame has been held for
+/// `hold_last` seconds, and the encoder is left to hold the end card itself.
+pub(crate) struct EndCardFrames<I> {
+    /// The frames to add the end card to
+    frames: I,
+    /// The end card, until its frame is added
+    end_card: Option<EndCard>,
+    /// The time to hold the last frame for before the end card, in seconds
+    hold_last: f64,
+    /// The last frame so far
+    last: Option<LastFrame>,
+}
+
+impl<I> EndCardFrames<I> {
+    /// Add the end card, if there is one, after the frames
+    pub fn new(frames: I, end_card: Option<EndCard>, hold_last: f64) -> Self {
+        EndCardFrames {
+            frames,
+            end_card,
+            hold_last,
+            last: None,
+        }
+    }
+}
+
+impl<I> Iterator for EndCardFrames<I>
+where
+    I: Iterator<Item = Result<RgbaFrame, Error>>,
+{
+    type Item = Result<RgbaFrame, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.frames.next() {
+            Some(Ok(frame)) => {
+                self.last = Some(LastFrame::of(&frame));
+                Some(Ok(frame))
+            }
+            Some(Err(e)) => Some(Err(e)),
+            None => {
+                let end_card = self.end_card.take()?;
+                let last = self.last.take()?;
+                Some(Ok(end_card.frame_after(last, self.hold_last)))
+            }
+        }
+    }
+}
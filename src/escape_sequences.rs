@@ -0,0 +1,237 @@
+//! Finding the escape sequences in a cast that don't show up in the gif
+//!
+//! `vt100` quietly skips the escape sequences that it doesn't understand, and the renderers only
+//! draw the text and colors of each cell, so a recording can look quite different in the gif than
+//! it did in the terminal. The output of the cast is scanned a second time here to tell the user
+//! which sequences were dropped.
+
+use std::collections::BTreeMap;
+
+/// The most distinct sequences to keep track of, so that a cast full of garbage can't use up
+/// memory. Any more are only counted.
+const MAX_DISTINCT_SEQUENCES: usize = 64;
+
+/// The DECSET modes that only change how the terminal handles input or reports to the application,
+/// which doesn't matter for a recording
+const INPUT_ONLY_MODES: &[i64] = &[
+    12,   // Blinking cursor
+    1004, // Focus events
+    1015, // urxvt mouse encoding
+    2026, // Synchronized output
+];
+
+/// The OSC commands that don't change what is on the screen
+const INVISIBLE_OSC_COMMANDS: &[&[u8]] = &[
+    b"7",   // Working directory
+    b"8",   // Hyperlinks
+    b"52",  // Clipboard
+    b"133", // Shell prompt marks
+];
+
+/// The escape sequences in a cast that were dropped while emulating or rendering the terminal
+#[derive(Default)]
+pub(crate) struct UnsupportedSequences {
+    /// The parser that finds the sequences in the output
+    parser: vte::Parser,
+    /// The sequences that were found
+    found: FoundSequences,
+}
+
+impl UnsupportedSequences {
+    /// Scan some of the output of the cast for unsupported sequences
+    pub fn process(&mut self, output: &[u8]) {
+        for &byte in output {
+            self.parser.advance(&mut self.found, byte);
+        }
+    }
+
+    /// Log a warning listing the unsupported sequences that have been found since the last time
+    /// this was called
+    pub fn warn(&mut self) {
+        let found = std::mem::take(&mut self.found);
+        if found.counts.is_empty() {
+            return;
+        }
+
+        let mut summary = String::new();
+        for (sequence, count) in &found.counts {
+            let times = if *count == 1 {
+                "once".into()
+            } else {
+                format!("{} times", count)
+            };
+            summary.push_str(&format!("\n    {}, {}", sequence, times));
+        }
+        if found.others > 0 {
+            summary.push_str(&format!("\n    {} other sequences", found.others));
+        }
+
+        log::warn!(
+            "The cast uses terminal features that aren't supported, so the gif may not look the \
+             same as the recording did in a terminal:{}",
+            summary
+        );
+    }
+}
+
+/// The unsupported sequences found in the output, counted by their description
+#[derive(Default)]
+struct FoundSequences {
+    /// The number of times each sequence was found
+    counts: BTreeMap<String, usize>,
+    /// The number of sequences found after there were too many distinct ones to keep track of
+    others: usize,
+}
+
+impl FoundSequences {
+    /// Count a sequence
+    fn add(&mut self, sequence: String) {
+        if let Some(count) = self.counts.get_mut(&sequence) {
+            *count += 1;
+        } else if self.counts.len() < MAX_DISTINCT_SEQUENCES {
+            self.counts.insert(sequence, 1);
+        } else {
+            self.others += 1;
+        }
+    }
+
+    /// Count the SGR attributes that can't be drawn
+    fn sgr(&mut self, params: &[i64]) {
+        let mut params = params.iter();
+        while let Some(&param) = params.next() {
+            let attribute = match param {
+                // The fontkit backend draws bold and italic text in their faces
+                #[cfg(not(feature = "backend-fontkit"))]
+                1 => "bold",
+                2 => "faint",
+                #[cfg(not(feature = "backend-fontkit"))]
+                3 => "italic",
+                5 | 6 => "blink",
+                7 => "inverse",
+                8 => "hidden",
+                9 => "strikethrough",
+                53 => "overline",
+                // Extended colors, whose remaining parameters aren't attributes
+                38 | 48 | 58 => {
+                    match params.next() {
+                        Some(2) => params.nth(2),
+                        Some(5) => params.next(),
+                        Some(n) => {
+                            self.add(format!("SGR {} {} (color)", param, n));
+                            return;
+                        }
+                        None => None,
+                    };
+                    continue;
+                }
+                _ => continue,
+            };
+            self.add(format!("SGR {} ({} text)", param, attribute));
+        }
+    }
+}
+
+/// Format the parameters of a sequence like `vt100` does in its logs
+fn param_str(params: &[i64]) -> String {
+    params
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// This matches the sequences that `vt100` handles in its own `vte::Perform` implementation
+impl vte::Perform for FoundSequences {
+    fn print(&mut self, _: char) {}
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            // NUL is padding
+            0 | 7..=13 => (),
+            // The character set shifts are used along with the line drawing character set
+            14 => self.add("SO (shift out to the G1 character set)".into()),
+            15 => self.add("SI (shift in to the G0 character set)".into()),
+            _ => self.add(format!("control character {:#04x}", byte)),
+        }
+    }
+
+    fn hook(&mut self, params: &[i64], intermediates: &[u8], _ignore: bool, action: char) {
+        match intermediates.first() {
+            None => self.add(format!("DCS {} {}", param_str(params), action)),
+            Some(&i) => self.add(format!(
+                "DCS {} {} {}",
+                i as char,
+                param_str(params),
+                action
+            )),
+        }
+    }
+
+    fn put(&mut self, _: u8) {}
+
+    fn unhook(&mut self) {}
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        match params.first() {
+            // The window title and icon name
+            Some(&b"0") | Some(&b"1") | Some(&b"2") => (),
+            Some(command) if INVISIBLE_OSC_COMMANDS.contains(command) => (),
+            Some(command) => self.add(format!("OSC {}", String::from_utf8_lossy(command))),
+            None => (),
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &[i64], intermediates: &[u8], _ignore: bool, c: char) {
+        match (intermediates.first(), c) {
+            (None, 'm') => self.sgr(params),
+            (None, '@'..='D')
+            | (None, 'G')
+            | (None, 'H')
+            | (None, 'L')
+            | (None, 'M')
+            | (None, 'P')
+            | (None, 'S')
+            | (None, 'T')
+            | (None, 'X')
+            | (None, 'd')
+            | (None, 'r') => (),
+            (None, 'J') | (None, 'K') | (Some(b'?'), 'J') | (Some(b'?'), 'K') => {
+                // Mode 3 clears the scrollback, which isn't kept
+                if params.iter().any(|&p| p > 3) {
+                    self.add(format!("CSI {} {}", param_str(params), c));
+                }
+            }
+            // Reports to the application and window manipulation don't change the screen, and
+            // cursor shapes aren't drawn
+            (None, 'c') | (None, 'n') | (None, 't') | (Some(b'>'), _) | (Some(b' '), 'q') => (),
+            (Some(b'?'), 'h') | (Some(b'?'), 'l') => {
+                for &mode in params {
+                    match mode {
+                        1 | 6 | 9 | 25 | 47 | 1000 | 1002 | 1003 | 1005 | 1006 | 1049 | 2004 => (),
+                        mode if INPUT_ONLY_MODES.contains(&mode) => (),
+                        mode => self.add(format!("CSI ? {} {}", mode, c)),
+                    }
+                }
+            }
+            (None, _) => self.add(format!("CSI {} {}", param_str(params), c)),
+            (Some(&i), _) => self.add(format!("CSI {} {} {}", i as char, param_str(params), c)),
+        }
+    }
+
+    fn esc_dispatch(&mut self, _params: &[i64], intermediates: &[u8], _ignore: bool, byte: u8) {
+        match (intermediates.first(), byte) {
+            (None, b'7') | (None, b'8') | (None, b'=') | (None, b'>') => (),
+            (None, b'M') | (None, b'c') | (None, b'g') => (),
+            // The string terminator at the end of OSC sequences
+            (None, b'\\') => (),
+            // Selecting the default character set
+            (Some(b'('), b'B') | (Some(b')'), b'B') => (),
+            (Some(b'('), b'0') | (Some(b')'), b'0') => self.add(format!(
+                "ESC {} 0 (line drawing character set)",
+                intermediates[0] as char
+            )),
+            (None, _) => self.add(format!("ESC {}", byte as char)),
+            (Some(&i), _) => self.add(format!("ESC {} {}", i as char, byte as char)),
+        }
+    }
+}
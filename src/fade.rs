@@ -0,0 +1,185 @@
+//! Fading the animation in from the background color and out to it
+//!
+//! The fade-in is a run of frames before the first frame of the recording that go from the
+//! terminal background to the first frame, and the rest of the recording is moved to after it. The
+//! fade-out is a run of frames after the end of the recording has been held that go from the
+//! final frame, which is the end card if there is one, to the background.
+
+use std::collections::VecDeque;
+
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+use crate::color_profile::{self, Rgb};
+use crate::min_delay::DEFAULT_MIN_FRAME_DELAY;
+use crate::types::RgbaFrame;
+use crate::Error;
+
+/// The longest time between the frames of a fade, in seconds
+const FADE_FRAME_DELAY: f64 = 0.05;
+
+/// Adds frames that fade the animation in from the background and out to it
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fade {
+    /// The time to fade in over, in seconds
+    fade_in: f64,
+    /// The time to fade out over, in seconds
+    fade_out: f64,
+    /// The color to fade from and to
+    background: RGBA8,
+}
+
+impl Fade {
+    /// Fade in from the `background` over `fade_in` seconds, and out to it over `fade_out`
+    /// seconds
+    ///
+    /// A fade of `0` seconds leaves that end of the animation as it is.
+    pub fn new(fade_in: f64, fade_out: f64, background: Rgb) -> Self {
+        let (r, g, b) = background;
+        Fade {
+            fade_in,
+            fade_out,
+            background: RGBA8::new(r, g, b, 255),
+        }
+    }
+
+    /// Whether or not the animation fades out at the end
+    pub fn fades_out(&self) -> bool {
+        self.fade_out > 0.
+    }
+
+    /// Get the time, in seconds, that each frame of the fade-out is shown for
+    pub fn fade_out_frame_delay(&self) -> f64 {
+        let (frames, delay) = steps(self.fade_out);
+        if frames == 0 {
+            return 0.;
+        }
+
+        delay / 100.
+    }
+
+    /// Add the frames to encode for a frame of the animation to `faded`: the frame, moved to after
+    /// the fade-in, and the frames of the fade-in before it if it is the first frame
+    ///
+    /// Frames may be given in any order. The first frame is the one with index `0`.
+    pub fn fade_in(&self, mut frame: RgbaFrame, faded: &mut impl Extend<RgbaFrame>) {
+        let (frames, delay) = steps(self.fade_in);
+        if frames == 0 {
+            faded.extend(Some(frame));
+            return;
+        }
+
+        if frame.index == 0 {
+            faded.extend((0..frames).map(|step| RgbaFrame {
+                index: step,
+                time: frame.time + step as f64 * delay,
+                image: self.faded(&frame, 1. - step as f64 / frames as f64),
+            }));
+        }
+        frame.index += frames;
+        frame.time += self.fade_in * 100.;
+        faded.extend(Some(frame));
+    }
+
+    /// Get the frames of the fade-out after the final frame of the animation, once the final
+    /// frame has been held for `hold` seconds
+    ///
+    /// The final frame is shown for at least the shortest delay that gifs play reliably, even if
+    /// it isn't held. The last frame of the fade-out is the background.
+    pub fn fade_out_after(&self, last: &RgbaFrame, hold: f64) -> Vec<RgbaFrame> {
+        let (frames, delay) = steps(self.fade_out);
+        let hold = hold.max(DEFAULT_MIN_FRAME_DELAY) * 100.;
+
+        (1..=frames)
+            .map(|step| RgbaFrame {
+                index: last.index + step,
+                time: last.time + hold + (step - 1) as f64 * delay,
+                image: self.faded(last, step as f64 / frames as f64),
+            })
+            .collect()
+    }
+
+    /// Get the image of a frame faded toward the background by `amount`, from `0` for none of
+    /// the background to `1` for all of it
+    fn faded(&self, frame: &RgbaFrame, amount: f64) -> ImgVec<RGBA8> {
+        let background = self.background;
+        let pixels = frame
+            .image
+            .pixels()
+            .map(|pixel| color_profile::mix(pixel, background, amount))
+            .collect();
+
+        ImgVec::new(pixels, frame.image.width(), frame.image.height())
+    }
+}
+
+/// Get the number of frames of a fade over `duration` seconds, and the time between them in
+/// hundredths of a second
+fn steps(duration: f64) -> (u64, f64) {
+    if duration <= 0. {
+        return (0, 0.);
+    }
+
+    let frames = (duration / FADE_FRAME_DELAY).ceil().max(1.);
+    (frames as u64, duration * 100. / frames)
+}
+
+/// An iterator adapter that fades frames in from the background and out to it
+///
+/// The frames must be in order. The latest frame is held back until the next one comes, so that
+/// the fade-out can be made from the last frame without copying every frame.
+pub(crate) struct FadeFrames<I> {
+    /// The frames to fade
+    frames: I,
+    /// The fades to add
+    fade: Fade,
+    /// The time to hold the last frame for before it fades out, in seconds
+    hold: f64,
+    /// The latest frame, which may be the last one
+    latest: Option<RgbaFrame>,
+    /// Frames that are ready to be returned
+    ready: VecDeque<RgbaFrame>,
+}
+
+impl<I> FadeFrames<I> {
+    /// Add the fades to the frames, holding the last frame for `hold` seconds before it fades out
+    pub fn new(frames: I, fade: Fade, hold: f64) -> Self {
+        FadeFrames {
+            frames,
+            fade,
+            hold,
+            latest: None,
+            ready: VecDeque::new(),
+        }
+    }
+}
+
+impl<I> Iterator for FadeFrames<I>
+where
+    I: Iterator<Item = Result<RgbaFrame, Error>>,
+{
+    type Item = Result<RgbaFrame, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(frame) = self.ready.pop_front() {
+                return Some(Ok(frame));
+            }
+
+            match self.frames.next() {
+                Some(Ok(frame)) => {
+                    self.ready.extend(self.latest.take());
+                    self.fade.fade_in(frame, &mut self.ready);
+                    self.latest = self.ready.pop_back();
+                }
+                Some(Err(e)) => return Some(Err(e)),
+                None => {
+                    let last = self.latest.take()?;
+                    self.ready
+                        .extend(self.fade.fade_out_after(&last, self.hold));
+                    self.ready.push_front(last);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Rendering a filmstrip of a cast
+//!
+//! A filmstrip is a single png with a few frames of the recording side by side, evenly spaced over
+//! its duration, for blog posts and for seeing what a cast contains at a glance.
+
+use std::io::Write;
+
+use imgref::ImgVec;
+use rgb::{ComponentBytes, RGBA8};
+
+use crate::types::RgbaFrame;
+use crate::Error;
+
+/// The width of the transparent gap between the frames of a filmstrip, in pixels
+const FILMSTRIP_GAP: usize = 8;
+
+/// Pick the frames to show in a filmstrip of `count` frames, given the time of every frame
+///
+/// The frames are the ones shown at evenly spaced times from the first frame to the last, so the
+/// first and the last frame are always picked. Frames are only picked once, so short casts get
+/// fewer frames. Returns the indexes of the picked frames, in order.
+pub(crate) fn pick(times: &[f64], count: usize) -> Vec<u64> {
+    let (first, last) = match (times.first(), times.last()) {
+        (Some(&first), Some(&last)) if count > 0 => (first, last),
+        _ => return Vec::new(),
+    };
+
+    let mut picked: Vec<u64> = Vec::with_capacity(count);
+    for step in 0..count {
+        let time = if count == 1 {
+            last
+        } else {
+            first + (last - first) * step as f64 / (count - 1) as f64
+        };
+        // The frame shown at a time is the last one that starts at or before it
+        let index = times
+            .partition_point(|&frame_time| frame_time <= time)
+            .saturating_sub(1) as u64;
+        if picked.last() != Some(&index) {
+            picked.push(index);
+        }
+    }
+
+    picked
+}
+
+/// Put frames side by side, from left to right, and write them to a png
+pub(crate) fn write<W: Write>(frames: &[RgbaFrame], writer: W) -> Result<(), Error> {
+    let strip = join(frames);
+    let error = |e: png::EncodingError| Error::Generic(format!("Could not write filmstrip: {}", e));
+
+    let mut encoder = png::Encoder::new(writer, strip.width() as u32, strip.height() as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+    let mut writer = encoder.write_header().map_err(error)?;
+    writer
+        .write_image_data(strip.buf().as_bytes())
+        .map_err(error)?;
+    writer.finish().map_err(error)?;
+
+    Ok(())
+}
+
+/// Put frames side by side with a transparent gap between them, lined up at the top
+fn join(frames: &[RgbaFrame]) -> ImgVec<RGBA8> {
+    let width = frames
+        .iter()
+        .map(|frame| frame.image.width())
+        .sum::<usize>()
+        + FILMSTRIP_GAP * frames.len().saturating_sub(1);
+    let height = frames
+        .iter()
+        .map(|frame| frame.image.height())
+        .max()
+        .unwrap_or(0);
+
+    let mut pixels = vec![RGBA8::new(0, 0, 0, 0); width * height];
+    let mut left = 0;
+    for frame in frames {
+        for (y, row) in frame.image.rows().enumerate() {
+            let start = y * width + left;
+            pixels[start..start + row.len()].copy_from_slice(row);
+        }
+        left += frame.image.width() + FILMSTRIP_GAP;
+    }
+
+    ImgVec::new(pixels, width, height)
+}
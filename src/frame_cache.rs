@@ -0,0 +1,83 @@
+//! A cache of rendered frames that is shared between renders
+//!
+//! Frames are stored under the journal key of everything that went into rendering them, so any
+//! render of a screen that was rendered before, with the same crop, title, caption, and zoom, loads
+//! the image instead of rasterizing it again. This makes trying out encoder settings, frame rates, or
+//! output formats on a recording cheap after the first render.
+//!
+//! The cache is never cleaned up by cast2gif. Remove the directory to clear it.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+use crate::frame_pool::FramePool;
+use crate::frame_spill::{read_image, write_image};
+
+/// Counter used to give each temporary frame file in this process a unique name
+static NEXT_TEMP_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A directory of rendered frames, by what they were rendered from
+#[derive(Debug)]
+pub(crate) struct FrameCache {
+    dir: PathBuf,
+}
+
+impl FrameCache {
+    /// Open the cache in `dir`, creating it if it doesn't exist
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+
+        Ok(FrameCache {
+            dir: dir.to_owned(),
+        })
+    }
+
+    /// Load the image with the given key and (width, height) size, if it is in the cache
+    ///
+    /// A frame that can't be read is left out, so that it will be rendered again.
+    pub fn load(
+        &self,
+        key: u64,
+        (width, height): (usize, usize),
+        pool: &FramePool,
+    ) -> Option<ImgVec<RGBA8>> {
+        match read_image(&self.frame_path(key, (width, height)), width, height, pool) {
+            Ok(image) => Some(image),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => {
+                log::warn!("Could not load frame {:016x} from the cache: {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Add a rendered image to the cache
+    pub fn store(&self, key: u64, image: &ImgVec<RGBA8>) -> io::Result<()> {
+        let path = self.frame_path(key, (image.width(), image.height()));
+
+        // Other renders may be reading the cache, so the image is only moved into place once it
+        // has been written completely
+        let temp_path = self.dir.join(format!(
+            ".{}-{}.tmp",
+            std::process::id(),
+            NEXT_TEMP_ID.fetch_add(1, SeqCst)
+        ));
+        let stored =
+            write_image(&temp_path, image).and_then(|_| std::fs::rename(&temp_path, &path));
+        if stored.is_err() {
+            std::fs::remove_file(&temp_path).ok();
+        }
+
+        stored
+    }
+
+    /// Get the path of a cached image
+    fn frame_path(&self, key: u64, (width, height): (usize, usize)) -> PathBuf {
+        self.dir
+            .join(format!("{:016x}-{}x{}.frame", key, width, height))
+    }
+}
@@ -0,0 +1,211 @@
+//! A journal of rendered frames, for resuming interrupted renders
+//!
+//! Every frame that is rasterized is compressed and written to the journal directory, and a line
+//! with its index and a key of everything that went into rendering it is added to the journal
+//! file. When a render is started again with the same directory, frames that have the same index
+//! and key as a journaled frame are loaded from disk instead of being rasterized again, so a render
+//! of a changed cast, or with changed settings, only reuses the frames that still look the same.
+//!
+//! Neither gifs nor videos can be continued once their encoder has stopped, so the whole output is
+//! encoded again from the loaded frames.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+use crate::frame_pool::FramePool;
+use crate::frame_renderer::View;
+use crate::frame_spill::{read_image, write_image};
+use crate::smooth_scroll::Scroll;
+use crate::types::{CropSettings, RgbaFrame, ScreenSnapshot};
+
+/// The first line of the journal file
+///
+/// Frames rendered by another version of cast2gif may look different, so journals with another
+/// header are started over.
+const JOURNAL_HEADER: &str = concat!("cast2gif frame journal ", env!("CARGO_PKG_VERSION"));
+
+/// The name of the journal file in the journal directory
+const JOURNAL_FILE: &str = "journal";
+
+/// A frame that was written to the journal
+#[derive(Debug)]
+struct JournalEntry {
+    /// The key of what the frame was rendered from
+    key: u64,
+    /// The width of the frame image
+    width: usize,
+    /// The height of the frame image
+    height: usize,
+}
+
+/// A directory of rendered frames that outlives the render
+#[derive(Debug)]
+pub(crate) struct FrameJournal {
+    /// The journal directory
+    dir: PathBuf,
+    /// The journal file, which new frames are appended to
+    journal: Mutex<File>,
+    /// The frames that were in the journal when it was opened, by index
+    entries: HashMap<u64, JournalEntry>,
+}
+
+impl FrameJournal {
+    /// Open the journal in `dir`, creating it if it doesn't exist
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(JOURNAL_FILE);
+
+        let entries = match std::fs::read_to_string(&path) {
+            Ok(journal) => parse_journal(&journal),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let (journal, entries) = match entries {
+            Some((entries, complete)) => {
+                let mut journal = std::fs::OpenOptions::new().append(true).open(&path)?;
+                // Start after the line that was cut off, if any
+                if !complete {
+                    journal.write_all(b"\n")?;
+                }
+                (journal, entries)
+            }
+            None => {
+                let mut journal = File::create(&path)?;
+                writeln!(journal, "{}", JOURNAL_HEADER)?;
+                (journal, HashMap::new())
+            }
+        };
+
+        if !entries.is_empty() {
+            log::info!(
+                "Resuming with {} frames from {}",
+                entries.len(),
+                dir.to_string_lossy()
+            );
+        }
+
+        Ok(FrameJournal {
+            dir: dir.to_owned(),
+            journal: Mutex::new(journal),
+            entries,
+        })
+    }
+
+    /// Load the image of the frame with the given index, if it was journaled with the same key
+    ///
+    /// A frame that can't be read is left out, so that it will be rendered again.
+    pub fn load(&self, index: u64, key: u64, pool: &FramePool) -> Option<ImgVec<RGBA8>> {
+        let entry = self.entries.get(&index).filter(|entry| entry.key == key)?;
+        match read_image(
+            &self.frame_path(index, key),
+            entry.width,
+            entry.height,
+            pool,
+        ) {
+            Ok(image) => Some(image),
+            Err(e) => {
+                log::warn!("Could not load frame {} from the journal: {}", index, e);
+                None
+            }
+        }
+    }
+
+    /// Write a rendered frame to the journal
+    pub fn record(&self, key: u64, frame: &RgbaFrame) -> io::Result<()> {
+        write_image(&self.frame_path(frame.index, key), &frame.image)?;
+
+        // The frame is only in the journal once its line is, which is written in one piece after
+        // the image so that an interrupted render never journals a partly written frame
+        let line = format!(
+            "{} {:016x} {} {}\n",
+            frame.index,
+            key,
+            frame.image.width(),
+            frame.image.height()
+        );
+        self.journal.lock().unwrap().write_all(line.as_bytes())
+    }
+
+    /// Remove the journal directory once the render that it is for has succeeded
+    pub fn remove(&self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            log::warn!(
+                "Could not remove frame journal {}: {}",
+                self.dir.to_string_lossy(),
+                e
+            );
+        }
+    }
+
+    /// Get the path of the image of a journaled frame
+    ///
+    /// The key is part of the name so that rendering a frame again can't change the image of an
+    /// entry that is already in the journal.
+    fn frame_path(&self, index: u64, key: u64) -> PathBuf {
+        self.dir.join(format!("{}-{:016x}.frame", index, key))
+    }
+}
+
+/// Parse the entries of a journal file, and whether or not its last line is complete, or get
+/// `None` if it was written by another version
+///
+/// Lines that can't be parsed are skipped, as is a last line that was cut off when the render was
+/// interrupted. Later entries for a frame replace earlier ones.
+fn parse_journal(journal: &str) -> Option<(HashMap<u64, JournalEntry>, bool)> {
+    let mut lines = journal
+        .split_inclusive('\n')
+        .filter_map(|line| line.strip_suffix('\n'));
+    if lines.next() != Some(JOURNAL_HEADER) {
+        return None;
+    }
+
+    let entries = lines
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let index = fields.next()?.parse().ok()?;
+            let key = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let width = fields.next()?.parse().ok()?;
+            let height = fields.next()?.parse().ok()?;
+            if fields.next().is_some() {
+                return None;
+            }
+
+            Some((index, JournalEntry { key, width, height }))
+        })
+        .collect();
+
+    Some((entries, journal.ends_with('\n')))
+}
+
+/// Get the journal key of a frame from everything that its image is rendered from
+///
+/// The key is also what frames are stored under in the [frame cache](crate::frame_cache), which
+/// may be shared by different versions of cast2gif, so the version is part of it.
+pub(crate) fn frame_key(
+    screen: &ScreenSnapshot,
+    scroll: Option<Scroll>,
+    crop: Option<CropSettings>,
+    background: RGBA8,
+    title: Option<&str>,
+    caption: Option<&str>,
+    zoom: Option<View>,
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    screen.hash(&mut hasher);
+    scroll.hash(&mut hasher);
+    crop.hash(&mut hasher);
+    background.hash(&mut hasher);
+    title.hash(&mut hasher);
+    caption.hash(&mut hasher);
+    zoom.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,63 @@
+//! Recycling the pixel buffers of rendered frames
+//!
+//! Every frame is rendered into a buffer the size of the whole image, and smooth scrolling,
+//! zooming, the title bar, and the aspect ratio padding each copy it into another one. The buffers
+//! that a render is done with are kept in a pool and handed out again for the next frames, instead
+//! of allocating new ones for every frame of a long cast.
+//!
+//! The gif encoder takes the buffers of the frames that are given to it and never gives them back,
+//! so the buffers that come back to the pool are the ones that the later stages of rendering
+//! replace, and the ones of frames that are spilled to disk, which the sequencer loads the frames
+//! back into.
+
+use std::sync::Mutex;
+
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+/// A pool of pixel buffers to render frames into
+#[derive(Debug)]
+pub(crate) struct FramePool {
+    /// The buffers that aren't in use
+    buffers: Mutex<Vec<Vec<RGBA8>>>,
+    /// The most buffers to keep
+    capacity: usize,
+}
+
+impl FramePool {
+    /// Create a pool that keeps up to `capacity` buffers that aren't in use
+    pub fn new(capacity: usize) -> Self {
+        FramePool {
+            buffers: Mutex::new(Vec::with_capacity(capacity)),
+            capacity,
+        }
+    }
+
+    /// Get an empty buffer with room for at least `len` pixels, to fill by extending it
+    pub fn take_empty(&self, len: usize) -> Vec<RGBA8> {
+        match self.buffers.lock().unwrap().pop() {
+            Some(mut buffer) => {
+                buffer.clear();
+                buffer.reserve(len);
+                buffer
+            }
+            None => Vec::with_capacity(len),
+        }
+    }
+
+    /// Get a buffer of `len` pixels of the `fill` color
+    pub fn take(&self, len: usize, fill: RGBA8) -> Vec<RGBA8> {
+        let mut buffer = self.take_empty(len);
+        buffer.resize(len, fill);
+        buffer
+    }
+
+    /// Give the buffer of an image that isn't needed anymore back to the pool, dropping it if the
+    /// pool is full
+    pub fn give(&self, image: ImgVec<RGBA8>) {
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < self.capacity {
+            buffers.push(image.into_buf());
+        }
+    }
+}
@@ -8,18 +8,250 @@ compile_error!("You must specify either the `backend-svg` or `backend-fontkit` f
 
 #[cfg(feature = "backend-svg")]
 mod svg;
+#[cfg(all(feature = "backend-svg", feature = "cli"))]
+pub(crate) use self::svg::BACKEND_NAME;
 #[cfg(feature = "backend-svg")]
-pub(crate) use self::svg::render_frame_to_png;
+pub(crate) use self::svg::{
+    add_title_bar, cell_advance, cell_size, draw_caption, render_frame_to_png, title_bar_height,
+    warm_up,
+};
+#[cfg(all(feature = "backend-svg", feature = "scripting"))]
+pub(crate) use self::svg::{draw_text, fill_rect};
 
 #[cfg(feature = "backend-fontkit")]
 mod fontkit;
+#[cfg(all(feature = "backend-fontkit", feature = "cli"))]
+pub(crate) use fontkit::BACKEND_NAME;
 #[cfg(feature = "backend-fontkit")]
-pub(crate) use fontkit::render_frame_to_png;
+pub(crate) use fontkit::{
+    add_title_bar, cell_advance, cell_size, draw_caption, render_frame_to_png, title_bar_height,
+    warm_up,
+};
+#[cfg(all(feature = "backend-fontkit", feature = "scripting"))]
+pub(crate) use fontkit::{draw_text, fill_rect};
+
+use std::hash::{Hash, Hasher};
+
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+use crate::frame_pool::FramePool;
+use crate::smooth_scroll::Scroll;
+use crate::types::{AspectRatio, CropSettings};
+
+/// A region of the terminal to show in the rendered frame, in cells
+///
+/// Unlike a crop, the sides of a view may be between cells, so that it can move smoothly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct View {
+    pub left: f64,
+    pub top: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Hash for View {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for side in &[self.left, self.top, self.width, self.height] {
+            side.to_bits().hash(state);
+        }
+    }
+}
+
+impl From<CropSettings> for View {
+    fn from(region: CropSettings) -> Self {
+        View {
+            left: region.left.into(),
+            top: region.top.into(),
+            width: region.width.into(),
+            height: region.height.into(),
+        }
+    }
+}
+
+/// Get the (width, height) in pixels of the frames rendered for a terminal of the given size
+///
+/// Frames are padded out to the `aspect` ratio, if there is one.
+pub(crate) fn frame_size(
+    rows: u16,
+    cols: u16,
+    crop: Option<CropSettings>,
+    title_bar: bool,
+    aspect: Option<AspectRatio>,
+) -> (usize, usize) {
+    let (_, cell_height) = cell_size();
+    let rows = crop.map(|x| x.height).unwrap_or(rows);
+    let cols = crop.map(|x| x.width).unwrap_or(cols);
+    let title_bar_height = if title_bar { title_bar_height() } else { 0 };
+    let size = (
+        (cols as f64 * cell_advance()).round() as usize,
+        rows as usize * cell_height + title_bar_height,
+    );
+
+    match aspect {
+        Some(aspect) => aspect.fit(size),
+        None => size,
+    }
+}
+
+/// Pad a rendered frame out to an aspect ratio with the `background` color, keeping the frame in
+/// the middle
+pub(crate) fn letterbox(
+    image: ImgVec<RGBA8>,
+    aspect: AspectRatio,
+    background: RGBA8,
+    pool: &FramePool,
+) -> ImgVec<RGBA8> {
+    let (width, height) = aspect.fit((image.width(), image.height()));
+    if (width, height) == (image.width(), image.height()) {
+        return image;
+    }
+
+    let left = (width - image.width()) / 2;
+    let top = (height - image.height()) / 2;
+    let mut pixels = pool.take(width * height, background);
+    for (y, row) in image.rows().enumerate() {
+        let start = (top + y) * width + left;
+        pixels[start..start + row.len()].copy_from_slice(row);
+    }
+    pool.give(image);
+
+    ImgVec::new(pixels, width, height)
+}
+
+/// Cut a frame that was rendered with the rows that scrolled off above the screen down to the
+/// part that shows the screen partway through the scroll
+pub(crate) fn slide(image: ImgVec<RGBA8>, scroll: Scroll, pool: &FramePool) -> ImgVec<RGBA8> {
+    let (_, cell_height) = cell_size();
+    let width = image.width();
+    let height = image
+        .height()
+        .saturating_sub(scroll.rows as usize * cell_height);
+    let top = ((scroll.offset * cell_height as f64).round() as usize).min(image.height() - height);
+
+    let mut pixels = pool.take_empty(width * height);
+    pixels.extend(image.sub_image(0, top, width, height).pixels());
+    pool.give(image);
+
+    ImgVec::new(pixels, width, height)
+}
+
+/// Zoom a rendered terminal into a view of it
+///
+/// `crop` is the part of the terminal that is in the image. The view is scaled up as far as it
+/// fits, keeping its aspect ratio, and the rest of the frame is filled with what is around it.
+pub(crate) fn zoom(
+    image: ImgVec<RGBA8>,
+    view: View,
+    crop: Option<CropSettings>,
+    pool: &FramePool,
+) -> ImgVec<RGBA8> {
+    let (cell_width, cell_height) = (cell_advance(), cell_size().1 as f64);
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let (crop_top, crop_left) = crop.map_or((0., 0.), |crop| (crop.top.into(), crop.left.into()));
+
+    // Get the view in pixels of the image, leaving out the part that is cropped off
+    let left = ((view.left - crop_left) * cell_width).max(0.);
+    let top = ((view.top - crop_top) * cell_height).max(0.);
+    let right = ((view.left + view.width - crop_left) * cell_width).min(width);
+    let bottom = ((view.top + view.height - crop_top) * cell_height).min(height);
+    if right <= left || bottom <= top {
+        return image;
+    }
+
+    // Widen the view to the shape of the image, keeping it centered where it can be
+    let scale = (width / (right - left)).min(height / (bottom - top));
+    let (view_width, view_height) = (width / scale, height / scale);
+    let view_left = ((left + right - view_width) / 2.).clamp(0., width - view_width);
+    let view_top = ((top + bottom - view_height) / 2.).clamp(0., height - view_height);
+    if scale == 1. && view_left == 0. && view_top == 0. {
+        return image;
+    }
+
+    let mut zoomed = pool.take_empty(image.width() * image.height());
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            let source_x = view_left + (x as f64 + 0.5) / scale - 0.5;
+            let source_y = view_top + (y as f64 + 0.5) / scale - 0.5;
+            zoomed.push(sample(&image, source_x, source_y));
+        }
+    }
+    let (width, height) = (image.width(), image.height());
+    pool.give(image);
+
+    ImgVec::new(zoomed, width, height)
+}
+
+/// Get the color of an image at a position between pixels, blending the four pixels around it
+pub(crate) fn sample(image: &ImgVec<RGBA8>, x: f64, y: f64) -> RGBA8 {
+    let max_x = image.width() - 1;
+    let max_y = image.height() - 1;
+    let x = x.clamp(0., max_x as f64);
+    let y = y.clamp(0., max_y as f64);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(max_x), (y0 + 1).min(max_y));
+    let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+
+    let blend = |a: u8, b: u8, c: u8, d: u8| {
+        let top = f64::from(a) * (1. - fx) + f64::from(b) * fx;
+        let bottom = f64::from(c) * (1. - fx) + f64::from(d) * fx;
+        (top * (1. - fy) + bottom * fy).round() as u8
+    };
+    let (a, b, c, d) = (
+        image[(x0, y0)],
+        image[(x1, y0)],
+        image[(x0, y1)],
+        image[(x1, y1)],
+    );
+
+    RGBA8::new(
+        blend(a.r, b.r, c.r, d.r),
+        blend(a.g, b.g, c.g, d.g),
+        blend(a.b, b.b, c.b, d.b),
+        blend(a.a, b.a, c.a, d.a),
+    )
+}
+
+/// Wrap text into lines of at most `max_cols` characters, breaking lines between words where
+/// possible
+#[cfg_attr(feature = "backend-svg", allow(dead_code))]
+fn wrap_text(text: &str, max_cols: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        let mut line_len = 0;
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+
+            // Start a new line if the word doesn't fit on this one
+            if line_len > 0 && line_len + 1 + word.len() > max_cols {
+                lines.push(std::mem::take(&mut line));
+                line_len = 0;
+            }
+            if line_len > 0 {
+                line.push(' ');
+                line_len += 1;
+            }
+
+            // Break up words that are too long for a line by themselves
+            while word.len() > max_cols {
+                let rest = word.split_off(max_cols);
+                lines.push(word.into_iter().collect());
+                word = rest;
+            }
+            line_len += word.len();
+            line.extend(word);
+        }
+        lines.push(line);
+    }
+
+    lines
+}
 
 /// Return (r, g b) u8 tuple formatted version of a terminal color
 ///
 /// Returns `None` if it is the default color
-fn parse_color(color: vt100::Color) -> Option<(u8, u8, u8)> {
+pub(crate) fn parse_color(color: vt100::Color) -> Option<(u8, u8, u8)> {
     use vt100::Color;
     match color {
         Color::Default => None,
@@ -6,19 +6,27 @@ use font_kit::{
     loaders::freetype::Font,
     metrics::Metrics,
 };
-use imgref::{Img, ImgVec};
+use imgref::{Img, ImgRefMut, ImgVec};
 use lazy_static::lazy_static;
 use pathfinder_geometry::{
+    rect::RectI,
     transform2d::Transform2F,
     vector::{Vector2F, Vector2I},
 };
+use rayon::prelude::*;
 use rgb::{RGBA, RGBA8};
+use unicode_normalization::UnicodeNormalization;
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::iter::FromIterator;
+use std::ops::Range;
+use std::rc::Rc;
 use std::sync::Arc;
 
 use super::parse_color;
-use crate::{cli::CropSettings, types::*};
+use crate::frame_pool::FramePool;
+use crate::types::*;
 
 lazy_static! {
     static ref FONT_DATA: Arc<Vec<u8>> = Arc::new(Vec::from_iter(
@@ -26,166 +34,835 @@ lazy_static! {
             .iter()
             .map(Clone::clone)
     ));
+    /// The bold face of the font, if it has one
+    static ref BOLD_FONT_DATA: Option<Arc<Vec<u8>>> =
+        Some(Arc::new(include_bytes!("./fontkit/Hack-Bold.ttf").to_vec()));
+    /// The italic face of the font, if it has one
+    static ref ITALIC_FONT_DATA: Option<Arc<Vec<u8>>> =
+        Some(Arc::new(include_bytes!("./fontkit/Hack-Italic.ttf").to_vec()));
+    /// The bold italic face of the font, if it has one
+    static ref BOLD_ITALIC_FONT_DATA: Option<Arc<Vec<u8>>> =
+        Some(Arc::new(include_bytes!("./fontkit/Hack-BoldItalic.ttf").to_vec()));
     static ref FONT_METRICS: Metrics = FONT.with(|f| f.metrics());
+    static ref CELL_METRICS: CellMetrics = CellMetrics::new(FONT_SIZE);
+    static ref UNDERLINE_METRICS: UnderlineMetrics = UnderlineMetrics::new();
+}
+
+// Glyph rendering config
+lazy_static! {
+    // static ref TRANS: Transform2F = Transform2F::default();
+    // TODO check hinting settings ( None might be faster with no difference in rendering )
+    static ref HINTING_OPTS: HintingOptions = HintingOptions::Vertical(5.);
+    static ref FORMAT: Format = Format::A8;
+    static ref RASTER_OPTS: RasterizationOptions = RasterizationOptions::GrayscaleAa;
+}
+
+/// The font size to render the terminal with
+// TODO make configurable font size
+const FONT_SIZE: f32 = 13.;
+
+/// The number of positions between two pixels that glyphs can be drawn at
+///
+/// Cells are as wide as the advance of the font, which is rarely a whole number of pixels, so each
+/// glyph is drawn at the nearest of these positions to where its cell starts.
+const SUBPIXEL_STEPS: u32 = 4;
+
+/// How far italic glyphs lean to the right when the font has no italic face, in pixels across for
+/// each pixel up
+const ITALIC_SHEAR: f32 = 0.2;
+
+/// The size of a terminal cell in pixels
+struct CellMetrics {
+    /// The raster bounds of a glyph in the cell
+    raster_rect: RectI,
+    /// The width of a glyph in the cell, which is the width of the text in captions and titles
+    width: i32,
+    /// The distance from the start of one cell to the start of the next, which is fractional, like
+    /// it is in real terminals
+    advance: f64,
+    /// The height of the cell
+    height: i32,
+}
+
+impl CellMetrics {
+    fn new(font_size: f32) -> Self {
+        // Get font height and width
+        let raster_rect = FONT
+            .with(|f| {
+                f.raster_bounds(
+                    f.glyph_for_char('A').expect("TODO"),
+                    font_size,
+                    Transform2F::default(),
+                    *HINTING_OPTS,
+                    *RASTER_OPTS,
+                )
+            })
+            .expect("TODO");
+        let advance = FONT
+            .with(|f| f.advance(f.glyph_for_char('A').expect("TODO")))
+            .expect("TODO")
+            .x() as f64
+            / FONT_METRICS.units_per_em as f64
+            * font_size as f64;
+        let height = ((FONT_METRICS.ascent - FONT_METRICS.descent)
+            / FONT_METRICS.units_per_em as f32
+            * font_size)
+            .ceil() as i32;
+
+        CellMetrics {
+            raster_rect,
+            width: raster_rect.width(),
+            advance,
+            height,
+        }
+    }
+}
+
+/// The attributes of a cell that change how its glyphs are rasterized
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+struct GlyphStyle {
+    /// Whether or not the glyphs are bold
+    bold: bool,
+    /// Whether or not the glyphs are italic
+    italic: bool,
+}
+
+impl GlyphStyle {
+    /// Get the style of the glyphs of a cell
+    fn of(cell: &ScreenCell) -> Self {
+        GlyphStyle {
+            bold: cell.bold,
+            italic: cell.italic,
+        }
+    }
+}
+
+/// The faces of the font other than the regular one, where it has them
+struct StyledFaces {
+    /// The bold face
+    bold: Option<Font>,
+    /// The italic face
+    italic: Option<Font>,
+    /// The bold italic face
+    bold_italic: Option<Font>,
+}
+
+impl StyledFaces {
+    fn load() -> Self {
+        let load = |data: &Option<Arc<Vec<u8>>>| {
+            data.clone()
+                .map(|data| Font::from_bytes(data, 0).expect("Could not load font"))
+        };
+
+        StyledFaces {
+            bold: load(&BOLD_FONT_DATA),
+            italic: load(&ITALIC_FONT_DATA),
+            bold_italic: load(&BOLD_ITALIC_FONT_DATA),
+        }
+    }
+}
+
+/// Run `f` with the face of the font to draw glyphs in the style with, and the part of the style
+/// that the face doesn't have
+///
+/// The part that the face doesn't have is drawn from it the way that terminals draw it when their
+/// font has no face for it. Bold italic text without a bold italic face is drawn from the italic
+/// face before the bold one, since emboldening looks closer to a real bold face than shearing does
+/// to a real italic one.
+fn with_face<T>(style: GlyphStyle, f: impl FnOnce(&Font, GlyphStyle) -> T) -> T {
+    STYLED_FACES.with(|faces| {
+        let styled = [
+            (&faces.bold_italic, true, true),
+            (&faces.italic, false, true),
+            (&faces.bold, true, false),
+        ];
+        let face = styled.iter().find_map(|&(face, bold, italic)| {
+            let face = face.as_ref()?;
+            // The face can't be more bold or italic than the style
+            if (bold && !style.bold) || (italic && !style.italic) {
+                return None;
+            }
+            let missing = GlyphStyle {
+                bold: style.bold && !bold,
+                italic: style.italic && !italic,
+            };
+            Some((face, missing))
+        });
+
+        match face {
+            Some((face, missing)) => f(face, missing),
+            None => FONT.with(|regular| f(regular, style)),
+        }
+    })
+}
+
+/// Where underlines go in a terminal cell
+struct UnderlineMetrics {
+    /// The first pixel row of the line
+    top: usize,
+    /// The thickness of the line in pixels
+    thickness: usize,
+}
+
+impl UnderlineMetrics {
+    /// Put underlines where the font draws its underscore, so that underlined text lines up with
+    /// text in the cells next to it that is written with underscores
+    fn new() -> Self {
+        let font_width = CELL_METRICS.width as usize;
+        let font_height = CELL_METRICS.height as usize;
+        let rows: Vec<usize> = with_rasterized_char('_', 0., GlyphStyle::default(), |canvas| {
+            (0..font_height)
+                .filter(|&y| {
+                    let row = &canvas.pixels[y * canvas.stride..(y + 1) * canvas.stride];
+                    row[..font_width].iter().any(|&coverage| coverage > 127)
+                })
+                .collect()
+        });
+
+        match rows.first() {
+            Some(&top) => UnderlineMetrics {
+                top,
+                thickness: rows.len(),
+            },
+            None => UnderlineMetrics {
+                top: font_height.saturating_sub(3),
+                thickness: 1,
+            },
+        }
+    }
+}
+
+/// The name of this renderer backend, which the `bench` subcommand reports
+#[cfg(feature = "cli")]
+pub(crate) const BACKEND_NAME: &str = "fontkit";
+
+/// Get the (width, height) of a terminal cell in pixels
+pub(crate) fn cell_size() -> (usize, usize) {
+    (CELL_METRICS.width as usize, CELL_METRICS.height as usize)
+}
+
+/// Get the distance in pixels from the start of one cell in a row to the start of the next
+pub(crate) fn cell_advance() -> f64 {
+    CELL_METRICS.advance
+}
+
+/// Get the pixel that the cell `col` cells from the left of the image starts at, which is also
+/// where the cell before it ends
+fn cell_left(col: usize) -> usize {
+    (col as f64 * CELL_METRICS.advance).round() as usize
+}
+
+/// Load the font on this thread and rasterize a glyph with it, so that the first frame rendered
+/// on the thread doesn't have to
+pub(crate) fn warm_up() {
+    lazy_static::initialize(&UNDERLINE_METRICS);
+    with_rasterized_char('A', 0., GlyphStyle::default(), |_| ());
 }
 
 thread_local! {
     // TODO clone the arc instead of cloning the iterator every time
     static FONT: Font = Font::from_bytes(FONT_DATA.clone(), 0).expect("Could not load font");
+    static STYLED_FACES: StyledFaces = StyledFaces::load();
+    /// The glyphs rasterized on this thread, by glyph and the offset they were drawn at
+    ///
+    /// Terminals draw the same few glyphs over and over at a few subpixel offsets, so each
+    /// variant is only rasterized once per thread.
+    static GLYPH_CACHE: RefCell<HashMap<(u32, u32, GlyphStyle), Rc<Canvas>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Blend `fg` with the given coverage `alpha` over the color `bg`
+///
+/// Alpha `a` over `b`, component wise: `(a * alpha + b * (255 - alpha)) / 255`, rounded to the
+/// nearest integer. This is done in integer math so that the compiler can vectorize it; it is the
+/// hot loop of the renderer. Translucent backgrounds, which are only drawn when the terminal
+/// background is, take the slower path of [`blend_over_translucent`].
+#[inline]
+fn blend_over(fg: RGBA8, bg: RGBA8, alpha: u8) -> RGBA8 {
+    match alpha {
+        0 => bg,
+        255 => RGBA::new(fg.r, fg.g, fg.b, 255),
+        alpha if bg.a < 255 => blend_over_translucent(fg, bg, alpha),
+        alpha => {
+            let alpha = alpha as u32;
+            let inv_alpha = 255 - alpha;
+            let mix = |a: u8, b: u8| ((a as u32 * alpha + b as u32 * inv_alpha + 127) / 255) as u8;
+            RGBA::new(mix(fg.r, bg.r), mix(fg.g, bg.g), mix(fg.b, bg.b), 255)
+        }
+    }
+}
+
+/// Blend `fg` with the given coverage `alpha` over the translucent color `bg`
+///
+/// The result is as opaque as the two colors are together, and its color is the mix of them
+/// weighted by how much each one covers.
+fn blend_over_translucent(fg: RGBA8, bg: RGBA8, alpha: u8) -> RGBA8 {
+    let fg_weight = alpha as u32 * 255;
+    let bg_weight = bg.a as u32 * (255 - alpha as u32);
+    let total = fg_weight + bg_weight;
+    if total == 0 {
+        return bg;
+    }
+    let mix =
+        |a: u8, b: u8| ((a as u32 * fg_weight + b as u32 * bg_weight + total / 2) / total) as u8;
+
+    RGBA::new(
+        mix(fg.r, bg.r),
+        mix(fg.g, bg.g),
+        mix(fg.b, bg.b),
+        ((total + 127) / 255) as u8,
+    )
+}
+
+/// Render the given columns of a terminal row into an image band one cell tall
+///
+/// The band is already filled with `default_bg`, which is the color of the cells that don't set
+/// their own background. The backgrounds of the cells are filled in before any text is drawn, so
+/// that glyphs drawn between pixels can reach into the cell next to them.
+fn render_row(
+    screen: &ScreenSnapshot,
+    row: u16,
+    cols: Range<u16>,
+    mut band: ImgRefMut<RGBA8>,
+    default_bg: RGBA8,
+) {
+    let font_height = CELL_METRICS.height as usize;
+    let band_width = band.width();
+
+    // Get the (foreground, background) colors that a cell is drawn in
+    let colors = |col: u16| {
+        let cell = screen.cell(row, col).expect("Error indexing cell");
+        let cell_bg_color = parse_color(cell.bgcolor)
+            .map(|x| RGBA::new(x.0, x.1, x.2, 255))
+            .unwrap_or(default_bg);
+        let cell_fg_color = parse_color(cell.fgcolor)
+            .map(|x| RGBA::new(x.0, x.1, x.2, 255))
+            .unwrap_or(RGBA::new(255, 255, 255, 255));
+
+        if screen.is_cursor_at(row, col) {
+            (cell_bg_color, cell_fg_color)
+        } else {
+            (cell_fg_color, cell_bg_color)
+        }
+    };
+
+    for (col_i, col) in cols.clone().enumerate() {
+        let (_, real_bg_color) = colors(col);
+        if real_bg_color != default_bg {
+            let (left, right) = (cell_left(col_i), cell_left(col_i + 1).min(band_width));
+            for pixel in band
+                .sub_image_mut(left, 0, right.saturating_sub(left), font_height)
+                .pixels_mut()
+            {
+                *pixel = real_bg_color;
+            }
+        }
+    }
+
+    for (col_i, col) in cols.enumerate() {
+        let cell = screen.cell(row, col).expect("Error indexing cell");
+        let (real_fg_color, _) = colors(col);
+
+        if let Some(contents) = &cell.contents {
+            // Draw the glyph at the subpixel position nearest to where the cell starts
+            let origin = col_i as f64 * CELL_METRICS.advance;
+            let steps = ((origin - origin.floor()) * SUBPIXEL_STEPS as f64).round() as u32;
+            let xpos = origin.floor() as usize + (steps / SUBPIXEL_STEPS) as usize;
+            let x_offset = (steps % SUBPIXEL_STEPS) as f32 / SUBPIXEL_STEPS as f32;
+            with_rasterized_cell(contents, x_offset, GlyphStyle::of(cell), |canvas| {
+                // Blend the glyph coverage over the cell background
+                let glyph_width = canvas.stride.min(band_width.saturating_sub(xpos));
+                for y in 0..font_height {
+                    let row = &canvas.pixels[y * canvas.stride..(y + 1) * canvas.stride];
+                    for (x, &alpha) in row[..glyph_width].iter().enumerate() {
+                        let pixel = &mut band[(xpos + x, y)];
+                        *pixel = blend_over(real_fg_color, *pixel, alpha);
+                    }
+                }
+            });
+        }
+
+        if let Some(underline) = cell.underline {
+            let color = parse_color(underline.color)
+                .map(|x| RGBA::new(x.0, x.1, x.2, 255))
+                .unwrap_or(real_fg_color);
+            let (left, right) = (cell_left(col_i), cell_left(col_i + 1).min(band_width));
+            let mut subimg = band.sub_image_mut(left, 0, right.saturating_sub(left), font_height);
+            draw_underline(&mut subimg, underline.style, color, cell_left(col as usize));
+        }
+    }
 }
 
-pub(crate) fn render_frame_to_png(frame: TerminalFrame, crop: Option<CropSettings>) -> RgbaFrame {
+/// Draw an underline across a cell
+///
+/// `x_offset` is how far the cell is from the left of the terminal in pixels, so that the dots,
+/// dashes, and waves of neighboring cells line up.
+fn draw_underline(
+    cell_img: &mut ImgRefMut<RGBA8>,
+    style: UnderlineStyle,
+    color: RGBA8,
+    x_offset: usize,
+) {
+    let (width, height) = (cell_img.width(), cell_img.height());
+    let thickness = UNDERLINE_METRICS.thickness.min(height);
+    let mut fill_rows = |top: usize, covered: &dyn Fn(usize) -> bool| {
+        for y in top..(top + thickness).min(height) {
+            for x in (0..width).filter(|&x| covered(x_offset + x)) {
+                cell_img[(x, y)] = color;
+            }
+        }
+    };
+
+    match style {
+        UnderlineStyle::Single => fill_rows(UNDERLINE_METRICS.top, &|_| true),
+        UnderlineStyle::Double => {
+            // Keep both lines inside of the cell
+            let top = UNDERLINE_METRICS
+                .top
+                .min(height.saturating_sub(thickness * 3));
+            fill_rows(top, &|_| true);
+            fill_rows(top + thickness * 2, &|_| true);
+        }
+        UnderlineStyle::Dotted => fill_rows(UNDERLINE_METRICS.top, &|x| (x / thickness) % 2 == 0),
+        // One dash per cell, centered in it
+        UnderlineStyle::Dashed => fill_rows(UNDERLINE_METRICS.top, &|x| {
+            ((x as f64 + 0.5) / CELL_METRICS.advance + 0.25).fract() < 0.5
+        }),
+        UnderlineStyle::Curly => {
+            // A wave one cell long that goes as far above and below the line as it is thick
+            let amplitude = thickness as f32;
+            let top = (UNDERLINE_METRICS.top as f32)
+                .min(height as f32 - amplitude * 2. - thickness as f32)
+                .max(0.);
+            let half_thickness = thickness as f32 / 2.;
+            let period = CELL_METRICS.advance as f32;
+            for x in 0..width {
+                let phase = (x_offset + x) as f32 + 0.5;
+                let center = top
+                    + half_thickness
+                    + amplitude * (1. - (phase / period * std::f32::consts::TAU).cos());
+                for y in 0..height {
+                    // Anti-alias the edges of the wave
+                    let coverage =
+                        (half_thickness + 0.5 - (y as f32 + 0.5 - center).abs()).clamp(0., 1.);
+                    if coverage > 0. {
+                        let pixel = &mut cell_img[(x, y)];
+                        *pixel = blend_over(color, *pixel, (coverage * 255.) as u8);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Rasterize the contents of a terminal cell like [`with_rasterized_glyph`], and run `f` with it
+///
+/// Combining characters are composed with the character before them if the font has a glyph for
+/// the composition, such as `é` for `e` and a combining acute accent. Otherwise the zero-width
+/// marks that the font has glyphs for are drawn over the character, and anything else in the
+/// cluster is left out.
+fn with_rasterized_cell<T>(
+    contents: &CellContents,
+    x_offset: f32,
+    style: GlyphStyle,
+    f: impl FnOnce(&Canvas) -> T,
+) -> T {
+    let mut chars = contents.chars();
+    let base = chars.next().unwrap_or(' ');
+    if chars.next().is_none() {
+        return with_rasterized_char(base, x_offset, style, f);
+    }
+
+    // Use the composed character if there is one
+    let composed: Vec<char> = contents.chars().nfc().collect();
+    let base = composed[0];
+    if composed.len() == 1 && with_face(style, |f, _| f.glyph_for_char(base).is_some()) {
+        return with_rasterized_char(base, x_offset, style, f);
+    }
+
+    // Find the marks to draw over the character. Their glyphs are positioned relative to the end
+    // of the character.
+    let (base_advance, marks) = with_face(style, |font, _| {
+        let advance = |glyph_id| font.advance(glyph_id).map(|a| a.x()).unwrap_or(0.);
+        let base_advance = font.glyph_for_char(base).map(advance).unwrap_or(0.);
+        let marks: Vec<u32> = composed[1..]
+            .iter()
+            .filter_map(|&c| font.glyph_for_char(c))
+            .filter(|&glyph_id| advance(glyph_id) == 0.)
+            .collect();
+
+        let units_per_px = FONT_METRICS.units_per_em as f32 / FONT_SIZE;
+        (base_advance / units_per_px, marks)
+    });
+    if marks.is_empty() {
+        return with_rasterized_char(base, x_offset, style, f);
+    }
+
+    // Combine the coverage of the character and its marks
+    let mut coverage = with_rasterized_char(base, x_offset, style, |canvas| Canvas {
+        pixels: canvas.pixels.clone(),
+        size: canvas.size,
+        stride: canvas.stride,
+        format: canvas.format,
+    });
+    for glyph_id in marks {
+        with_rasterized_glyph(glyph_id, x_offset + base_advance, style, |canvas| {
+            for (pixel, &mark) in coverage.pixels.iter_mut().zip(&canvas.pixels) {
+                *pixel = (*pixel).max(mark);
+            }
+        });
+    }
+
+    f(&coverage)
+}
+
+/// Rasterize a character like [`with_rasterized_glyph`], and run `f` with it
+fn with_rasterized_char<T>(
+    c: char,
+    x_offset: f32,
+    style: GlyphStyle,
+    f: impl FnOnce(&Canvas) -> T,
+) -> T {
+    // TODO: We currently use `.` as a fallback char, but we should use a better one and maybe pick a
+    // font that supports all the characters used in the TUI-rs demo.
+    let glyph_id = with_face(style, |f, _| {
+        f.glyph_for_char(c)
+            .unwrap_or_else(|| f.glyph_for_char('.').expect("TODO"))
+    });
+
+    with_rasterized_glyph(glyph_id, x_offset, style, f)
+}
+
+/// Rasterize a glyph, one cell in size plus a pixel for the glyph to be moved into, with its
+/// origin moved `x_offset` pixels to the right, and run `f` with it
+///
+/// The glyph is only rasterized the first time it is drawn at the offset in the style on this
+/// thread. Glyph IDs are of the [face](with_face) of the style.
+fn with_rasterized_glyph<T>(
+    glyph_id: u32,
+    x_offset: f32,
+    style: GlyphStyle,
+    f: impl FnOnce(&Canvas) -> T,
+) -> T {
+    let key = (glyph_id, x_offset.to_bits(), style);
+    let cached = GLYPH_CACHE.with(|cache| cache.borrow().get(&key).cloned());
+    let canvas = cached.unwrap_or_else(|| {
+        let canvas = Rc::new(rasterize_glyph(glyph_id, x_offset, style));
+        GLYPH_CACHE.with(|cache| cache.borrow_mut().insert(key, canvas.clone()));
+        canvas
+    });
+
+    f(&canvas)
+}
+
+/// Thicken the strokes of a rasterized glyph by drawing it again a pixel to the right, the way
+/// that terminals draw bold text when their font has no bold face
+fn embolden(canvas: &mut Canvas) {
+    for row in canvas.pixels.chunks_exact_mut(canvas.stride) {
+        for x in (1..row.len()).rev() {
+            row[x] = row[x].max(row[x - 1]);
+        }
+    }
+}
+
+/// Rasterize a glyph like [`with_rasterized_glyph`], without the cache
+///
+/// Bold glyphs are [emboldened](embolden) if the font has no bold face. Italic glyphs are sheared
+/// to lean to the right around the middle of the cell, so that they stay inside of it, if the font
+/// has no italic face.
+fn rasterize_glyph(glyph_id: u32, x_offset: f32, style: GlyphStyle) -> Canvas {
+    let raster_rect = CELL_METRICS.raster_rect;
+    let font_width = CELL_METRICS.width;
+    let font_height = CELL_METRICS.height;
+    let font_height_offset = (font_height - raster_rect.height()) / 2;
+    let font_transform =
+        Transform2F::from_translation(Vector2F::new(x_offset, -font_height_offset as f32));
+
+    let mut transform =
+        Transform2F::from_translation(-raster_rect.origin().to_f32()) * font_transform;
+
+    let mut canvas = Canvas::new(Vector2I::new(font_width + 1, font_height), *FORMAT);
+    let missing = with_face(style, |f, missing| {
+        if missing.italic {
+            // The shear leans the glyph around its baseline, so move it back left by as much as
+            // the middle of the cell leans
+            let baseline = transform.vector.y();
+            let lean = ITALIC_SHEAR * (baseline - font_height as f32 / 2.);
+            let shear = Transform2F::row_major(1., 0., -ITALIC_SHEAR, 1., 0., 0.);
+            transform = Transform2F::from_translation(Vector2F::new(-lean, 0.)) * transform * shear;
+        }
+
+        f.rasterize_glyph(
+            &mut canvas,
+            glyph_id,
+            FONT_SIZE,
+            transform,
+            *HINTING_OPTS,
+            *RASTER_OPTS,
+        )
+        .map(|()| missing)
+    })
+    .expect("TODO");
+    if missing.bold {
+        embolden(&mut canvas);
+    }
+
+    canvas
+}
+
+/// The color of caption text
+const CAPTION_FG_COLOR: RGBA8 = RGBA::new(255, 255, 255, 255);
+/// The color of the box behind captions
+const CAPTION_BG_COLOR: RGBA8 = RGBA::new(0, 0, 0, 255);
+/// The opacity of the box behind captions
+const CAPTION_BG_ALPHA: u8 = 180;
+
+/// Draw caption text centered over the bottom of a rendered frame
+///
+/// Lines that don't fit across the frame are wrapped, and lines that don't fit in the frame's
+/// height are dropped from the top.
+pub(crate) fn draw_caption(image: &mut ImgVec<RGBA8>, text: &str) {
+    let font_width = CELL_METRICS.width as usize;
+    let font_height = CELL_METRICS.height as usize;
+    let padding = font_height / 2;
+    let (width, height) = (image.width(), image.height());
+
+    // Wrap the text to fit inside of the frame, leaving a cell of margin on either side
+    let max_cols = (width / font_width).saturating_sub(2).max(1);
+    let mut lines = super::wrap_text(text, max_cols);
+    let max_lines = height.saturating_sub(padding * 2) / font_height;
+    if max_lines == 0 || lines.is_empty() {
+        return;
+    }
+    if lines.len() > max_lines {
+        lines.drain(..lines.len() - max_lines);
+    }
+
+    // Darken the area behind the text
+    let box_top = height - (lines.len() * font_height + padding * 2);
+    let mut caption_box = image.sub_image_mut(0, box_top, width, height - box_top);
+    for pixel in caption_box.pixels_mut() {
+        *pixel = blend_over(CAPTION_BG_COLOR, *pixel, CAPTION_BG_ALPHA);
+    }
+
+    // Draw the text
+    for (line_i, line) in lines.iter().enumerate() {
+        let ypos = box_top + padding + line_i * font_height;
+        let xpos = width.saturating_sub(line.chars().count() * font_width) / 2;
+        draw_text(
+            image,
+            (xpos as isize, ypos as isize),
+            line,
+            CAPTION_FG_COLOR,
+        );
+    }
+}
+
+/// Draw a line of text with its top left corner at the given (x, y) pixel, clipped to the image
+///
+/// The alpha of the color is how opaque the text is.
+pub(crate) fn draw_text(
+    image: &mut ImgVec<RGBA8>,
+    (x, y): (isize, isize),
+    text: &str,
+    color: RGBA8,
+) {
+    let font_width = CELL_METRICS.width as usize;
+    let font_height = CELL_METRICS.height as usize;
+    let (width, height) = (image.width() as isize, image.height() as isize);
+    if y >= height || y + font_height as isize <= 0 {
+        return;
+    }
+
+    for (char_i, c) in text.chars().enumerate() {
+        let char_x = x + (char_i * font_width) as isize;
+        if char_x >= width {
+            break;
+        }
+        if char_x + font_width as isize <= 0 {
+            continue;
+        }
+
+        with_rasterized_char(c, 0., GlyphStyle::default(), |canvas| {
+            for glyph_y in 0..font_height {
+                let pixel_y = y + glyph_y as isize;
+                if pixel_y < 0 || pixel_y >= height {
+                    continue;
+                }
+                let row = &canvas.pixels[glyph_y * canvas.stride..(glyph_y + 1) * canvas.stride];
+                for (glyph_x, &coverage) in row[..font_width].iter().enumerate() {
+                    let pixel_x = char_x + glyph_x as isize;
+                    if pixel_x < 0 || pixel_x >= width {
+                        continue;
+                    }
+                    let pixel = &mut image[(pixel_x as usize, pixel_y as usize)];
+                    *pixel = blend_over(color, *pixel, scale_alpha(coverage, color.a));
+                }
+            }
+        });
+    }
+}
+
+/// Blend a rectangle of the given color over the image, with its top left corner at the given
+/// (x, y) pixel, clipped to the image
+#[cfg(feature = "scripting")]
+pub(crate) fn fill_rect(
+    image: &mut ImgVec<RGBA8>,
+    (x, y): (isize, isize),
+    (rect_width, rect_height): (usize, usize),
+    color: RGBA8,
+) {
+    let clip = |start: isize, size: usize, max: usize| {
+        let end = start.saturating_add(size as isize).min(max as isize);
+        let start = start.max(0);
+        (start < end).then(|| (start as usize, (end - start) as usize))
+    };
+    let (x, width) = match clip(x, rect_width, image.width()) {
+        Some(clipped) => clipped,
+        None => return,
+    };
+    let (y, height) = match clip(y, rect_height, image.height()) {
+        Some(clipped) => clipped,
+        None => return,
+    };
+
+    for pixel in image.sub_image_mut(x, y, width, height).pixels_mut() {
+        *pixel = blend_over(color, *pixel, color.a);
+    }
+}
+
+/// Scale a glyph coverage by an opacity
+fn scale_alpha(coverage: u8, alpha: u8) -> u8 {
+    ((coverage as u32 * alpha as u32 + 127) / 255) as u8
+}
+
+/// The color of the title bar
+const TITLE_BAR_COLOR: RGBA8 = RGBA::new(48, 48, 48, 255);
+/// The color of the title text
+const TITLE_FG_COLOR: RGBA8 = RGBA::new(216, 216, 216, 255);
+/// The colors of the window buttons on the left of the title bar
+const TITLE_BUTTON_COLORS: [RGBA8; 3] = [
+    RGBA::new(171, 70, 66, 255),
+    RGBA::new(247, 202, 136, 255),
+    RGBA::new(161, 181, 108, 255),
+];
+
+/// Get the height of the title bar in pixels
+pub(crate) fn title_bar_height() -> usize {
+    CELL_METRICS.height as usize * 2
+}
+
+/// Add a window title bar with the given title above a rendered frame
+pub(crate) fn add_title_bar(image: ImgVec<RGBA8>, title: &str, pool: &FramePool) -> ImgVec<RGBA8> {
+    let font_width = CELL_METRICS.width as usize;
+    let font_height = CELL_METRICS.height as usize;
+    let bar_height = title_bar_height();
+    let width = image.width();
+    let height = image.height() + bar_height;
+
+    // Copy the frame below the title bar
+    let mut pixels = pool.take_empty(width * height);
+    pixels.resize(width * bar_height, TITLE_BAR_COLOR);
+    pixels.extend(image.pixels());
+    pool.give(image);
+    let mut image = Img::new(pixels, width, height);
+
+    // Draw the window buttons
+    let radius = font_height as f32 / 4.;
+    let center_y = bar_height as f32 / 2.;
+    for (i, &color) in TITLE_BUTTON_COLORS.iter().enumerate() {
+        let center_x = font_width as f32 + radius + i as f32 * radius * 3.;
+        for y in 0..bar_height {
+            for x in 0..width.min((center_x + radius) as usize + 1) {
+                let distance =
+                    ((x as f32 + 0.5 - center_x).powi(2) + (y as f32 + 0.5 - center_y).powi(2))
+                        .sqrt();
+                // Anti-alias the edge of the circle
+                let coverage = (radius + 0.5 - distance).clamp(0., 1.);
+                if coverage > 0. {
+                    image[(x, y)] = blend_over(color, image[(x, y)], (coverage * 255.) as u8);
+                }
+            }
+        }
+    }
+
+    // Draw the title, centered, leaving room for the buttons on either side
+    let buttons_width = font_width + (radius * 8.) as usize;
+    let max_cols = width.saturating_sub(buttons_width * 2) / font_width;
+    let mut title: Vec<char> = title.chars().collect();
+    if title.len() > max_cols {
+        title.truncate(max_cols.saturating_sub(3));
+        title.extend("...".chars());
+        title.truncate(max_cols);
+    }
+    let xpos = width.saturating_sub(title.len() * font_width) / 2;
+    let ypos = (bar_height - font_height) / 2;
+    for (char_i, &c) in title.iter().enumerate() {
+        let mut subimg =
+            image.sub_image_mut(xpos + char_i * font_width, ypos, font_width, font_height);
+        with_rasterized_char(c, 0., GlyphStyle::default(), |canvas| {
+            for y in 0..font_height {
+                let row = &canvas.pixels[y * canvas.stride..(y + 1) * canvas.stride];
+                for (x, &alpha) in row[..font_width].iter().enumerate() {
+                    subimg[(x, y)] = blend_over(TITLE_FG_COLOR, subimg[(x, y)], alpha);
+                }
+            }
+        });
+    }
+
+    image
+}
+
+/// Render a terminal frame to an image in a buffer from the pool, with `background` as the
+/// terminal background
+pub(crate) fn render_frame_to_png(
+    frame: TerminalFrame,
+    crop: Option<CropSettings>,
+    background: RGBA8,
+    pool: &FramePool,
+) -> RgbaFrame {
     flame!(guard "Render Frame To PNG");
 
     flame!(start "Init Values");
-    let font_size = 13f32; // TODO make configurable font size
     let (rows, cols) = frame.screen.size();
-    // TODO: Configurable background color
-    const DEFAULT_BG_COLOR: RGBA8 = RGBA::new(0, 0, 0, 255);
 
     let crop_rows = crop.map(|x| x.height).unwrap_or(rows);
     let crop_cols = crop.map(|x| x.width).unwrap_or(cols);
     let crop_top = crop.map(|x| x.top).unwrap_or(0);
     let crop_left = crop.map(|x| x.left).unwrap_or(0);
 
-    // Glyph rendering config
-    lazy_static! {
-        // static ref TRANS: Transform2F = Transform2F::default();
-        // TODO check hinting settings ( None might be faster with no difference in rendering )
-        static ref HINTING_OPTS: HintingOptions = HintingOptions::Vertical(5.);
-        static ref FORMAT: Format = Format::A8;
-        static ref RASTER_OPTS: RasterizationOptions = RasterizationOptions::GrayscaleAa;
-    }
-
-    // Get font height and width
-    let raster_rect = FONT
-        .with(|f| {
-            f.raster_bounds(
-                f.glyph_for_char('A').expect("TODO"),
-                font_size,
-                Transform2F::default(),
-                *HINTING_OPTS,
-                *RASTER_OPTS,
-            )
-        })
-        .expect("TODO");
-    let font_width = raster_rect.width();
-    let font_height = ((FONT_METRICS.ascent - FONT_METRICS.descent)
-        / FONT_METRICS.units_per_em as f32
-        * font_size)
-        .ceil() as i32;
-    let font_height_offset = (font_height - raster_rect.height()) / 2;
-    let font_transform =
-        Transform2F::from_translation(Vector2F::new(0., -font_height_offset as f32));
+    let font_height = CELL_METRICS.height;
 
     let height = (crop_rows as i32 * font_height) as usize;
-    let width = (crop_cols as i32 * font_width) as usize;
+    let width = cell_left(crop_cols as usize);
 
     // Image to render to
     let pixel_count = width * height;
-    let mut pixels: Vec<RGBA8> = Vec::with_capacity(pixel_count);
-    for _ in 0..pixel_count {
-        pixels.push(DEFAULT_BG_COLOR);
-    }
-    let mut image: ImgVec<RGBA8> = Img::new(pixels, width, height);
+    let mut pixels = pool.take(pixel_count, background);
     // TODO: Render cursor position
-    let _cursor_position = frame.screen.cursor_position();
+    let _cursors = frame.screen.cursors();
 
     flame!(end "Init Values");
 
     flame!(start "Render Cells");
-    for (row_i, row) in (crop_top..(crop_top + crop_rows)).enumerate() {
-        for (col_i, col) in (crop_left..(crop_left + crop_cols)).enumerate() {
-            let cell = frame.screen.cell(row, col).expect("Error indexing cell");
-            let ypos = row_i as i32 * font_height;
-            let xpos = col_i as i32 * font_width;
-            let mut subimg = image.sub_image_mut(
-                xpos as usize,
-                ypos as usize,
-                font_width as usize,
-                font_height as usize,
-            );
-
-            let cell_bg_color = parse_color(cell.bgcolor())
-                .map(|x| RGBA::new(x.0, x.1, x.2, 255))
-                .unwrap_or(DEFAULT_BG_COLOR);
-            let cell_fg_color = parse_color(cell.fgcolor())
-                .map(|x| RGBA::new(x.0, x.1, x.2, 255))
-                .unwrap_or(RGBA::new(255, 255, 255, 255));
-
-            let real_bg_color;
-            let real_fg_color;
-            if frame.screen.cursor_position() == (row, col) {
-                real_fg_color = cell_bg_color;
-                real_bg_color = cell_fg_color;
-            } else {
-                real_bg_color = cell_bg_color;
-                real_fg_color = cell_fg_color;
-            }
-
-            if real_bg_color != DEFAULT_BG_COLOR {
-                for pixel in subimg.pixels_mut() {
-                    *pixel = real_bg_color;
-                }
-            }
-
-            if cell.has_contents() {
-                use palette::{Blend, LinSrgba, Pixel};
-                let mut canvas = Canvas::new(Vector2I::new(font_width, font_height), *FORMAT);
-                let contents = cell.contents();
-                if contents == "" {
-                    break;
-                }
-                let cell_char: char = contents.parse().expect("Could not parse char");
-
-                // TODO: We currently use `.` as a fallback char, but we should use a better one and maybe pick a
-                // font that supports all the characters used in the TUI-rs demo.
-                let glyph_id = FONT.with(|f| {
-                    f.glyph_for_char(cell_char)
-                        .unwrap_or_else(|| f.glyph_for_char('.').expect("TODO"))
-                });
-
-                FONT.with(|f| {
-                    f.rasterize_glyph(
-                        &mut canvas,
-                        glyph_id,
-                        font_size as f32,
-                        Transform2F::from_translation(-raster_rect.origin().to_f32())
-                            * font_transform,
-                        *HINTING_OPTS,
-                        *RASTER_OPTS,
-                    )
-                })
-                .expect("TODO");
-
-                // Alpha `a` over `b`: component wize: a + b * (255 - alpha)
-                for y in 0..font_height {
-                    let (row_start, row_end) =
-                        (y as usize * canvas.stride, (y + 1) as usize * canvas.stride);
-                    let row = &canvas.pixels[row_start..row_end];
-                    for x in 0..font_width {
-                        let alpha = row[x as usize];
-                        let bg: LinSrgba<f32> = LinSrgba::from_raw(&[
-                            real_bg_color.r,
-                            real_bg_color.g,
-                            real_bg_color.b,
-                            255,
-                        ])
-                        .into_format();
-                        let fg: LinSrgba<f32> = LinSrgba::from_raw(&[
-                            real_fg_color.r,
-                            real_fg_color.g,
-                            real_fg_color.b,
-                            alpha,
-                        ])
-                        .into_format();
-                        let out: [u8; 4] = fg.over(bg).into_format().into_raw();
-                        subimg[(x as usize, y as usize)] = RGBA8::new(out[0], out[1], out[2], 255);
-                    }
-                }
-            }
-        }
+    // Render the terminal rows in parallel, each into its own band of the image, so that large
+    // terminals don't take much longer to render than small ones
+    let band_size = width * font_height as usize;
+    if band_size > 0 {
+        pixels
+            .par_chunks_mut(band_size)
+            .enumerate()
+            .for_each(|(row_i, band)| {
+                render_row(
+                    &frame.screen,
+                    crop_top + row_i as u16,
+                    crop_left..(crop_left + crop_cols),
+                    Img::new(band, width, font_height as usize),
+                    background,
+                )
+            });
     }
+    let image: ImgVec<RGBA8> = Img::new(pixels, width, height);
     flame!(end "Render Cells");
 
     flame!(start "Create Image");
@@ -1,6 +1,8 @@
 /// The SVG-based implementation of the frame renderer
+use imgref::ImgVec;
 use rgb::{AsPixels, RGBA8};
 
+use crate::frame_pool::FramePool;
 use crate::types::*;
 
 fn parse_color(color: vt100::Color) -> Option<String> {
@@ -8,25 +10,53 @@ fn parse_color(color: vt100::Color) -> Option<String> {
     Some(format!("#{}", base16::encode_lower(&[r, g, b])))
 }
 
-fn render_frame_to_svg(frame: &TerminalFrame) -> SvgFrame {
+// Set the size of the terminal cells
+// TODO: Make this dynamic based on the font and font-size
+const FONT_SIZE: u16 = 10;
+const CELL_WIDTH: u16 = 6;
+const CELL_HEIGHT: u16 = FONT_SIZE;
+
+/// The name of this renderer backend, which the `bench` subcommand reports
+#[cfg(feature = "cli")]
+pub(crate) const BACKEND_NAME: &str = "svg";
+
+/// Get the (width, height) of a terminal cell in pixels
+pub(crate) fn cell_size() -> (usize, usize) {
+    (CELL_WIDTH as usize, CELL_HEIGHT as usize)
+}
+
+/// Get the distance in pixels from the start of one cell in a row to the start of the next
+pub(crate) fn cell_advance() -> f64 {
+    CELL_WIDTH as f64
+}
+
+fn render_frame_to_svg(
+    frame: &TerminalFrame,
+    crop: Option<CropSettings>,
+    background: RGBA8,
+) -> SvgFrame {
     use svg::{
         node::{
-            element::{Rectangle, Text},
+            element::{Path, Rectangle, Text},
             Text as TextNode,
         },
         Document,
     };
 
-    // Set the size of the terminal cells
-    // TODO: Make this dynamic based on the font and font-size
-    let font_size = 10;
-    let cell_width = 6;
-    let cell_height = font_size;
+    let font_size = FONT_SIZE;
+    let cell_width = CELL_WIDTH;
+    let cell_height = CELL_HEIGHT;
 
-    // Get the size of the terminal screen
+    // Get the part of the terminal screen to render
     let (rows, cols) = frame.screen.size();
-    let doc_height = rows * cell_height;
-    let doc_width = cols * cell_width;
+    let crop = crop.unwrap_or(CropSettings {
+        top: 0,
+        left: 0,
+        width: cols,
+        height: rows,
+    });
+    let doc_height = crop.height * cell_height;
+    let doc_width = crop.width * cell_width;
 
     // Create the svg document
     let mut doc = Document::new()
@@ -35,7 +65,11 @@ fn render_frame_to_svg(frame: &TerminalFrame) -> SvgFrame {
         .set("width", doc_width);
 
     // TODO: Allow custom
-    let background_color = "#000000";
+    let background_color = format!(
+        "#{}",
+        base16::encode_lower(&[background.r, background.g, background.b])
+    );
+    let background_opacity = background.a as f32 / 255.;
     let foreground_color = "#ffffff";
 
     // Draw the terminal background
@@ -44,8 +78,9 @@ fn render_frame_to_svg(frame: &TerminalFrame) -> SvgFrame {
             .set(
                 "style",
                 format!(
-                    "fill:{bgcolor};fill-opacity:1;stroke:none",
-                    bgcolor = background_color
+                    "fill:{bgcolor};fill-opacity:{opacity};stroke:none",
+                    bgcolor = background_color,
+                    opacity = background_opacity
                 ),
             )
             .set("x", "0")
@@ -55,18 +90,23 @@ fn render_frame_to_svg(frame: &TerminalFrame) -> SvgFrame {
     );
 
     // Iterate through each cell
-    for row in 0..rows {
-        for col in 0..cols {
+    for row in 0..crop.height {
+        for col in 0..crop.width {
             // Get the cell
-            let cell = frame.screen.cell(row, col).unwrap_or_else(|| {
-                panic!(
-                    "Missing cell at position ({}, {}) in frame at {}",
-                    row, col, frame.time
-                )
-            });
+            let cell = frame
+                .screen
+                .cell(crop.top + row, crop.left + col)
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Missing cell at position ({}, {}) in frame at {}",
+                        crop.top + row,
+                        crop.left + col,
+                        frame.time
+                    )
+                });
 
             // If the cell has a background color
-            if let Some(bg_color) = parse_color(cell.bgcolor()) {
+            if let Some(bg_color) = parse_color(cell.bgcolor) {
                 doc = doc.add(
                     Rectangle::new()
                         .set("x", (col * cell_width).to_string())
@@ -83,10 +123,9 @@ fn render_frame_to_svg(frame: &TerminalFrame) -> SvgFrame {
                 );
             }
             // If the cell is not empty
-            let contents = cell.contents();
-            if contents != "" && contents != " " {
+            if let Some(contents) = cell.contents.map(|c| c.to_string()).filter(|c| c != " ") {
                 let text_color =
-                    parse_color(cell.fgcolor()).unwrap_or_else(|| foreground_color.into());
+                    parse_color(cell.fgcolor).unwrap_or_else(|| foreground_color.into());
                 // Add the cell's text to the SVG
                 doc = doc.add(
                     Text::new()
@@ -112,6 +151,48 @@ fn render_frame_to_svg(frame: &TerminalFrame) -> SvgFrame {
                         ),
                 );
             }
+            // If the cell is underlined
+            if let Some(underline) = cell.underline {
+                let line_color = parse_color(underline.color)
+                    .or_else(|| parse_color(cell.fgcolor))
+                    .unwrap_or_else(|| foreground_color.into());
+                let (x, width) = (col * cell_width, cell_width);
+                let y = (row + 1) * cell_height - 1;
+                let (path, dashes) = match underline.style {
+                    UnderlineStyle::Single => (format!("M{},{}h{}", x, y, width), None),
+                    UnderlineStyle::Double => (
+                        format!("M{},{}h{}M{},{}h{}", x, y - 2, width, x, y, width),
+                        None,
+                    ),
+                    UnderlineStyle::Dotted => (format!("M{},{}h{}", x, y, width), Some("1 1")),
+                    UnderlineStyle::Dashed => {
+                        (format!("M{},{}h{}", x + width / 4, y, width / 2), None)
+                    }
+                    // A wave one cell long, above and below the line
+                    UnderlineStyle::Curly => (
+                        format!(
+                            "M{},{}q{},-2 {},0t{},0",
+                            x,
+                            y,
+                            width / 4,
+                            width / 2,
+                            width / 2
+                        ),
+                        None,
+                    ),
+                };
+                let mut line = Path::new().set("d", path).set(
+                    "style",
+                    format!(
+                        "fill:none;stroke:{color};stroke-width:1",
+                        color = line_color
+                    ),
+                );
+                if let Some(dashes) = dashes {
+                    line = line.set("stroke-dasharray", dashes);
+                }
+                doc = doc.add(line);
+            }
         }
     }
 
@@ -127,10 +208,66 @@ fn render_frame_to_svg(frame: &TerminalFrame) -> SvgFrame {
     }
 }
 
-pub(crate) fn render_frame_to_png(frame: TerminalFrame) -> RgbaFrame {
+/// Get the height of the title bar in pixels
+/// Warm up the renderer on this thread
+///
+/// Frames are rendered by resvg, which doesn't load anything ahead of time, so this does nothing.
+pub(crate) fn warm_up() {}
+
+pub(crate) fn title_bar_height() -> usize {
+    0
+}
+
+/// Add a window title bar above a rendered frame
+///
+/// Title bars are not supported by this backend yet, so this returns the frame unchanged.
+pub(crate) fn add_title_bar(
+    image: ImgVec<RGBA8>,
+    _title: &str,
+    _pool: &FramePool,
+) -> ImgVec<RGBA8> {
+    log::warn!("Title bars are not supported by the svg backend");
+    image
+}
+
+/// Draw caption text over the bottom of a rendered frame
+///
+/// Captions are not supported by this backend yet, so this does nothing.
+pub(crate) fn draw_caption(_image: &mut ImgVec<RGBA8>, _text: &str) {
+    log::warn!("Subtitles are not supported by the svg backend");
+}
+
+#[cfg(feature = "scripting")]
+pub(crate) fn draw_text(
+    _image: &mut ImgVec<RGBA8>,
+    _pos: (isize, isize),
+    _text: &str,
+    _color: RGBA8,
+) {
+    log::warn!("Overlay text is not supported by the svg backend");
+}
+
+#[cfg(feature = "scripting")]
+pub(crate) fn fill_rect(
+    _image: &mut ImgVec<RGBA8>,
+    _pos: (isize, isize),
+    _size: (usize, usize),
+    _color: RGBA8,
+) {
+    log::warn!("Overlay boxes are not supported by the svg backend");
+}
+
+pub(crate) fn render_frame_to_png(
+    frame: TerminalFrame,
+    crop: Option<CropSettings>,
+    background: RGBA8,
+    pool: &FramePool,
+) -> RgbaFrame {
     use resvg::prelude::*;
+    flame!(guard "Render Frame To PNG");
+
     // Get the SVG render of the frame
-    let svg_doc = render_frame_to_svg(&frame);
+    let svg_doc = render_frame_to_svg(&frame, crop, background);
 
     let opt = resvg::Options::default();
     let rtree = usvg::Tree::from_str(&svg_doc.doc.to_string(), &opt.usvg).expect("TODO");
@@ -145,18 +282,15 @@ pub(crate) fn render_frame_to_png(frame: TerminalFrame) -> RgbaFrame {
 
     // Collect image
     let rgba8_pixels = img.make_rgba_vec();
-    let rgba8_pixels: Vec<RGBA8> = rgba8_pixels
-        .as_slice()
-        .as_pixels()
-        .iter()
-        .map(Clone::clone)
-        .collect();
+    let rgba8_pixels = rgba8_pixels.as_slice().as_pixels();
+    let mut pixels = pool.take_empty(rgba8_pixels.len());
+    pixels.extend_from_slice(rgba8_pixels);
 
     RgbaFrame {
         time: frame.time,
         index: frame.index,
         image: imgref::Img::new(
-            rgba8_pixels,
+            pixels,
             // TODO: avoid using `as`
             svg_doc.width as usize,
             svg_doc.height as usize,
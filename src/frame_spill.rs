@@ -0,0 +1,146 @@
+//! Disk-backed storage for rendered frames
+//!
+//! When the gif encoder falls behind the rasterizer, rendered frames can be spilled to disk,
+//! compressed, instead of being held in memory until the encoder is ready for them.
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use imgref::{Img, ImgVec};
+use rgb::{ComponentBytes, RGBA8};
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+use std::sync::Arc;
+
+use crate::frame_pool::FramePool;
+use crate::types::RgbaFrame;
+
+/// Counter used to give each spill directory in this process a unique name
+static NEXT_SPILL_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory that rendered frames are spilled to
+///
+/// The directory and any frames left in it are removed when this is dropped.
+#[derive(Debug)]
+pub(crate) struct FrameSpill {
+    dir: PathBuf,
+}
+
+impl FrameSpill {
+    /// Create a new spill directory inside of `parent`
+    pub fn new(parent: &Path) -> io::Result<Self> {
+        let dir = parent.join(format!(
+            "cast2gif-spill-{}-{}",
+            std::process::id(),
+            NEXT_SPILL_ID.fetch_add(1, SeqCst)
+        ));
+        std::fs::create_dir_all(&dir)?;
+
+        Ok(FrameSpill { dir })
+    }
+
+    /// Write a frame to disk, giving its buffer back to the pool
+    pub fn spill(self: &Arc<Self>, frame: RgbaFrame, pool: &FramePool) -> io::Result<SpilledFrame> {
+        let path = self.dir.join(format!("{}.frame", frame.index));
+        let (width, height) = (frame.image.width(), frame.image.height());
+        write_image(&path, &frame.image)?;
+        pool.give(frame.image);
+
+        Ok(SpilledFrame {
+            index: frame.index,
+            time: frame.time,
+            width,
+            height,
+            path,
+            _spill: self.clone(),
+        })
+    }
+}
+
+impl Drop for FrameSpill {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            log::warn!(
+                "Could not remove frame spill directory {}: {}",
+                self.dir.to_string_lossy(),
+                e
+            );
+        }
+    }
+}
+
+/// A rendered frame that has been written to disk
+#[derive(Debug)]
+pub(crate) struct SpilledFrame {
+    /// The index of the frame in the animation
+    pub index: u64,
+    /// The time the frame occurrs in the animation timeline
+    pub time: f64,
+    /// The width of the frame image
+    width: usize,
+    /// The height of the frame image
+    height: usize,
+    /// The file the frame was written to
+    path: PathBuf,
+    /// The spill directory, kept alive until the frame is loaded
+    _spill: Arc<FrameSpill>,
+}
+
+impl SpilledFrame {
+    /// Read the frame back from disk into a buffer from the pool, removing the spilled file
+    pub fn load(self, pool: &FramePool) -> io::Result<RgbaFrame> {
+        let image = read_image(&self.path, self.width, self.height, pool)?;
+        std::fs::remove_file(&self.path)?;
+
+        Ok(RgbaFrame {
+            index: self.index,
+            time: self.time,
+            image,
+        })
+    }
+}
+
+/// Compress an image and write it to a file
+pub(crate) fn write_image(path: &Path, image: &ImgVec<RGBA8>) -> io::Result<()> {
+    // Write the image row by row so that we don't have to copy it into a contiguous buffer
+    let mut encoder = DeflateEncoder::new(BufWriter::new(File::create(path)?), Compression::fast());
+    for row in image.rows() {
+        encoder.write_all(row.as_bytes())?;
+    }
+    encoder.finish()?.flush()
+}
+
+/// Read an image of the given size that was written by [`write_image`], into a buffer from the pool
+pub(crate) fn read_image(
+    path: &Path,
+    width: usize,
+    height: usize,
+    pool: &FramePool,
+) -> io::Result<ImgVec<RGBA8>> {
+    let file = File::open(path)?;
+    // Decompress straight into the image's pixel buffer
+    let mut pixels = pool.take(width * height, RGBA8::default());
+    DeflateDecoder::new(BufReader::new(file)).read_exact(pixels.as_bytes_mut())?;
+
+    Ok(Img::new(pixels, width, height))
+}
+
+/// A rendered frame waiting to be sequenced
+#[derive(Debug)]
+pub(crate) enum QueuedFrame {
+    /// A frame held in memory
+    Memory(RgbaFrame),
+    /// A frame spilled to disk
+    Spilled(SpilledFrame),
+}
+
+impl QueuedFrame {
+    /// Get the rendered frame, loading it from disk into a buffer from the pool if necessary
+    pub fn load(self, pool: &FramePool) -> io::Result<RgbaFrame> {
+        match self {
+            QueuedFrame::Memory(frame) => Ok(frame),
+            QueuedFrame::Spilled(frame) => frame.load(pool),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+//! Reading what a gif is made of without decoding its frames
+//!
+//! Gif decoders skip over the comments of a gif, so the blocks of the gif are walked here instead.
+//! Comments are collected, and the delay of each frame is read from the graphic control block in
+//! front of it. The image data of the frames is skipped without being decompressed.
+
+use std::io::{self, BufReader, Read};
+
+use crate::Error;
+
+/// The byte that starts an extension block
+const EXTENSION: u8 = 0x21;
+/// The byte that starts an image block, which is a frame
+const IMAGE: u8 = 0x2C;
+/// The byte that ends a gif
+const TRAILER: u8 = 0x3B;
+/// The label of the extension with the delay of the next frame
+const GRAPHIC_CONTROL: u8 = 0xF9;
+/// The label of a comment extension
+const COMMENT: u8 = 0xFE;
+
+/// What a gif is made of
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GifInfo {
+    /// The width of the gif, in pixels
+    pub width: u16,
+    /// The height of the gif, in pixels
+    pub height: u16,
+    /// The comments of the gif, in the order that they are written in
+    pub comments: Vec<String>,
+    /// The delay of each frame, in centiseconds
+    pub delays: Vec<u16>,
+}
+
+impl GifInfo {
+    /// Get the time that the gif plays for, in seconds
+    pub fn duration(&self) -> f64 {
+        self.delays
+            .iter()
+            .map(|&delay| f64::from(delay))
+            .sum::<f64>()
+            / 100.
+    }
+}
+
+/// Read a byte
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+/// Read a little-endian `u16`
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+/// Skip over a number of bytes
+fn skip<R: Read>(reader: &mut R, len: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(len), &mut io::sink())?;
+    if skipped < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    Ok(())
+}
+
+/// Read the sub-blocks that the data of a block is split into, up to the empty one that ends them
+fn read_sub_blocks<R: Read>(reader: &mut R, data: &mut Vec<u8>) -> io::Result<()> {
+    loop {
+        let len = read_u8(reader)?;
+        if len == 0 {
+            return Ok(());
+        }
+
+        let start = data.len();
+        data.resize(start + usize::from(len), 0);
+        reader.read_exact(&mut data[start..])?;
+    }
+}
+
+/// Skip over the sub-blocks of a block, up to the empty one that ends them
+fn skip_sub_blocks<R: Read>(reader: &mut R) -> io::Result<()> {
+    loop {
+        match read_u8(reader)? {
+            0 => return Ok(()),
+            len => skip(reader, u64::from(len))?,
+        }
+    }
+}
+
+/// Get the size in bytes of the color table that follows a block with the given flags
+fn color_table_size(flags: u8) -> u64 {
+    if flags & 0x80 == 0 {
+        return 0;
+    }
+
+    3 << ((flags & 0x07) + 1)
+}
+
+/// Walk the blocks of a gif
+fn read_blocks<R: Read>(mut reader: R) -> io::Result<GifInfo> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+
+    let mut signature = [0; 6];
+    reader.read_exact(&mut signature)?;
+    if !signature.starts_with(b"GIF") {
+        return Err(invalid("The file isn't a gif".into()));
+    }
+    let width = read_u16(&mut reader)?;
+    let height = read_u16(&mut reader)?;
+    let flags = read_u8(&mut reader)?;
+    // The background color index and the pixel aspect ratio
+    skip(&mut reader, 2)?;
+    skip(&mut reader, color_table_size(flags))?;
+
+    let mut info = GifInfo {
+        width,
+        height,
+        ..GifInfo::default()
+    };
+    // The delay of the next frame, from the last graphic control block
+    let mut delay = 0;
+    loop {
+        match read_u8(&mut reader)? {
+            EXTENSION => {
+                let label = read_u8(&mut reader)?;
+                let mut data = Vec::new();
+                read_sub_blocks(&mut reader, &mut data)?;
+                match label {
+                    // The delay is after the flags
+                    GRAPHIC_CONTROL if data.len() >= 3 => {
+                        delay = u16::from_le_bytes([data[1], data[2]])
+                    }
+                    COMMENT => info
+                        .comments
+                        .push(String::from_utf8_lossy(&data).into_owned()),
+                    _ => (),
+                }
+            }
+            IMAGE => {
+                // The position and size of the frame
+                skip(&mut reader, 8)?;
+                let flags = read_u8(&mut reader)?;
+                skip(&mut reader, color_table_size(flags))?;
+                // The LZW code size
+                skip(&mut reader, 1)?;
+                skip_sub_blocks(&mut reader)?;
+                info.delays.push(std::mem::take(&mut delay));
+            }
+            TRAILER => return Ok(info),
+            block => return Err(invalid(format!("Unknown gif block: {:#04x}", block))),
+        }
+    }
+}
+
+/// Read the size, comments, and frame delays of a gif
+pub(crate) fn read<R: Read>(reader: R) -> Result<GifInfo, Error> {
+    read_blocks(BufReader::new(reader)).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => Error::Generic("Could not read gif: It ends early".into()),
+        _ => Error::Generic(format!("Could not read gif: {}", e)),
+    })
+}
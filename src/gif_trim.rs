@@ -0,0 +1,331 @@
+//! Cropping the frames of a gif to the pixels that they change
+//!
+//! Most frames of a terminal recording only change a few cells, so a frame only needs to cover the
+//! box around the pixels that it changes. gifski crops the top, bottom, and left of the frames that
+//! it writes, but not the right, and it always writes the last frame whole. The gifs that it writes
+//! are passed through here, which crops every frame after the first to the bounding box of the
+//! pixels that change when it is shown over the frames before it.
+
+use std::io::{self, Read, Write};
+
+use crate::Error;
+
+/// The number of bytes to collect before sending them through a pipe, if it isn't flushed first
+const PIPE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Make a pipe to pass a gif from the gif writer to the thread that trims it
+///
+/// Written bytes are sent to the reader when the writer is flushed or dropped.
+pub(crate) fn pipe() -> (PipeWriter, PipeReader) {
+    let (sender, receiver) = flume::bounded(16);
+    (
+        PipeWriter {
+            sender,
+            buffer: Vec::new(),
+        },
+        PipeReader {
+            receiver,
+            chunk: Vec::new(),
+            pos: 0,
+        },
+    )
+}
+
+/// The end of a [`pipe`] that the gif is written to
+pub(crate) struct PipeWriter {
+    sender: flume::Sender<Vec<u8>>,
+    /// The bytes that haven't been sent yet
+    buffer: Vec<u8>,
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if self.buffer.len() >= PIPE_CHUNK_SIZE {
+            self.flush()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.sender
+            .send(std::mem::take(&mut self.buffer))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "The gif trimmer stopped"))
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.flush().ok();
+    }
+}
+
+/// The end of a [`pipe`] that the gif is read from
+///
+/// The reader reaches the end of the gif once the writer is dropped.
+pub(crate) struct PipeReader {
+    receiver: flume::Receiver<Vec<u8>>,
+    /// The bytes that were last received
+    chunk: Vec<u8>,
+    /// The position of the next byte to read in `chunk`
+    pos: usize,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let read = buf.len().min(self.chunk.len() - self.pos);
+        buf[..read].copy_from_slice(&self.chunk[self.pos..self.pos + read]);
+        self.pos += read;
+
+        Ok(read)
+    }
+}
+
+/// The colors of the pixels of a canvas, with `None` for transparent pixels
+type Pixels = Vec<Option<[u8; 3]>>;
+
+/// The pixels that are on screen while a gif plays
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Pixels,
+}
+
+/// A rectangle of the canvas, in pixels
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    left: usize,
+    top: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Rect {
+    /// Get the part of a frame that is on the canvas
+    fn of_frame(frame: &gif::Frame, canvas: &Canvas) -> Self {
+        let left = usize::from(frame.left).min(canvas.width);
+        let top = usize::from(frame.top).min(canvas.height);
+        Rect {
+            left,
+            top,
+            width: usize::from(frame.width).min(canvas.width - left),
+            height: usize::from(frame.height).min(canvas.height - top),
+        }
+    }
+}
+
+impl Canvas {
+    fn new(width: u16, height: u16) -> Self {
+        let (width, height) = (usize::from(width), usize::from(height));
+        Canvas {
+            width,
+            height,
+            pixels: vec![None; width * height],
+        }
+    }
+
+    /// Get the color that a frame paints at the (x, y) position in it, or `None` if it leaves the
+    /// pixel as it was
+    fn frame_color(frame: &gif::Frame, palette: &[u8], x: usize, y: usize) -> Option<[u8; 3]> {
+        let index = frame.buffer[y * usize::from(frame.width) + x];
+        if frame.transparent == Some(index) {
+            return None;
+        }
+
+        // Colors that aren't in the palette are shown as black by most decoders
+        let start = usize::from(index) * 3;
+        Some(match palette.get(start..start + 3) {
+            Some(color) => [color[0], color[1], color[2]],
+            None => [0; 3],
+        })
+    }
+
+    /// Find the bounding box of the pixels that a frame changes, relative to the frame
+    fn changed_box(&self, frame: &gif::Frame, palette: &[u8]) -> Option<Rect> {
+        let rect = Rect::of_frame(frame, self);
+        let (mut left, mut top, mut right, mut bottom) = (usize::MAX, usize::MAX, 0, 0);
+        for y in 0..rect.height {
+            let row = (rect.top + y) * self.width + rect.left;
+            for x in 0..rect.width {
+                let changed = Self::frame_color(frame, palette, x, y)
+                    .is_some_and(|color| self.pixels[row + x] != Some(color));
+                if changed {
+                    left = left.min(x);
+                    top = top.min(y);
+                    right = right.max(x + 1);
+                    bottom = bottom.max(y + 1);
+                }
+            }
+        }
+
+        (right > 0).then(|| Rect {
+            left,
+            top,
+            width: right - left,
+            height: bottom - top,
+        })
+    }
+
+    /// Paint a frame onto the canvas
+    fn paint(&mut self, frame: &gif::Frame, palette: &[u8]) {
+        let rect = Rect::of_frame(frame, self);
+        for y in 0..rect.height {
+            let row = (rect.top + y) * self.width + rect.left;
+            for x in 0..rect.width {
+                if let Some(color) = Self::frame_color(frame, palette, x, y) {
+                    self.pixels[row + x] = Some(color);
+                }
+            }
+        }
+    }
+
+    /// Clear the part of the canvas that a frame covered, which is how viewers dispose of frames
+    /// to the background
+    fn clear(&mut self, rect: Rect) {
+        for y in rect.top..rect.top + rect.height {
+            let row = y * self.width;
+            for pixel in &mut self.pixels[row + rect.left..row + rect.left + rect.width] {
+                *pixel = None;
+            }
+        }
+    }
+}
+
+/// The number of frames in a gif and the time that it plays for
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct GifLength {
+    pub frames: u64,
+    /// The time that the gif plays for, in seconds
+    pub duration: f64,
+}
+
+/// Crop a frame to a rectangle of it
+fn crop(frame: &mut gif::Frame, rect: Rect) {
+    let width = usize::from(frame.width);
+    let mut buffer = Vec::with_capacity(rect.width * rect.height);
+    for y in rect.top..rect.top + rect.height {
+        let row = y * width;
+        buffer.extend_from_slice(&frame.buffer[row + rect.left..row + rect.left + rect.width]);
+    }
+
+    // The rectangle is inside of the frame, so its position and size fit in a `u16`
+    frame.left += rect.left as u16;
+    frame.top += rect.top as u16;
+    frame.width = rect.width as u16;
+    frame.height = rect.height as u16;
+    frame.buffer = buffer.into();
+}
+
+/// Crop the frames of a gif to the pixels that they change, writing the cropped gif to `writer`
+///
+/// `comment`, if any, is written into a comment extension before the first frame. `frame_written`
+/// is called with the writer after each frame is written, such as to flush it so that the start of
+/// the gif can be read while the rest of it is still being written. Returns the length of the gif.
+pub(crate) fn trim<R, W>(
+    gif: R,
+    writer: W,
+    repeat: gifski::Repeat,
+    comment: Option<&str>,
+    mut frame_written: impl FnMut(&mut W) -> io::Result<()>,
+) -> Result<GifLength, Error>
+where
+    R: Read,
+    W: Write,
+{
+    let decoding_error =
+        |e: gif::DecodingError| Error::Generic(format!("Could not read gif: {}", e));
+    let encoding_error =
+        |e: gif::EncodingError| Error::Generic(format!("Could not write gif: {}", e));
+
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    // The size of the gif is already limited by the renderer
+    options.set_memory_limit(gif::MemoryLimit(0));
+    let mut decoder = options.read_info(gif).map_err(decoding_error)?;
+
+    let global_palette = decoder.global_palette().unwrap_or(&[]).to_vec();
+    let mut encoder = gif::Encoder::new(writer, decoder.width(), decoder.height(), &global_palette)
+        .map_err(encoding_error)?;
+    encoder
+        .set_repeat(match repeat {
+            gifski::Repeat::Finite(count) => gif::Repeat::Finite(count),
+            gifski::Repeat::Infinite => gif::Repeat::Infinite,
+        })
+        .map_err(encoding_error)?;
+    if let Some(comment) = comment {
+        encoder
+            .write_raw_extension(gif::Extension::Comment.into(), &[comment.as_bytes()])
+            .map_err(|e| Error::Generic(format!("Could not write gif: {}", e)))?;
+    }
+
+    let mut canvas = Canvas::new(decoder.width(), decoder.height());
+    // How to dispose of the last frame, with the part of the canvas that it covered and the canvas
+    // as it was before it, when it has to be restored
+    let mut disposal: Option<(gif::DisposalMethod, Rect, Option<Pixels>)> = None;
+    let mut first = true;
+    let mut length = GifLength::default();
+    while let Some(frame) = decoder.read_next_frame().map_err(decoding_error)? {
+        let mut frame = frame.clone();
+        let palette = frame
+            .palette
+            .clone()
+            .unwrap_or_else(|| global_palette.clone());
+
+        match disposal.take() {
+            Some((gif::DisposalMethod::Background, rect, _)) => canvas.clear(rect),
+            Some((gif::DisposalMethod::Previous, _, Some(previous))) => canvas.pixels = previous,
+            _ => (),
+        }
+
+        // The area that a frame disposes of depends on its size, so only frames that are kept on
+        // screen can be cropped
+        let kept = matches!(
+            frame.dispose,
+            gif::DisposalMethod::Keep | gif::DisposalMethod::Any
+        );
+        if kept && !first && !frame.buffer.is_empty() {
+            // A frame that doesn't change anything still holds its delay, so keep one pixel of it
+            let rect = canvas.changed_box(&frame, &palette).unwrap_or(Rect {
+                left: 0,
+                top: 0,
+                width: 1,
+                height: 1,
+            });
+            crop(&mut frame, rect);
+        }
+        frame.interlaced = false;
+        first = false;
+
+        let previous =
+            (frame.dispose == gif::DisposalMethod::Previous).then(|| canvas.pixels.clone());
+        disposal = Some((frame.dispose, Rect::of_frame(&frame, &canvas), previous));
+        canvas.paint(&frame, &palette);
+
+        encoder.write_frame(&frame).map_err(encoding_error)?;
+        length.frames += 1;
+        length.duration += f64::from(frame.delay) / 100.;
+        frame_written(encoder.get_mut())
+            .map_err(|e| Error::Generic(format!("Could not write gif: {}", e)))?;
+    }
+
+    encoder
+        .into_inner()
+        .map_err(|e| Error::Generic(format!("Could not write gif: {}", e)))?;
+
+    Ok(length)
+}
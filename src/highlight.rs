@@ -0,0 +1,120 @@
+//! Highlighting the cells that change between frames
+//!
+//! Cells that changed since the previous frame are drawn with a tinted background, which fades
+//! back to their own background over the frames after it. This makes it easy to spot what a
+//! command modified on a busy screen.
+
+use crate::cast_parser::AsciinemaError;
+use crate::color_profile::Rgb;
+use crate::types::{ScreenSnapshot, TerminalFrame};
+use crate::ColorProfile;
+
+/// The color that changed cells are tinted with
+const HIGHLIGHT_COLOR: Rgb = (255, 200, 0);
+
+/// How much of the highlight color is mixed into the background of cells that just changed
+const HIGHLIGHT_OPACITY: f64 = 0.5;
+
+/// An iterator adapter that tints the cells that changed since the previous frame
+pub(crate) struct HighlightFrames<I> {
+    /// The frames to highlight
+    frames: I,
+    /// The number of frames that the highlight takes to fade out
+    fade_frames: u32,
+    /// The colors that the frames are rendered with, which the tint is mixed with
+    profile: ColorProfile,
+    /// The screen of the previous frame, before it was tinted
+    previous_screen: Option<ScreenSnapshot>,
+    /// The number of frames since each cell of the screen last changed, in row-major order
+    ages: Vec<u32>,
+}
+
+impl<I> HighlightFrames<I> {
+    /// Tint the cells that change, fading the tint out over `fade_frames` frames
+    ///
+    /// The tint is mixed with the background colors of the `profile` that the frames are rendered
+    /// with. Frames are passed through untouched if `fade_frames` is `0`.
+    pub fn new(frames: I, fade_frames: u32, profile: ColorProfile) -> Self {
+        HighlightFrames {
+            frames,
+            fade_frames,
+            profile,
+            previous_screen: None,
+            ages: Vec::new(),
+        }
+    }
+
+    /// Tint the cells of a screen that changed recently, updating the ages of its cells
+    fn highlight(&mut self, screen: &mut ScreenSnapshot) {
+        if self.fade_frames == 0 {
+            return;
+        }
+
+        let (rows, cols) = screen.size();
+        let previous_screen = self.previous_screen.replace(screen.clone());
+        let previous_screen = match previous_screen {
+            Some(previous) if previous.size() == (rows, cols) => previous,
+            // Nothing has changed on the first screen, and there is nothing to compare to if the
+            // size changed
+            _ => {
+                self.ages = vec![self.fade_frames; rows as usize * cols as usize];
+                return;
+            }
+        };
+
+        for row in 0..rows {
+            for col in 0..cols {
+                let age = &mut self.ages[row as usize * cols as usize + col as usize];
+                if screen.cell(row, col) != previous_screen.cell(row, col) {
+                    *age = 0;
+                } else {
+                    *age = age.saturating_add(1);
+                }
+                if *age >= self.fade_frames {
+                    continue;
+                }
+
+                let cell = screen
+                    .cell_mut(row, col)
+                    .expect("The cell is on the screen");
+                let background = self
+                    .profile
+                    .color(cell.bgcolor)
+                    .unwrap_or(self.profile.background);
+                let opacity = HIGHLIGHT_OPACITY * f64::from(self.fade_frames - *age)
+                    / f64::from(self.fade_frames);
+                let (r, g, b) = mix(background, HIGHLIGHT_COLOR, opacity);
+                cell.bgcolor = vt100::Color::Rgb(r, g, b);
+            }
+        }
+    }
+}
+
+impl<I> Iterator for HighlightFrames<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = match self.frames.next()? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        self.highlight(&mut frame.screen);
+
+        Some(Ok(frame))
+    }
+}
+
+/// Mix `amount` of one color into another
+pub(crate) fn mix(color: Rgb, other: Rgb, amount: f64) -> Rgb {
+    let channel =
+        |a: u8, b: u8| (f64::from(a) * (1. - amount) + f64::from(b) * amount).round() as u8;
+
+    (
+        channel(color.0, other.0),
+        channel(color.1, other.1),
+        channel(color.2, other.2),
+    )
+}
@@ -0,0 +1,132 @@
+//! Holding the last frame of the gif on screen
+//!
+//! The gif encoder doesn't take a duration for the last frame. It shows it for as long as the gap
+//! between the last two frames, or, if the first frame isn't at the start of the animation, for as
+//! long as the time before the first frame. Either way, gifs loop back to the start almost as soon
+//! as the recording reaches its final state.
+
+use crate::end_card::{EndCard, LastFrame};
+use crate::fade::Fade;
+use crate::types::RgbaFrame;
+
+/// The time the last frame is held for when none is configured, in seconds
+pub(crate) const DEFAULT_HOLD_LAST: f64 = 2.;
+
+/// The first timestamp, in seconds, that the gif encoder treats as a delay before the first frame
+const MIN_FIRST_FRAME_OFFSET: f64 = 1. / 100.;
+
+/// Tracks the frames that are given to the gif encoder to make a copy of the last frame that holds
+/// it on screen
+///
+/// The last frame is kept back from the encoder until the other frames have been given to it, so
+/// that it doesn't have to be copied until it is known to be the last one.
+///
+/// The encoder drops the first of two identical frames, so adding the copy only changes how long
+/// the last frame is shown for. If there is an end card, it is shown after the last frame is held,
+/// and the copy holds the end card instead. If the animation fades out, the frames of the fade-out
+/// take the place of the copy.
+pub(crate) struct LastFrameHold {
+    /// The time to hold the last frame for, in seconds
+    hold: f64,
+    /// The end card to show after the last frame, if any
+    end_card: Option<EndCard>,
+    /// The fade-out to add after the final frame, if any
+    fade: Fade,
+    /// The index and timestamp of the first frame
+    first: Option<(u64, f64)>,
+    /// The frame with the highest index so far, which is kept back from the encoder
+    last: Option<RgbaFrame>,
+}
+
+impl LastFrameHold {
+    /// Hold the last frame for `hold` seconds, followed by the end card if there is one and the
+    /// fade-out of `fade`
+    pub fn new(hold: f64, end_card: Option<EndCard>, fade: Fade) -> Self {
+        LastFrameHold {
+            hold,
+            end_card,
+            fade,
+            first: None,
+            last: None,
+        }
+    }
+
+    /// Track a frame that is about to be given to the encoder, returning the frame to give it
+    ///
+    /// That is the frame itself, unless it is the last frame so far, in which case it is kept back
+    /// and the frame that was the last one is returned instead, if any. Frames may be tracked in
+    /// any order.
+    pub fn track(&mut self, frame: RgbaFrame) -> Option<RgbaFrame> {
+        if !matches!(self.first, Some((index, _)) if index < frame.index) {
+            self.first = Some((frame.index, frame.timestamp()));
+        }
+
+        match &self.last {
+            Some(last) if last.index > frame.index => Some(frame),
+            _ => self.last.replace(frame),
+        }
+    }
+
+    /// Get the frames to add after all of the tracked frames: the last frame, which was kept back,
+    /// followed by the end card, if there is one, and the copy that holds the last of them on
+    /// screen, if one is needed, or else the fade-out
+    pub fn finish(mut self) -> Vec<RgbaFrame> {
+        let last = match self.last.take() {
+            Some(last) => last,
+            None => return Vec::new(),
+        };
+
+        let ending = match &self.end_card {
+            Some(end_card) => {
+                let card = end_card.frame_after(LastFrame::of(&last), self.hold);
+                let ending = self.ending(&card, end_card.hold());
+                std::iter::once(card).chain(ending).collect()
+            }
+            None => self.ending(&last, self.hold),
+        };
+
+        std::iter::once(last).chain(ending).collect()
+    }
+
+    /// Get the frames that end the animation after its final frame, which is held for `hold`
+    /// seconds: the fade-out, or else the copy that holds it
+    fn ending(&self, last: &RgbaFrame, hold: f64) -> Vec<RgbaFrame> {
+        if self.fade.fades_out() {
+            self.fade.fade_out_after(last, hold)
+        } else {
+            self.hold_frame(last, hold).into_iter().collect()
+        }
+    }
+
+    /// Get the copy of a frame to add after it to hold it on screen for `hold` seconds, if one is
+    /// needed
+    ///
+    /// Returns `None` if there are no frames or the frame shouldn't be held. If the first frame is
+    /// offset by more than the hold time, the last frame can't be shown for less than the offset,
+    /// and it is left as it is.
+    fn hold_frame(&self, last: &RgbaFrame, hold: f64) -> Option<RgbaFrame> {
+        let (_, first_timestamp) = self.first?;
+        if hold <= 0. {
+            return None;
+        }
+
+        let last_timestamp = last.timestamp();
+        let timestamp = if first_timestamp > MIN_FIRST_FRAME_OFFSET {
+            // The last frame is shown until its own timestamp, shifted by the offset of the first
+            last_timestamp + hold - first_timestamp
+        } else {
+            // The copy replaces the last frame, and is shown from the last frame's timestamp for
+            // twice the gap between them
+            last_timestamp + hold / 2.
+        };
+        if timestamp <= last_timestamp {
+            return None;
+        }
+
+        Some(RgbaFrame {
+            index: last.index + 1,
+            time: timestamp * 100.,
+            image: last.image.clone(),
+        })
+    }
+}
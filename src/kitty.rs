@@ -0,0 +1,211 @@
+//! Encoding animations in the kitty graphics protocol
+//!
+//! The animation is a stream of kitty graphics protocol escape sequences that loads every frame
+//! into the terminal and then starts playing them, so writing it to a terminal that supports
+//! animated images, such as kitty or WezTerm, plays the recording inline, like `cat demo.kitty`
+//! does.
+//!
+//! Each frame after the first only sends the rectangle of pixels that changed since the frame
+//! before it, drawn over a copy of that frame, and the pixels are compressed with zlib.
+
+use std::io::{BufWriter, Write};
+
+use base64::Engine;
+use flate2::write::ZlibEncoder;
+use imgref::{ImgRef, ImgVec};
+use rgb::{ComponentBytes, RGBA8};
+
+use crate::types::RgbaFrame;
+use crate::Error;
+
+/// The largest amount of base64 data that may be sent in one graphics command
+const CHUNK_SIZE: usize = 4096;
+
+/// The image number that the animation is loaded with
+///
+/// The terminal gives every image that is loaded with a number its own ID, and commands with the
+/// number go to the latest image with it, so playing the animation again doesn't change earlier
+/// copies of it.
+const IMAGE_NUMBER: u32 = 0xca57;
+
+/// A region of a frame, as its (x, y) position and (width, height) size in pixels
+type Region = ((usize, usize), (usize, usize));
+
+/// A frame that is waiting for the frame after it, which it is shown until
+struct PendingFrame {
+    frame: RgbaFrame,
+    /// The region that changed since the previous frame, or `None` for the first frame
+    changed: Option<Region>,
+}
+
+/// Encode frames to a kitty graphics protocol animation
+///
+/// `size` is the (width, height) of the frames in pixels, and the last frame is shown for
+/// `hold_last` seconds before the animation loops.
+pub(crate) fn encode<F, W>(
+    frames: F,
+    (width, height): (usize, usize),
+    writer: W,
+    hold_last: f64,
+) -> Result<(), Error>
+where
+    F: Iterator<Item = Result<RgbaFrame, Error>>,
+    W: Write,
+{
+    let mut writer = KittyWriter {
+        writer: BufWriter::new(writer),
+        frame_count: 0,
+    };
+    let mut pending: Option<PendingFrame> = None;
+    for frame in frames {
+        let frame = frame?;
+        if (frame.image.width(), frame.image.height()) != (width, height) {
+            return Err(Error::Generic(format!(
+                "Frame {} is {}x{} pixels instead of {}x{}",
+                frame.index,
+                frame.image.width(),
+                frame.image.height(),
+                width,
+                height
+            )));
+        }
+
+        let changed = match &pending {
+            Some(previous) => match changed_region(&previous.frame.image, &frame.image) {
+                Some(changed) => Some(changed),
+                // The previous frame is shown until the next frame that changes
+                None => continue,
+            },
+            None => None,
+        };
+        if let Some(previous) = pending.take() {
+            writer.write_frame(&previous, frame.timestamp() - previous.frame.timestamp())?;
+        }
+        pending = Some(PendingFrame { frame, changed });
+    }
+
+    if let Some(last) = pending {
+        writer.write_frame(&last, hold_last)?;
+        writer.play()?;
+    }
+
+    writer.finish()
+}
+
+/// Writes the graphics commands of an animation
+struct KittyWriter<W: Write> {
+    writer: BufWriter<W>,
+    /// The number of frames that have been written
+    frame_count: usize,
+}
+
+impl<W: Write> KittyWriter<W> {
+    /// Write a frame that is shown for `duration` seconds
+    fn write_frame(&mut self, pending: &PendingFrame, duration: f64) -> Result<(), Error> {
+        // A gap of 0 would use the terminal's default, so every frame is shown for at least 1ms
+        let gap = ((duration * 1000.).round() as u64).max(1);
+        let image = &pending.frame.image;
+        match pending.changed {
+            // The first frame is shown where the cursor is, and is where the animation starts
+            None => {
+                self.write_image(
+                    &format!(
+                        "a=T,I={},f=32,s={},v={}",
+                        IMAGE_NUMBER,
+                        image.width(),
+                        image.height()
+                    ),
+                    image.as_ref(),
+                )?;
+                self.write_command(&format!("a=a,I={},r=1,z={}", IMAGE_NUMBER, gap))?;
+            }
+            // Later frames are drawn over the frame before them
+            Some(((x, y), (width, height))) => self.write_image(
+                &format!(
+                    "a=f,I={},f=32,x={},y={},s={},v={},c={},z={}",
+                    IMAGE_NUMBER, x, y, width, height, self.frame_count, gap
+                ),
+                image.sub_image(x, y, width, height),
+            )?,
+        }
+        self.frame_count += 1;
+
+        Ok(())
+    }
+
+    /// Start playing the animation, looping forever
+    fn play(&mut self) -> Result<(), Error> {
+        self.write_command(&format!("a=a,I={},s=3,v=1", IMAGE_NUMBER))
+    }
+
+    /// Write a command that sends the pixels of an image, split into as many chunks as it needs
+    fn write_image(&mut self, keys: &str, image: ImgRef<RGBA8>) -> Result<(), Error> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        for row in image.rows() {
+            encoder.write_all(row.as_bytes()).map_err(write_error)?;
+        }
+        let compressed = encoder.finish().map_err(write_error)?;
+        let data = base64::engine::general_purpose::STANDARD.encode(compressed);
+
+        let chunks: Vec<_> = data.as_bytes().chunks(CHUNK_SIZE).collect();
+        for (i, chunk) in chunks.iter().enumerate() {
+            // Only the first chunk has the keys, and every chunk but the last one says that more
+            // are coming
+            let more = (i + 1 < chunks.len()) as u8;
+            if i == 0 {
+                write!(self.writer, "\x1b_G{},o=z,q=2,m={};", keys, more)
+            } else {
+                write!(self.writer, "\x1b_Gm={};", more)
+            }
+            .map_err(write_error)?;
+            self.writer.write_all(chunk).map_err(write_error)?;
+            self.writer.write_all(b"\x1b\\").map_err(write_error)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a command without any data
+    ///
+    /// Responses are turned off with `q=2`, so that they don't end up as input to the shell when
+    /// the animation is played from a file.
+    fn write_command(&mut self, keys: &str) -> Result<(), Error> {
+        write!(self.writer, "\x1b_G{},q=2\x1b\\", keys).map_err(write_error)
+    }
+
+    /// Move the cursor below the animation and flush the output
+    fn finish(mut self) -> Result<(), Error> {
+        writeln!(self.writer).map_err(write_error)?;
+        self.writer.flush().map_err(write_error)
+    }
+}
+
+/// Get the error for output that can't be written
+fn write_error(e: std::io::Error) -> Error {
+    Error::Generic(format!("Could not write animation: {}", e))
+}
+
+/// Get the smallest region that contains every pixel that is different between two images of the
+/// same size, or `None` if they are the same
+fn changed_region(before: &ImgVec<RGBA8>, after: &ImgVec<RGBA8>) -> Option<Region> {
+    let changed_rows: Vec<usize> = before
+        .rows()
+        .zip(after.rows())
+        .enumerate()
+        .filter(|(_, (before, after))| before != after)
+        .map(|(y, _)| y)
+        .collect();
+    let (top, bottom) = (*changed_rows.first()?, *changed_rows.last()?);
+
+    let (mut left, mut right) = (usize::MAX, 0);
+    for y in top..=bottom {
+        let (before, after) = (&before[y], &after[y]);
+        if let Some(x) = before.iter().zip(after).position(|(a, b)| a != b) {
+            left = left.min(x);
+            let x = before.len() - 1 - before.iter().zip(after).rev().position(|(a, b)| a != b)?;
+            right = right.max(x);
+        }
+    }
+
+    Some(((left, top), (right - left + 1, bottom - top + 1)))
+}
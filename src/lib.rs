@@ -1,23 +1,138 @@
-use cli::CropSettings;
 use lazy_static::lazy_static;
 use thiserror::Error;
 
 use std::io::{Read, Write};
-use std::sync::atomic::{AtomicBool, Ordering::SeqCst};
+use std::panic::AssertUnwindSafe;
 use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 #[macro_use]
 pub(crate) mod macros;
+pub(crate) mod animated_svg;
+pub(crate) mod bell_flash;
+pub(crate) mod camera;
+mod cast_builder;
 pub(crate) mod cast_parser;
+pub(crate) mod color_profile;
+mod contrast;
+pub(crate) mod converter;
+pub(crate) mod cursor_trail;
+pub(crate) mod end_card;
+pub(crate) mod escape_sequences;
+mod fade;
+mod filmstrip;
+pub(crate) mod frame_cache;
+pub(crate) mod frame_journal;
+pub(crate) mod frame_pool;
 pub(crate) mod frame_renderer;
+pub(crate) mod frame_spill;
+mod gif_info;
+pub(crate) mod gif_trim;
+pub(crate) mod highlight;
+mod hold_last;
+mod kitty;
+mod light;
+mod loop_crossfade;
+pub(crate) mod marker_captions;
+pub(crate) mod min_delay;
+mod monochrome;
+#[cfg(feature = "scripting")]
+pub(crate) mod overlay_script;
+#[cfg(feature = "plugins")]
+pub(crate) mod plugin;
+pub(crate) mod png_sequence;
+pub(crate) mod prompts;
+pub(crate) mod quantize;
+pub(crate) mod redact;
+mod render_stats;
+pub(crate) mod secrets;
+pub(crate) mod side_by_side;
+pub(crate) mod smooth_scroll;
+pub(crate) mod spotlight;
+pub(crate) mod subtitles;
+pub(crate) mod target_size;
 pub(crate) mod types;
+pub(crate) mod typing_speed;
+pub(crate) mod underline;
+pub(crate) mod video;
 
+use animated_svg::AnimatedSvg;
+use bell_flash::BellFlashFrames;
+use camera::CameraFrames;
+pub use camera::CameraPath;
+pub use cast_builder::CastBuilder;
 use cast_parser::AsciinemaError;
+pub use color_profile::ColorProfile;
+use contrast::ContrastFrames;
+pub use converter::Converter;
+use converter::{CastFrames, CastReader, CastSource, MappedCast, SideBySide};
+use cursor_trail::CursorTrailFrames;
+pub use end_card::EndCard;
+use end_card::EndCardFrames;
+use fade::{Fade, FadeFrames};
+use frame_cache::FrameCache;
+use frame_journal::FrameJournal;
+use frame_pool::FramePool;
+use frame_spill::{FrameSpill, QueuedFrame};
+pub use gif_info::GifInfo;
+use highlight::HighlightFrames;
+use hold_last::LastFrameHold;
+use light::LightFrames;
+use loop_crossfade::LoopCrossfade;
+use marker_captions::MarkerCaptions;
+use min_delay::MinDelayFrames;
+use monochrome::TwoToneFrames;
+pub use monochrome::{Monochrome, MonochromeStyle};
+#[cfg(feature = "scripting")]
+pub use overlay_script::OverlayScript;
+#[cfg(feature = "plugins")]
+pub use plugin::Plugin;
+pub use png_sequence::{FrameNameTemplate, ManifestFormat, PngCompression};
+pub use prompts::ShellCommand;
+pub use quantize::Quantizer;
+use redact::RedactedFrames;
+pub use redact::Redactions;
+use render_stats::{EncodedFrames, ParsedFrames, Stage};
+pub use render_stats::{RenderStats, RenderSummary};
+pub use secrets::FoundSecret;
+use secrets::SecretScanner;
+use side_by_side::{SideBySideCasts, SideBySideFrames};
+use smooth_scroll::SmoothScrollFrames;
+use spotlight::SpotlightFrames;
+pub use spotlight::Spotlights;
+use subtitles::SubtitleFrames;
+pub use subtitles::Subtitles;
 pub use types::*;
 
 #[cfg(feature = "cli")]
 pub mod cli;
 
+/// Entry points for the fuzz targets in the `fuzz` directory
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub mod fuzzing {
+    use crate::cast_parser::TerminalFrameIter;
+    use crate::TimingMode;
+
+    /// Parse a cast file and emulate the terminal through every event in it, with each timing mode
+    pub fn terminal_frame_iter(data: &[u8]) {
+        for timing in [TimingMode::Events, TimingMode::Fixed(0.1)] {
+            let frames = match TerminalFrameIter::new(data) {
+                Ok(frames) => frames.with_timing(timing),
+                Err(_) => return,
+            };
+            for frame in frames {
+                if frame.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+
 /// A Cast2Gif Error
 #[derive(Error, Debug)]
 pub enum Error {
@@ -30,36 +145,135 @@ pub enum Error {
 }
 
 lazy_static! {
-    /// Indicates whether or not we have initialize the rayon thread pool yet.
-    static ref THREAD_POOL_INITIALIZED: AtomicBool = AtomicBool::new(false);
-}
-
-/// Initialize the rayon thread pool
-fn configure_thread_pool() {
-    // If the thread pool is uninitialized
-    if !THREAD_POOL_INITIALIZED.load(SeqCst) {
-        // Configure and build the global rayon thread pool
-        rayon::ThreadPoolBuilder::new()
-            // Configure the panic handler
-            .panic_handler(|_| {
-                log::error!(concat!(
-                    "A worker thread has crashed. This is a bug. Please report this on the our ",
-                    "issue tracker\n\n",
-                    "    https://github.com/katharostech/cast2gif/issues"
-                ));
-            })
-            .build_global()
-            .expect("Rayon pool can only be initialized once");
+    /// The thread pool used to render frames when the caller doesn't configure one
+    ///
+    /// It is built the first time it is needed and shared by every conversion after that,
+    /// including conversions that run at the same time.
+    static ref DEFAULT_THREAD_POOL: Arc<rayon::ThreadPool> =
+        Arc::new(build_thread_pool(None).expect("Could not build thread pool"));
+}
+
+/// Build a rayon thread pool for rendering frames
+fn build_thread_pool(num_threads: Option<usize>) -> Result<rayon::ThreadPool, Error> {
+    let mut builder = rayon::ThreadPoolBuilder::new()
+        .thread_name(|i| format!("cast2gif-render-{}", i))
+        // Configure the panic handler
+        .panic_handler(|_| {
+            log::error!(concat!(
+                "A worker thread has crashed. This is a bug. Please report this on the our ",
+                "issue tracker\n\n",
+                "    https://github.com/katharostech/cast2gif/issues"
+            ));
+        });
+
+    if let Some(num_threads) = num_threads {
+        builder = builder.num_threads(num_threads);
+    }
+
+    builder
+        .build()
+        .map_err(|e| Error::Generic(format!("Could not build thread pool: {}", e)))
+}
+
+/// Get the thread pool to render frames on for the given settings
+fn thread_pool(settings: &ConvertSettings) -> Result<Arc<rayon::ThreadPool>, Error> {
+    Ok(if let Some(pool) = &settings.thread_pool {
+        pool.clone()
+    } else if let Some(threads) = settings.threads {
+        Arc::new(build_thread_pool(Some(threads))?)
+    } else {
+        DEFAULT_THREAD_POOL.clone()
+    })
+}
+
+/// Get how many frame buffers to keep for re-use for the given settings
+///
+/// Every render in flight holds a buffer, and another one for the stage that it is copied into.
+fn frame_pool_size(settings: &ConvertSettings) -> usize {
+    let threads = match &settings.thread_pool {
+        Some(pool) => pool.current_num_threads(),
+        None => settings
+            .threads
+            .unwrap_or_else(|| DEFAULT_THREAD_POOL.current_num_threads()),
+    };
+    threads * 2
+}
+
+/// The first error that stopped the conversion pipeline
+///
+/// Every stage of the pipeline checks for an error between frames and stops if there is one.
+/// Recording an error also wakes up the rasterizer if it is waiting for sequencing to catch up, so
+/// that it can stop. Once the stages stop, they drop their ends of the channels between them, which
+/// lets the progress thread exit. Passing the deadline of the conversion counts as an error too.
+struct PipelineError {
+    error: Mutex<Option<Error>>,
+    sequencing_is_behind: Arc<(Mutex<bool>, Condvar)>,
+    deadline: Option<Instant>,
+}
 
-        // Indicate thread pool is initialized
-        THREAD_POOL_INITIALIZED.store(false, SeqCst);
+impl PipelineError {
+    fn new(sequencing_is_behind: Arc<(Mutex<bool>, Condvar)>, deadline: Option<Instant>) -> Self {
+        PipelineError {
+            error: Mutex::new(None),
+            sequencing_is_behind,
+            deadline,
+        }
+    }
+
+    /// Record an error, unless an earlier one has already been recorded
+    fn set(&self, error: Error) {
+        {
+            let mut first = self.error.lock().unwrap();
+            if first.is_none() {
+                *first = Some(error);
+            }
+        }
+
+        let (lock, cvar) = &*self.sequencing_is_behind;
+        *lock.lock().unwrap() = false;
+        cvar.notify_all();
+    }
+
+    /// Whether or not an error has been recorded, recording one if the deadline has passed
+    fn is_set(&self) -> bool {
+        let mut error = self.error.lock().unwrap();
+        if error.is_none() && self.time_left() == Some(Duration::ZERO) {
+            // This is checked while the rasterizer holds the lock on `sequencing_is_behind`, so it
+            // can't be woken up like `set` does, but it waits with a timeout of the time left
+            *error = Some(Error::Generic(
+                "The conversion took longer than its deadline".into(),
+            ));
+        }
+
+        error.is_some()
+    }
+
+    /// The time left until the deadline, if there is one
+    fn time_left(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Take the recorded error
+    fn take(&self) -> Option<Error> {
+        self.error.lock().unwrap().take()
     }
 }
 
+/// Get the message of a caught panic
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown error")
+}
+
 fn progress_thread<C: CastProgressHandler>(
     progress_reciever: flume::Receiver<ProgressCmd>,
     mut progress_handler: C,
     sequencing_is_behind: Arc<(Mutex<bool>, Condvar)>,
+    max_frames_in_flight: u64,
 ) {
     macro_rules! update_sequencing_is_behind {
         ($value:expr) => {
@@ -77,168 +291,1579 @@ fn progress_thread<C: CastProgressHandler>(
         match cmd {
             ProgressCmd::IncrementCount => {
                 progress.count += 1;
-                if progress.count - progress.sequence_progress >= 100 {
+                if progress.count - progress.sequence_progress >= max_frames_in_flight {
                     update_sequencing_is_behind!(true);
                 }
             }
             ProgressCmd::IncrementRasterProgress => progress.raster_progress += 1,
             ProgressCmd::IncrementSequenceProgress => {
                 progress.sequence_progress += 1;
-                if progress.count - progress.sequence_progress < 100 {
+                if progress.count - progress.sequence_progress < max_frames_in_flight {
                     update_sequencing_is_behind!(false);
                 }
             }
+            ProgressCmd::ParsedUntil(time) => progress.parse_progress = time,
+            ProgressCmd::FinishParsing => progress.parsed = true,
         }
         progress_handler.update_progress(&progress);
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn png_raster_thread<Fi>(
     frames: Fi,
+    thread_pool: Arc<rayon::ThreadPool>,
     progress_sender: flume::Sender<ProgressCmd>,
-    frame_sender: flume::Sender<RgbaFrame>,
-    crop: Option<CropSettings>,
+    frame_sender: flume::Sender<QueuedFrame>,
+    rasterizer: Arc<FrameRasterizer>,
+    frame_spill: Option<Arc<FrameSpill>>,
     sequencing_is_behind: Arc<(Mutex<bool>, Condvar)>,
+    quantizer: Quantizer,
+    failed: Arc<PipelineError>,
 ) where
     Fi: IntoIterator<Item = Result<TerminalFrame, AsciinemaError>>,
 {
     // For each frame
     for frame in frames {
+        // Stop if the pipeline has failed
+        if failed.is_set() {
+            break;
+        }
+
         // Unwrap frame result
-        let frame = frame.expect("TODO");
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => {
+                failed.set(e.into());
+                break;
+            }
+        };
+
+        // If sequencing is behind, spill the frame to disk if we can, otherwise wait until
+        // sequencing catches up
+        let spill = {
+            let (lock, cvar) = &*sequencing_is_behind;
+            let mut sib = lock.lock().unwrap();
+            if *sib && frame_spill.is_some() {
+                frame_spill.clone()
+            } else {
+                // Sequencing won't catch up if the pipeline has failed
+                while *sib && !failed.is_set() {
+                    sib = match failed.time_left() {
+                        Some(time_left) => cvar.wait_timeout(sib, time_left).unwrap().0,
+                        None => cvar.wait(sib).unwrap(),
+                    };
+                }
+                None
+            }
+        };
 
-        let (lock, cvar) = &*sequencing_is_behind;
-        let mut sib = lock.lock().unwrap();
-        while *sib {
-            // Wait until sequencing catches up
-            sib = cvar.wait(sib).unwrap();
+        // The wait may have been cut short by a failure
+        if failed.is_set() {
+            break;
         }
 
-        // Increment frame count
+        // Increment frame count. The channels in the pipeline only close once it has stopped,
+        // which records its own error, so failed sends are ignored.
         progress_sender
-            .send(ProgressCmd::IncrementCount)
-            .expect("TODO");
+            .send(ProgressCmd::ParsedUntil(frame.time.max(0.) as u64))
+            .ok();
+        progress_sender.send(ProgressCmd::IncrementCount).ok();
 
-        // Spawn a thread to render the frame
+        // Spawn a task on the thread pool to render the frame
         let fs = frame_sender.clone();
         let ps = progress_sender.clone();
-        rayon::spawn(move || {
-            let frame = frame_renderer::render_frame_to_png(frame, crop);
-            fs.send(frame).expect("TODO");
-            ps.send(ProgressCmd::IncrementRasterProgress).expect("TODO");
+        let rasterizer = rasterizer.clone();
+        let failed = failed.clone();
+        thread_pool.spawn(move || {
+            // Don't render frames that were queued before the pipeline failed
+            if failed.is_set() {
+                return;
+            }
+
+            let index = frame.index;
+            let rendered = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut frame = rasterizer.rasterize(frame)?;
+                quantizer.quantize(&mut frame.image);
+                Ok(match spill {
+                    Some(spill) => {
+                        QueuedFrame::Spilled(spill.spill(frame, &rasterizer.pool).map_err(|e| {
+                            Error::Generic(format!("Could not spill frame to disk: {}", e))
+                        })?)
+                    }
+                    None => QueuedFrame::Memory(frame),
+                })
+            }))
+            .unwrap_or_else(|panic| {
+                Err(Error::Generic(format!(
+                    "A worker thread crashed while rendering frame {}: {}",
+                    index,
+                    panic_message(&*panic)
+                )))
+            });
+
+            match rendered {
+                Ok(frame) => {
+                    fs.send(frame).ok();
+                    ps.send(ProgressCmd::IncrementRasterProgress).ok();
+                }
+                Err(e) => failed.set(e),
+            }
         });
     }
+
+    progress_sender.send(ProgressCmd::FinishParsing).ok();
+}
+
+/// Render a terminal frame to an image, with the zoom, title bar, and captions that the settings
+/// ask for
+#[allow(clippy::too_many_arguments)]
+fn rasterize_frame(
+    frame: TerminalFrame,
+    crop: Option<CropSettings>,
+    background: rgb::RGBA8,
+    title: Option<&str>,
+    subtitles: &Subtitles,
+    spotlights: &Spotlights,
+    camera: &CameraPath,
+    pool: &FramePool,
+) -> RgbaFrame {
+    let caption = frame_caption(&frame, subtitles).map(str::to_owned);
+    let zoom = frame_zoom(&frame, spotlights, camera);
+    let scroll = frame.scroll;
+    let mut frame = match scroll {
+        Some(scroll) => {
+            let mut frame =
+                frame_renderer::render_frame_to_png(frame, scroll.crop(crop), background, pool);
+            frame.image = frame_renderer::slide(frame.image, scroll, pool);
+            frame
+        }
+        None => frame_renderer::render_frame_to_png(frame, crop, background, pool),
+    };
+    if let Some(view) = zoom {
+        frame.image = frame_renderer::zoom(frame.image, view, crop, pool);
+    }
+    if let Some(title) = title {
+        frame.image = frame_renderer::add_title_bar(frame.image, title, pool);
+    }
+    if let Some(caption) = caption {
+        frame_renderer::draw_caption(&mut frame.image, &caption);
+    }
+
+    frame
+}
+
+/// Get the caption to draw over a terminal frame
+fn frame_caption<'a>(frame: &'a TerminalFrame, subtitles: &'a Subtitles) -> Option<&'a str> {
+    // Subtitles take the place of marker captions that are shown at the same time
+    subtitles
+        .cue_at(frame.time / 100.)
+        .or(frame.caption.as_deref())
+}
+
+/// Get the view of the terminal to zoom a terminal frame into, if a spotlight zooms into one or
+/// there is a camera
+fn frame_zoom(
+    frame: &TerminalFrame,
+    spotlights: &Spotlights,
+    camera: &CameraPath,
+) -> Option<frame_renderer::View> {
+    let time = frame.time / 100.;
+    spotlights
+        .at(time)
+        .filter(|spotlight| spotlight.zoom)
+        .map(|spotlight| spotlight.region.into())
+        .or_else(|| camera.view_at(time))
+}
+
+/// Everything that terminal frames are turned into images with
+struct FrameRasterizer {
+    /// The region of the terminal to render, or `None` to render the whole terminal
+    crop: Option<CropSettings>,
+    /// The color of the terminal background, as opaque as the settings ask for
+    background: rgb::RGBA8,
+    /// The aspect ratio to pad the frames to, if any
+    aspect: Option<AspectRatio>,
+    /// The colors to redraw the frames in, if they are monochrome
+    monochrome: Option<Monochrome>,
+    /// The title to show in the title bar of frames that don't have their own title, or `None`
+    /// if there is no title bar
+    title: Option<String>,
+    /// The subtitles to draw over the frames
+    subtitles: Subtitles,
+    /// The spotlights that zoom into the frames
+    spotlights: Spotlights,
+    /// The path of the camera that pans and zooms over the frames
+    camera: CameraPath,
+    /// The journal to load frames from and record them in, if the render can be resumed
+    journal: Option<FrameJournal>,
+    /// The cache to load frames from and store them in, if any
+    cache: Option<FrameCache>,
+    /// The plugins that each frame is passed through once it is rendered
+    #[cfg(feature = "plugins")]
+    plugins: Vec<Plugin>,
+    /// The overlay scripts that draw over each frame after the plugins
+    #[cfg(feature = "scripting")]
+    scripts: Vec<OverlayScript>,
+    /// Where the rendered frames are counted
+    stats: Arc<RenderStats>,
+    /// The buffers that frames are rendered into
+    pool: Arc<FramePool>,
+}
+
+impl FrameRasterizer {
+    /// Get the rasterizer for a render with the given settings, opening the journal and the cache
+    /// that they ask for
+    ///
+    /// `cast_title` is shown in the title bar when the settings don't give a title.
+    fn new(settings: &ConvertSettings, cast_title: Option<String>) -> Result<Self, Error> {
+        let title = if settings.shows_title_bar() {
+            let title = settings.title.clone().or(cast_title).unwrap_or_default();
+            Some(settings.redactions.redact_text(&title))
+        } else {
+            None
+        };
+        let journal = settings
+            .journal_dir
+            .as_deref()
+            .map(FrameJournal::open)
+            .transpose()
+            .map_err(|e| Error::Generic(format!("Could not open frame journal: {}", e)))?;
+        let cache = settings
+            .cache_dir
+            .as_deref()
+            .map(FrameCache::open)
+            .transpose()
+            .map_err(|e| Error::Generic(format!("Could not open frame cache: {}", e)))?;
+
+        let (r, g, b) = settings.profile().background;
+        Ok(FrameRasterizer {
+            crop: settings.crop,
+            background: rgb::RGBA8::new(r, g, b, settings.background_alpha()),
+            aspect: settings.aspect,
+            monochrome: settings.monochrome,
+            title,
+            subtitles: settings.subtitles.clone(),
+            spotlights: settings.spotlights.clone(),
+            camera: settings.camera.clone(),
+            journal,
+            cache,
+            #[cfg(feature = "plugins")]
+            plugins: settings.plugins.clone(),
+            #[cfg(feature = "scripting")]
+            scripts: settings.scripts.clone(),
+            stats: settings.stats(),
+            pool: Arc::new(FramePool::new(frame_pool_size(settings))),
+        })
+    }
+
+    /// Redraw a rendered frame in the monochrome colors, if the settings ask for them
+    fn recolor(&self, mut frame: RgbaFrame) -> RgbaFrame {
+        if let Some(monochrome) = &self.monochrome {
+            monochrome.apply(&mut frame.image);
+        }
+
+        frame
+    }
+
+    /// Render the terminal background opaque, for outputs that can't be translucent
+    ///
+    /// Warns if the settings asked for a translucent background, naming the output as `format`.
+    fn opaque(mut self, format: &str) -> Self {
+        if self.background.a < 255 {
+            log::warn!(
+                "{} can't be translucent, so the terminal background will be opaque",
+                format
+            );
+            self.background.a = 255;
+        }
+
+        self
+    }
+
+    /// Keep at most `capacity` frame buffers for re-use
+    fn pool_capacity(mut self, capacity: usize) -> Self {
+        self.pool = Arc::new(FramePool::new(capacity));
+        self
+    }
+
+    /// Get the (width, height) size in pixels of the frames of a terminal with the given size
+    fn frame_size(&self, rows: u16, cols: u16) -> (usize, usize) {
+        frame_renderer::frame_size(rows, cols, self.crop, self.title.is_some(), self.aspect)
+    }
+
+    /// Get the (width, height) size in pixels of the frames of a terminal with the given size
+    /// before they are padded to the aspect ratio
+    fn rendered_size(&self, rows: u16, cols: u16) -> (usize, usize) {
+        frame_renderer::frame_size(rows, cols, self.crop, self.title.is_some(), None)
+    }
+
+    /// Pad a rendered frame to the aspect ratio with the terminal background
+    fn letterbox(&self, mut frame: RgbaFrame) -> RgbaFrame {
+        if let Some(aspect) = self.aspect {
+            frame.image =
+                frame_renderer::letterbox(frame.image, aspect, self.background, &self.pool);
+        }
+
+        frame
+    }
+
+    /// Render a terminal frame and pass it through the plugins and the overlay scripts, redrawing
+    /// it in monochrome last
+    fn rasterize(&self, frame: TerminalFrame) -> Result<RgbaFrame, Error> {
+        self.stats.mark(Stage::Rasterize);
+        #[cfg(feature = "scripting")]
+        let marker = frame.marker.clone();
+        let frame = self.letterbox(self.load_or_rasterize(frame)?);
+        #[cfg(feature = "plugins")]
+        let frame = self
+            .plugins
+            .iter()
+            .try_fold(frame, |frame, plugin| plugin.apply(frame))?;
+        #[cfg(feature = "scripting")]
+        let frame = self
+            .scripts
+            .iter()
+            .try_fold(frame, |frame, script| script.apply(frame, marker.as_ref()))?;
+        let frame = self.recolor(frame);
+        self.stats.rendered();
+        self.stats.mark(Stage::Rasterize);
+
+        Ok(frame)
+    }
+
+    /// Render a terminal frame like [`rasterize_frame`], loading it from the journal or the cache
+    /// instead if it was rendered before, and adding it to them if it wasn't
+    fn load_or_rasterize(&self, frame: TerminalFrame) -> Result<RgbaFrame, Error> {
+        // Frames have a title when the title bar follows the titles that the cast sets
+        let frame_title = frame.title.clone();
+        let title = self
+            .title
+            .as_deref()
+            .map(|title| frame_title.as_deref().unwrap_or(title));
+        if self.journal.is_none() && self.cache.is_none() {
+            return Ok(rasterize_frame(
+                frame,
+                self.crop,
+                self.background,
+                title,
+                &self.subtitles,
+                &self.spotlights,
+                &self.camera,
+                &self.pool,
+            ));
+        }
+
+        let caption = frame_caption(&frame, &self.subtitles);
+        let zoom = frame_zoom(&frame, &self.spotlights, &self.camera);
+        let key = frame_journal::frame_key(
+            &frame.screen,
+            frame.scroll,
+            self.crop,
+            self.background,
+            title,
+            caption,
+            zoom,
+        );
+        // Sliding frames are rendered with the rows that scrolled off, which are cut off again
+        let (rows, cols) = frame.screen.size();
+        let rows = rows - frame.scroll.map_or(0, |scroll| scroll.rows);
+        let loaded = self
+            .journal
+            .as_ref()
+            .and_then(|journal| journal.load(frame.index, key, &self.pool))
+            .or_else(|| {
+                let cache = self.cache.as_ref()?;
+                cache.load(key, self.rendered_size(rows, cols), &self.pool)
+            });
+        if let Some(image) = loaded {
+            return Ok(RgbaFrame {
+                index: frame.index,
+                time: frame.time,
+                image,
+            });
+        }
+
+        let frame = rasterize_frame(
+            frame,
+            self.crop,
+            self.background,
+            title,
+            &self.subtitles,
+            &self.spotlights,
+            &self.camera,
+            &self.pool,
+        );
+        if let Some(journal) = &self.journal {
+            journal.record(key, &frame).map_err(|e| {
+                Error::Generic(format!("Could not write frame to the journal: {}", e))
+            })?;
+        }
+        // The render doesn't need the cache, so failing to fill it doesn't fail the render
+        if let Some(cache) = &self.cache {
+            if let Err(e) = cache.store(key, &frame.image) {
+                log::warn!("Could not add frame {} to the cache: {}", frame.index, e);
+            }
+        }
+
+        Ok(frame)
+    }
+
+    /// Clean up once the render has succeeded, removing its journal
+    fn finish(&self) {
+        if let Some(journal) = &self.journal {
+            journal.remove();
+        }
+    }
+}
+
+/// Get the frames to rasterize from the frames of a cast
+///
+/// This redacts the frames, flashes the screen where the bell rang, and makes sure there are
+/// frames for captions and subtitles to appear and disappear on, then merges frames that would be
+/// shown too briefly before highlighting what changed between the frames that are left, showing
+/// the spotlights, and adding frames for the camera to move on. The time this takes, along with
+/// reading the cast, is measured as the parse stage of the render.
+fn prepare_frames<Fi>(
+    term_frames: Fi,
+    settings: &ConvertSettings,
+) -> impl Iterator<Item = Result<TerminalFrame, AsciinemaError>>
+where
+    Fi: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    let profile = settings.profile();
+    let term_frames = RedactedFrames::new(term_frames, &settings.redactions);
+    let term_frames = LightFrames::new(term_frames, settings.light, ColorProfile::default());
+    let term_frames = BellFlashFrames::new(term_frames, settings.bell_flash, profile.clone());
+    let term_frames = MarkerCaptions::new(term_frames, settings.marker_captions.unwrap_or(0.));
+    let term_frames = SubtitleFrames::new(term_frames, &settings.subtitles);
+    let term_frames = MinDelayFrames::new(term_frames, settings.min_frame_delay());
+    let term_frames = ContrastFrames::new(term_frames, settings.min_contrast, profile.clone());
+    let term_frames = TwoToneFrames::new(term_frames, settings.monochrome, profile.clone());
+    let term_frames = HighlightFrames::new(
+        term_frames,
+        settings.highlight_changes.unwrap_or(0),
+        profile.clone(),
+    );
+    let term_frames = CursorTrailFrames::new(
+        term_frames,
+        settings.cursor_trail.unwrap_or(0),
+        profile.clone(),
+    );
+    let term_frames = SpotlightFrames::new(
+        term_frames,
+        &settings.spotlights,
+        settings.min_frame_delay(),
+        profile,
+    );
+    let term_frames = CameraFrames::new(term_frames, &settings.camera, settings.min_frame_delay());
+    let term_frames = SmoothScrollFrames::new(
+        term_frames,
+        settings.smooth_scroll.unwrap_or(0),
+        settings.min_frame_delay(),
+    );
+    ParsedFrames::new(term_frames, settings.stats())
 }
 
+#[allow(clippy::too_many_arguments)]
 fn gif_sequencer_thread(
-    frame_receiver: flume::Receiver<RgbaFrame>,
-    mut gif_collector: gifski::Collector,
+    frame_receiver: flume::Receiver<QueuedFrame>,
+    gif_collector: gifski::Collector,
+    fade: Fade,
+    mut crossfade: LoopCrossfade,
+    mut hold: LastFrameHold,
+    progress_sender: flume::Sender<ProgressCmd>,
+    stats: Arc<RenderStats>,
+    pool: Arc<FramePool>,
+    failed: Arc<PipelineError>,
 ) {
+    // The frames to add for each frame that is received, kept to re-use its allocation
+    let mut frames = Vec::new();
     for frame in frame_receiver {
-        // Add frame to gif
+        // Stop if the pipeline has failed
+        if failed.is_set() {
+            return;
+        }
+
+        // Load the frame if it was spilled to disk
+        let frame = match frame.load(&pool) {
+            Ok(frame) => frame,
+            Err(e) => {
+                failed.set(Error::Generic(format!(
+                    "Could not load spilled frame: {}",
+                    e
+                )));
+                return;
+            }
+        };
+
+        // Add frame to gif, after the fade-in if it is the first frame, unless it is held back to
+        // be cross-faded into the first frame. This only fails if the gif writer has stopped,
+        // which reports its own error.
+        fade.fade_in(frame, &mut frames);
+        let ready = frames.drain(..).filter_map(|frame| crossfade.push(frame));
+        for frame in ready.filter_map(|frame| hold.track(frame)) {
+            stats.sequenced(1);
+            let timestamp = frame.timestamp();
+            let added = gif_collector
+                // TODO: avoid `as`
+                .add_frame_rgba(frame.index as usize, frame.image, timestamp);
+            if added.is_err() {
+                return;
+            }
+        }
+
+        // The frame is sequenced once the encoder has it. The encoder buffers frames before
+        // writing them and merges identical ones, so counting written frames would leave the
+        // rasterizer waiting for frames that are never written.
+        progress_sender
+            .send(ProgressCmd::IncrementSequenceProgress)
+            .ok();
+    }
+
+    // Add the last frames, cross-faded into the first one
+    for frame in crossfade
+        .finish()
+        .into_iter()
+        .filter_map(|frame| hold.track(frame))
+    {
+        stats.sequenced(1);
+        let timestamp = frame.timestamp();
+        gif_collector
+            .add_frame_rgba(frame.index as usize, frame.image, timestamp)
+            .ok();
+    }
+
+    // Add the last frame, which was kept back to hold it on screen before the gif loops, and show
+    // the end card and the fade-out after it
+    for frame in hold.finish() {
+        stats.sequenced(1);
+        let timestamp = frame.timestamp();
         gif_collector
-            // TODO: avoid `as`
-            .add_frame_rgba(
-                frame.index as usize,
-                frame.image,
-                (frame.time * 0.01) as f64,
-            )
-            .expect("TODO");
+            .add_frame_rgba(frame.index as usize, frame.image, timestamp)
+            .ok();
+    }
+}
+
+/// The number of frames to keep in memory when no memory limit is set
+const DEFAULT_MAX_FRAMES_IN_FLIGHT: u64 = 100;
+
+/// Get the number of rendered frames that may wait for sequencing at once
+fn max_frames_in_flight(rows: u16, cols: u16, settings: &ConvertSettings) -> u64 {
+    if let Some(max_memory) = settings.max_memory {
+        // Each in-flight frame holds a screen snapshot until it is rasterized and an RGBA image
+        // until it is sequenced
+        let (width, height) = frame_renderer::frame_size(
+            rows,
+            cols,
+            settings.crop,
+            settings.shows_title_bar(),
+            settings.aspect,
+        );
+        let frame_bytes = width * height * std::mem::size_of::<rgb::RGBA8>()
+            + rows as usize * cols as usize * std::mem::size_of::<ScreenCell>();
+
+        (max_memory / frame_bytes.max(1)).max(1) as u64
+    } else {
+        DEFAULT_MAX_FRAMES_IN_FLIGHT
+    }
+}
+
+/// The largest width or height that a gif can have
+const MAX_GIF_DIMENSION: usize = u16::MAX as usize;
+
+/// Get the gif encoder settings for a terminal of the given size
+///
+/// Frames that are too large for a gif are scaled down to fit, keeping their aspect ratio.
+fn gif_settings(rows: u16, cols: u16, settings: &ConvertSettings) -> gifski::Settings {
+    let mut gif_settings = gifski::Settings {
+        width: None,
+        height: None,
+        quality: 100,
+        repeat: gifski::Repeat::Infinite,
+        fast: settings.fast,
+    };
+
+    let (width, height) = frame_renderer::frame_size(
+        rows,
+        cols,
+        settings.crop,
+        settings.shows_title_bar(),
+        settings.aspect,
+    );
+    let largest = width.max(height);
+    if largest > MAX_GIF_DIMENSION {
+        let scale = MAX_GIF_DIMENSION as f64 / largest as f64;
+        let scaled_width = ((width as f64 * scale) as u32).max(1);
+        let scaled_height = ((height as f64 * scale) as u32).max(1);
+        log::warn!(
+            "The frames are {}x{} pixels, but a gif can't be more than {} pixels wide or tall. \
+             Scaling the gif down to {}x{} pixels. Use --crop to render less of the terminal \
+             instead.",
+            width,
+            height,
+            MAX_GIF_DIMENSION,
+            scaled_width,
+            scaled_height
+        );
+        gif_settings.width = Some(scaled_width);
+        gif_settings.height = Some(scaled_height);
     }
+
+    gif_settings
 }
 
 /// Convert a asciinema cast file to a gif image
 ///
 /// Provide the asciinema cast file as a reader of the cast file and the image will be output to
 /// the writer.
+///
+/// Conversions don't share any state other than the default thread pool, so they may be run any
+/// number of times in one process, and at the same time on different threads.
 pub fn convert_to_gif_with_progress<R, W, C>(
     reader: R,
     writer: W,
     update_progress: C,
-    crop: Option<CropSettings>,
+    settings: ConvertSettings,
 ) -> Result<(), Error>
 where
     R: Read + Send + 'static,
     W: Write + Send,
     C: CastProgressHandler + 'static,
 {
-    // Configure the rayon thread pool
-    configure_thread_pool();
+    Converter::lazy(settings).render_gif(CastReader(reader), writer, update_progress)
+}
+
+/// Convert a asciinema cast file to a gif image, reading the cast through a memory map
+///
+/// This avoids the overhead of reading each line of very large cast files into a buffer before
+/// parsing it. The file must not be modified while it is being converted.
+pub fn convert_file_to_gif_with_progress<W, C>(
+    file: &std::fs::File,
+    writer: W,
+    update_progress: C,
+    settings: ConvertSettings,
+) -> Result<(), Error>
+where
+    W: Write + Send,
+    C: CastProgressHandler + 'static,
+{
+    Converter::lazy(settings).render_gif(MappedCast(file), writer, update_progress)
+}
+
+/// Render terminal frames to a gif
+///
+/// `size` is the (rows, cols) size of the terminal in the frames, and `cast_title` is the title
+/// that is shown in the title bar when the settings don't give one.
+fn render_gif<Fi, W, C>(
+    term_frames: Fi,
+    (rows, cols): (u16, u16),
+    cast_title: Option<String>,
+    mut writer: W,
+    update_progress: C,
+    settings: ConvertSettings,
+) -> Result<(), Error>
+where
+    Fi: Iterator<Item = Result<TerminalFrame, AsciinemaError>> + Send + 'static,
+    W: Write + Send,
+    C: CastProgressHandler + 'static,
+{
+    // Get the thread pool to render on. Only the frame rendering runs on the pool, the pipeline
+    // stages below get their own threads so that they can't starve the renderers, or each other,
+    // of pool workers.
+    let thread_pool = thread_pool(&settings)?;
 
     // Because sequencing tends to take time, we should throttle rasterizing for it to catch up to prevent excessive memory usage
     // Progress thread dictates when this throttling occurs since, obviously, it knows the progress
     let sequencing_is_behind = Arc::new((Mutex::new(false), Condvar::new()));
 
+    // The first error in any stage of the pipeline stops the rest of it
+    let failed = Arc::new(PipelineError::new(
+        sequencing_is_behind.clone(),
+        settings.deadline,
+    ));
+
+    if let Some(crop) = settings.crop {
+        crop.validate(rows, cols)?;
+    }
+    settings.spotlights.validate(rows, cols)?;
+    settings.camera.validate(rows, cols)?;
+    let max_frames_in_flight = max_frames_in_flight(rows, cols, &settings);
+    let gif_settings = gif_settings(rows, cols, &settings);
+
     // Create the progress thread and channel
     let (progress_sender, progress_receiver) = flume::unbounded();
 
     let sib = sequencing_is_behind.clone();
-    rayon::spawn(move || progress_thread(progress_receiver, update_progress, sib));
-
-    // Create channel for getting rendered frames
-    let (raster_sender, raster_receiver) = flume::unbounded();
+    std::thread::spawn(move || {
+        progress_thread(
+            progress_receiver,
+            update_progress,
+            sib,
+            max_frames_in_flight,
+        )
+    });
 
-    // Create iterator over terminal frames
-    let term_frames = cast_parser::TerminalFrameIter::new(reader).expect("TODO");
+    // Create channel for getting rendered frames. Rasterizing is throttled before this fills up,
+    // but bounding it keeps frames from piling up past the memory limit if the throttling lags
+    // behind.
+    let (raster_sender, raster_receiver) = flume::bounded(max_frames_in_flight as usize);
 
     // Spawn the png rasterizer thread
     let ps = progress_sender.clone();
     let sib = sequencing_is_behind.clone();
-    rayon::spawn(move || png_raster_thread(term_frames, ps, raster_sender, crop, sib));
+    // The buffers kept for re-use count against the memory limit too
+    let pool_capacity = frame_pool_size(&settings).min(max_frames_in_flight as usize);
+    let rasterizer = Arc::new(
+        FrameRasterizer::new(&settings, cast_title)?
+            .opaque("Gifs")
+            .pool_capacity(pool_capacity),
+    );
+    let r = rasterizer.clone();
+    let term_frames = prepare_frames(term_frames, &settings);
+    let hold_last = settings.hold_last();
+    let fade = settings.fade();
+    let frame_spill = settings
+        .spill_dir
+        .as_ref()
+        .map(|dir| FrameSpill::new(dir).map(Arc::new))
+        .transpose()
+        .map_err(|e| Error::Generic(format!("Could not create frame spill directory: {}", e)))?;
+    let quantizer = settings.quantizer;
+    let f = failed.clone();
+    let raster_thread = std::thread::spawn(move || {
+        png_raster_thread(
+            term_frames,
+            thread_pool,
+            ps,
+            raster_sender,
+            r,
+            frame_spill,
+            sib,
+            quantizer,
+            f,
+        )
+    });
+
+    // When tuning for a target size, cache every frame so that the gif can be re-encoded
+    if let Some(target_size) = settings.target_size {
+        let mut frames = Vec::new();
+        for frame in raster_receiver {
+            match frame.load(&rasterizer.pool) {
+                Ok(frame) => fade.fade_in(frame, &mut frames),
+                Err(e) => {
+                    failed.set(Error::Generic(format!(
+                        "Could not load spilled frame: {}",
+                        e
+                    )));
+                    break;
+                }
+            }
+            progress_sender
+                .send(ProgressCmd::IncrementSequenceProgress)
+                .ok();
+        }
+        finish_pipeline(&failed, vec![raster_thread])?;
+
+        let gif = target_size::encode(
+            frames,
+            target_size,
+            gif_settings,
+            settings.comment.as_deref(),
+            hold_last,
+            settings.loop_crossfade(),
+            settings.end_card().as_ref(),
+            fade,
+            &settings.stats(),
+        )?;
+        writer
+            .write_all(&gif)
+            .and_then(|_| writer.flush())
+            .map_err(|e| Error::Generic(format!("Could not write gif: {}", e)))?;
+        rasterizer.finish();
+
+        return Ok(());
+    }
 
     // Create gifski gif encoder
-    let (collector, gif_writer) = gifski::new(gifski::Settings {
-        width: None,
-        height: None,
-        quality: 100,
-        repeat: gifski::Repeat::Infinite,
-        fast: false,
-    })
-    .expect("TODO");
+    let repeat = gif_settings.repeat;
+    let (collector, gif_writer) = gifski::new(gif_settings)?;
 
     // Spawn the gif sequencer thread
-    // NOTE: Even though we are handing the rasterized images to the gif collector
-    // in a separate thread, the gif *writer* seems to write sequentially. Also because
-    // Our frame index doesn't start at zero ( kind of a bug? ) it waits until all of the
-    // frames have been set before sequencing. In practice this is not actually an issue
-    // because we pretty much saturate the CPU while rasterizing anyway and it isn't faster
-    // to try to sequence at the same time anyway.
-    rayon::spawn(move || gif_sequencer_thread(raster_receiver, collector));
-
-    // Write out the recieved gif
-    let buf = std::io::BufWriter::new(writer);
-    let mut progress_handler = GifWriterProgressHandler::new(progress_sender);
-    gif_writer.write(buf, &mut progress_handler).expect("TODO");
+    // NOTE: Quantization doesn't run on the sequencer. The NeuQuant and median cut quantizers run
+    // on the thread pool with the rasterizer, one frame per task. For imagequant, the gif writer
+    // quantizes on its own threads, separately from remapping and writing. It quantizes up to one
+    // frame per core at once, at most 4, and puts them back in order before they are remapped and
+    // written.
+    let f = failed.clone();
+    let crossfade = LoopCrossfade::new(settings.loop_crossfade());
+    let hold = LastFrameHold::new(hold_last, settings.end_card(), fade);
+    let ps = progress_sender.clone();
+    let stats = settings.stats();
+    let s = stats.clone();
+    let pool = rasterizer.pool.clone();
+    let sequencer_thread = std::thread::spawn(move || {
+        gif_sequencer_thread(
+            raster_receiver,
+            collector,
+            fade,
+            crossfade,
+            hold,
+            ps,
+            s,
+            pool,
+            f,
+        )
+    });
+
+    // Write out the recieved gif, cropping its frames to the pixels that they change on the way.
+    // The output is flushed after every frame so that the start of the gif is available to readers
+    // of the output while the later frames are still rendering.
+    let (pipe_writer, pipe_reader) = gif_trim::pipe();
+    let buf = SharedWriter(Arc::new(Mutex::new(std::io::BufWriter::new(pipe_writer))));
+    let comment = settings.comment.as_deref();
+    drop(progress_sender);
+    std::thread::scope(|scope| {
+        let f = failed.clone();
+        let trimmer = scope.spawn(move || {
+            let mut writer = std::io::BufWriter::new(writer);
+            let trimmed = gif_trim::trim(pipe_reader, &mut writer, repeat, comment, |w| w.flush())
+                .and_then(|length| {
+                    writer
+                        .flush()
+                        .map_err(|e| Error::Generic(format!("Could not write gif: {}", e)))?;
+                    Ok(length)
+                });
+            match trimmed {
+                Ok(length) => stats.encoded(length.frames, length.duration),
+                Err(e) => f.set(e),
+            }
+        });
+
+        let mut progress_handler = GifWriterProgressHandler::new(buf.clone(), failed.clone());
+        if let Err(e) = gif_writer.write(buf, &mut progress_handler) {
+            failed.set(e.into());
+        }
+        // Dropping the last handle to the pipe ends the gif for the trimmer
+        drop(progress_handler);
+        if trimmer.join().is_err() {
+            failed.set(Error::Generic(
+                "The gif trimmer thread crashed. This is a bug.".into(),
+            ));
+        }
+    });
+
+    finish_pipeline(&failed, vec![raster_thread, sequencer_thread])?;
+    rasterizer.finish();
 
     Ok(())
 }
 
-struct GifWriterProgressHandler {
-    progress_sender: flume::Sender<ProgressCmd>,
+/// Get the result of a conversion pipeline once its output has been written
+///
+/// Unless the pipeline was stopped by an error, every stage has finished by now, so this also makes
+/// sure that none of the stage threads crashed.
+fn finish_pipeline(
+    failed: &PipelineError,
+    stage_threads: Vec<std::thread::JoinHandle<()>>,
+) -> Result<(), Error> {
+    if !failed.is_set() {
+        for thread in stage_threads {
+            if thread.join().is_err() {
+                failed.set(Error::Generic(
+                    "A conversion pipeline thread crashed. This is a bug.".into(),
+                ));
+            }
+        }
+    }
+
+    match failed.take() {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
 }
 
-impl GifWriterProgressHandler {
-    fn new(progress_sender: flume::Sender<ProgressCmd>) -> Self {
-        Self { progress_sender }
+/// A buffered writer that can be shared between the gif writer and its progress handler
+struct SharedWriter<W: Write>(Arc<Mutex<std::io::BufWriter<W>>>);
+
+impl<W: Write> Clone for SharedWriter<W> {
+    fn clone(&self) -> Self {
+        SharedWriter(self.0.clone())
     }
 }
 
-impl gifski::progress::ProgressReporter for GifWriterProgressHandler {
+impl<W: Write> Write for SharedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
+struct GifWriterProgressHandler<W: Write> {
+    writer: SharedWriter<W>,
+    failed: Arc<PipelineError>,
+}
+
+impl<W: Write> GifWriterProgressHandler<W> {
+    fn new(writer: SharedWriter<W>, failed: Arc<PipelineError>) -> Self {
+        Self { writer, failed }
+    }
+}
+
+impl<W: Write + Send> gifski::progress::ProgressReporter for GifWriterProgressHandler<W> {
     fn increase(&mut self) -> bool {
-        self.progress_sender
-            .send(ProgressCmd::IncrementSequenceProgress)
-            .expect("TODO");
+        // Abort the render if another stage of the pipeline has failed
+        if self.failed.is_set() {
+            return false;
+        }
 
-        true
+        // Flush the frame that was just written, aborting the render if the output is broken
+        match self.writer.flush() {
+            Ok(()) => true,
+            Err(e) => {
+                self.failed
+                    .set(Error::Generic(format!("Could not write gif: {}", e)));
+                false
+            }
+        }
     }
 
     fn done(&mut self, _msg: &str) {}
 }
 
-pub fn convert_to_gif<R, W>(reader: R, writer: W, crop: Option<CropSettings>) -> Result<(), Error>
+pub fn convert_to_gif<R, W>(reader: R, writer: W, settings: ConvertSettings) -> Result<(), Error>
 where
     R: Read + Send + 'static,
     W: Write + Send,
 {
-    convert_to_gif_with_progress(reader, writer, NullProgressHandler, crop)
+    convert_to_gif_with_progress(reader, writer, NullProgressHandler, settings)
+}
+
+/// Convert a asciinema cast file to an animated SVG, like the ones made by svg-term-cli
+///
+/// SVGs don't have subtitles or marker captions and can't be fit to a target size, so those
+/// settings are ignored.
+pub fn convert_to_svg<R, W>(
+    reader: R,
+    writer: W,
+    settings: ConvertSettings,
+    svg_settings: SvgSettings,
+) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    Converter::lazy(settings).render_svg(CastReader(reader), writer, svg_settings)
+}
+
+/// Convert a asciinema cast file to an animated SVG, reading the cast through a memory map
+///
+/// The file must not be modified while it is being converted.
+pub fn convert_file_to_svg<W: Write>(
+    file: &std::fs::File,
+    writer: W,
+    settings: ConvertSettings,
+    svg_settings: SvgSettings,
+) -> Result<(), Error> {
+    Converter::lazy(settings).render_svg(MappedCast(file), writer, svg_settings)
+}
+
+/// Render terminal frames to an animated SVG, like [`render_gif`] does for gifs
+fn render_svg<Fi, W>(
+    term_frames: Fi,
+    (rows, cols): (u16, u16),
+    cast_title: Option<String>,
+    writer: W,
+    settings: ConvertSettings,
+    mut svg_settings: SvgSettings,
+) -> Result<(), Error>
+where
+    Fi: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+    W: Write,
+{
+    if !settings.subtitles.is_empty() {
+        log::warn!("Subtitles can't be drawn on svgs, so they will be left out");
+    }
+    if settings.marker_captions.is_some() {
+        log::warn!("Marker captions can't be drawn on svgs, so they will be left out");
+    }
+    #[cfg(feature = "scripting")]
+    {
+        if !settings.scripts.is_empty() {
+            log::warn!("Overlay scripts can't draw on svgs, so they will be left out");
+        }
+    }
+    if settings.target_size.is_some() {
+        log::warn!("Svgs can't be fit to a target size, so the whole recording will be rendered");
+    }
+    if settings.spotlights.zooms() {
+        log::warn!("Svgs can't be zoomed, so zoomed spotlights will dim around them instead");
+    }
+    if !settings.camera.is_empty() {
+        log::warn!("Svgs can't pan or zoom, so the camera will be left out");
+    }
+    if settings.aspect.is_some() {
+        log::warn!("Svgs are sized by their window and padding, so the aspect ratio is ignored");
+    }
+    if settings.monochrome.is_some() {
+        log::warn!("Svgs can't be redrawn in monochrome, so they will keep their colors");
+    }
+    if settings.smooth_scroll.is_some() {
+        log::warn!("Svgs can't slide between rows, so they will scroll a row at a time");
+    }
+
+    if let Some(crop) = settings.crop {
+        crop.validate(rows, cols)?;
+    }
+    settings.spotlights.validate(rows, cols)?;
+    settings.camera.validate(rows, cols)?;
+    let title = if settings.shows_title_bar() {
+        let title = settings.title.clone().or(cast_title).unwrap_or_default();
+        Some(settings.redactions.redact_text(&title))
+    } else {
+        None
+    };
+
+    let term_frames = RedactedFrames::new(term_frames, &settings.redactions);
+    let term_frames = LightFrames::new(term_frames, settings.light, svg_settings.profile.clone());
+    if settings.light {
+        svg_settings.profile = light::light_profile(&svg_settings.profile);
+    }
+    let term_frames = BellFlashFrames::new(
+        term_frames,
+        settings.bell_flash,
+        svg_settings.profile.clone(),
+    );
+    let term_frames = MinDelayFrames::new(term_frames, settings.min_frame_delay());
+    let term_frames = ContrastFrames::new(
+        term_frames,
+        settings.min_contrast,
+        svg_settings.profile.clone(),
+    );
+    let term_frames = HighlightFrames::new(
+        term_frames,
+        settings.highlight_changes.unwrap_or(0),
+        svg_settings.profile.clone(),
+    );
+    let term_frames = CursorTrailFrames::new(
+        term_frames,
+        settings.cursor_trail.unwrap_or(0),
+        svg_settings.profile.clone(),
+    );
+    let term_frames = SpotlightFrames::new(
+        term_frames,
+        &settings.spotlights.dim_only(),
+        settings.min_frame_delay(),
+        svg_settings.profile.clone(),
+    );
+    let stats = settings.stats();
+    let term_frames = ParsedFrames::new(term_frames, stats.clone());
+    let mut svg = AnimatedSvg::new(
+        rows,
+        cols,
+        settings.crop,
+        title,
+        settings.background_opacity(),
+        svg_settings,
+    );
+    for frame in term_frames {
+        svg.add_frame(&frame?);
+        stats.sequenced(1);
+    }
+
+    svg.write(writer, settings.hold_last())?;
+    stats.encoded(svg.frame_count(), svg.duration(settings.hold_last()) / 100.);
+
+    Ok(())
+}
+
+/// Convert a asciinema cast file to an mp4 video
+///
+/// The video is encoded by `ffmpeg`, which must be installed. Videos can't be fit to a target size,
+/// so that setting is ignored.
+pub fn convert_to_mp4<R, W>(
+    reader: R,
+    writer: W,
+    settings: ConvertSettings,
+    video_settings: VideoSettings,
+) -> Result<(), Error>
+where
+    R: Read,
+    W: Write + Send,
+{
+    Converter::lazy(settings).render_mp4(CastReader(reader), writer, video_settings)
+}
+
+/// Convert a asciinema cast file to an mp4 video, reading the cast through a memory map
+///
+/// The file must not be modified while it is being converted.
+pub fn convert_file_to_mp4<W: Write + Send>(
+    file: &std::fs::File,
+    writer: W,
+    settings: ConvertSettings,
+    video_settings: VideoSettings,
+) -> Result<(), Error> {
+    Converter::lazy(settings).render_mp4(MappedCast(file), writer, video_settings)
+}
+
+/// Render terminal frames to an mp4 video, like [`render_gif`] does for gifs
+fn render_mp4<Fi, W>(
+    term_frames: Fi,
+    (rows, cols): (u16, u16),
+    cast_title: Option<String>,
+    writer: W,
+    settings: ConvertSettings,
+    video_settings: VideoSettings,
+) -> Result<(), Error>
+where
+    Fi: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+    W: Write + Send,
+{
+    if settings.target_size.is_some() {
+        log::warn!("Videos can't be fit to a target size, so the whole recording will be rendered");
+    }
+
+    if let Some(crop) = settings.crop {
+        crop.validate(rows, cols)?;
+    }
+    settings.spotlights.validate(rows, cols)?;
+    settings.camera.validate(rows, cols)?;
+    let rasterizer = FrameRasterizer::new(&settings, cast_title)?.opaque("Videos");
+    let size = rasterizer.frame_size(rows, cols);
+
+    let frames = prepare_frames(term_frames, &settings).map(|frame| rasterizer.rasterize(frame?));
+    let frames = EndCardFrames::new(frames, settings.end_card(), settings.hold_last());
+    let frames = FadeFrames::new(frames, settings.fade(), settings.end_hold());
+    let frames = EncodedFrames::new(frames, settings.stats(), settings.final_hold());
+
+    video::encode(
+        frames,
+        size,
+        writer,
+        settings.final_hold(),
+        video_settings.audio.as_deref(),
+    )?;
+    settings.stats().mark(Stage::Encode);
+    rasterizer.finish();
+
+    Ok(())
+}
+
+/// Convert a asciinema cast file to an animation in the kitty graphics protocol
+///
+/// Writing the animation to a terminal that supports animated images in the kitty graphics
+/// protocol, such as kitty or WezTerm, plays it inline. Animations can't be fit to a target size,
+/// so that setting is ignored.
+pub fn convert_to_kitty<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    settings: ConvertSettings,
+) -> Result<(), Error> {
+    Converter::lazy(settings).render_kitty(CastReader(reader), writer)
+}
+
+/// Convert a asciinema cast file to an animation in the kitty graphics protocol, reading the cast
+/// through a memory map
+///
+/// The file must not be modified while it is being converted.
+pub fn convert_file_to_kitty<W: Write>(
+    file: &std::fs::File,
+    writer: W,
+    settings: ConvertSettings,
+) -> Result<(), Error> {
+    Converter::lazy(settings).render_kitty(MappedCast(file), writer)
+}
+
+/// Render terminal frames to an animation in the kitty graphics protocol, like [`render_gif`] does
+/// for gifs
+fn render_kitty<Fi, W>(
+    term_frames: Fi,
+    (rows, cols): (u16, u16),
+    cast_title: Option<String>,
+    writer: W,
+    settings: ConvertSettings,
+) -> Result<(), Error>
+where
+    Fi: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+    W: Write,
+{
+    if settings.target_size.is_some() {
+        log::warn!(
+            "Kitty animations can't be fit to a target size, so the whole recording will be \
+             rendered"
+        );
+    }
+
+    if let Some(crop) = settings.crop {
+        crop.validate(rows, cols)?;
+    }
+    settings.spotlights.validate(rows, cols)?;
+    settings.camera.validate(rows, cols)?;
+    let rasterizer = FrameRasterizer::new(&settings, cast_title)?;
+    let size = rasterizer.frame_size(rows, cols);
+
+    let frames = prepare_frames(term_frames, &settings).map(|frame| rasterizer.rasterize(frame?));
+    let frames = EndCardFrames::new(frames, settings.end_card(), settings.hold_last());
+    let frames = FadeFrames::new(frames, settings.fade(), settings.end_hold());
+    let frames = EncodedFrames::new(frames, settings.stats(), settings.final_hold());
+
+    kitty::encode(frames, size, writer, settings.final_hold())?;
+    settings.stats().mark(Stage::Encode);
+    rasterizer.finish();
+
+    Ok(())
+}
+
+/// Convert a asciinema cast file to a sequence of png images, one for each frame, written to a
+/// directory
+///
+/// The directory is created if it doesn't exist, and the images are named by the frame name
+/// template of the png settings. Image sequences can't be fit to a target size, so that setting is
+/// ignored.
+pub fn convert_to_png_sequence<R: Read>(
+    reader: R,
+    dir: &std::path::Path,
+    settings: ConvertSettings,
+    png_settings: PngSequenceSettings,
+) -> Result<(), Error> {
+    Converter::lazy(settings).render_png_sequence(CastReader(reader), dir, png_settings)
+}
+
+/// Convert a asciinema cast file to a sequence of png images, reading the cast through a memory
+/// map
+///
+/// The file must not be modified while it is being converted.
+pub fn convert_file_to_png_sequence(
+    file: &std::fs::File,
+    dir: &std::path::Path,
+    settings: ConvertSettings,
+    png_settings: PngSequenceSettings,
+) -> Result<(), Error> {
+    Converter::lazy(settings).render_png_sequence(MappedCast(file), dir, png_settings)
+}
+
+/// Render terminal frames to a sequence of png images, like [`render_gif`] does for gifs
+fn render_png_sequence<Fi>(
+    term_frames: Fi,
+    (rows, cols): (u16, u16),
+    cast_title: Option<String>,
+    dir: &std::path::Path,
+    settings: ConvertSettings,
+    png_settings: PngSequenceSettings,
+) -> Result<(), Error>
+where
+    Fi: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    if settings.target_size.is_some() {
+        log::warn!(
+            "Image sequences can't be fit to a target size, so the whole recording will be \
+             rendered"
+        );
+    }
+
+    if let Some(crop) = settings.crop {
+        crop.validate(rows, cols)?;
+    }
+    settings.spotlights.validate(rows, cols)?;
+    settings.camera.validate(rows, cols)?;
+    let rasterizer = FrameRasterizer::new(&settings, cast_title)?;
+
+    let frames = prepare_frames(term_frames, &settings).map(|frame| rasterizer.rasterize(frame?));
+    let frames = EndCardFrames::new(frames, settings.end_card(), settings.hold_last());
+    let frames = FadeFrames::new(frames, settings.fade(), settings.end_hold());
+    let frames = EncodedFrames::new(frames, settings.stats(), settings.final_hold());
+
+    png_sequence::write(frames, dir, &png_settings, settings.final_hold())?;
+    settings.stats().mark(Stage::Encode);
+    rasterizer.finish();
+
+    Ok(())
+}
+
+/// Render `frames` evenly spaced frames of a asciinema cast file side by side into a png
+/// filmstrip, reading the cast through a memory map
+///
+/// The frames are spaced evenly from the first frame to the last, and short casts may get fewer
+/// frames. The cast is read twice: once to time the frames, and once to render the ones that are
+/// shown. The file must not be modified while it is being converted.
+pub fn convert_file_to_filmstrip<W: Write>(
+    file: &std::fs::File,
+    writer: W,
+    settings: ConvertSettings,
+    frames: usize,
+) -> Result<(), Error> {
+    // Time the frames without rendering them to pick the ones to show
+    let (term_frames, _, _) = MappedCast(file).frames(&settings)?;
+    let times = prepare_frames(term_frames, &settings)
+        .map(|frame| Ok(frame?.time))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let picked = filmstrip::pick(&times, frames);
+    if picked.is_empty() {
+        return Err(Error::Generic(
+            "The cast doesn't have any frames to show in a filmstrip".into(),
+        ));
+    }
+
+    let (term_frames, (rows, cols), cast_title) = MappedCast(file).frames(&settings)?;
+    if let Some(crop) = settings.crop {
+        crop.validate(rows, cols)?;
+    }
+    settings.spotlights.validate(rows, cols)?;
+    settings.camera.validate(rows, cols)?;
+    let rasterizer = FrameRasterizer::new(&settings, cast_title)?;
+
+    let mut images = Vec::with_capacity(picked.len());
+    for frame in prepare_frames(term_frames, &settings) {
+        let frame = frame?;
+        if picked.binary_search(&frame.index).is_ok() {
+            images.push(rasterizer.rasterize(frame)?);
+        }
+    }
+    filmstrip::write(&images, writer)?;
+    rasterizer.finish();
+
+    Ok(())
+}
+
+/// Find the commands run at shell prompts in a asciinema cast file, such as to split it into a
+/// chapter for each command
+///
+/// The times of the commands are in seconds of the cast, so that they can be given to
+/// [`TrimSettings`]. Commands are found from the OSC 133 semantic prompt marks of the shell, or
+/// guessed from lines that look like prompts if the cast doesn't have any.
+pub fn find_commands<R: Read>(reader: R) -> Result<Vec<ShellCommand>, Error> {
+    let mut term_frames = cast_parser::TerminalFrameIter::new(reader)?
+        .with_initial_delay(InitialDelay::Keep)
+        .with_command_detection();
+    for frame in &mut term_frames {
+        frame?;
+    }
+
+    Ok(term_frames.commands().to_vec())
+}
+
+/// Find what look like secrets, such as access keys and tokens, in the text that a render of a
+/// asciinema cast file would show, to check a cast before publishing a render of it
+///
+/// The cast is read with the trim, timing, and redactions of the settings, so secrets outside of
+/// the trim, or hidden by the redactions, aren't found. Each secret is found once, at the time
+/// that it is first shown.
+pub fn find_secrets<R: Read>(
+    reader: R,
+    settings: &ConvertSettings,
+) -> Result<Vec<FoundSecret>, Error> {
+    let term_frames = cast_parser::TerminalFrameIter::new(reader)?
+        .with_settings(settings)
+        .without_sequence_warnings();
+    let mut scanner = SecretScanner::default();
+    for frame in RedactedFrames::new(term_frames, &settings.redactions) {
+        scanner.scan(&frame?);
+    }
+
+    Ok(scanner.into_found())
+}
+
+/// Apply the timing settings of a conversion to the events of a asciinema cast file, without
+/// rendering it, such as to save a trimmed or sped up copy of the cast
+///
+/// The trim, speed map, initial delay, typing speed, and marker pauses of the settings are applied
+/// to the events, so that the cast plays like a render of the original would.
+pub fn retime_cast<R: Read>(reader: R, settings: &ConvertSettings) -> Result<CastBuilder, Error> {
+    Ok(cast_parser::TerminalFrameIter::new(reader)?
+        .with_settings(settings)
+        .retimed()?)
+}
+
+/// Read quickly through a asciinema cast file, without rendering it, to find how many events and
+/// how much time of it would be rendered with the settings
+///
+/// The summary gives totals for the [`CastRenderProgress::parse_progress`] of a render of the same
+/// cast to be measured against.
+pub fn scan_cast<R: Read>(reader: R, settings: &ConvertSettings) -> Result<CastSummary, Error> {
+    Ok(cast_parser::TerminalFrameIter::new(reader)?
+        .with_settings(settings)
+        .scan()?)
+}
+
+/// Read quickly through a asciinema cast file like [`scan_cast`], reading the cast through a
+/// memory map
+pub fn scan_cast_file(
+    file: &std::fs::File,
+    settings: &ConvertSettings,
+) -> Result<CastSummary, Error> {
+    let (term_frames, _, _) = MappedCast(file).frames(settings)?;
+
+    Ok(term_frames.scan()?)
+}
+
+/// Read the size, the comments, and the delay of each frame of a gif, without decoding its frames
+///
+/// Gifs rendered with a [`ConvertSettings::comment`] have it among their comments.
+pub fn read_gif_info<R: Read>(reader: R) -> Result<GifInfo, Error> {
+    gif_info::read(reader)
+}
+
+/// Merge the frames of two casts to render them side by side, returning the frames, the (rows,
+/// cols) size of the screen that they are on, and the titles of the casts
+pub(crate) fn side_by_side_frames<L: Read, R: Read>(
+    left: L,
+    right: R,
+    settings: &ConvertSettings,
+) -> Result<CastFrames<SideBySideCasts<L, R>>, Error> {
+    let left = cast_parser::TerminalFrameIter::new(left)?.with_settings(settings);
+    let right = cast_parser::TerminalFrameIter::new(right)?.with_settings(settings);
+    let (left_size, right_size) = (left.size(), right.size());
+    let size = side_by_side::size(left_size, right_size)
+        .ok_or_else(|| Error::Generic("The casts are too wide to render side by side".into()))?;
+    let titles: Vec<String> = vec![left.title(), right.title()]
+        .into_iter()
+        .flatten()
+        .collect();
+    let title = Some(titles.join(" | ")).filter(|title| !title.is_empty());
+
+    Ok((
+        SideBySideFrames::new(left, left_size, right, right_size),
+        size,
+        title,
+    ))
+}
+
+/// Convert two asciinema cast files to a gif that shows them side by side
+///
+/// Both casts start playing at the same time, and the settings apply to each of them, other than
+/// the crop, which is in cells of the combined screen. The second cast starts three columns after
+/// the end of the first one.
+pub fn convert_side_by_side_to_gif_with_progress<L, R, W, C>(
+    left: L,
+    right: R,
+    writer: W,
+    update_progress: C,
+    settings: ConvertSettings,
+) -> Result<(), Error>
+where
+    L: Read + Send + 'static,
+    R: Read + Send + 'static,
+    W: Write + Send,
+    C: CastProgressHandler + 'static,
+{
+    Converter::lazy(settings).render_gif(SideBySide(left, right), writer, update_progress)
+}
+
+pub fn convert_side_by_side_to_gif<L, R, W>(
+    left: L,
+    right: R,
+    writer: W,
+    settings: ConvertSettings,
+) -> Result<(), Error>
+where
+    L: Read + Send + 'static,
+    R: Read + Send + 'static,
+    W: Write + Send,
+{
+    convert_side_by_side_to_gif_with_progress(left, right, writer, NullProgressHandler, settings)
+}
+
+/// Convert two asciinema cast files to an animated SVG that shows them side by side, like
+/// [`convert_side_by_side_to_gif`]
+pub fn convert_side_by_side_to_svg<L, R, W>(
+    left: L,
+    right: R,
+    writer: W,
+    settings: ConvertSettings,
+    svg_settings: SvgSettings,
+) -> Result<(), Error>
+where
+    L: Read,
+    R: Read,
+    W: Write,
+{
+    Converter::lazy(settings).render_svg(SideBySide(left, right), writer, svg_settings)
+}
+
+/// Convert two asciinema cast files to an mp4 video that shows them side by side, like
+/// [`convert_side_by_side_to_gif`]
+pub fn convert_side_by_side_to_mp4<L, R, W>(
+    left: L,
+    right: R,
+    writer: W,
+    settings: ConvertSettings,
+    video_settings: VideoSettings,
+) -> Result<(), Error>
+where
+    L: Read,
+    R: Read,
+    W: Write + Send,
+{
+    Converter::lazy(settings).render_mp4(SideBySide(left, right), writer, video_settings)
+}
+
+/// Convert two asciinema cast files to an animation in the kitty graphics protocol that shows them
+/// side by side, like [`convert_side_by_side_to_gif`]
+pub fn convert_side_by_side_to_kitty<L, R, W>(
+    left: L,
+    right: R,
+    writer: W,
+    settings: ConvertSettings,
+) -> Result<(), Error>
+where
+    L: Read,
+    R: Read,
+    W: Write,
+{
+    Converter::lazy(settings).render_kitty(SideBySide(left, right), writer)
+}
+
+/// Convert two asciinema cast files to a sequence of png images that show them side by side, like
+/// [`convert_side_by_side_to_gif`]
+pub fn convert_side_by_side_to_png_sequence<L, R>(
+    left: L,
+    right: R,
+    dir: &std::path::Path,
+    settings: ConvertSettings,
+    png_settings: PngSequenceSettings,
+) -> Result<(), Error>
+where
+    L: Read,
+    R: Read,
+{
+    Converter::lazy(settings).render_png_sequence(SideBySide(left, right), dir, png_settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A short cast that changes the screen a few times
+    fn cast() -> CastBuilder {
+        CastBuilder::new(20, 4)
+            .output_at(0.1, "$ ls\r\n")
+            .output_at(0.4, "\x1b[1mCargo.toml\x1b[0m  src\r\n$ ")
+            .output_at(0.8, "exit")
+    }
+
+    fn convert(cast: &CastBuilder) -> Vec<u8> {
+        let mut gif = Vec::new();
+        convert_to_gif(cast.reader(), &mut gif, ConvertSettings::default())
+            .expect("The cast converts");
+        gif
+    }
+
+    #[test]
+    fn converts_repeatedly_and_concurrently() {
+        let cast = cast();
+
+        let first = convert(&cast);
+        assert!(first.starts_with(b"GIF89a"));
+        assert_eq!(convert(&cast), first);
+
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4).map(|_| scope.spawn(|| convert(&cast))).collect();
+            for thread in threads {
+                assert_eq!(thread.join().expect("The conversion doesn't panic"), first);
+            }
+        });
+    }
 }
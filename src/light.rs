@@ -0,0 +1,189 @@
+//! Rendering dark themes on a light background
+//!
+//! A light render draws the terminal on a white background instead of the dark one it was recorded
+//! on, so that recordings match documentation sites with light themes. Colors aren't inverted,
+//! which would turn every hue into its opposite. Instead, each color keeps its hue and is made
+//! lighter or darker so that it stands out from what is behind it as much as it did in the
+//! recording: backgrounds against the terminal background, and text against the background of its
+//! cell. Text that already stands out as much on the light background keeps its color.
+
+use crate::cast_parser::AsciinemaError;
+use crate::color_profile::Rgb;
+use crate::contrast::{contrast_ratio, linear, luminance};
+use crate::types::{ScreenSnapshot, TerminalFrame};
+use crate::ColorProfile;
+
+/// The terminal background of light renders
+pub(crate) const LIGHT_BACKGROUND: Rgb = (255, 255, 255);
+
+/// Whether or not a color is light enough to be the background of a light theme
+fn is_light(color: Rgb) -> bool {
+    luminance(color) >= 0.5
+}
+
+/// Get the sRGB channel of a linear light, from `0` to `1`
+fn encode(linear: f64) -> u8 {
+    let channel = if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1. / 2.4) - 0.055
+    };
+
+    (channel.clamp(0., 1.) * 255.).round() as u8
+}
+
+/// Get the color with the same hue as `color` that has the given relative luminance
+fn with_luminance(color: Rgb, target: f64) -> Rgb {
+    let target = target.clamp(0., 1.);
+    let current = luminance(color);
+    let (r, g, b) = (linear(color.0), linear(color.1), linear(color.2));
+    // Darker colors are scaled toward black, and lighter ones are mixed toward white, which both
+    // change the luminance in proportion
+    let channel = |channel: f64| {
+        if target <= current {
+            channel * target / current
+        } else {
+            channel + (1. - channel) * (target - current) / (1. - current)
+        }
+    };
+
+    (encode(channel(r)), encode(channel(g)), encode(channel(b)))
+}
+
+/// Get the color with the same hue as `color` that has a contrast ratio of `contrast` with
+/// `background`, or as close to it as the color can get
+///
+/// The color is made darker than the background if that can reach the contrast, or else lighter.
+fn with_contrast(color: Rgb, background: Rgb, contrast: f64) -> Rgb {
+    let background = luminance(background);
+    let darker = (background + 0.05) / contrast - 0.05;
+    let lighter = (background + 0.05) * contrast - 0.05;
+    let target = if darker >= 0. || 1. - lighter < darker {
+        darker
+    } else {
+        lighter
+    };
+
+    with_luminance(color, target)
+}
+
+/// Get the color of a background in a light render, from its color in the recording
+///
+/// The color stands out from the light terminal background as much as it stood out from the dark
+/// one.
+fn light_background(color: Rgb, dark: Rgb) -> Rgb {
+    with_contrast(color, LIGHT_BACKGROUND, contrast_ratio(color, dark))
+}
+
+/// Get the color of text in a light render, from its color in the recording
+///
+/// `background` is the color behind the text in the recording, and `light` is the color behind it
+/// in the light render. Text keeps its color if that stands out from `light` at least as much as
+/// it stood out from `background`.
+fn light_text(color: Rgb, background: Rgb, light: Rgb) -> Rgb {
+    let contrast = contrast_ratio(color, background);
+    if contrast_ratio(color, light) >= contrast {
+        return color;
+    }
+
+    with_contrast(color, light, contrast)
+}
+
+/// Get the colors of a profile in a light render
+///
+/// Profiles that already have a light background are left as they are.
+pub(crate) fn light_profile(profile: &ColorProfile) -> ColorProfile {
+    if is_light(profile.background) {
+        return profile.clone();
+    }
+
+    let text = |color| light_text(color, profile.background, LIGHT_BACKGROUND);
+    let mut palette = profile.palette;
+    for color in palette.iter_mut() {
+        *color = text(*color);
+    }
+
+    ColorProfile {
+        foreground: text(profile.foreground),
+        background: LIGHT_BACKGROUND,
+        cursor: profile.cursor.map(text),
+        palette,
+    }
+}
+
+/// An iterator adapter that recolors frames for a light render
+pub(crate) struct LightFrames<I> {
+    /// The frames to recolor
+    frames: I,
+    /// Whether or not the frames are rendered on a light background
+    light: bool,
+    /// The colors that the frames were recorded with
+    profile: ColorProfile,
+}
+
+impl<I> LightFrames<I> {
+    /// Recolor the frames for a light render if `light` is true, from the colors of the `profile`
+    ///
+    /// Frames are passed through untouched if `light` is false or the profile already has a light
+    /// background.
+    pub fn new(frames: I, light: bool, profile: ColorProfile) -> Self {
+        LightFrames {
+            frames,
+            light: light && !is_light(profile.background),
+            profile,
+        }
+    }
+
+    /// Change the colors of every cell on a screen to their colors in a light render
+    ///
+    /// Cells that use the default background keep it, so that it can still be translucent, but
+    /// every other color is made explicit, since the default text color changes too.
+    fn lighten(&self, screen: &mut ScreenSnapshot) {
+        let profile = &self.profile;
+        let rgb = |(r, g, b): Rgb| vt100::Color::Rgb(r, g, b);
+        let (rows, cols) = screen.size();
+        for row in 0..rows {
+            for col in 0..cols {
+                let cell = screen
+                    .cell_mut(row, col)
+                    .expect("The cell is on the screen");
+
+                let background = profile.color(cell.bgcolor).unwrap_or(profile.background);
+                let light = match profile.color(cell.bgcolor) {
+                    Some(color) => {
+                        let color = light_background(color, profile.background);
+                        cell.bgcolor = rgb(color);
+                        color
+                    }
+                    None => LIGHT_BACKGROUND,
+                };
+                let foreground = profile.color(cell.fgcolor).unwrap_or(profile.foreground);
+                cell.fgcolor = rgb(light_text(foreground, background, light));
+                if let Some(underline) = &mut cell.underline {
+                    if let Some(color) = profile.color(underline.color) {
+                        underline.color = rgb(light_text(color, background, light));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<I> Iterator for LightFrames<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = match self.frames.next()? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        if self.light {
+            self.lighten(&mut frame.screen);
+        }
+
+        Some(Ok(frame))
+    }
+}
@@ -0,0 +1,94 @@
+//! Cross-fading the end of a looping gif back into its start
+//!
+//! Gifs that loop forever jump from the final state of the recording straight back to the first
+//! frame. The last frames are blended a little more into the first frame each, so that the last
+//! frame looks just like the first one and the loop point can't be seen.
+
+use std::collections::BTreeMap;
+
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+use crate::color_profile;
+use crate::types::RgbaFrame;
+
+/// Holds back the last frames given to the gif encoder to cross-fade them into the first frame
+pub(crate) struct LoopCrossfade {
+    /// The number of frames to cross-fade over
+    frames: usize,
+    /// The index and image of the first frame
+    first: Option<(u64, ImgVec<RGBA8>)>,
+    /// The frames with the highest indexes so far, which may be the last ones
+    tail: BTreeMap<u64, RgbaFrame>,
+}
+
+impl LoopCrossfade {
+    /// Cross-fade the last `frames` frames into the first frame
+    ///
+    /// Frames are passed through untouched if `frames` is `0`.
+    pub fn new(frames: usize) -> Self {
+        LoopCrossfade {
+            frames,
+            first: None,
+            tail: BTreeMap::new(),
+        }
+    }
+
+    /// Take a frame that is given to the encoder, and get the frame that can be encoded now, if
+    /// any
+    ///
+    /// Frames may be given in any order. The frames that could be among the last ones are held
+    /// back until [`finish`][Self::finish].
+    pub fn push(&mut self, frame: RgbaFrame) -> Option<RgbaFrame> {
+        if self.frames == 0 {
+            return Some(frame);
+        }
+
+        if !matches!(self.first, Some((index, _)) if index < frame.index) {
+            self.first = Some((frame.index, frame.image.clone()));
+        }
+        self.tail.insert(frame.index, frame);
+        if self.tail.len() <= self.frames {
+            return None;
+        }
+
+        let earliest = *self.tail.keys().next()?;
+        self.tail.remove(&earliest)
+    }
+
+    /// Get the last frames, cross-faded into the first frame, in order
+    ///
+    /// The first frame is never faded, even if it is one of the last frames, and frames that
+    /// aren't the size of the first frame are left as they are.
+    pub fn finish(self) -> Vec<RgbaFrame> {
+        let (first_index, first) = match self.first {
+            Some(first) => first,
+            None => return self.tail.into_values().collect(),
+        };
+
+        let fading = self.tail.range(first_index + 1..).count();
+        let mut faded = 0;
+        self.tail
+            .into_values()
+            .map(|mut frame| {
+                if frame.index > first_index {
+                    faded += 1;
+                    blend(&mut frame.image, &first, faded as f64 / fading as f64);
+                }
+                frame
+            })
+            .collect()
+    }
+}
+
+/// Blend an image toward another one of the same size by `amount`, from `0` for none of the other
+/// image to `1` for all of it
+fn blend(image: &mut ImgVec<RGBA8>, other: &ImgVec<RGBA8>, amount: f64) {
+    if image.width() != other.width() || image.height() != other.height() {
+        return;
+    }
+
+    for (pixel, other) in image.pixels_mut().zip(other.pixels()) {
+        *pixel = color_profile::mix(*pixel, other, amount);
+    }
+}
@@ -0,0 +1,98 @@
+//! Captions from the labels of markers
+//!
+//! asciinema markers can have a label, such as the name of the part of a demo that starts at
+//! them. Showing the label as a caption for a while after its marker gives the rendered gif
+//! chapter titles.
+
+use std::sync::Arc;
+
+use crate::cast_parser::AsciinemaError;
+use crate::types::TerminalFrame;
+
+/// An iterator adapter that shows the marker label of a frame as the caption of the frames after
+/// it, until the caption's time is up
+///
+/// Frames are only emitted when the screen changes, so the last frame is repeated without the
+/// caption when it ends before the next change.
+pub(crate) struct MarkerCaptions<I> {
+    /// The frames to caption
+    frames: I,
+    /// The time to show each caption for, in centiseconds
+    duration: f64,
+    /// The caption that is being shown and the time that it ends at, in centiseconds
+    caption: Option<(Arc<str>, f64)>,
+    /// The last frame that was emitted
+    last_frame: Option<TerminalFrame>,
+    /// A frame that has been read, but not yet emitted
+    pending_frame: Option<TerminalFrame>,
+    /// The index of the next frame
+    next_index: u64,
+}
+
+impl<I> MarkerCaptions<I> {
+    /// Show the labels of markers for `duration` seconds
+    pub fn new(frames: I, duration: f64) -> Self {
+        MarkerCaptions {
+            frames,
+            duration: duration * 100.,
+            caption: None,
+            last_frame: None,
+            pending_frame: None,
+            next_index: 0,
+        }
+    }
+
+    /// Emit a frame with the next index
+    fn emit(&mut self, mut frame: TerminalFrame) -> TerminalFrame {
+        frame.index = self.next_index;
+        self.next_index += 1;
+        self.last_frame = Some(frame.clone());
+
+        frame
+    }
+}
+
+impl<I> Iterator for MarkerCaptions<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = match self
+            .pending_frame
+            .take()
+            .map(Ok)
+            .or_else(|| self.frames.next())?
+        {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+
+        if let Some((_, end)) = self.caption {
+            if end <= frame.time {
+                self.caption = None;
+            }
+
+            // Repeat the last frame without the caption if it ends before this frame
+            if end < frame.time {
+                self.pending_frame = Some(frame);
+                let mut repeated = self
+                    .last_frame
+                    .clone()
+                    .expect("Captions are only shown after a frame");
+                repeated.time = end;
+                repeated.caption = None;
+                return Some(Ok(self.emit(repeated)));
+            }
+        }
+
+        // A new marker replaces the caption that is being shown
+        if let Some(label) = frame.caption.take() {
+            self.caption = Some((label, frame.time + self.duration));
+        }
+        frame.caption = self.caption.as_ref().map(|(label, _)| label.clone());
+
+        Some(Ok(self.emit(frame)))
+    }
+}
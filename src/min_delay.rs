@@ -0,0 +1,167 @@
+//! Merging frames that come too close together
+//!
+//! Gif delays are in centiseconds, and browsers slow down frames with delays of less than about
+//! 20 milliseconds to 100 milliseconds, so bursts of quick changes to the screen play back much
+//! slower than they were recorded.
+
+use crate::cast_parser::AsciinemaError;
+use crate::types::TerminalFrame;
+
+/// The minimum frame delay used when none is configured, in seconds
+pub(crate) const DEFAULT_MIN_FRAME_DELAY: f64 = 0.02;
+
+/// An iterator adapter that merges frames that come sooner than a minimum delay after the previous
+/// frame
+///
+/// Frames that come too soon are replaced by the last of them, which is shown as soon as the
+/// minimum delay has passed.
+pub(crate) struct MinDelayFrames<I> {
+    /// The frames to merge
+    frames: I,
+    /// The minimum time between frames, in centiseconds
+    min_delay: f64,
+    /// The time of the last frame that was emitted
+    last_time: Option<f64>,
+    /// The latest frame that came too soon after the last emitted frame
+    deferred_frame: Option<TerminalFrame>,
+    /// A frame that has been read, but not yet emitted
+    pending_frame: Option<TerminalFrame>,
+    /// The index of the next frame
+    next_index: u64,
+}
+
+impl<I> MinDelayFrames<I> {
+    /// Merge frames that come less than `min_delay` seconds after the previous frame
+    pub fn new(frames: I, min_delay: f64) -> Self {
+        MinDelayFrames {
+            frames,
+            min_delay: min_delay * 100.,
+            last_time: None,
+            deferred_frame: None,
+            pending_frame: None,
+            next_index: 0,
+        }
+    }
+
+    /// Emit a frame with the next index
+    fn emit(&mut self, mut frame: TerminalFrame) -> TerminalFrame {
+        frame.index = self.next_index;
+        self.next_index += 1;
+        self.last_time = Some(frame.time);
+
+        frame
+    }
+}
+
+impl<I> Iterator for MinDelayFrames<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let earliest_time = self.last_time.map(|t| t + self.min_delay);
+            let frame = match self
+                .pending_frame
+                .take()
+                .map(Ok)
+                .or_else(|| self.frames.next())
+            {
+                Some(Ok(frame)) => frame,
+                Some(Err(e)) => return Some(Err(e)),
+                // Show the last of the merged frames once the recording ends
+                None => {
+                    let mut deferred = self.deferred_frame.take()?;
+                    deferred.time = earliest_time.expect("Frames are only deferred after another");
+                    return Some(Ok(self.emit(deferred)));
+                }
+            };
+
+            match earliest_time {
+                // Merge frames that come too soon
+                Some(earliest_time) if frame.time < earliest_time => {
+                    self.deferred_frame = Some(frame);
+                }
+                // Show the merged frames as soon as they are allowed to, before this frame
+                Some(earliest_time) if self.deferred_frame.is_some() => {
+                    let mut deferred = self.deferred_frame.take().expect("Checked above");
+                    deferred.time = earliest_time;
+                    self.pending_frame = Some(frame);
+                    return Some(Ok(self.emit(deferred)));
+                }
+                _ => return Some(Ok(self.emit(frame))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cast_parser::TerminalFrameIter;
+    use crate::CastBuilder;
+
+    /// Get the (index, time, first row of the screen) of every frame of a cast, with frames less
+    /// than `min_delay` seconds apart merged
+    fn merged(cast: CastBuilder, min_delay: f64) -> Vec<(u64, f64, String)> {
+        let frames = TerminalFrameIter::new(cast.reader()).expect("The cast parses");
+        MinDelayFrames::new(frames, min_delay)
+            .map(|frame| {
+                let frame = frame.expect("The frame parses");
+                let row = (0..frame.screen.size().1)
+                    .filter_map(|col| {
+                        Some(frame.screen.cell(0, col)?.contents.as_ref()?.to_string())
+                    })
+                    .collect();
+                (frame.index, frame.time.round(), row)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merges_frames_that_come_too_soon() {
+        let cast = CastBuilder::new(10, 2)
+            .output_at(0., "a")
+            .output_at(0.4, "b")
+            .output_at(0.41, "c")
+            .output_at(0.7, "d");
+
+        assert_eq!(
+            merged(cast, 0.05),
+            vec![
+                (0, 0., "a".to_string()),
+                (1, 40., "ab".to_string()),
+                (2, 45., "abc".to_string()),
+                (3, 70., "abcd".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn shows_merged_frames_at_the_end_of_the_recording() {
+        let cast = CastBuilder::new(10, 2)
+            .output_at(0., "a")
+            .output_at(0.4, "b")
+            .output_at(0.41, "c")
+            .output_at(0.42, "d");
+
+        assert_eq!(
+            merged(cast, 0.05),
+            vec![
+                (0, 0., "a".to_string()),
+                (1, 40., "ab".to_string()),
+                (2, 45., "abcd".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_every_frame_without_a_min_delay() {
+        let cast = CastBuilder::new(10, 2)
+            .output_at(0., "a")
+            .output_at(0.001, "b");
+
+        assert_eq!(merged(cast, 0.).len(), 2);
+    }
+}
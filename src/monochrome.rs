@@ -0,0 +1,161 @@
+//! Rendering in two colors
+//!
+//! A monochrome render redraws every pixel of the frames in shades between a paper color, for the
+//! darkest colors, and an ink color, for the lightest ones, by how light the pixel is. The shades
+//! can be kept as a grayscale or rounded to just the two colors, which gifs compress extremely
+//! well. Text that would round to the same color as the cell behind it is drawn in the other color
+//! before the frames are rendered, so that it can still be read.
+
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+use crate::cast_parser::AsciinemaError;
+use crate::color_profile::Rgb;
+use crate::highlight::mix;
+use crate::types::{ScreenSnapshot, TerminalFrame};
+use crate::ColorProfile;
+
+/// Get how light a color looks, from `0` for black to `1` for white
+///
+/// Colors are weighed as sRGB values, like every other blend.
+fn lightness((r, g, b): Rgb) -> f64 {
+    (0.2126 * f64::from(r) + 0.7152 * f64::from(g) + 0.0722 * f64::from(b)) / 255.
+}
+
+/// Whether or not a color is drawn in the ink in a two-tone render
+fn is_ink(color: Rgb) -> bool {
+    lightness(color) >= 0.5
+}
+
+/// How the colors of a monochrome render are collapsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MonochromeStyle {
+    /// Every shade between the paper and the ink, by how light each color is
+    Grayscale,
+    /// Only the paper and the ink, whichever each color is closer to
+    TwoTone,
+}
+
+/// The colors to render frames in instead of the colors of the cast
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Monochrome {
+    /// How the colors are collapsed
+    pub style: MonochromeStyle,
+    /// The color that the lightest colors, such as the default text, are drawn in
+    pub ink: Rgb,
+    /// The color that the darkest colors, such as the default background, are drawn in
+    pub paper: Rgb,
+}
+
+impl Monochrome {
+    /// Render in the given style with the default text color as the ink and the default
+    /// background as the paper
+    pub fn new(style: MonochromeStyle) -> Self {
+        let profile = ColorProfile::default();
+        Monochrome {
+            style,
+            ink: profile.foreground,
+            paper: profile.background,
+        }
+    }
+
+    /// Get the color that a color is drawn in
+    pub(crate) fn rgb(&self, color: Rgb) -> Rgb {
+        let amount = match self.style {
+            MonochromeStyle::Grayscale => lightness(color),
+            MonochromeStyle::TwoTone if is_ink(color) => 1.,
+            MonochromeStyle::TwoTone => 0.,
+        };
+
+        mix(self.paper, self.ink, amount)
+    }
+
+    /// Redraw every pixel of an image in the monochrome colors, keeping its opacity
+    pub(crate) fn apply(&self, image: &mut ImgVec<RGBA8>) {
+        for pixel in image.pixels_mut() {
+            let (r, g, b) = self.rgb((pixel.r, pixel.g, pixel.b));
+            *pixel = RGBA8::new(r, g, b, pixel.a);
+        }
+    }
+}
+
+/// An iterator adapter that makes text that would be drawn in the same color as the cell behind it
+/// in a two-tone render white or black, whichever the cell isn't
+pub(crate) struct TwoToneFrames<I> {
+    /// The frames to change the text colors of
+    frames: I,
+    /// Whether or not the frames are rendered in two tones
+    two_tone: bool,
+    /// The colors that the frames are rendered with
+    profile: ColorProfile,
+}
+
+impl<I> TwoToneFrames<I> {
+    /// Keep the text of the frames readable if they are rendered in `monochrome`, with the colors
+    /// of the `profile`
+    ///
+    /// Frames are passed through untouched unless `monochrome` is two-tone.
+    pub fn new(frames: I, monochrome: Option<Monochrome>, profile: ColorProfile) -> Self {
+        TwoToneFrames {
+            frames,
+            two_tone: matches!(
+                monochrome,
+                Some(Monochrome {
+                    style: MonochromeStyle::TwoTone,
+                    ..
+                })
+            ),
+            profile,
+        }
+    }
+
+    /// Change the color of the text on a screen that would be lost in the cell behind it
+    fn separate(&self, screen: &mut ScreenSnapshot) {
+        let (rows, cols) = screen.size();
+        for row in 0..rows {
+            for col in 0..cols {
+                let cell = screen
+                    .cell_mut(row, col)
+                    .expect("The cell is on the screen");
+                if cell.contents.is_none() {
+                    continue;
+                }
+
+                let foreground = self
+                    .profile
+                    .color(cell.fgcolor)
+                    .unwrap_or(self.profile.foreground);
+                let background = self
+                    .profile
+                    .color(cell.bgcolor)
+                    .unwrap_or(self.profile.background);
+                if is_ink(foreground) == is_ink(background) {
+                    cell.fgcolor = if is_ink(background) {
+                        vt100::Color::Rgb(0, 0, 0)
+                    } else {
+                        vt100::Color::Rgb(255, 255, 255)
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl<I> Iterator for TwoToneFrames<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = match self.frames.next()? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        if self.two_tone {
+            self.separate(&mut frame.screen);
+        }
+
+        Some(Ok(frame))
+    }
+}
@@ -0,0 +1,285 @@
+//! Overlay scripts that draw text and boxes over rendered frames
+//!
+//! An overlay script is a [Rhai](https://rhai.rs) script that defines an `overlay(frame)`
+//! function, which is called for every rendered frame to draw annotations that change over time,
+//! such as "step 2 of 5" for the part of a demo that is being shown. The frame has these
+//! properties:
+//!
+//! - `index`: the index of the frame in the animation
+//! - `time`: the time that the frame is shown at, in seconds
+//! - `width` and `height`: the size of the frame in pixels
+//! - `cell_width` and `cell_height`: the size of a terminal cell, and of a character of text, in
+//!   pixels
+//! - `marker`: the number of the last marker in the cast at or before the frame, counting from 1,
+//!   or 0 if there hasn't been one
+//! - `marker_label`: the label of that marker, or an empty string
+//!
+//! and these methods, which take positions and sizes in pixels from the top left of the frame:
+//!
+//! - `frame.text(x, y, text)` and `frame.text(x, y, text, color)`: draw a line of text with its
+//!   top left corner at (x, y), in white unless a color is given
+//! - `frame.rect(x, y, width, height, color)`: fill a box
+//!
+//! Colors are given like `"#ab4642"`, or with an alpha channel for transparency like
+//! `"#00000080"`. Anything that is drawn outside of the frame is cut off. The top level of the
+//! script is run before every call of `overlay`, so constants that it defines can be used in
+//! `overlay` as `global::NAME`.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rgb::{RGBA, RGBA8};
+use rhai::{Dynamic, Engine, EvalAltResult, ImmutableString, Scope, AST, FLOAT, INT};
+
+use crate::frame_renderer;
+use crate::types::{Marker, RgbaFrame};
+use crate::Error;
+
+/// The color of text that is drawn without one
+const DEFAULT_TEXT_COLOR: RGBA8 = RGBA::new(255, 255, 255, 255);
+
+/// A script that draws over rendered frames
+///
+/// Cloning a script is cheap, and the clones share the compiled script.
+#[derive(Clone)]
+pub struct OverlayScript {
+    inner: Arc<CompiledScript>,
+}
+
+/// A compiled overlay script
+struct CompiledScript {
+    /// The name of the script, for error messages
+    name: String,
+    /// The engine that the script is run with
+    engine: Engine,
+    /// The compiled script
+    ast: AST,
+}
+
+/// Something that a script drew, which is drawn onto the frame once the script returns
+#[derive(Debug, Clone)]
+enum Shape {
+    Text {
+        position: (isize, isize),
+        text: String,
+        color: RGBA8,
+    },
+    Rect {
+        position: (isize, isize),
+        size: (usize, usize),
+        color: RGBA8,
+    },
+}
+
+/// The frame that is given to the `overlay` function of a script
+///
+/// Rhai passes arguments by value, so the shapes are shared with the copy of the frame that the
+/// script gets.
+#[derive(Debug, Clone)]
+struct ScriptFrame {
+    index: INT,
+    time: FLOAT,
+    width: INT,
+    height: INT,
+    marker: INT,
+    marker_label: ImmutableString,
+    shapes: Arc<Mutex<Vec<Shape>>>,
+}
+
+impl OverlayScript {
+    /// Compile an overlay script
+    ///
+    /// The `name` is used to tell which script failed in error messages.
+    pub fn new<N: Into<String>>(name: N, script: &str) -> Result<Self, Error> {
+        let name = name.into();
+        let engine = script_engine(&name);
+        let ast = engine.compile(script).map_err(|e| {
+            Error::Generic(format!("Could not load overlay script {}: {}", name, e))
+        })?;
+        if !ast
+            .iter_functions()
+            .any(|f| f.name == "overlay" && f.params.len() == 1)
+        {
+            return Err(Error::Generic(format!(
+                "Could not load overlay script {}: It doesn't define an `overlay(frame)` function",
+                name
+            )));
+        }
+
+        Ok(OverlayScript {
+            inner: Arc::new(CompiledScript { name, engine, ast }),
+        })
+    }
+
+    /// Load an overlay script from a file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let script = std::fs::read_to_string(path).map_err(|e| {
+            Error::Generic(format!(
+                "Could not read overlay script {}: {}",
+                path.to_string_lossy(),
+                e
+            ))
+        })?;
+
+        Self::new(path.to_string_lossy(), &script)
+    }
+
+    /// Get the name of the script
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    /// Draw the overlay of a rendered frame, which comes after the given marker
+    pub(crate) fn apply(
+        &self,
+        mut frame: RgbaFrame,
+        marker: Option<&Marker>,
+    ) -> Result<RgbaFrame, Error> {
+        let script = &self.inner;
+        let shapes = Arc::new(Mutex::new(Vec::new()));
+        let script_frame = ScriptFrame {
+            index: frame.index as INT,
+            time: frame.timestamp(),
+            width: frame.image.width() as INT,
+            height: frame.image.height() as INT,
+            marker: marker.map_or(0, |marker| marker.number.into()),
+            marker_label: marker.map_or_else(Default::default, |marker| (&*marker.label).into()),
+            shapes: shapes.clone(),
+        };
+
+        // Whatever `overlay` returns is ignored
+        script
+            .engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &script.ast, "overlay", (script_frame,))
+            .map(drop)
+            .map_err(|e| {
+                Error::Generic(format!(
+                    "Overlay script {} failed on frame {}: {}",
+                    script.name, frame.index, e
+                ))
+            })?;
+
+        for shape in shapes.lock().unwrap().iter() {
+            match shape {
+                Shape::Text {
+                    position,
+                    text,
+                    color,
+                } => frame_renderer::draw_text(&mut frame.image, *position, text, *color),
+                Shape::Rect {
+                    position,
+                    size,
+                    color,
+                } => frame_renderer::fill_rect(&mut frame.image, *position, *size, *color),
+            }
+        }
+
+        Ok(frame)
+    }
+}
+
+impl fmt::Debug for OverlayScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OverlayScript")
+            .field("name", &self.inner.name)
+            .finish()
+    }
+}
+
+impl ScriptFrame {
+    /// Add a shape to draw over the frame
+    fn draw(&mut self, shape: Shape) {
+        self.shapes.lock().unwrap().push(shape);
+    }
+}
+
+/// Create the engine that a script is run with, with the frame type registered
+fn script_engine(name: &str) -> Engine {
+    let mut engine = Engine::new();
+
+    // Print from scripts to the log, so that it doesn't get mixed up with the progress bar
+    let print_name = name.to_owned();
+    engine.on_print(move |text| log::info!("{}: {}", print_name, text));
+    let debug_name = name.to_owned();
+    engine.on_debug(move |text, _, position| log::debug!("{} {}: {}", debug_name, position, text));
+
+    engine
+        .register_type_with_name::<ScriptFrame>("Frame")
+        .register_get("index", |frame: &mut ScriptFrame| frame.index)
+        .register_get("time", |frame: &mut ScriptFrame| frame.time)
+        .register_get("width", |frame: &mut ScriptFrame| frame.width)
+        .register_get("height", |frame: &mut ScriptFrame| frame.height)
+        .register_get("cell_width", |_: &mut ScriptFrame| {
+            frame_renderer::cell_size().0 as INT
+        })
+        .register_get("cell_height", |_: &mut ScriptFrame| {
+            frame_renderer::cell_size().1 as INT
+        })
+        .register_get("marker", |frame: &mut ScriptFrame| frame.marker)
+        .register_get("marker_label", |frame: &mut ScriptFrame| {
+            frame.marker_label.clone()
+        })
+        .register_fn(
+            "text",
+            |frame: &mut ScriptFrame, x: INT, y: INT, text: ImmutableString| {
+                frame.draw(Shape::Text {
+                    position: (x as isize, y as isize),
+                    text: text.to_string(),
+                    color: DEFAULT_TEXT_COLOR,
+                })
+            },
+        )
+        .register_fn(
+            "text",
+            |frame: &mut ScriptFrame,
+             x: INT,
+             y: INT,
+             text: ImmutableString,
+             color: &str|
+             -> Result<(), Box<EvalAltResult>> {
+                frame.draw(Shape::Text {
+                    position: (x as isize, y as isize),
+                    text: text.to_string(),
+                    color: script_color(color)?,
+                });
+                Ok(())
+            },
+        )
+        .register_fn(
+            "rect",
+            |frame: &mut ScriptFrame,
+             x: INT,
+             y: INT,
+             width: INT,
+             height: INT,
+             color: &str|
+             -> Result<(), Box<EvalAltResult>> {
+                frame.draw(Shape::Rect {
+                    position: (x as isize, y as isize),
+                    size: (width.max(0) as usize, height.max(0) as usize),
+                    color: script_color(color)?,
+                });
+                Ok(())
+            },
+        );
+
+    engine
+}
+
+/// Parse a color given to a script function, such as `#ab4642` or `#00000080`
+fn script_color(color: &str) -> Result<RGBA8, Box<EvalAltResult>> {
+    let channels = color
+        .strip_prefix('#')
+        .and_then(|hex| base16::decode(hex).ok());
+    match channels.as_deref() {
+        Some(&[r, g, b]) => Ok(RGBA::new(r, g, b, 255)),
+        Some(&[r, g, b, a]) => Ok(RGBA::new(r, g, b, a)),
+        _ => Err(format!(
+            "`{}` is not a color. Colors are given like \"#ab4642\" or \"#00000080\"",
+            color
+        )
+        .into()),
+    }
+}
@@ -0,0 +1,216 @@
+//! WebAssembly plugins that draw over rendered frames
+//!
+//! A plugin is a WebAssembly module, or its text format, that is given every rendered frame and
+//! changes its pixels, for overlays such as watermarks, progress bars, or effects. A plugin
+//! exports:
+//!
+//! - `memory`: its memory, which frames are copied into and back out of
+//! - `cast2gif_alloc(size: i32) -> i32`: get the address of `size` bytes of memory to copy frames
+//!   into. This is only called again when the size of the frames changes.
+//! - `cast2gif_frame(address: i32, width: i32, height: i32, index: i64, time: f64)`: change the
+//!   frame at the address in place. The frame is `width * height` RGBA pixels in row-major order,
+//!   with one byte per channel, and `time` is the time that it is shown at, in seconds.
+//!
+//! Frames are rendered in parallel, so a plugin is instantiated once for each frame that is being
+//! changed at the same time and may be given frames in any order. A plugin that traps fails the
+//! render.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rgb::ComponentBytes;
+use wasmi::{Engine, Linker, Memory, Module, Store, TypedFunc};
+
+use crate::types::RgbaFrame;
+use crate::Error;
+
+/// A WebAssembly plugin that changes rendered frames
+///
+/// Cloning a plugin is cheap, and the clones share their instances.
+#[derive(Clone)]
+pub struct Plugin {
+    inner: Arc<PluginModule>,
+}
+
+/// A compiled plugin and the instances of it that aren't being used
+struct PluginModule {
+    /// The name of the plugin, for error messages
+    name: String,
+    /// The engine that the module was compiled with
+    engine: Engine,
+    /// The compiled module
+    module: Module,
+    /// Instances that are waiting for a frame
+    instances: Mutex<Vec<PluginInstance>>,
+}
+
+/// An instance of a plugin
+struct PluginInstance {
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    frame: TypedFunc<(i32, i32, i32, i64, f64), ()>,
+    /// The address and size of the frame buffer that the plugin allocated, if any
+    buffer: Option<(i32, usize)>,
+}
+
+impl Plugin {
+    /// Compile a plugin from a WebAssembly module, or its text format
+    ///
+    /// The `name` is used to tell which plugin failed in error messages.
+    pub fn new<N: Into<String>>(name: N, wasm: &[u8]) -> Result<Self, Error> {
+        let name = name.into();
+        let engine = Engine::default();
+        let module = Module::new(&engine, wasm)
+            .map_err(|e| Error::Generic(format!("Could not load plugin {}: {}", name, e)))?;
+        let plugin = PluginModule {
+            name,
+            engine,
+            module,
+            instances: Mutex::new(Vec::new()),
+        };
+
+        // Make sure that the plugin can be instantiated before the render starts
+        let instance = plugin.instantiate()?;
+        plugin.instances.lock().unwrap().push(instance);
+
+        Ok(Plugin {
+            inner: Arc::new(plugin),
+        })
+    }
+
+    /// Load a plugin from a `.wasm` or `.wat` file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let wasm = std::fs::read(path).map_err(|e| {
+            Error::Generic(format!(
+                "Could not read plugin {}: {}",
+                path.to_string_lossy(),
+                e
+            ))
+        })?;
+
+        Self::new(path.to_string_lossy(), &wasm)
+    }
+
+    /// Get the name of the plugin
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    /// Pass a rendered frame through the plugin
+    pub(crate) fn apply(&self, mut frame: RgbaFrame) -> Result<RgbaFrame, Error> {
+        let plugin = &self.inner;
+        let instance = plugin.instances.lock().unwrap().pop();
+        let mut instance = match instance {
+            Some(instance) => instance,
+            None => plugin.instantiate()?,
+        };
+
+        instance.apply(&mut frame).map_err(|e| {
+            Error::Generic(format!(
+                "Plugin {} failed on frame {}: {}",
+                plugin.name, frame.index, e
+            ))
+        })?;
+
+        // Instances that failed may be broken, so they are only reused if they succeeded
+        plugin.instances.lock().unwrap().push(instance);
+
+        Ok(frame)
+    }
+}
+
+impl fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Plugin")
+            .field("name", &self.inner.name)
+            .finish()
+    }
+}
+
+impl PluginModule {
+    /// Create a new instance of the plugin
+    fn instantiate(&self) -> Result<PluginInstance, Error> {
+        let error = |e: &dyn fmt::Display| {
+            Error::Generic(format!("Could not start plugin {}: {}", self.name, e))
+        };
+
+        let mut store = Store::new(&self.engine, ());
+        let instance = Linker::new(&self.engine)
+            .instantiate_and_start(&mut store, &self.module)
+            .map_err(|e| error(&e))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| error(&"It doesn't export its `memory`"))?;
+        let alloc = instance
+            .get_typed_func(&store, "cast2gif_alloc")
+            .map_err(|e| error(&format!("`cast2gif_alloc`: {}", e)))?;
+        let frame = instance
+            .get_typed_func(&store, "cast2gif_frame")
+            .map_err(|e| error(&format!("`cast2gif_frame`: {}", e)))?;
+
+        Ok(PluginInstance {
+            store,
+            memory,
+            alloc,
+            frame,
+            buffer: None,
+        })
+    }
+}
+
+impl PluginInstance {
+    /// Let the plugin change a frame
+    fn apply(&mut self, frame: &mut RgbaFrame) -> Result<(), String> {
+        let (width, height) = (frame.image.width(), frame.image.height());
+        let size = width * height * 4;
+        let to_i32 = |n: usize| i32::try_from(n).map_err(|_| "The frame is too big".to_string());
+
+        let wasm_address = match self.buffer {
+            Some((address, buffer_size)) if buffer_size == size => address,
+            _ => {
+                let address = self
+                    .alloc
+                    .call(&mut self.store, to_i32(size)?)
+                    .map_err(|e| e.to_string())?;
+                self.buffer = Some((address, size));
+                address
+            }
+        };
+        // Addresses are unsigned
+        let address = wasm_address as u32 as usize;
+
+        let buffer = self
+            .memory
+            .data_mut(&mut self.store)
+            .get_mut(address..address + size)
+            .ok_or("`cast2gif_alloc` returned memory that is out of bounds")?;
+        for (row, data) in frame.image.rows().zip(buffer.chunks_exact_mut(width * 4)) {
+            data.copy_from_slice(row.as_bytes());
+        }
+
+        self.frame
+            .call(
+                &mut self.store,
+                (
+                    wasm_address,
+                    to_i32(width)?,
+                    to_i32(height)?,
+                    frame.index as i64,
+                    frame.timestamp(),
+                ),
+            )
+            .map_err(|e| e.to_string())?;
+
+        // The plugin may have grown its memory, which can move it, but memory never shrinks
+        let buffer = &self.memory.data(&self.store)[address..address + size];
+        for (row, data) in frame.image.rows_mut().zip(buffer.chunks_exact(width * 4)) {
+            row.as_bytes_mut().copy_from_slice(data);
+        }
+
+        Ok(())
+    }
+}
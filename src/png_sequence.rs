@@ -0,0 +1,419 @@
+//! Writing the frames as a sequence of png images
+//!
+//! Video editors and encoders take image sequences more readily than gifs, but they tend to be
+//! picky about how the images are named. Every rendered frame is written to its own png in the
+//! output directory, named by a template with the index and time of the frame. A manifest of the
+//! frames and how long each one is shown for can be written next to them, so that editors place
+//! them on their timeline with the timing of the recording.
+
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use rgb::ComponentBytes;
+use serde::Serialize;
+
+use crate::types::{PngSequenceSettings, RgbaFrame};
+use crate::Error;
+
+/// The file name template used when none is given
+pub const DEFAULT_FRAME_NAME: &str = "frame-{index:05}.png";
+
+/// The name that replaces `{cast}` when the cast doesn't have one
+const DEFAULT_CAST_NAME: &str = "cast";
+
+/// How hard to compress the pngs of an image sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PngCompression {
+    /// Compress quickly, making larger files
+    Fast,
+    /// Balance the speed of compressing with the size of the files
+    #[default]
+    Default,
+    /// Make the smallest files, compressing slowly
+    Best,
+}
+
+impl From<PngCompression> for png::Compression {
+    fn from(compression: PngCompression) -> Self {
+        match compression {
+            PngCompression::Fast => png::Compression::Fast,
+            PngCompression::Default => png::Compression::Default,
+            PngCompression::Best => png::Compression::Best,
+        }
+    }
+}
+
+/// A manifest of the frames of an image sequence, written to the output directory along with them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    /// `manifest.json`, with the file name, index, time, and duration of every frame
+    Json,
+    /// `manifest.edl`, a CMX 3600 edit decision list that places every frame on a timeline at the
+    /// frame rate of videos
+    Edl,
+}
+
+impl ManifestFormat {
+    /// Get the file name of the manifest
+    fn file_name(self) -> &'static str {
+        match self {
+            ManifestFormat::Json => "manifest.json",
+            ManifestFormat::Edl => "manifest.edl",
+        }
+    }
+}
+
+/// A template for the file names of the frames of an image sequence
+///
+/// The template is a file name with placeholders in braces, which are replaced for each frame:
+///
+/// - `{index}`: the index of the frame, starting at 0
+/// - `{time_ms}`: the time that the frame is shown at, in milliseconds
+/// - `{cast}`: the name of the cast, such as the name of the cast file without its extension
+///
+/// A number may give a width after a colon, such as `{index:05}`, to pad it with zeros. Literal
+/// braces are written as `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameNameTemplate {
+    /// The pieces of the name, in order
+    parts: Vec<NamePart>,
+}
+
+/// A piece of a [`FrameNameTemplate`]
+#[derive(Debug, Clone, PartialEq)]
+enum NamePart {
+    /// Text that is copied into the name
+    Text(String),
+    /// The index of the frame, padded with zeros to the given width
+    Index(usize),
+    /// The time of the frame in milliseconds, padded with zeros to the given width
+    TimeMs(usize),
+    /// The name of the cast
+    Cast,
+}
+
+impl FrameNameTemplate {
+    /// Get the file name of a frame of a cast with the given index and time, in centiseconds
+    pub(crate) fn name(&self, cast: &str, index: u64, time: f64) -> String {
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                NamePart::Text(text) => name.push_str(text),
+                NamePart::Cast => name.push_str(cast),
+                NamePart::Index(width) => {
+                    write!(name, "{:0width$}", index, width = width).ok();
+                }
+                NamePart::TimeMs(width) => {
+                    let time_ms = (time * 10.).round().max(0.) as u64;
+                    write!(name, "{:0width$}", time_ms, width = width).ok();
+                }
+            }
+        }
+
+        name
+    }
+}
+
+impl Default for FrameNameTemplate {
+    fn default() -> Self {
+        DEFAULT_FRAME_NAME
+            .parse()
+            .expect("The default frame name is valid")
+    }
+}
+
+impl FromStr for FrameNameTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| {
+            Error::Generic(format!("Invalid frame name template `{}`: {}", s, reason))
+        };
+
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '}' => {
+                    return Err(invalid(
+                        "a `}` isn't closing a placeholder; use `}}` for one",
+                    ))
+                }
+                '{' => {
+                    let rest = chars.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| invalid("a placeholder isn't closed"))?;
+                    let placeholder = &rest[..end];
+                    chars = rest[end + 1..].chars();
+
+                    let (name, width) = match placeholder.split_once(':') {
+                        Some((name, width)) => match width.parse::<usize>() {
+                            Ok(width) if width <= 20 => (name, width),
+                            _ => {
+                                return Err(invalid(&format!(
+                                    "the width of `{{{}}}` isn't a number of digits",
+                                    placeholder
+                                )))
+                            }
+                        },
+                        None => (placeholder, 0),
+                    };
+                    let part = match name {
+                        "index" => NamePart::Index(width),
+                        "time_ms" => NamePart::TimeMs(width),
+                        "cast" if placeholder.contains(':') => {
+                            return Err(invalid("`{cast}` isn't a number, so it can't be padded"))
+                        }
+                        "cast" => NamePart::Cast,
+                        _ => {
+                            return Err(invalid(&format!(
+                                "unknown placeholder `{{{}}}`; use `{{index}}`, `{{time_ms}}`, \
+                                 or `{{cast}}`",
+                                name
+                            )))
+                        }
+                    };
+                    if !text.is_empty() {
+                        parts.push(NamePart::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(part);
+                }
+                '/' | '\\' => return Err(invalid("it can't contain a path separator")),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(NamePart::Text(text));
+        }
+
+        if !parts
+            .iter()
+            .any(|part| matches!(part, NamePart::Index(_) | NamePart::TimeMs(_)))
+        {
+            return Err(invalid(
+                "it needs an `{index}` or `{time_ms}` placeholder to tell the frames apart",
+            ));
+        }
+
+        Ok(FrameNameTemplate { parts })
+    }
+}
+
+/// A frame that was written to the image sequence, as it is listed in the manifest
+#[derive(Debug, Clone, Serialize)]
+struct WrittenFrame {
+    /// The file name of the frame
+    file: String,
+    index: u64,
+    /// The time that the frame is shown at, in milliseconds
+    time_ms: u64,
+    /// The time that the frame is shown for, in milliseconds
+    duration_ms: u64,
+}
+
+/// The manifest of an image sequence, as it is written to JSON
+#[derive(Debug, Clone, Serialize)]
+struct JsonManifest<'a> {
+    /// The name of the cast
+    cast: &'a str,
+    /// The width of the frames, in pixels
+    width: usize,
+    /// The height of the frames, in pixels
+    height: usize,
+    /// The time that the last frame stops being shown at, in milliseconds
+    duration_ms: u64,
+    frames: &'a [WrittenFrame],
+}
+
+/// Write frames to png files in a directory, creating the directory if it doesn't exist
+///
+/// The last frame is shown for `final_hold` seconds, which the manifest, if there is one, lists as
+/// its duration.
+pub(crate) fn write<F>(
+    frames: F,
+    dir: &Path,
+    settings: &PngSequenceSettings,
+    final_hold: f64,
+) -> Result<(), Error>
+where
+    F: Iterator<Item = Result<RgbaFrame, Error>>,
+{
+    std::fs::create_dir_all(dir).map_err(|e| {
+        Error::Generic(format!(
+            "Could not create output directory {}: {}",
+            dir.to_string_lossy(),
+            e
+        ))
+    })?;
+
+    let cast = settings.cast_name.as_deref().unwrap_or(DEFAULT_CAST_NAME);
+    let mut written: Vec<WrittenFrame> = Vec::new();
+    let mut size = (0, 0);
+    for frame in frames {
+        let frame = frame?;
+        let name = settings.file_name.name(cast, frame.index, frame.time);
+        // Frames are in order, so any frames with the same name are next to each other
+        if written.last().map(|last| &last.file) == Some(&name) {
+            return Err(Error::Generic(format!(
+                "More than one frame would be written to {}. Add `{{index}}` to the frame name \
+                 template to tell them apart.",
+                name
+            )));
+        }
+
+        let path = dir.join(&name);
+        write_png(&frame, &path, settings).map_err(|e| {
+            Error::Generic(format!(
+                "Could not write frame {}: {}",
+                path.to_string_lossy(),
+                e
+            ))
+        })?;
+
+        let time_ms = ms(frame.time / 100.);
+        if let Some(last) = written.last_mut() {
+            last.duration_ms = time_ms.saturating_sub(last.time_ms);
+        }
+        written.push(WrittenFrame {
+            file: name,
+            index: frame.index,
+            time_ms,
+            duration_ms: ms(final_hold),
+        });
+        size = (frame.image.width(), frame.image.height());
+    }
+
+    if let Some(format) = settings.manifest {
+        let path = dir.join(format.file_name());
+        write_manifest(format, &path, cast, size, &written).map_err(|e| {
+            Error::Generic(format!(
+                "Could not write manifest {}: {}",
+                path.to_string_lossy(),
+                e
+            ))
+        })?;
+    }
+
+    Ok(())
+}
+
+/// Convert a time in seconds to whole milliseconds
+fn ms(seconds: f64) -> u64 {
+    (seconds * 1000.).round().max(0.) as u64
+}
+
+/// Write the manifest of the frames that were written
+fn write_manifest(
+    format: ManifestFormat,
+    path: &Path,
+    cast: &str,
+    (width, height): (usize, usize),
+    frames: &[WrittenFrame],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    match format {
+        ManifestFormat::Json => {
+            let manifest = JsonManifest {
+                cast,
+                width,
+                height,
+                duration_ms: frames
+                    .last()
+                    .map_or(0, |last| last.time_ms + last.duration_ms),
+                frames,
+            };
+            serde_json::to_writer_pretty(&mut writer, &manifest)?;
+            writeln!(writer)?;
+        }
+        ManifestFormat::Edl => write_edl(&mut writer, cast, frames)?,
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Write an edit decision list with an event for every frame, at the frame rate of videos
+///
+/// Frames that are too short to cover a frame of the timeline are left out, like they are left out
+/// of videos.
+fn write_edl<W: Write>(writer: &mut W, cast: &str, frames: &[WrittenFrame]) -> std::io::Result<()> {
+    writeln!(writer, "TITLE: {}", cast)?;
+    writeln!(writer, "FCM: NON-DROP FRAME")?;
+
+    let timeline_frame = |ms: u64| (ms as f64 / 1000. * crate::video::VIDEO_FPS).round() as u64;
+    let mut event = 0;
+    for frame in frames {
+        let start = timeline_frame(frame.time_ms);
+        let end = timeline_frame(frame.time_ms + frame.duration_ms);
+        if end <= start {
+            continue;
+        }
+
+        event += 1;
+        writeln!(writer)?;
+        writeln!(
+            writer,
+            "{:03}  AX       V     C        {} {} {} {}",
+            event,
+            timecode(0),
+            timecode(end - start),
+            timecode(start),
+            timecode(end)
+        )?;
+        writeln!(writer, "* FROM CLIP NAME: {}", frame.file)?;
+    }
+
+    Ok(())
+}
+
+/// Format a number of frames of the timeline as a non-drop-frame timecode, like `00:01:02:15`
+fn timecode(frames: u64) -> String {
+    let fps = crate::video::VIDEO_FPS as u64;
+    let seconds = frames / fps;
+    format!(
+        "{:02}:{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        frames % fps
+    )
+}
+
+/// Write a frame to a png file
+fn write_png(
+    frame: &RgbaFrame,
+    path: &Path,
+    settings: &PngSequenceSettings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(file),
+        frame.image.width() as u32,
+        frame.image.height() as u32,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(settings.compression.into());
+    // Tag the colors as sRGB so that viewers on wide-gamut displays show them as the terminal did
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+
+    let (pixels, _, _) = frame.image.as_ref().to_contiguous_buf();
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(pixels.as_bytes())?;
+    writer.finish()?;
+
+    Ok(())
+}
@@ -0,0 +1,292 @@
+//! Finding the commands run at shell prompts
+//!
+//! Shells with semantic prompt integration, like the ones set up by kitty, WezTerm, and iTerm2,
+//! mark their prompts with OSC 133 sequences: `A` where the prompt starts, `B` where the command
+//! line starts, `C` where the output of the command starts, and `D` with its exit status when it
+//! finishes. Casts of those shells give the exact command of every prompt.
+//!
+//! Other casts fall back to a guess: when Enter is pressed at the end of a line that looks like a
+//! prompt followed by some text, like `user@host:~$ ls -la`, the text is taken to be a command.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Serialize;
+
+lazy_static! {
+    /// A line that looks like a shell prompt, which ends in one of the usual prompt characters,
+    /// with the command typed after it, if there is one
+    static ref PROMPT_LINE: Regex =
+        Regex::new(r"^(.{0,80}?[$#%>❯»])(?: +(\S.*))?$").expect("The prompt regex is valid");
+}
+
+/// A command run at a shell prompt in a cast
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ShellCommand {
+    /// The time that the prompt of the command was shown at, in seconds, or the time that the
+    /// command was run at if the prompt wasn't found
+    pub prompt_time: f64,
+    /// The time that the command was run at, in seconds
+    pub time: f64,
+    /// The command line
+    pub command: String,
+    /// The exit status of the command, if the shell reported it
+    pub exit_status: Option<i32>,
+}
+
+/// A semantic prompt mark
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PromptMark {
+    /// The prompt starts
+    PromptStart,
+    /// The prompt ends and the command line starts
+    CommandStart,
+    /// The command line ends and the output of the command starts
+    OutputStart,
+    /// The command finished with the exit status, if there is one
+    Finished(Option<i32>),
+}
+
+/// Finds the semantic prompt marks in the output
+#[derive(Default)]
+struct MarkScanner {
+    /// The offset in the output of the byte that is being scanned
+    offset: usize,
+    /// The marks that were found, with the offsets of the output just after them
+    marks: Vec<(usize, PromptMark)>,
+}
+
+impl vte::Perform for MarkScanner {
+    fn print(&mut self, _: char) {}
+
+    fn execute(&mut self, _: u8) {}
+
+    fn hook(&mut self, _: &[i64], _: &[u8], _: bool, _: char) {}
+
+    fn put(&mut self, _: u8) {}
+
+    fn unhook(&mut self) {}
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        if params.first() != Some(&&b"133"[..]) {
+            return;
+        }
+
+        let mark = match params.get(1).copied() {
+            Some(b"A") => PromptMark::PromptStart,
+            Some(b"B") => PromptMark::CommandStart,
+            Some(b"C") => PromptMark::OutputStart,
+            Some(b"D") => PromptMark::Finished(
+                params
+                    .get(2)
+                    .and_then(|status| std::str::from_utf8(status).ok()?.parse().ok()),
+            ),
+            _ => return,
+        };
+        self.marks.push((self.offset + 1, mark));
+    }
+
+    fn csi_dispatch(&mut self, _: &[i64], _: &[u8], _: bool, _: char) {}
+
+    fn esc_dispatch(&mut self, _: &[i64], _: &[u8], _: bool, _: u8) {}
+}
+
+/// Finds the commands run at shell prompts while a cast is emulated
+#[derive(Default)]
+pub(crate) struct PromptDetector {
+    /// The parser that finds the semantic prompt marks in the output
+    parser: vte::Parser,
+    /// The marks found in the output that is being scanned
+    scanner: MarkScanner,
+    /// Whether or not the cast has semantic prompt marks, which are trusted over guesses
+    semantic: bool,
+    /// The (row, col) position that the last prompt started at, for semantic prompts
+    prompt_start: Option<(u16, u16)>,
+    /// The (row, col) position that the last command line started at, for semantic prompts
+    command_start: Option<(u16, u16)>,
+    /// The time that the last prompt was shown at, if its command hasn't been run yet
+    prompt_time: Option<f64>,
+    /// The text of the line that the cursor was at the end of before the last output, for
+    /// guessing
+    cursor_line: Option<String>,
+    /// Whether or not the last command is still running, so that its exit status is its own
+    running: bool,
+    /// The commands that have been found
+    commands: Vec<ShellCommand>,
+}
+
+impl PromptDetector {
+    /// Get the commands that have been found
+    pub fn commands(&self) -> &[ShellCommand] {
+        &self.commands
+    }
+
+    /// Find the semantic prompt marks in some output, returning them along with the offset of
+    /// the output just after each of them
+    pub fn scan(&mut self, output: &[u8]) -> Vec<(usize, PromptMark)> {
+        for (offset, &byte) in output.iter().enumerate() {
+            self.scanner.offset = offset;
+            self.parser.advance(&mut self.scanner, byte);
+        }
+        let marks = std::mem::take(&mut self.scanner.marks);
+        self.semantic |= !marks.is_empty();
+
+        marks
+    }
+
+    /// Remember the line that the cursor is at the end of before some output is applied to the
+    /// screen, to guess whether it was a command once the output is applied
+    pub fn before_output(&mut self, screen: &vt100::Screen) {
+        self.cursor_line = None;
+        if self.semantic {
+            return;
+        }
+
+        let (row, col) = screen.cursor_position();
+        let line = text_between(screen, (row, 0), (row, screen.size().1));
+        if line.chars().count() <= col as usize {
+            self.cursor_line = Some(line);
+        }
+    }
+
+    /// Guess whether some output that was applied to the screen at the given time ran a command,
+    /// returning the command if it did
+    pub fn after_output(
+        &mut self,
+        output: &[u8],
+        screen: &vt100::Screen,
+        time: f64,
+    ) -> Option<ShellCommand> {
+        if self.semantic {
+            return None;
+        }
+
+        // Enter moves the cursor to the start of the next line
+        let entered = output.starts_with(b"\r") || output.starts_with(b"\n");
+        if let Some(command) = self
+            .cursor_line
+            .take()
+            .filter(|_| entered)
+            .and_then(|line| {
+                PROMPT_LINE
+                    .captures(&line)
+                    .map(|captures| Some(captures.get(2)?.as_str().to_owned()))
+            })
+        {
+            match command {
+                Some(command) => return Some(self.add_command(command, time)),
+                // Enter at an empty prompt shows a new prompt
+                None => self.prompt_time = None,
+            }
+        }
+
+        // Keep track of when an empty prompt is shown
+        if self.prompt_time.is_none() {
+            let (row, col) = screen.cursor_position();
+            let line = text_between(screen, (row, 0), (row, screen.size().1));
+            let is_prompt = PROMPT_LINE
+                .captures(&line)
+                .is_some_and(|captures| captures.get(2).is_none());
+            if is_prompt && line.chars().count() <= col as usize {
+                self.prompt_time = Some(time);
+            }
+        }
+
+        None
+    }
+
+    /// Apply a semantic prompt mark that was found at the given time, with the screen as it was
+    /// right after the mark, returning the command that was run, if it was run at the mark
+    pub fn mark(
+        &mut self,
+        mark: PromptMark,
+        screen: &vt100::Screen,
+        time: f64,
+    ) -> Option<ShellCommand> {
+        match mark {
+            PromptMark::PromptStart => {
+                self.prompt_start = Some(screen.cursor_position());
+                self.command_start = None;
+                self.prompt_time = Some(time);
+                self.running = false;
+            }
+            PromptMark::CommandStart => self.command_start = Some(screen.cursor_position()),
+            PromptMark::OutputStart => {
+                let end = screen.cursor_position();
+                let command = match (self.command_start.take(), self.prompt_start.take()) {
+                    (Some(start), _) => text_between(screen, start, end).trim().to_owned(),
+                    // Without the start of the command line, take the prompt off of the line
+                    (None, Some(start)) => {
+                        let line = text_between(screen, start, end);
+                        let line = line.trim();
+                        PROMPT_LINE
+                            .captures(line)
+                            .and_then(|captures| captures.get(2))
+                            .map_or(line, |command| command.as_str())
+                            .to_owned()
+                    }
+                    (None, None) => return None,
+                };
+                if !command.is_empty() {
+                    return Some(self.add_command(command, time));
+                }
+            }
+            PromptMark::Finished(exit_status) => {
+                if self.running {
+                    if let Some(command) = self.commands.last_mut() {
+                        command.exit_status = exit_status;
+                    }
+                }
+                self.running = false;
+            }
+        }
+
+        None
+    }
+
+    /// Add a command that was run at the given time
+    fn add_command(&mut self, command: String, time: f64) -> ShellCommand {
+        let command = ShellCommand {
+            prompt_time: self.prompt_time.take().unwrap_or(time),
+            time,
+            command,
+            exit_status: None,
+        };
+        self.commands.push(command.clone());
+        self.running = true;
+
+        command
+    }
+}
+
+/// Get the text of the screen from the `start` (row, col) position up to the `end` one
+///
+/// Rows that are filled up to their last column wrap onto the row below them, and the other rows
+/// are joined with a space.
+fn text_between(screen: &vt100::Screen, start: (u16, u16), end: (u16, u16)) -> String {
+    let cols = screen.size().1;
+    let mut text = String::new();
+    for row in start.0..=end.0 {
+        let first_col = if row == start.0 { start.1 } else { 0 };
+        let last_col = if row == end.0 { end.1.min(cols) } else { cols };
+        let mut line = String::new();
+        for col in first_col..last_col {
+            match screen.cell(row, col) {
+                Some(cell) if cell.is_wide_continuation() => (),
+                Some(cell) if cell.has_contents() => line.push_str(&cell.contents()),
+                _ => line.push(' '),
+            }
+        }
+
+        let wraps = screen
+            .cell(row, cols.saturating_sub(1))
+            .is_some_and(|cell| cell.has_contents());
+        if row != end.0 && !wraps {
+            text.push_str(line.trim_end());
+            text.push(' ');
+        } else {
+            text.push_str(&line);
+        }
+    }
+
+    text.trim_end().to_owned()
+}
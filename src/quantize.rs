@@ -0,0 +1,178 @@
+//! Reducing the colors of frames before they are encoded into a gif
+//!
+//! gifski picks the palette of every frame with imagequant, which keeps gradients smooth but can
+//! blur the few flat colors of text. The other quantizers reduce each frame to a palette of at
+//! most 256 colors before it is handed to gifski, which then keeps those colors exactly.
+
+use std::collections::HashMap;
+
+use imgref::ImgVec;
+use rgb::RGBA8;
+
+/// The number of colors that a gif frame can have
+const MAX_COLORS: usize = 256;
+
+/// How many of the pixels NeuQuant samples: 1 samples all of them, 30 samples the fewest
+const NEUQUANT_SAMPLE_FACTOR: i32 = 10;
+
+/// The algorithm that picks the colors of each frame of a gif
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quantizer {
+    /// imagequant, through gifski, which dithers and is best for gradients and images
+    #[default]
+    Imagequant,
+    /// The NeuQuant neural network that the gif crate uses, which is fast
+    NeuQuant,
+    /// Median cut, which keeps the flat colors of text and TUIs
+    MedianCut,
+}
+
+impl Quantizer {
+    /// Reduce the colors of a frame with this quantizer
+    ///
+    /// Frames are left as they are for imagequant, because gifski quantizes them.
+    pub(crate) fn quantize(self, image: &mut ImgVec<RGBA8>) {
+        match self {
+            Quantizer::Imagequant => (),
+            Quantizer::NeuQuant => neuquant(image),
+            Quantizer::MedianCut => median_cut(image),
+        }
+    }
+}
+
+/// Reduce the colors of a frame with NeuQuant
+fn neuquant(image: &mut ImgVec<RGBA8>) {
+    let bytes: Vec<u8> = image
+        .pixels()
+        .flat_map(|pixel| [pixel.r, pixel.g, pixel.b, pixel.a])
+        .collect();
+    let quantizer = color_quant::NeuQuant::new(NEUQUANT_SAMPLE_FACTOR, MAX_COLORS, &bytes);
+    let palette: Vec<RGBA8> = quantizer
+        .color_map_rgba()
+        .chunks_exact(4)
+        .map(|color| RGBA8::new(color[0], color[1], color[2], color[3]))
+        .collect();
+
+    let mut cache = HashMap::new();
+    for pixel in image.pixels_mut() {
+        let rgba = [pixel.r, pixel.g, pixel.b, pixel.a];
+        *pixel = *cache
+            .entry(*pixel)
+            .or_insert_with(|| palette[quantizer.index_of(&rgba)]);
+    }
+}
+
+/// A box of colors that median cut splits, with how many pixels have each color
+struct ColorBox {
+    colors: Vec<(RGBA8, usize)>,
+}
+
+impl ColorBox {
+    /// Get a channel of a color
+    fn channel(color: RGBA8, channel: usize) -> u8 {
+        match channel {
+            0 => color.r,
+            1 => color.g,
+            2 => color.b,
+            _ => color.a,
+        }
+    }
+
+    /// Get the channel that the colors of the box differ the most in, and how much they differ
+    fn widest_channel(&self) -> (usize, u8) {
+        (0..4)
+            .map(|channel| {
+                let values = self
+                    .colors
+                    .iter()
+                    .map(|&(color, _)| Self::channel(color, channel));
+                let min = values.clone().min().unwrap_or(0);
+                let max = values.max().unwrap_or(0);
+                (channel, max - min)
+            })
+            .max_by_key(|&(_, range)| range)
+            .unwrap_or((0, 0))
+    }
+
+    /// Split the box at the median pixel of its widest channel
+    fn split(mut self) -> (ColorBox, ColorBox) {
+        let (channel, _) = self.widest_channel();
+        self.colors
+            .sort_unstable_by_key(|&(color, _)| Self::channel(color, channel));
+
+        let total: usize = self.colors.iter().map(|&(_, count)| count).sum();
+        let mut seen = 0;
+        let mut median = 1;
+        for (i, &(_, count)) in self.colors.iter().enumerate() {
+            seen += count;
+            if seen * 2 >= total {
+                median = i + 1;
+                break;
+            }
+        }
+        // Both halves need at least one color
+        let median = median.clamp(1, self.colors.len() - 1);
+
+        let upper = self.colors.split_off(median);
+        (self, ColorBox { colors: upper })
+    }
+
+    /// Get the average color of the box, weighted by how many pixels have each color
+    fn average(&self) -> RGBA8 {
+        let mut sums = [0u64; 4];
+        let mut total = 0u64;
+        for &(color, count) in &self.colors {
+            for (channel, sum) in sums.iter_mut().enumerate() {
+                *sum += u64::from(Self::channel(color, channel)) * count as u64;
+            }
+            total += count as u64;
+        }
+        let [r, g, b, a] = sums.map(|sum| (sum / total.max(1)) as u8);
+
+        RGBA8::new(r, g, b, a)
+    }
+}
+
+/// Reduce the colors of a frame with median cut
+fn median_cut(image: &mut ImgVec<RGBA8>) {
+    let mut histogram: HashMap<RGBA8, usize> = HashMap::new();
+    for pixel in image.pixels() {
+        *histogram.entry(pixel).or_insert(0) += 1;
+    }
+    // Frames that already fit in a gif palette don't need to be reduced
+    if histogram.len() <= MAX_COLORS {
+        return;
+    }
+
+    let mut boxes = vec![ColorBox {
+        colors: histogram.into_iter().collect(),
+    }];
+    while boxes.len() < MAX_COLORS {
+        // Split the box that spans the widest range of a channel
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, color_box)| color_box.colors.len() > 1)
+            .max_by_key(|(_, color_box)| color_box.widest_channel().1)
+            .map(|(i, _)| i);
+        let widest = match widest {
+            Some(i) => i,
+            None => break,
+        };
+
+        let (lower, upper) = boxes.swap_remove(widest).split();
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+
+    let mut palette = HashMap::new();
+    for color_box in &boxes {
+        let average = color_box.average();
+        for &(color, _) in &color_box.colors {
+            palette.insert(color, average);
+        }
+    }
+    for pixel in image.pixels_mut() {
+        *pixel = palette[pixel];
+    }
+}
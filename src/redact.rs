@@ -0,0 +1,167 @@
+//! Redacting text from the terminal
+//!
+//! Recordings tend to pick up things that shouldn't be published, like access tokens, email
+//! addresses, and host names. Text that matches a redaction pattern is blacked out with block
+//! characters before the frames are rendered, so it never makes it into the output.
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use regex::Regex;
+
+use crate::cast_parser::AsciinemaError;
+use crate::types::{CellContents, ScreenSnapshot, TerminalFrame};
+use crate::Error;
+
+/// The character that redacted text is replaced with
+const REDACTED_CHAR: char = '█';
+
+/// Get the text of a screen, along with the row, column, and range of the text of each cell
+///
+/// Rows end in a newline, except for rows that are filled up to their last column, which are
+/// joined to the row below them, since their text likely wraps onto it.
+pub(crate) fn screen_text(screen: &ScreenSnapshot) -> (String, Vec<(u16, u16, Range<usize>)>) {
+    let (rows, cols) = screen.size();
+
+    let mut text = String::new();
+    let mut cells = Vec::with_capacity(rows as usize * cols as usize);
+    for row in 0..rows {
+        for col in 0..cols {
+            let start = text.len();
+            match screen.cell(row, col).and_then(|cell| cell.contents) {
+                Some(contents) => text.extend(contents.chars()),
+                None => text.push(' '),
+            }
+            cells.push((row, col, start..text.len()));
+        }
+        let wraps = screen
+            .cell(row, cols.saturating_sub(1))
+            .and_then(|cell| cell.contents)
+            .is_some_and(|contents| contents.chars().any(|c| !c.is_whitespace()));
+        if !wraps {
+            text.push('\n');
+        }
+    }
+
+    (text, cells)
+}
+
+/// A set of regular expressions for text to redact from the frames
+#[derive(Debug, Clone, Default)]
+pub struct Redactions {
+    /// The patterns to redact
+    patterns: Vec<Regex>,
+}
+
+impl Redactions {
+    /// Compile the patterns to redact, which use the syntax of the `regex` crate
+    pub fn new<I, S>(patterns: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let patterns = patterns
+            .into_iter()
+            .map(|pattern| {
+                let pattern = pattern.as_ref();
+                Regex::new(pattern).map_err(|e| {
+                    Error::Generic(format!("Invalid redaction pattern `{}`: {}", pattern, e))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Redactions { patterns })
+    }
+
+    /// Whether or not there are no patterns
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Replace every character of the text that matches a pattern with a block
+    pub(crate) fn redact_text(&self, text: &str) -> String {
+        self.patterns.iter().fold(text.to_owned(), |text, pattern| {
+            pattern
+                .replace_all(&text, |captures: &regex::Captures| {
+                    REDACTED_CHAR
+                        .to_string()
+                        .repeat(captures[0].chars().count())
+                })
+                .into_owned()
+        })
+    }
+
+    /// Replace the contents of every cell of a screen that has text matching a pattern with a
+    /// block
+    ///
+    /// The rows are matched one at a time, except that a row that is filled up to its last
+    /// column is matched together with the row below it, so that text that wraps onto the next
+    /// line is still redacted.
+    fn redact_screen(&self, screen: &mut ScreenSnapshot) {
+        let (text, cells) = screen_text(screen);
+        let redacted = CellContents::new(&REDACTED_CHAR.to_string());
+        for pattern in &self.patterns {
+            for found in pattern.find_iter(&text).filter(|found| !found.is_empty()) {
+                let first = cells.partition_point(|(_, _, range)| range.end <= found.start());
+                for (row, col, _) in cells[first..]
+                    .iter()
+                    .take_while(|(_, _, range)| range.start < found.end())
+                {
+                    let cell = screen
+                        .cell_mut(*row, *col)
+                        .expect("The cell is on the screen");
+                    cell.contents = Some(redacted);
+                }
+            }
+        }
+    }
+
+    /// Redact the screen of a frame, along with the text that the frame shows outside of it
+    fn redact_frame(&self, frame: &mut TerminalFrame) {
+        self.redact_screen(&mut frame.screen);
+
+        let redact = |text: &Arc<str>| Arc::from(self.redact_text(text));
+        frame.caption = frame.caption.as_ref().map(redact);
+        frame.title = frame.title.as_ref().map(redact);
+        if let Some(marker) = &mut frame.marker {
+            marker.label = redact(&marker.label);
+        }
+    }
+}
+
+/// An iterator adapter that redacts the frames
+pub(crate) struct RedactedFrames<I> {
+    /// The frames to redact
+    frames: I,
+    /// The patterns to redact from them
+    redactions: Redactions,
+}
+
+impl<I> RedactedFrames<I> {
+    /// Redact the text that matches `redactions` from the frames
+    pub fn new(frames: I, redactions: &Redactions) -> Self {
+        RedactedFrames {
+            frames,
+            redactions: redactions.clone(),
+        }
+    }
+}
+
+impl<I> Iterator for RedactedFrames<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut frame = match self.frames.next()? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        if !self.redactions.is_empty() {
+            self.redactions.redact_frame(&mut frame);
+        }
+
+        Some(Ok(frame))
+    }
+}
@@ -0,0 +1,234 @@
+//! Measuring what a render did
+//!
+//! A [`RenderStats`] that is shared with a render through [`ConvertSettings::stats`] counts the
+//! frames that pass through the stages of the render and times the stages, so that the render can
+//! be summed up once it is done.
+//!
+//! [`ConvertSettings::stats`]: crate::ConvertSettings::stats
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::types::RgbaFrame;
+use crate::Error;
+
+/// A stage of a render
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stage {
+    /// Reading the cast and preparing the terminal frames to render
+    Parse,
+    /// Rendering the terminal frames to images
+    Rasterize,
+    /// Encoding the rendered frames into the output
+    Encode,
+}
+
+/// The time from when a stage started working to when it last worked
+#[derive(Debug, Default, Clone, Copy)]
+struct Span {
+    start: Option<Instant>,
+    end: Option<Instant>,
+}
+
+impl Span {
+    /// Record that the stage is working now
+    fn mark(&mut self, now: Instant) {
+        self.start.get_or_insert(now);
+        self.end = Some(now);
+    }
+
+    /// Get the time that the stage spanned
+    fn duration(&self) -> Duration {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) => end - start,
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// What has been measured of a render so far
+#[derive(Debug, Default)]
+struct Measured {
+    parsed_frames: u64,
+    rendered_frames: u64,
+    sequenced_frames: u64,
+    encoded_frames: u64,
+    duration: f64,
+    parse: Span,
+    rasterize: Span,
+    encode: Span,
+}
+
+/// Measures a render as it runs
+///
+/// The stages of a render run at the same time, so the time of each stage is the wall-clock time
+/// from when it started to when it finished, and the times of the stages overlap.
+#[derive(Debug, Default)]
+pub struct RenderStats {
+    measured: Mutex<Measured>,
+}
+
+/// The measurements of a finished render
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct RenderSummary {
+    /// The number of terminal frames that were prepared to render
+    pub parsed_frames: u64,
+    /// The number of frames that were rendered to images
+    pub rendered_frames: u64,
+    /// The number of frames that were given to the encoder, including the frames that were added
+    /// to hold the end, show the end card, and fade
+    pub sequenced_frames: u64,
+    /// The number of frames in the output
+    ///
+    /// Gif encoders merge identical frames that follow each other, so this may be less than the
+    /// number of sequenced frames.
+    pub encoded_frames: u64,
+    /// The time that the output plays for, in seconds
+    pub duration: f64,
+    /// The time spent reading the cast and preparing the frames to render
+    pub parse_time: Duration,
+    /// The time spent rendering frames to images
+    pub raster_time: Duration,
+    /// The time spent encoding the output
+    pub encode_time: Duration,
+}
+
+impl RenderStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get what has been measured of the render
+    pub fn summary(&self) -> RenderSummary {
+        let measured = self.measured.lock().unwrap();
+        RenderSummary {
+            parsed_frames: measured.parsed_frames,
+            rendered_frames: measured.rendered_frames,
+            sequenced_frames: measured.sequenced_frames,
+            encoded_frames: measured.encoded_frames,
+            duration: measured.duration,
+            parse_time: measured.parse.duration(),
+            raster_time: measured.rasterize.duration(),
+            encode_time: measured.encode.duration(),
+        }
+    }
+
+    /// Record that a stage is working now
+    pub(crate) fn mark(&self, stage: Stage) {
+        let now = Instant::now();
+        let mut measured = self.measured.lock().unwrap();
+        match stage {
+            Stage::Parse => measured.parse.mark(now),
+            Stage::Rasterize => measured.rasterize.mark(now),
+            Stage::Encode => measured.encode.mark(now),
+        }
+    }
+
+    /// Count a terminal frame that was prepared to render
+    pub(crate) fn parsed(&self) {
+        self.measured.lock().unwrap().parsed_frames += 1;
+    }
+
+    /// Count a frame that was rendered to an image
+    pub(crate) fn rendered(&self) {
+        self.measured.lock().unwrap().rendered_frames += 1;
+    }
+
+    /// Count frames that were given to the encoder
+    pub(crate) fn sequenced(&self, frames: u64) {
+        self.mark(Stage::Encode);
+        self.measured.lock().unwrap().sequenced_frames += frames;
+    }
+
+    /// Record the number of frames in the output and the time it plays for, in seconds, once it
+    /// has been encoded
+    pub(crate) fn encoded(&self, frames: u64, duration: f64) {
+        self.mark(Stage::Encode);
+        let mut measured = self.measured.lock().unwrap();
+        measured.encoded_frames = frames;
+        measured.duration = duration;
+    }
+}
+
+/// An iterator adapter that counts the terminal frames that are prepared to render and times the
+/// parse stage as they are produced
+pub(crate) struct ParsedFrames<I> {
+    frames: I,
+    stats: Arc<RenderStats>,
+}
+
+impl<I> ParsedFrames<I> {
+    pub fn new(frames: I, stats: Arc<RenderStats>) -> Self {
+        ParsedFrames { frames, stats }
+    }
+}
+
+impl<I: Iterator> Iterator for ParsedFrames<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.stats.mark(Stage::Parse);
+        let frame = self.frames.next();
+        self.stats.mark(Stage::Parse);
+        if frame.is_some() {
+            self.stats.parsed();
+        }
+
+        frame
+    }
+}
+
+/// An iterator adapter that counts the frames given to an encoder that encodes every frame that it
+/// is given, in order
+///
+/// The frames are recorded as encoded once the last one has been taken, shown for `final_hold`
+/// seconds.
+pub(crate) struct EncodedFrames<I> {
+    frames: I,
+    stats: Arc<RenderStats>,
+    final_hold: f64,
+    count: u64,
+    /// The times of the first and the last frame so far, in hundredths of a second
+    times: Option<(f64, f64)>,
+}
+
+impl<I> EncodedFrames<I> {
+    /// Count the frames, the last of which is shown for `final_hold` seconds
+    pub fn new(frames: I, stats: Arc<RenderStats>, final_hold: f64) -> Self {
+        EncodedFrames {
+            frames,
+            stats,
+            final_hold,
+            count: 0,
+            times: None,
+        }
+    }
+}
+
+impl<I> Iterator for EncodedFrames<I>
+where
+    I: Iterator<Item = Result<RgbaFrame, Error>>,
+{
+    type Item = Result<RgbaFrame, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.frames.next();
+        match &frame {
+            Some(Ok(frame)) => {
+                self.stats.sequenced(1);
+                self.count += 1;
+                let first = self.times.map_or(frame.time, |(first, _)| first);
+                self.times = Some((first, frame.time));
+            }
+            Some(Err(_)) => (),
+            None => {
+                let duration = self
+                    .times
+                    .map_or(0., |(first, last)| (last - first) / 100. + self.final_hold);
+                self.stats.encoded(self.count, duration);
+            }
+        }
+
+        frame
+    }
+}
@@ -0,0 +1,141 @@
+//! Rendering two casts side by side
+//!
+//! Both casts play from their own start at the same time, so that before and after recordings of
+//! the same commands can be compared.
+
+use crate::cast_parser::{AsciinemaError, TerminalFrameIter};
+use crate::types::{Marker, ScreenSnapshot, TerminalFrame};
+
+use std::iter::Peekable;
+use std::sync::Arc;
+
+/// The number of columns between the two terminals
+const GAP: u16 = 3;
+
+/// Get the (rows, cols) size of the screen that two terminals of the given sizes are put on
+///
+/// Returns `None` if the screen would be too wide.
+pub(crate) fn size(left: (u16, u16), right: (u16, u16)) -> Option<(u16, u16)> {
+    let cols = left.1.checked_add(GAP)?.checked_add(right.1)?;
+
+    Some((left.0.max(right.0), cols))
+}
+
+/// An iterator adapter that merges the frames of two casts into frames that show their screens
+/// side by side
+///
+/// A frame is emitted whenever either screen changes. Each screen is empty until its first frame
+/// and keeps showing its last frame once its cast has ended.
+pub(crate) struct SideBySideFrames<L: Iterator, R: Iterator> {
+    left: Peekable<L>,
+    right: Peekable<R>,
+    /// The latest screen of the left cast
+    left_screen: ScreenSnapshot,
+    /// The latest screen of the right cast
+    right_screen: ScreenSnapshot,
+    /// The latest marker of the left cast
+    marker: Option<Marker>,
+    /// The latest window title of the left cast
+    left_title: Option<Arc<str>>,
+    /// The latest window title of the right cast
+    right_title: Option<Arc<str>>,
+    /// The index of the next frame
+    next_index: u64,
+}
+
+impl<L, R> SideBySideFrames<L, R>
+where
+    L: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+    R: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    /// Merge the frames of two casts, given with the (rows, cols) sizes of their terminals
+    pub fn new(left: L, left_size: (u16, u16), right: R, right_size: (u16, u16)) -> Self {
+        SideBySideFrames {
+            left: left.peekable(),
+            right: right.peekable(),
+            left_screen: ScreenSnapshot::blank(left_size.0, left_size.1),
+            right_screen: ScreenSnapshot::blank(right_size.0, right_size.1),
+            marker: None,
+            left_title: None,
+            right_title: None,
+            next_index: 0,
+        }
+    }
+}
+
+/// The frames of two cast files side by side
+pub(crate) type SideBySideCasts<L, R> =
+    SideBySideFrames<TerminalFrameIter<L>, TerminalFrameIter<R>>;
+
+/// Get the time of the next frame of a cast, or negative infinity for errors so that they are
+/// returned right away
+fn next_time(frame: Option<&Result<TerminalFrame, AsciinemaError>>) -> Option<f64> {
+    match frame? {
+        Ok(frame) => Some(frame.time),
+        Err(_) => Some(f64::NEG_INFINITY),
+    }
+}
+
+impl<L, R> Iterator for SideBySideFrames<L, R>
+where
+    L: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+    R: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let left_time = next_time(self.left.peek());
+        let right_time = next_time(self.right.peek());
+        let time = match (left_time, right_time) {
+            (Some(left), Some(right)) => left.min(right),
+            (time, None) | (None, time) => time?,
+        };
+
+        // Take the frames of both casts if they change at the same time. If both of them have a
+        // marker label, the left one is kept.
+        let mut caption = None;
+        let mut bell = false;
+        if left_time == Some(time) {
+            match self.left.next()? {
+                Ok(frame) => {
+                    self.left_screen = frame.screen;
+                    self.marker = frame.marker;
+                    self.left_title = frame.title;
+                    caption = frame.caption;
+                    bell = frame.bell;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        if right_time == Some(time) {
+            match self.right.next()? {
+                Ok(frame) => {
+                    self.right_screen = frame.screen;
+                    self.right_title = frame.title;
+                    caption = caption.or(frame.caption);
+                    bell |= frame.bell;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        // The titles are joined like the titles of the cast headers
+        let title = match (&self.left_title, &self.right_title) {
+            (Some(left), Some(right)) => Some(format!("{} | {}", left, right).into()),
+            (title, None) | (None, title) => title.clone(),
+        };
+
+        let index = self.next_index;
+        self.next_index += 1;
+        Some(Ok(TerminalFrame {
+            index,
+            time,
+            screen: ScreenSnapshot::side_by_side(&self.left_screen, &self.right_screen, GAP),
+            caption,
+            marker: self.marker.clone(),
+            title,
+            bell,
+            scroll: None,
+        }))
+    }
+}
@@ -0,0 +1,192 @@
+//! Sliding the screen smoothly when it scrolls
+//!
+//! Terminals scroll a whole row at a time, which looks jumpy in renders with a high frame rate.
+//! When a frame is the screen before it moved up by whole rows, frames are added before it that
+//! slide the screen up by part of a row each. The rows that scrolled off are kept above the screen
+//! in those frames, so that they slide out of the top instead of disappearing.
+
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+
+use crate::cast_parser::AsciinemaError;
+use crate::types::{CropSettings, ScreenSnapshot, TerminalFrame};
+
+/// The shortest time between sliding frames, in centiseconds, which is the shortest delay that
+/// gifs play reliably
+const SLIDE_INTERVAL: f64 = 2.;
+
+/// How far a frame partway through a smooth scroll has slid
+///
+/// The screen of the frame has the rows that scrolled off above it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Scroll {
+    /// The number of rows that scrolled off the top of the screen
+    pub rows: u16,
+    /// How far the screen has slid up, from `0` rows to `rows`
+    pub offset: f64,
+}
+
+impl Hash for Scroll {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rows.hash(state);
+        self.offset.to_bits().hash(state);
+    }
+}
+
+impl Scroll {
+    /// Get the region of the screen of a sliding frame to render for the given crop, which takes
+    /// in the rows above the screen
+    pub(crate) fn crop(&self, crop: Option<CropSettings>) -> Option<CropSettings> {
+        crop.map(|crop| CropSettings {
+            height: crop.height + self.rows,
+            ..crop
+        })
+    }
+}
+
+/// Get the number of rows that a screen moved up by to become the next one, if it scrolled
+///
+/// Only the text of the rows is compared, so that colors that change from frame to frame, like
+/// highlights, don't hide a scroll. The last row of the previous screen is left out, since output
+/// is often still being written to it when the screen scrolls, and rows without any text can't
+/// show that the screen scrolled.
+fn scrolled_rows(previous: &ScreenSnapshot, next: &ScreenSnapshot) -> Option<u16> {
+    let (rows, cols) = next.size();
+    if previous.size() != (rows, cols) {
+        return None;
+    }
+    let same_text = |previous_row: u16, next_row: u16| {
+        (0..cols).all(|col| {
+            previous.cell(previous_row, col).map(|cell| cell.contents)
+                == next.cell(next_row, col).map(|cell| cell.contents)
+        })
+    };
+    let has_text = |row: u16| {
+        (0..cols).any(|col| {
+            next.cell(row, col)
+                .is_some_and(|cell| cell.contents.is_some())
+        })
+    };
+
+    if (0..rows).all(|row| same_text(row, row)) {
+        return None;
+    }
+    (1..rows.saturating_sub(1)).find(|&scrolled| {
+        let mut compared = 0..rows - scrolled - 1;
+        compared.clone().any(has_text) && compared.all(|row| same_text(row + scrolled, row))
+    })
+}
+
+/// An iterator adapter that slides the screen up over several frames when it scrolls
+///
+/// The sliding frames are shown from the time that the screen scrolled, at least the minimum
+/// frame delay apart, and the scrolled frame is moved to after them. Screens scroll in fewer
+/// frames when the next frame comes too soon for all of them, and not at all if there isn't room
+/// for one.
+pub(crate) struct SmoothScrollFrames<I> {
+    /// The frames to slide the scrolls of
+    frames: I,
+    /// The number of frames to slide the screen over, or `0` to leave the frames as they are
+    steps: u32,
+    /// The time between sliding frames, in centiseconds
+    interval: f64,
+    /// The screen of the last frame that was read
+    last_screen: Option<ScreenSnapshot>,
+    /// The frames to emit before reading any more
+    queued: VecDeque<TerminalFrame>,
+    /// A frame that has been read, but not yet looked at
+    pending: Option<Result<TerminalFrame, AsciinemaError>>,
+    /// The index of the next frame
+    next_index: u64,
+}
+
+impl<I> SmoothScrollFrames<I> {
+    /// Slide the screen up over `steps` frames that are at least `min_delay` seconds apart when
+    /// it scrolls
+    pub fn new(frames: I, steps: u32, min_delay: f64) -> Self {
+        SmoothScrollFrames {
+            frames,
+            steps,
+            interval: SLIDE_INTERVAL.max(min_delay * 100.),
+            last_screen: None,
+            queued: VecDeque::new(),
+            pending: None,
+            next_index: 0,
+        }
+    }
+
+    /// Emit a frame with the next index
+    fn emit(&mut self, mut frame: TerminalFrame) -> TerminalFrame {
+        frame.index = self.next_index;
+        self.next_index += 1;
+
+        frame
+    }
+}
+
+impl<I> SmoothScrollFrames<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    /// Queue the frames that slide `previous` up by `rows` rows into the screen of `frame`,
+    /// followed by `frame` itself
+    fn slide(&mut self, previous: &ScreenSnapshot, mut frame: TerminalFrame, rows: u16) {
+        // The slide has to finish before the next frame is shown
+        self.pending = self.frames.next();
+        let steps = match &self.pending {
+            Some(Ok(next)) => {
+                let room = ((next.time - frame.time) / self.interval).floor() - 1.;
+                (room.max(0.) as u32).min(self.steps)
+            }
+            _ => self.steps,
+        };
+
+        let screen = frame.screen.with_rows_above(previous, rows);
+        for step in 0..steps {
+            let mut sliding = frame.clone();
+            sliding.time = frame.time + step as f64 * self.interval;
+            sliding.screen = screen.clone();
+            sliding.scroll = Some(Scroll {
+                rows,
+                offset: rows as f64 * (step + 1) as f64 / (steps + 1) as f64,
+            });
+            self.queued.push_back(sliding);
+        }
+        frame.time += steps as f64 * self.interval;
+        self.queued.push_back(frame);
+    }
+}
+
+impl<I> Iterator for SmoothScrollFrames<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(frame) = self.queued.pop_front() {
+            return Some(Ok(self.emit(frame)));
+        }
+
+        let frame = match self.pending.take().or_else(|| self.frames.next())? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        if self.steps == 0 {
+            return Some(Ok(self.emit(frame)));
+        }
+
+        let previous = self.last_screen.replace(frame.screen.clone());
+        let scrolled = previous
+            .as_ref()
+            .and_then(|previous| Some((previous, scrolled_rows(previous, &frame.screen)?)));
+        match scrolled {
+            Some((previous, rows)) => {
+                self.slide(previous, frame, rows);
+                let frame = self.queued.pop_front().expect("The frame was just queued");
+                Some(Ok(self.emit(frame)))
+            }
+            None => Some(Ok(self.emit(frame))),
+        }
+    }
+}
@@ -0,0 +1,383 @@
+//! Spotlighting regions of the terminal
+//!
+//! A spotlight file maps time ranges of the rendered gif to rectangles of terminal cells, so that
+//! tutorials can point viewers at the part of the screen that matters without editing the video
+//! afterwards. While a spotlight is on, everything outside of its rectangle is dimmed, or the
+//! frame is zoomed into the rectangle.
+
+use std::str::FromStr;
+
+use crate::cast_parser::AsciinemaError;
+use crate::color_profile::Rgb;
+use crate::highlight::mix;
+use crate::types::{CropSettings, ScreenSnapshot, TerminalFrame};
+use crate::{ColorProfile, Error};
+
+/// The color that cells outside of a spotlight are dimmed towards
+const DIM_COLOR: Rgb = (0, 0, 0);
+
+/// How much of the dim color is mixed into the cells outside of a spotlight
+const DIM_AMOUNT: f64 = 0.6;
+
+/// A set of spotlights to show over time ranges of the rendered frames
+///
+/// Parsed from a file with one spotlight per line, as a `start-end` range of seconds of the
+/// rendered gif, followed by a rectangle of terminal cells like a crop, and optionally by `dim` or
+/// `zoom`:
+///
+/// ```text
+/// # Point at the prompt, then zoom into the output
+/// 2-5 top=0,left=0,width=40,height=1
+/// 5.5-9 top=1,left=0,width=40,height=6 zoom
+/// ```
+///
+/// Empty lines and lines starting with `#` are skipped. Spotlights may not overlap.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Spotlights {
+    /// The spotlights, sorted by start time
+    spotlights: Vec<Spotlight>,
+}
+
+/// A single spotlight of [`Spotlights`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Spotlight {
+    /// When the spotlight turns on, in seconds
+    start: f64,
+    /// When the spotlight turns off, in seconds
+    end: f64,
+    /// The cells that the spotlight is on
+    pub region: CropSettings,
+    /// Whether to zoom into the region instead of dimming everything around it
+    pub zoom: bool,
+}
+
+impl Spotlights {
+    /// Whether or not there are no spotlights
+    pub fn is_empty(&self) -> bool {
+        self.spotlights.is_empty()
+    }
+
+    /// Make sure that every spotlight fits inside of a terminal of the given size
+    pub fn validate(&self, rows: u16, cols: u16) -> Result<(), Error> {
+        for spotlight in &self.spotlights {
+            let region = spotlight.region;
+            let fits = region.width > 0
+                && region.height > 0
+                && region.left as u32 + region.width as u32 <= cols as u32
+                && region.top as u32 + region.height as u32 <= rows as u32;
+            if !fits {
+                return Err(Error::Generic(format!(
+                    "Spotlight {}-{} on {} does not fit inside of the terminal, which is {} \
+                     columns wide and {} rows tall",
+                    spotlight.start, spotlight.end, region, cols, rows
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the spotlight that is on at the given time, in seconds
+    pub(crate) fn at(&self, time: f64) -> Option<&Spotlight> {
+        self.spotlights
+            .iter()
+            .find(|spotlight| spotlight.start <= time && time < spotlight.end)
+    }
+
+    /// Get the spotlights with all of them dimming, for outputs that can't be zoomed
+    pub(crate) fn dim_only(&self) -> Self {
+        let spotlights = self
+            .spotlights
+            .iter()
+            .map(|spotlight| Spotlight {
+                zoom: false,
+                ..*spotlight
+            })
+            .collect();
+
+        Spotlights { spotlights }
+    }
+
+    /// Whether or not any of the spotlights zoom
+    pub(crate) fn zooms(&self) -> bool {
+        self.spotlights.iter().any(|spotlight| spotlight.zoom)
+    }
+
+    /// Get the times, in seconds, that spotlights turn on or off at, sorted
+    fn boundaries(&self) -> Vec<f64> {
+        let mut boundaries: Vec<f64> = self
+            .spotlights
+            .iter()
+            .flat_map(|spotlight| vec![spotlight.start, spotlight.end])
+            .collect();
+        boundaries.sort_by(|a, b| a.partial_cmp(b).expect("Spotlight times are never NaN"));
+        boundaries.dedup();
+
+        boundaries
+    }
+}
+
+impl FromStr for Spotlights {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut spotlights = Vec::new();
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: &str| {
+                Error::Generic(format!(
+                    "Invalid spotlight on line {}: {}. Expected `start-end \
+                     top=T,left=L,width=W,height=H`, optionally followed by `dim` or `zoom`",
+                    i + 1,
+                    reason
+                ))
+            };
+
+            let mut parts = line.split_whitespace();
+            let range = parts.next().ok_or_else(|| invalid("it is empty"))?;
+            let region = parts.next().ok_or_else(|| invalid("it has no rectangle"))?;
+            let zoom = match parts.next() {
+                None | Some("dim") => false,
+                Some("zoom") => true,
+                Some(_) => return Err(invalid("the effect isn't `dim` or `zoom`")),
+            };
+            if parts.next().is_some() {
+                return Err(invalid("there is more after the effect"));
+            }
+
+            let mut start_end = range.splitn(2, '-');
+            let parse_time = |time: Option<&str>| match time.map(str::parse::<f64>) {
+                Some(Ok(time)) if time >= 0. && time.is_finite() => Ok(time),
+                _ => Err(invalid("the time range isn't two numbers of seconds")),
+            };
+            let (start, end) = (parse_time(start_end.next())?, parse_time(start_end.next())?);
+            if end <= start {
+                return Err(invalid("it ends before it starts"));
+            }
+
+            spotlights.push(Spotlight {
+                start,
+                end,
+                region: region.parse().map_err(|e: Error| invalid(&e.to_string()))?,
+                zoom,
+            });
+        }
+
+        // Make sure spotlights don't overlap
+        spotlights.sort_by(|a, b| {
+            a.start
+                .partial_cmp(&b.start)
+                .expect("Checked for NaN above")
+        });
+        for pair in spotlights.windows(2) {
+            if pair[1].start < pair[0].end {
+                return Err(Error::Generic(format!(
+                    "Spotlights {}-{} and {}-{} overlap",
+                    pair[0].start, pair[0].end, pair[1].start, pair[1].end
+                )));
+            }
+        }
+
+        Ok(Spotlights { spotlights })
+    }
+}
+
+/// An iterator adapter that dims the cells outside of the spotlights, and repeats the last frame
+/// wherever a spotlight turns on or off
+///
+/// Frames are only emitted when the screen changes, so without the repeated frames a spotlight
+/// would only turn on, or off, at the next change to the screen. This comes after the frames are
+/// merged by the minimum frame delay, so the repeated frames are kept at least that far apart from
+/// the frames around them, and a spotlight that would change too close to the next frame changes
+/// on that frame instead.
+pub(crate) struct SpotlightFrames<I> {
+    /// The frames to spotlight
+    frames: I,
+    /// The spotlights to show
+    spotlights: Spotlights,
+    /// The spotlight boundaries that haven't been passed yet, in centiseconds, reversed so that
+    /// the next one can be popped off the end
+    boundaries: Vec<f64>,
+    /// The minimum time between frames, in centiseconds
+    min_delay: f64,
+    /// The colors that the frames are rendered with, which are dimmed
+    profile: ColorProfile,
+    /// The last frame that was emitted, before it was dimmed
+    last_frame: Option<TerminalFrame>,
+    /// A frame that has been read, but not yet emitted
+    pending_frame: Option<TerminalFrame>,
+    /// The index of the next frame
+    next_index: u64,
+}
+
+impl<I> SpotlightFrames<I> {
+    /// Show spotlights over frames that are at least `min_delay` seconds apart, dimming the
+    /// colors of the `profile` that the frames are rendered with
+    ///
+    /// Zoomed spotlights aren't drawn on the screen, because the frames are zoomed once they are
+    /// rendered.
+    pub fn new(frames: I, spotlights: &Spotlights, min_delay: f64, profile: ColorProfile) -> Self {
+        let mut boundaries: Vec<f64> = spotlights.boundaries().iter().map(|b| b * 100.).collect();
+        boundaries.reverse();
+
+        SpotlightFrames {
+            frames,
+            spotlights: spotlights.clone(),
+            boundaries,
+            min_delay: min_delay * 100.,
+            profile,
+            last_frame: None,
+            pending_frame: None,
+            next_index: 0,
+        }
+    }
+
+    /// Emit a frame with the next index, dimmed around the spotlight that is on at its time
+    fn emit(&mut self, mut frame: TerminalFrame) -> TerminalFrame {
+        frame.index = self.next_index;
+        self.next_index += 1;
+        self.last_frame = Some(frame.clone());
+
+        if let Some(spotlight) = self.spotlights.at(frame.time / 100.) {
+            if !spotlight.zoom {
+                dim_around(&mut frame.screen, spotlight.region, &self.profile);
+            }
+        }
+
+        frame
+    }
+}
+
+impl<I> Iterator for SpotlightFrames<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = match self
+            .pending_frame
+            .take()
+            .map(Ok)
+            .or_else(|| self.frames.next())?
+        {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+
+        if let Some(last_time) = self.last_frame.as_ref().map(|f| f.time) {
+            while let Some(&boundary) = self.boundaries.last() {
+                if boundary >= frame.time {
+                    break;
+                }
+                self.boundaries.pop();
+
+                // Repeat the last frame at the boundary if there isn't a frame there already,
+                // waiting until the last frame has been shown for long enough
+                let time = boundary.max(last_time + self.min_delay);
+                if boundary > last_time && time <= frame.time - self.min_delay {
+                    self.pending_frame = Some(frame);
+                    let mut repeated = self.last_frame.clone().expect("Checked above");
+                    repeated.time = time;
+                    return Some(Ok(self.emit(repeated)));
+                }
+            }
+        }
+
+        Some(Ok(self.emit(frame)))
+    }
+}
+
+/// Dim the cells of a screen that are outside of a region
+fn dim_around(screen: &mut ScreenSnapshot, region: CropSettings, profile: &ColorProfile) {
+    let (rows, cols) = screen.size();
+    let rows_inside = region.top..region.top.saturating_add(region.height);
+    let cols_inside = region.left..region.left.saturating_add(region.width);
+    for row in 0..rows {
+        for col in 0..cols {
+            if rows_inside.contains(&row) && cols_inside.contains(&col) {
+                continue;
+            }
+
+            let cell = screen
+                .cell_mut(row, col)
+                .expect("The cell is on the screen");
+            let foreground = profile.color(cell.fgcolor).unwrap_or(profile.foreground);
+            let background = profile.color(cell.bgcolor).unwrap_or(profile.background);
+            let (r, g, b) = mix(foreground, DIM_COLOR, DIM_AMOUNT);
+            cell.fgcolor = vt100::Color::Rgb(r, g, b);
+            let (r, g, b) = mix(background, DIM_COLOR, DIM_AMOUNT);
+            cell.bgcolor = vt100::Color::Rgb(r, g, b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Get the message of the error that parsing a spotlight file fails with
+    fn error(spotlights: &str) -> String {
+        spotlights
+            .parse::<Spotlights>()
+            .expect_err("The spotlights don't parse")
+            .to_string()
+    }
+
+    #[test]
+    fn parses_spotlights() {
+        let spotlights: Spotlights = "# Point at the prompt\n\
+                                      \n\
+                                      5.5-9 top=1,left=0,width=40,height=6 zoom\n\
+                                      2-5 top=0,left=0,width=40,height=1 dim\n\
+                                      9-10 top=2,left=3,width=4,height=5"
+            .parse()
+            .expect("The spotlights parse");
+
+        let spotlight = spotlights.at(2.).expect("A spotlight is on");
+        assert_eq!(spotlight.region.width, 40);
+        assert!(!spotlight.zoom);
+        assert!(spotlights.at(5.2).is_none());
+        assert!(spotlights.at(6.).expect("A spotlight is on").zoom);
+        assert_eq!(
+            spotlights.at(9.5).expect("A spotlight is on").region.left,
+            3
+        );
+        assert_eq!(spotlights.boundaries(), vec![2., 5., 5.5, 9., 10.]);
+        assert!(spotlights.zooms());
+        assert!(!spotlights.dim_only().zooms());
+    }
+
+    #[test]
+    fn rejects_invalid_spotlights() {
+        assert!(error("2-5").contains("line 1: it has no rectangle"));
+        assert!(error("# Comment\n2-5 top=0,left=0,width=4,height=1 blur")
+            .contains("line 2: the effect isn't `dim` or `zoom`"));
+        assert!(error("2-5 top=0,left=0,width=4,height=1 zoom now")
+            .contains("there is more after the effect"));
+        assert!(error("2 top=0,left=0,width=4,height=1")
+            .contains("the time range isn't two numbers of seconds"));
+        assert!(error("two-5 top=0,left=0,width=4,height=1")
+            .contains("the time range isn't two numbers of seconds"));
+        assert!(error("5-2 top=0,left=0,width=4,height=1").contains("it ends before it starts"));
+        assert!(error("2-5 top=0,right=0").contains("Invalid spotlight on line 1"));
+        assert_eq!(
+            error("2-5 top=0,left=0,width=4,height=1\n4-6 top=0,left=0,width=4,height=1"),
+            "Spotlights 2-5 and 4-6 overlap"
+        );
+    }
+
+    #[test]
+    fn rejects_spotlights_outside_of_the_terminal() {
+        let spotlights: Spotlights = "2-5 top=8,left=0,width=40,height=4"
+            .parse()
+            .expect("The spotlights parse");
+
+        assert!(spotlights.validate(12, 40).is_ok());
+        assert!(spotlights.validate(11, 40).is_err());
+        assert!(spotlights.validate(12, 39).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! SRT subtitles
+//!
+//! This module contains the SRT subtitle parser and the frame iterator adapter that makes sure
+//! there is a frame wherever a subtitle cue starts or ends.
+
+use std::str::FromStr;
+
+use crate::cast_parser::AsciinemaError;
+use crate::types::TerminalFrame;
+use crate::Error;
+
+/// A set of subtitle cues to draw over the rendered frames
+///
+/// Parsed from the contents of an SRT file. Cue times are in seconds of the rendered gif.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Subtitles {
+    /// The cues, sorted by start time
+    cues: Vec<SubtitleCue>,
+}
+
+/// A single cue of [`Subtitles`]
+#[derive(Debug, Clone, PartialEq)]
+struct SubtitleCue {
+    start: f64,
+    end: f64,
+    text: String,
+}
+
+impl Subtitles {
+    /// Whether or not there are no cues
+    pub fn is_empty(&self) -> bool {
+        self.cues.is_empty()
+    }
+
+    /// Get the text of the cue shown at the given time, in seconds
+    pub fn cue_at(&self, time: f64) -> Option<&str> {
+        self.cues
+            .iter()
+            .find(|cue| cue.start <= time && time < cue.end)
+            .map(|cue| cue.text.as_str())
+    }
+
+    /// Get the times, in seconds, that cues start or end at, sorted
+    fn boundaries(&self) -> Vec<f64> {
+        let mut boundaries: Vec<f64> = self
+            .cues
+            .iter()
+            .flat_map(|cue| vec![cue.start, cue.end])
+            .collect();
+        boundaries.sort_by(|a, b| a.partial_cmp(b).expect("Cue times are never NaN"));
+        boundaries.dedup();
+
+        boundaries
+    }
+}
+
+/// Parse an SRT timestamp such as `00:01:02,500` into seconds
+fn parse_timestamp(s: &str) -> Option<f64> {
+    let s = s.trim().replace(',', ".");
+    let mut parts = s.rsplitn(3, ':');
+    let seconds: f64 = parts.next()?.parse().ok()?;
+    let minutes: f64 = parts.next().map(str::parse).transpose().ok()?.unwrap_or(0.);
+    let hours: f64 = parts.next().map(str::parse).transpose().ok()?.unwrap_or(0.);
+
+    let time = hours * 3600. + minutes * 60. + seconds;
+    if time.is_finite() && time >= 0. {
+        Some(time)
+    } else {
+        None
+    }
+}
+
+/// Remove formatting tags such as `<i>` from cue text
+fn strip_tags(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => stripped.push(c),
+            _ => (),
+        }
+    }
+
+    stripped
+}
+
+impl FromStr for Subtitles {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+
+        let mut cues = Vec::new();
+        for block in s.split("\n\n").map(str::trim).filter(|b| !b.is_empty()) {
+            let mut lines = block.lines();
+
+            // The cue number is optional, so find the timing line
+            let mut timing = lines.next().unwrap_or("");
+            if !timing.contains("-->") {
+                timing = lines.next().unwrap_or("");
+            }
+            let invalid = || Error::Generic(format!("Invalid subtitle cue:\n{}", block));
+            let mut start_end = timing.splitn(2, "-->");
+            let start = start_end.next().and_then(parse_timestamp);
+            // Ignore any position settings after the end time
+            let end = start_end
+                .next()
+                .and_then(|end| end.split_whitespace().next())
+                .and_then(parse_timestamp);
+            let (start, end) = match (start, end) {
+                (Some(start), Some(end)) if end > start => (start, end),
+                _ => return Err(invalid()),
+            };
+
+            let text = strip_tags(&lines.collect::<Vec<_>>().join("\n"));
+            cues.push(SubtitleCue { start, end, text });
+        }
+
+        cues.sort_by(|a, b| {
+            a.start
+                .partial_cmp(&b.start)
+                .expect("Cue times are never NaN")
+        });
+
+        Ok(Subtitles { cues })
+    }
+}
+
+/// An iterator adapter that repeats the last frame wherever a subtitle cue starts or ends
+///
+/// Frames are only emitted when the screen changes, so without this a cue would only appear, or
+/// disappear, at the next change to the screen. Cues that start or end after the last frame have
+/// the last frame repeated at their boundaries too, which makes the animation last until the last
+/// cue ends.
+pub(crate) struct SubtitleFrames<I> {
+    /// The frames to add the cue frames to
+    frames: I,
+    /// The cue boundaries that haven't been passed yet, in centiseconds, reversed so that the
+    /// next one can be popped off the end
+    boundaries: Vec<f64>,
+    /// The last frame that was emitted
+    last_frame: Option<TerminalFrame>,
+    /// A frame that has been read, but not yet emitted
+    pending_frame: Option<TerminalFrame>,
+    /// The index of the next frame
+    next_index: u64,
+}
+
+impl<I> SubtitleFrames<I> {
+    pub fn new(frames: I, subtitles: &Subtitles) -> Self {
+        let mut boundaries: Vec<f64> = subtitles.boundaries().iter().map(|b| b * 100.).collect();
+        boundaries.reverse();
+
+        SubtitleFrames {
+            frames,
+            boundaries,
+            last_frame: None,
+            pending_frame: None,
+            next_index: 0,
+        }
+    }
+
+    /// Emit a frame with the next index
+    fn emit(&mut self, mut frame: TerminalFrame) -> TerminalFrame {
+        frame.index = self.next_index;
+        self.next_index += 1;
+        self.last_frame = Some(frame.clone());
+
+        frame
+    }
+}
+
+impl<I> Iterator for SubtitleFrames<I>
+where
+    I: Iterator<Item = Result<TerminalFrame, AsciinemaError>>,
+{
+    type Item = Result<TerminalFrame, AsciinemaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = match self
+            .pending_frame
+            .take()
+            .map(Ok)
+            .or_else(|| self.frames.next())
+        {
+            Some(Ok(frame)) => Some(frame),
+            Some(Err(e)) => return Some(Err(e)),
+            None => None,
+        };
+
+        if let Some(last_time) = self.last_frame.as_ref().map(|f| f.time) {
+            // Skip the boundaries that already have a frame
+            while matches!(self.boundaries.last(), Some(&b) if b <= last_time) {
+                self.boundaries.pop();
+            }
+
+            // Repeat the last frame at the boundary if it comes before this frame, or if there
+            // are no frames left
+            if let Some(&boundary) = self.boundaries.last() {
+                if frame.as_ref().is_none_or(|frame| boundary < frame.time) {
+                    self.boundaries.pop();
+                    self.pending_frame = frame;
+                    let mut repeated = self.last_frame.clone().expect("Checked above");
+                    repeated.time = boundary;
+                    return Some(Ok(self.emit(repeated)));
+                }
+            }
+        }
+
+        frame.map(|frame| Ok(self.emit(frame)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("00:01:02,500"), Some(62.5));
+        assert_eq!(parse_timestamp("01:00:00,000"), Some(3600.));
+        assert_eq!(parse_timestamp(" 00:00:01.25 "), Some(1.25));
+        assert_eq!(parse_timestamp("02,5"), Some(2.5));
+        assert_eq!(parse_timestamp("00:00:-01,000"), None);
+        assert_eq!(parse_timestamp("00:xx:01,000"), None);
+        assert_eq!(parse_timestamp(""), None);
+    }
+
+    #[test]
+    fn parses_multiline_cues() {
+        let srt = "\u{feff}1\r\n\
+                   00:00:01,000 --> 00:00:02,500\r\n\
+                   First line\r\n\
+                   <i>second</i> line\r\n\
+                   \r\n\
+                   00:00:03,000 --> 00:00:04,000 X1:10 X2:20\r\n\
+                   Without a number\r\n";
+        let subtitles: Subtitles = srt.parse().expect("The subtitles parse");
+
+        assert_eq!(subtitles.cue_at(0.5), None);
+        assert_eq!(subtitles.cue_at(1.), Some("First line\nsecond line"));
+        assert_eq!(subtitles.cue_at(2.5), None);
+        assert_eq!(subtitles.cue_at(3.5), Some("Without a number"));
+        assert_eq!(subtitles.boundaries(), vec![1., 2.5, 3., 4.]);
+    }
+
+    #[test]
+    fn sorts_cues_by_start_time() {
+        let srt = "00:00:05,000 --> 00:00:06,000\nLater\n\n00:00:01,000 --> 00:00:02,000\nSooner";
+        let subtitles: Subtitles = srt.parse().expect("The subtitles parse");
+
+        assert_eq!(subtitles.cue_at(1.5), Some("Sooner"));
+        assert_eq!(subtitles.cue_at(5.5), Some("Later"));
+    }
+
+    #[test]
+    fn rejects_invalid_cues() {
+        assert!("00:00:02,000 --> 00:00:01,000\nBackwards"
+            .parse::<Subtitles>()
+            .is_err());
+        assert!("00:00:01,000\nNo end".parse::<Subtitles>().is_err());
+        assert!("1\nJust text".parse::<Subtitles>().is_err());
+    }
+}
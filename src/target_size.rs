@@ -0,0 +1,228 @@
+//! Encoding a gif to fit under a target file size
+//!
+//! The rendered frames are cached and re-encoded with progressively lower quality settings until
+//! the gif fits.
+
+use crate::end_card::EndCard;
+use crate::fade::Fade;
+use crate::gif_trim::{self, GifLength};
+use crate::hold_last::LastFrameHold;
+use crate::loop_crossfade::LoopCrossfade;
+use crate::render_stats::{RenderStats, Stage};
+use crate::types::RgbaFrame;
+use crate::Error;
+
+/// A set of encoder settings to try when searching for a gif that fits the target size
+#[derive(Debug, Clone, Copy)]
+struct TuningStep {
+    /// The gifski quality, which also controls the palette size and the amount of dithering
+    quality: u8,
+    /// The minimum time between frames, in centiseconds, or `None` to keep every frame
+    min_frame_interval: Option<f64>,
+}
+
+/// A gif that was encoded with the settings of one tuning step
+struct Attempt {
+    gif: Vec<u8>,
+    /// The number of frames that were given to the encoder
+    sequenced: u64,
+    length: GifLength,
+}
+
+/// The encoder settings to try, in order, until the gif fits
+const TUNING_STEPS: &[TuningStep] = &[
+    TuningStep {
+        quality: 100,
+        min_frame_interval: None,
+    },
+    TuningStep {
+        quality: 80,
+        min_frame_interval: None,
+    },
+    TuningStep {
+        quality: 60,
+        min_frame_interval: None,
+    },
+    TuningStep {
+        quality: 60,
+        min_frame_interval: Some(5.),
+    },
+    TuningStep {
+        quality: 40,
+        min_frame_interval: Some(10.),
+    },
+    TuningStep {
+        quality: 30,
+        min_frame_interval: Some(20.),
+    },
+    TuningStep {
+        quality: 20,
+        min_frame_interval: Some(50.),
+    },
+];
+
+/// Encode the frames with the best settings that fit the gif in `target_size` bytes
+///
+/// If the gif doesn't fit even with the lowest settings, the smallest attempt is returned. The last
+/// `loop_crossfade` frames of every attempt are cross-faded into the first frame, and the end card,
+/// if any, and the fade-out of `fade` are added after the last frame. The frames should already
+/// have been faded in. Every attempt has the `comment`, if any. The gif that is returned is
+/// recorded in `stats`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn encode(
+    mut frames: Vec<RgbaFrame>,
+    target_size: u64,
+    gif_settings: gifski::Settings,
+    comment: Option<&str>,
+    hold_last: f64,
+    loop_crossfade: usize,
+    end_card: Option<&EndCard>,
+    fade: Fade,
+    stats: &RenderStats,
+) -> Result<Vec<u8>, Error> {
+    stats.mark(Stage::Encode);
+    frames.sort_by_key(|frame| frame.index);
+    let record = |attempt: Attempt| {
+        stats.sequenced(attempt.sequenced);
+        stats.encoded(attempt.length.frames, attempt.length.duration);
+        attempt.gif
+    };
+
+    let mut smallest: Option<Attempt> = None;
+    for step in TUNING_STEPS {
+        let attempt = encode_step(
+            &frames,
+            *step,
+            gif_settings,
+            comment,
+            hold_last,
+            loop_crossfade,
+            end_card,
+            fade,
+        )?;
+        log::info!(
+            "Encoded gif at quality {} with a minimum frame interval of {}: {} bytes",
+            step.quality,
+            match step.min_frame_interval {
+                Some(interval) => format!("{}s", interval / 100.),
+                None => "none".into(),
+            },
+            attempt.gif.len()
+        );
+
+        if attempt.gif.len() as u64 <= target_size {
+            return Ok(record(attempt));
+        }
+        if !matches!(&smallest, Some(smallest) if smallest.gif.len() <= attempt.gif.len()) {
+            smallest = Some(attempt);
+        }
+    }
+
+    let smallest = match smallest {
+        Some(smallest) => smallest,
+        None => return Ok(Vec::new()),
+    };
+    log::warn!(
+        "Could not fit the gif in {} bytes, the smallest encoding is {} bytes",
+        target_size,
+        smallest.gif.len()
+    );
+
+    Ok(record(smallest))
+}
+
+/// Get the frames to encode with at least `min_frame_interval` centiseconds between them, as the
+/// time to show each frame at and the frame to show
+///
+/// The frames are split into windows of the interval, starting at the first frame of each window.
+/// Each window shows the last frame in it for the whole window, so that the gif still ends on the
+/// last frame, and every screen that is shown for a while is shown.
+fn thin_frames(
+    frames: &[RgbaFrame],
+    min_frame_interval: Option<f64>,
+) -> impl Iterator<Item = (f64, &RgbaFrame)> {
+    let mut frames = frames.iter().peekable();
+    std::iter::from_fn(move || {
+        let first = frames.next()?;
+        let mut last = first;
+        if let Some(interval) = min_frame_interval {
+            while let Some(frame) = frames.next_if(|frame| frame.time - first.time < interval) {
+                last = frame;
+            }
+        }
+
+        Some((first.time, last))
+    })
+}
+
+/// Encode the frames with the settings of one tuning step
+#[allow(clippy::too_many_arguments)]
+fn encode_step(
+    frames: &[RgbaFrame],
+    step: TuningStep,
+    gif_settings: gifski::Settings,
+    comment: Option<&str>,
+    hold_last: f64,
+    loop_crossfade: usize,
+    end_card: Option<&EndCard>,
+    fade: Fade,
+) -> Result<Attempt, Error> {
+    let (collector, writer) = gifski::new(gifski::Settings {
+        quality: step.quality,
+        ..gif_settings
+    })?;
+
+    let frames = thin_frames(frames, step.min_frame_interval);
+
+    std::thread::scope(|scope| {
+        let sequencer = scope.spawn(move || -> Result<u64, Error> {
+            let mut crossfade = LoopCrossfade::new(loop_crossfade);
+            let mut hold = LastFrameHold::new(hold_last, end_card.cloned(), fade);
+            let mut sequenced = 0;
+            let mut add = |frame: RgbaFrame| {
+                sequenced += 1;
+                let timestamp = frame.timestamp();
+                collector.add_frame_rgba(frame.index as usize, frame.image, timestamp)
+            };
+            for (index, (time, frame)) in frames.enumerate() {
+                let frame = RgbaFrame {
+                    index: index as u64,
+                    time,
+                    ..frame.clone()
+                };
+                if let Some(frame) = crossfade.push(frame).and_then(|frame| hold.track(frame)) {
+                    add(frame)?;
+                }
+            }
+
+            // Add the last frames, cross-faded into the first one
+            for frame in crossfade.finish() {
+                if let Some(frame) = hold.track(frame) {
+                    add(frame)?;
+                }
+            }
+
+            // Add the last frame, which was kept back to hold it on screen before the gif loops
+            for frame in hold.finish() {
+                add(frame)?;
+            }
+            Ok(sequenced)
+        });
+
+        let mut gif = Vec::new();
+        let written = writer.write(&mut gif, &mut gifski::progress::NoProgress {});
+        let sequenced = sequencer.join().expect("Gif sequencer thread panicked")?;
+        written?;
+
+        let mut trimmed = Vec::new();
+        let length = gif_trim::trim(&gif[..], &mut trimmed, gif_settings.repeat, comment, |_| {
+            Ok(())
+        })?;
+
+        Ok(Attempt {
+            gif: trimmed,
+            sequenced,
+            length,
+        })
+    })
+}
@@ -0,0 +1,342 @@
+//! Helpers for visual regression tests
+//!
+//! Casts are rendered to the same frames that go into a gif, one at a time and with the bundled
+//! font, so a cast renders to the same images on every machine. The frames can then be compared
+//! against reference PNGs with [`assert_frame_matches`]:
+//!
+//! ```no_run
+//! use cast2gif::test_util::{assert_frame_matches, render_frames, Tolerance};
+//!
+//! let cast = std::fs::File::open("tests/demo.cast").unwrap();
+//! let frames = render_frames(cast, &Default::default()).unwrap();
+//! let last = frames.last().unwrap();
+//! assert_frame_matches(last, "tests/reference/demo-last.png", Tolerance::default());
+//! ```
+//!
+//! Set the `CAST2GIF_UPDATE_REFERENCES` environment variable to write the frames to their
+//! reference files instead of comparing them, such as after a change to the renderer.
+
+use std::fmt;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::Path;
+
+use crate::types::TerminalFrame;
+use crate::{cast_parser, ConvertSettings, Error};
+
+/// The environment variable that makes [`assert_frame_matches`] write reference files
+const UPDATE_REFERENCES_VAR: &str = "CAST2GIF_UPDATE_REFERENCES";
+
+/// A rendered frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// The time the frame is shown at, in seconds
+    pub time: f64,
+    /// The width of the image in pixels
+    pub width: usize,
+    /// The height of the image in pixels
+    pub height: usize,
+    /// The RGBA pixels of the image in row-major order, with 4 bytes per pixel
+    pub rgba: Vec<u8>,
+}
+
+impl Frame {
+    /// Read a frame from a PNG file
+    ///
+    /// The frame's time is `0`, because PNGs don't have one.
+    pub fn load_png<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let error = |e: &dyn fmt::Display| {
+            Error::Generic(format!(
+                "Could not read PNG {}: {}",
+                path.to_string_lossy(),
+                e
+            ))
+        };
+
+        let file = std::fs::File::open(path).map_err(|e| error(&e))?;
+        let mut decoder = png::Decoder::new(BufReader::new(file));
+        // Expand palettes and low bit depths to 8 bit channels
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|e| error(&e))?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| error(&e))?;
+        buf.truncate(info.buffer_size());
+
+        let rgba = match info.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => buf
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+                .collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+            png::ColorType::Indexed => {
+                return Err(error(&"Palettes should have been expanded"));
+            }
+        };
+
+        Ok(Frame {
+            time: 0.,
+            width: info.width as usize,
+            height: info.height as usize,
+            rgba,
+        })
+    }
+
+    /// Write the frame to a PNG file
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let error = |e: &dyn fmt::Display| {
+            Error::Generic(format!(
+                "Could not write PNG {}: {}",
+                path.to_string_lossy(),
+                e
+            ))
+        };
+
+        let file = std::fs::File::create(path).map_err(|e| error(&e))?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| error(&e))?;
+        writer
+            .write_image_data(&self.rgba)
+            .and_then(|_| writer.finish())
+            .map_err(|e| error(&e))
+    }
+
+    /// Get the RGBA color of the pixel at the given position
+    ///
+    /// # Panics
+    ///
+    /// Panics if the position is outside of the image.
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        let i = (y * self.width + x) * 4;
+        [
+            self.rgba[i],
+            self.rgba[i + 1],
+            self.rgba[i + 2],
+            self.rgba[i + 3],
+        ]
+    }
+}
+
+/// Render a cast to the frames of the gif that it would be converted to
+///
+/// The frames are rendered one at a time on the current thread. Settings that only apply to the
+/// gif encoder, such as the target size, are ignored.
+pub fn render_frames<R: Read>(reader: R, settings: &ConvertSettings) -> Result<Vec<Frame>, Error> {
+    let term_frames = cast_parser::TerminalFrameIter::new(reader)?.with_settings(settings);
+    let (rows, cols) = term_frames.size();
+    let cast_title = term_frames.title();
+    if let Some(crop) = settings.crop {
+        crop.validate(rows, cols)?;
+    }
+    let rasterizer = crate::FrameRasterizer::new(settings, cast_title)?;
+
+    crate::prepare_frames(term_frames, settings)
+        .map(|frame| -> Result<Frame, Error> {
+            let frame: TerminalFrame = frame?;
+            let frame = rasterizer.rasterize(frame)?;
+            Ok(Frame {
+                time: frame.timestamp(),
+                width: frame.image.width(),
+                height: frame.image.height(),
+                rgba: frame
+                    .image
+                    .pixels()
+                    .flat_map(|pixel| [pixel.r, pixel.g, pixel.b, pixel.a])
+                    .collect(),
+            })
+        })
+        .collect()
+}
+
+/// How much a frame may differ from its reference image
+///
+/// The default allows for the small differences in anti-aliasing between platforms and font
+/// rasterizer versions: channels may be 4 levels off, and 0.1% of the pixels may be further off
+/// than that.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tolerance {
+    /// The most that a channel of a pixel may differ by without the pixel counting as different
+    pub channel: u8,
+    /// The fraction of pixels, from 0 to 1, that may be different
+    pub pixels: f64,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Tolerance {
+            channel: 4,
+            pixels: 0.001,
+        }
+    }
+}
+
+/// How a frame differs from its reference image
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mismatch {
+    /// The images aren't the same size
+    Size {
+        /// The (width, height) of the frame
+        actual: (usize, usize),
+        /// The (width, height) of the reference image
+        expected: (usize, usize),
+    },
+    /// More pixels are different than the tolerance allows
+    Pixels {
+        /// The number of pixels that are different
+        different: usize,
+        /// The number of pixels in the images
+        total: usize,
+        /// The (x, y) position of the first pixel that is different
+        first: (usize, usize),
+        /// The largest difference of a channel of any pixel
+        max_difference: u8,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mismatch::Size { actual, expected } => write!(
+                f,
+                "The frame is {}x{} pixels, but the reference is {}x{}",
+                actual.0, actual.1, expected.0, expected.1
+            ),
+            Mismatch::Pixels {
+                different,
+                total,
+                first,
+                max_difference,
+            } => write!(
+                f,
+                "{} of {} pixels are different, starting at ({}, {}), with channels that differ by \
+                 up to {}",
+                different, total, first.0, first.1, max_difference
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// Compare a frame to a reference image
+pub fn compare(actual: &Frame, expected: &Frame, tolerance: Tolerance) -> Result<(), Mismatch> {
+    if (actual.width, actual.height) != (expected.width, expected.height) {
+        return Err(Mismatch::Size {
+            actual: (actual.width, actual.height),
+            expected: (expected.width, expected.height),
+        });
+    }
+
+    let mut different = 0;
+    let mut first = None;
+    let mut max_difference = 0;
+    let pixels = actual
+        .rgba
+        .chunks_exact(4)
+        .zip(expected.rgba.chunks_exact(4));
+    for (i, (a, b)) in pixels.enumerate() {
+        let difference = a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap_or(0);
+        if difference > tolerance.channel {
+            different += 1;
+            first.get_or_insert((i % actual.width, i / actual.width));
+            max_difference = max_difference.max(difference);
+        }
+    }
+
+    let total = actual.width * actual.height;
+    if different as f64 > tolerance.pixels * total as f64 {
+        return Err(Mismatch::Pixels {
+            different,
+            total,
+            first: first.expect("There is a different pixel"),
+            max_difference,
+        });
+    }
+
+    Ok(())
+}
+
+/// Assert that a frame matches the reference PNG at the given path
+///
+/// If the `CAST2GIF_UPDATE_REFERENCES` environment variable is set, the frame is written to the
+/// path instead.
+///
+/// # Panics
+///
+/// Panics if the frame doesn't match, or if the reference can't be read or written. When it
+/// doesn't match, the frame is written next to the reference with `.actual.png` at the end of
+/// its name so that it can be looked at.
+pub fn assert_frame_matches<P: AsRef<Path>>(frame: &Frame, reference: P, tolerance: Tolerance) {
+    let reference = reference.as_ref();
+    if std::env::var_os(UPDATE_REFERENCES_VAR).is_some() {
+        if let Err(e) = frame.save_png(reference) {
+            panic!("{}", e);
+        }
+        return;
+    }
+
+    let expected = match Frame::load_png(reference) {
+        Ok(expected) => expected,
+        Err(e) => panic!(
+            "{}. Set {} to create the reference.",
+            e, UPDATE_REFERENCES_VAR
+        ),
+    };
+    if let Err(mismatch) = compare(frame, &expected, tolerance) {
+        let actual = reference.with_extension("actual.png");
+        let saved = match frame.save_png(&actual) {
+            Ok(()) => format!("The frame was written to {}", actual.to_string_lossy()),
+            Err(e) => e.to_string(),
+        };
+        panic!(
+            "The frame at {}s doesn't match {}: {}. {}",
+            frame.time,
+            reference.to_string_lossy(),
+            mismatch,
+            saved
+        );
+    }
+}
+
+#[cfg(all(test, feature = "backend-fontkit"))]
+mod tests {
+    use super::*;
+    use crate::CastBuilder;
+
+    /// The directory of the reference PNGs
+    fn reference_dir() -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/reference")
+    }
+
+    #[test]
+    fn renders_styled_text() {
+        let cast = CastBuilder::new(24, 4)
+            .output_at(0.1, "plain \x1b[1mbold\x1b[0m \x1b[3mitalic\x1b[0m\r\n")
+            .output_at(
+                0.2,
+                "\x1b[31mred\x1b[0m \x1b[42mgreen\x1b[0m \x1b[4munderline\x1b[0m\r\n",
+            )
+            .output_at(0.3, "\x1b[7mreversed\x1b[0m $ ");
+        let frames = render_frames(cast.reader(), &Default::default()).unwrap();
+        let last = frames.last().unwrap();
+
+        assert_frame_matches(
+            last,
+            reference_dir().join("styled-text.png"),
+            Tolerance::default(),
+        );
+    }
+}
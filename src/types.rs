@@ -1,7 +1,14 @@
 use imgref::ImgVec;
 use rgb::RGBA8;
 
+use crate::{CameraPath, ColorProfile, Error, Redactions, Spotlights, Subtitles};
+
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
 
 /// A terminal frame
 #[derive(Clone)]
@@ -9,9 +16,31 @@ pub(crate) struct TerminalFrame {
     /// The index of the frame in the animation
     pub index: u64,
     /// The time the frame occurrs in the animation timeline
-    pub time: f32,
+    pub time: f64,
     /// The terminal screen state at this frame
-    pub screen: vt100::Screen,
+    pub screen: ScreenSnapshot,
+    /// The caption to draw over the frame
+    ///
+    /// Frames from the cast parser only have the label of the marker that they were emitted for,
+    /// which [`MarkerCaptions`](crate::marker_captions::MarkerCaptions) shows on the frames after
+    /// it.
+    pub caption: Option<Arc<str>>,
+    /// The last marker at or before the frame, if the cast parser was asked to keep track of
+    /// markers
+    pub marker: Option<Marker>,
+    /// The title of the terminal window at the frame, if the cast parser was asked to keep track
+    /// of it
+    ///
+    /// This is the last title that the cast set, or the title from the cast header until it sets
+    /// one.
+    pub title: Option<Arc<str>>,
+    /// Whether or not the bell rang since the previous frame, if the cast parser was asked to keep
+    /// track of bells
+    pub bell: bool,
+    /// How far the frame has slid, if it is partway through a smooth scroll
+    ///
+    /// The screen of a sliding frame has the rows that scrolled off above it.
+    pub scroll: Option<crate::smooth_scroll::Scroll>,
 }
 
 impl fmt::Debug for TerminalFrame {
@@ -20,17 +49,320 @@ impl fmt::Debug for TerminalFrame {
             .field("index", &self.index)
             .field("time", &self.time)
             .field("screen", &"...")
+            .field("caption", &self.caption)
+            .field("marker", &self.marker)
+            .field("title", &self.title)
+            .field("bell", &self.bell)
+            .field("scroll", &self.scroll)
             .finish()
     }
 }
 
+/// A marker in a cast, which frames after it are part of
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Marker {
+    /// The number of the marker in the cast, counting from 1
+    pub number: u32,
+    /// The label of the marker, which may be empty
+    pub label: Arc<str>,
+}
+
+/// A compact snapshot of the visible state of a terminal screen
+///
+/// This is what we keep for each frame instead of a clone of the whole `vt100::Screen`, which
+/// carries along the parser state, scrollback, and per-cell attribute storage that the renderers
+/// don't need.
+#[derive(Clone, Debug, PartialEq, Hash)]
+pub(crate) struct ScreenSnapshot {
+    /// The number of rows on the screen
+    rows: u16,
+    /// The number of columns on the screen
+    cols: u16,
+    /// The cells of the screen in row-major order
+    cells: Vec<ScreenCell>,
+    /// The (row, col) positions of the visible cursors
+    ///
+    /// A terminal has at most one, but screens that are put side by side keep the cursors of both.
+    cursors: Vec<(u16, u16)>,
+}
+
+impl ScreenSnapshot {
+    /// Take a snapshot of a terminal screen
+    pub fn from_screen(screen: &vt100::Screen) -> Self {
+        let (rows, cols) = screen.size();
+        let mut cells = Vec::with_capacity(rows as usize * cols as usize);
+        for row in 0..rows {
+            for col in 0..cols {
+                cells.push(
+                    screen
+                        .cell(row, col)
+                        .map(ScreenCell::from_cell)
+                        .unwrap_or_default(),
+                );
+            }
+        }
+
+        let cursors = if screen.hide_cursor() {
+            Vec::new()
+        } else {
+            vec![screen.cursor_position()]
+        };
+
+        ScreenSnapshot {
+            rows,
+            cols,
+            cells,
+            cursors,
+        }
+    }
+
+    /// Get an empty screen of the given size
+    pub fn blank(rows: u16, cols: u16) -> Self {
+        ScreenSnapshot {
+            rows,
+            cols,
+            cells: vec![ScreenCell::default(); rows as usize * cols as usize],
+            cursors: Vec::new(),
+        }
+    }
+
+    /// Put two screens next to each other, with `gap` columns between them that have a line down
+    /// the middle
+    ///
+    /// The shorter screen is filled out with empty rows at the bottom.
+    pub fn side_by_side(left: &Self, right: &Self, gap: u16) -> Self {
+        let rows = left.rows.max(right.rows);
+        let cols = left.cols + gap + right.cols;
+        let separator = ScreenCell {
+            contents: Some(CellContents::new("│")),
+            fgcolor: vt100::Color::Idx(8),
+            bgcolor: vt100::Color::Default,
+            bold: false,
+            italic: false,
+            underline: None,
+        };
+
+        let mut cells = Vec::with_capacity(rows as usize * cols as usize);
+        for row in 0..rows {
+            let cell = |screen: &Self, col| screen.cell(row, col).copied().unwrap_or_default();
+            cells.extend((0..left.cols).map(|col| cell(left, col)));
+            cells.extend((0..gap).map(|col| {
+                if col == gap / 2 {
+                    separator
+                } else {
+                    ScreenCell::default()
+                }
+            }));
+            cells.extend((0..right.cols).map(|col| cell(right, col)));
+        }
+
+        let right_offset = left.cols + gap;
+        let cursors = left
+            .cursors
+            .iter()
+            .copied()
+            .chain(
+                right
+                    .cursors
+                    .iter()
+                    .map(|&(row, col)| (row, col + right_offset)),
+            )
+            .collect();
+
+        ScreenSnapshot {
+            rows,
+            cols,
+            cells,
+            cursors,
+        }
+    }
+
+    /// Get the screen with the top `rows` rows of the `above` screen put above it
+    ///
+    /// The screens must be as wide as each other.
+    pub fn with_rows_above(&self, above: &Self, rows: u16) -> Self {
+        let mut cells = Vec::with_capacity((self.rows + rows) as usize * self.cols as usize);
+        cells.extend_from_slice(&above.cells[..rows as usize * above.cols as usize]);
+        cells.extend_from_slice(&self.cells);
+        let cursors = self
+            .cursors
+            .iter()
+            .map(|&(row, col)| (row + rows, col))
+            .collect();
+
+        ScreenSnapshot {
+            rows: self.rows + rows,
+            cols: self.cols,
+            cells,
+            cursors,
+        }
+    }
+
+    /// Get the (rows, cols) size of the screen
+    pub fn size(&self) -> (u16, u16) {
+        (self.rows, self.cols)
+    }
+
+    /// Get the cell at the given position, or `None` if it is off of the screen
+    pub fn cell(&self, row: u16, col: u16) -> Option<&ScreenCell> {
+        if row >= self.rows || col >= self.cols {
+            return None;
+        }
+        self.cells
+            .get(row as usize * self.cols as usize + col as usize)
+    }
+
+    /// Get a mutable reference to the cell at the given position, or `None` if it is off of the
+    /// screen
+    pub fn cell_mut(&mut self, row: u16, col: u16) -> Option<&mut ScreenCell> {
+        if row >= self.rows || col >= self.cols {
+            return None;
+        }
+        self.cells
+            .get_mut(row as usize * self.cols as usize + col as usize)
+    }
+
+    /// Get the (row, col) positions of the visible cursors
+    pub fn cursors(&self) -> &[(u16, u16)] {
+        &self.cursors
+    }
+
+    /// Get whether or not a cursor should be drawn over the cell at the given position
+    pub fn is_cursor_at(&self, row: u16, col: u16) -> bool {
+        self.cursors.contains(&(row, col))
+    }
+}
+
+/// A single cell in a [`ScreenSnapshot`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ScreenCell {
+    /// The text in the cell, if any
+    pub contents: Option<CellContents>,
+    /// The foreground color of the cell
+    pub fgcolor: vt100::Color,
+    /// The background color of the cell
+    pub bgcolor: vt100::Color,
+    /// Whether or not the text of the cell is bold
+    pub bold: bool,
+    /// Whether or not the text of the cell is italic
+    pub italic: bool,
+    /// The line under the text of the cell, if it is underlined
+    pub underline: Option<Underline>,
+}
+
+impl ScreenCell {
+    fn from_cell(cell: &vt100::Cell) -> Self {
+        ScreenCell {
+            contents: Some(CellContents::new(&cell.contents())).filter(|c| c.len > 0),
+            fgcolor: cell.fgcolor(),
+            bgcolor: cell.bgcolor(),
+            bold: cell.bold(),
+            italic: cell.italic(),
+            underline: None,
+        }
+    }
+}
+
+/// The line under the text of a [`ScreenCell`]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Underline {
+    /// The shape of the line
+    pub style: UnderlineStyle,
+    /// The color of the line, which is the color of the text if it is the default color
+    pub color: vt100::Color,
+}
+
+/// The shapes of the lines that text can be underlined with, as set by `SGR 4:n`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum UnderlineStyle {
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+impl Hash for ScreenCell {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // `vt100::Color` doesn't implement `Hash`
+        fn hash_color<H: Hasher>(color: vt100::Color, state: &mut H) {
+            match color {
+                vt100::Color::Default => 0u8.hash(state),
+                vt100::Color::Idx(i) => (1u8, i).hash(state),
+                vt100::Color::Rgb(r, g, b) => (2u8, r, g, b).hash(state),
+            }
+        }
+
+        self.contents.hash(state);
+        hash_color(self.fgcolor, state);
+        hash_color(self.bgcolor, state);
+        self.bold.hash(state);
+        self.italic.hash(state);
+        self.underline.map(|underline| underline.style).hash(state);
+        if let Some(underline) = self.underline {
+            hash_color(underline.color, state);
+        }
+    }
+}
+
+impl Default for ScreenCell {
+    fn default() -> Self {
+        ScreenCell {
+            contents: None,
+            fgcolor: vt100::Color::Default,
+            bgcolor: vt100::Color::Default,
+            bold: false,
+            italic: false,
+            underline: None,
+        }
+    }
+}
+
+/// The most characters kept for a cell, which is the same limit that `vt100` has
+const MAX_CELL_CHARS: usize = 6;
+
+/// The text in a terminal cell
+///
+/// This is a grapheme cluster: a character followed by any zero-width characters that combine with
+/// it, such as accents.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Hash)]
+pub(crate) struct CellContents {
+    chars: [char; MAX_CELL_CHARS],
+    len: u8,
+}
+
+impl CellContents {
+    /// Get the contents of a cell from its text, dropping any characters over the limit
+    pub fn new(text: &str) -> Self {
+        let mut contents = CellContents::default();
+        for c in text.chars().take(MAX_CELL_CHARS) {
+            contents.chars[contents.len as usize] = c;
+            contents.len += 1;
+        }
+
+        contents
+    }
+
+    /// Iterate over the characters in the cell
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.chars[..self.len as usize].iter().copied()
+    }
+}
+
+impl fmt::Display for CellContents {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.chars().try_for_each(|c| write!(f, "{}", c))
+    }
+}
+
 /// An SVG render of a terminal frame
+#[cfg(feature = "backend-svg")]
 #[derive(Clone)]
 pub(crate) struct SvgFrame {
     /// The index of the frame in the animation
     pub index: u64,
     /// The time the frame occurrs in the animation timeline
-    pub time: f32,
+    pub time: f64,
     /// The SVG for the frame
     pub doc: svg::Document,
     /// The height of the SVG document in pixels
@@ -39,6 +371,7 @@ pub(crate) struct SvgFrame {
     pub width: u16,
 }
 
+#[cfg(feature = "backend-svg")]
 impl fmt::Debug for SvgFrame {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Frame")
@@ -57,11 +390,22 @@ pub(crate) struct RgbaFrame {
     /// The index of the frame in the animation
     pub index: u64,
     /// The time the frame occurrs in the animation timeline
-    pub time: f32,
+    pub time: f64,
     /// The RGBA image for the frame
     pub image: ImgVec<RGBA8>,
 }
 
+impl RgbaFrame {
+    /// The time the frame is presented at, in seconds
+    ///
+    /// The gif encoder rounds the end of every frame to the centisecond timeline of the gif
+    /// instead of rounding each frame's delay on its own, so delays don't drift out of sync over
+    /// long recordings.
+    pub fn timestamp(&self) -> f64 {
+        self.time / 100.
+    }
+}
+
 impl fmt::Debug for RgbaFrame {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Frame")
@@ -72,6 +416,788 @@ impl fmt::Debug for RgbaFrame {
     }
 }
 
+/// Settings for a cast conversion
+#[derive(Debug, Clone)]
+pub struct ConvertSettings {
+    /// The region of the terminal to render, or `None` to render the whole terminal
+    pub crop: Option<CropSettings>,
+    /// The aspect ratio to pad the frames to with the terminal background, or `None` to leave
+    /// them the shape of the terminal
+    pub aspect: Option<AspectRatio>,
+    /// The approximate number of bytes that rendered frames waiting to be sequenced may take up
+    ///
+    /// Rasterizing is throttled when this would be exceeded, and the frames waiting for the
+    /// sequencer and the frame buffers kept for re-use are limited to fit in it. Frames that the
+    /// gif encoder has taken are not counted. If `None`, up to 100 frames will be kept in memory
+    /// at a time.
+    pub max_memory: Option<usize>,
+    /// The number of threads to render frames with
+    ///
+    /// If `None`, the number of threads will be determined by rayon. This is ignored if
+    /// `thread_pool` is set.
+    pub threads: Option<usize>,
+    /// The thread pool to render frames on
+    ///
+    /// If `None`, a thread pool will be created for cast2gif. The global rayon thread pool is never
+    /// used.
+    pub thread_pool: Option<Arc<rayon::ThreadPool>>,
+    /// Where to record the frames and the time that the render took, to sum it up once it is done
+    ///
+    /// If `None`, the render isn't measured.
+    pub stats: Option<Arc<crate::RenderStats>>,
+    /// The time that a gif conversion has to be done by
+    ///
+    /// A gif conversion that is still running at this time stops with an error. If `None`, it may
+    /// take as long as it needs.
+    pub deadline: Option<Instant>,
+    /// A directory to spill rendered frames to when sequencing falls behind
+    ///
+    /// If set, frames over the in-flight limit are compressed and written to a temporary directory
+    /// inside of this one instead of throttling rasterization.
+    pub spill_dir: Option<PathBuf>,
+    /// A directory to keep a journal of rendered frames in, so that an interrupted render can be
+    /// resumed
+    ///
+    /// Frames that were rendered into the journal by an earlier run are loaded from it instead of
+    /// being rendered again. The directory is removed once the render succeeds. Svgs aren't
+    /// journaled, because they aren't rasterized.
+    pub journal_dir: Option<PathBuf>,
+    /// A directory to cache rendered frames in, to share them between renders
+    ///
+    /// Frames that are in the cache are loaded from it instead of being rendered again, such as
+    /// when a cast is rendered again with different encoder settings. The cache is never cleaned
+    /// up. Svgs aren't cached, because they aren't rasterized.
+    pub cache_dir: Option<PathBuf>,
+    /// Text to embed in a comment of gif output, such as what the gif was rendered from
+    ///
+    /// Gif viewers don't show comments, but they can be read back with [`read_gif_info`].
+    ///
+    /// [`read_gif_info`]: crate::read_gif_info
+    pub comment: Option<String>,
+    /// Encode the gif faster at the cost of quality
+    pub fast: bool,
+    /// The algorithm that picks the colors of the frames of the gif
+    pub quantizer: crate::Quantizer,
+    /// How frames are timed
+    pub timing: TimingMode,
+    /// Speed factors to apply to different parts of the recording
+    pub speed_map: SpeedMap,
+    /// The part of the recording to render
+    pub trim: TrimSettings,
+    /// Subtitles to draw over the frames
+    pub subtitles: Subtitles,
+    /// Spotlights that dim everything around a region of the terminal, or zoom into it, over
+    /// time ranges of the rendered frames
+    pub spotlights: Spotlights,
+    /// A path for a camera to pan and zoom along over the rendered frames
+    ///
+    /// Zoomed spotlights take the place of the camera while they are on.
+    pub camera: CameraPath,
+    /// Text to black out of the terminal, the window titles, and the marker captions before the
+    /// frames are rendered
+    pub redactions: Redactions,
+    /// How opaque the terminal background is, from `0` for transparent to `1` for opaque
+    ///
+    /// Cells that don't set their own background let whatever the image is shown over show
+    /// through them, like a terminal with a translucent background does. If `None`, the background
+    /// is opaque. Gifs and videos can't be translucent, so they ignore this.
+    pub background_opacity: Option<f64>,
+    /// Draw a window title bar above the terminal
+    ///
+    /// The title is taken from `title`. If `title` is `None`, the title bar shows the titles that
+    /// the cast sets for the window as they change, and the title from the cast file until it
+    /// sets one. Svgs only show the title from the cast file.
+    pub title_bar: bool,
+    /// The title to show in the title bar, overriding the title from the cast file
+    ///
+    /// Setting a title also enables the title bar.
+    pub title: Option<String>,
+    /// The maximum size of the gif, in bytes
+    ///
+    /// If set, every rendered frame is kept in memory and the gif is re-encoded with lower quality
+    /// and frame rates until it fits. If it can't be made to fit, the smallest encoding is written.
+    pub target_size: Option<u64>,
+    /// The shortest time, in seconds, to show a frame for
+    ///
+    /// Frames that come sooner after the previous frame are merged into the next one that is
+    /// shown. If `None`, frames are shown for at least 20 milliseconds, which is the shortest delay
+    /// that browsers play gifs at.
+    pub min_frame_delay: Option<f64>,
+    /// The time, in seconds, to show the last frame for before the gif loops
+    ///
+    /// If `None`, the last frame is held for two seconds. If `0`, the last frame is shown for as
+    /// long as the gif encoder picks.
+    pub hold_last: Option<f64>,
+    /// An image to show after the last frame has been held, for as long as the end card says
+    ///
+    /// The image is scaled to fit the frames. Svgs don't show end cards, because they aren't
+    /// rasterized.
+    pub end_card: Option<crate::EndCard>,
+    /// The number of frames at the end of a gif to cross-fade into the first frame, so that it
+    /// loops without a visible jump
+    ///
+    /// The last frame looks just like the first frame. If `None`, the gif jumps back to the start.
+    /// Gifs that end on an end card aren't cross-faded.
+    pub loop_crossfade: Option<u32>,
+    /// The time, in seconds, to fade in from the terminal background over at the start
+    ///
+    /// The recording starts once it has faded in. If `None`, the first frame is shown right away.
+    /// Svgs don't fade, because they aren't rasterized.
+    pub fade_in: Option<f64>,
+    /// The time, in seconds, to fade out to the terminal background over at the end, once the last
+    /// frame, or the end card, has been held
+    ///
+    /// If `None`, the final frame is shown until the animation loops.
+    pub fade_out: Option<f64>,
+    /// What to do with the time between the start of the recording and its first output
+    pub initial_delay: InitialDelay,
+    /// Re-time typed characters to appear at this many characters per second
+    ///
+    /// Output that looks like the echo of a single key press is moved to follow the key press
+    /// before it at a steady rate. The rest of the output keeps the time between its events. If
+    /// `None`, typing is shown as it was recorded.
+    pub typing_speed: Option<f64>,
+    /// Hold the frame at each marker in the cast for this many seconds, moving the rest of the
+    /// recording later
+    ///
+    /// If `None`, markers don't pause the recording.
+    pub pause_on_markers: Option<f64>,
+    /// Redraw text lighter or darker where it has less than this WCAG contrast ratio with the color
+    /// behind it, from `1` to `21`
+    ///
+    /// If `None`, text keeps the colors of the cast.
+    pub min_contrast: Option<f64>,
+    /// Render the terminal on a light background, making the colors of the cast lighter or darker
+    /// so that they stand out from it as much as they did on the dark one
+    pub light: bool,
+    /// Redraw the frames in shades of an ink and a paper color instead of the colors of the cast
+    ///
+    /// This doesn't apply to svgs, which aren't rasterized. If `None`, frames keep their colors.
+    pub monochrome: Option<crate::Monochrome>,
+    /// Tint the cells that changed since the previous frame, fading the tint out over this many
+    /// frames
+    ///
+    /// If `None` or `0`, changes aren't highlighted.
+    pub highlight_changes: Option<u32>,
+    /// Draw a halo around the cursor and a trail behind it as it moves, fading the trail out over
+    /// this many frames
+    ///
+    /// If `None` or `0`, the cursor is drawn as it is.
+    pub cursor_trail: Option<u32>,
+    /// Slide the screen up over this many frames when it scrolls, instead of moving it a whole
+    /// row at a time
+    ///
+    /// This doesn't apply to svgs, which aren't rasterized. If `None` or `0`, the screen scrolls
+    /// like it did in the terminal.
+    pub smooth_scroll: Option<u32>,
+    /// Show the labels of markers in the cast as captions for this many seconds after each marker
+    ///
+    /// If `None`, markers are ignored.
+    pub marker_captions: Option<f64>,
+    /// Add a marker labeled with the command for every command run at a shell prompt in the cast
+    ///
+    /// Commands are found from the OSC 133 semantic prompt marks of the shell, or guessed from
+    /// lines that look like prompts if the cast doesn't have any.
+    pub command_markers: bool,
+    /// WebAssembly plugins to pass every rendered frame through, in order
+    ///
+    /// Frames are cached and journaled before the plugins change them. Svgs aren't passed through
+    /// plugins, because they aren't rasterized.
+    #[cfg(feature = "plugins")]
+    pub plugins: Vec<crate::Plugin>,
+    /// Flash the screen briefly when the cast rings the bell
+    ///
+    /// On by default, like on the command line.
+    pub bell_flash: bool,
+    /// Overlay scripts that draw over every rendered frame, in order, after the plugins
+    ///
+    /// Like plugins, scripts don't apply to svgs.
+    #[cfg(feature = "scripting")]
+    pub scripts: Vec<crate::OverlayScript>,
+}
+
+impl Default for ConvertSettings {
+    fn default() -> Self {
+        ConvertSettings {
+            crop: None,
+            aspect: None,
+            max_memory: None,
+            threads: None,
+            thread_pool: None,
+            stats: None,
+            deadline: None,
+            spill_dir: None,
+            journal_dir: None,
+            cache_dir: None,
+            comment: None,
+            fast: false,
+            quantizer: Default::default(),
+            timing: Default::default(),
+            speed_map: Default::default(),
+            trim: Default::default(),
+            subtitles: Default::default(),
+            spotlights: Default::default(),
+            camera: Default::default(),
+            redactions: Default::default(),
+            background_opacity: None,
+            title_bar: false,
+            title: None,
+            target_size: None,
+            min_frame_delay: None,
+            hold_last: None,
+            end_card: None,
+            loop_crossfade: None,
+            fade_in: None,
+            fade_out: None,
+            initial_delay: Default::default(),
+            typing_speed: None,
+            pause_on_markers: None,
+            min_contrast: None,
+            light: false,
+            monochrome: None,
+            highlight_changes: None,
+            cursor_trail: None,
+            smooth_scroll: None,
+            marker_captions: None,
+            command_markers: false,
+            #[cfg(feature = "plugins")]
+            plugins: Vec::new(),
+            bell_flash: true,
+            #[cfg(feature = "scripting")]
+            scripts: Vec::new(),
+        }
+    }
+}
+
+impl ConvertSettings {
+    /// Whether or not frames are rendered with a title bar
+    pub(crate) fn shows_title_bar(&self) -> bool {
+        self.title_bar || self.title.is_some()
+    }
+
+    /// Whether or not the frames need to know the title that the cast gave the window
+    ///
+    /// The title bar shows the titles that the cast sets, unless it is given a title.
+    pub(crate) fn tracks_titles(&self) -> bool {
+        self.title_bar && self.title.is_none()
+    }
+
+    /// The shortest time, in seconds, to show a frame for
+    pub(crate) fn min_frame_delay(&self) -> f64 {
+        self.min_frame_delay
+            .unwrap_or(crate::min_delay::DEFAULT_MIN_FRAME_DELAY)
+    }
+
+    /// How opaque the terminal background is, from `0` for transparent to `1` for opaque
+    pub(crate) fn background_opacity(&self) -> f64 {
+        self.background_opacity.unwrap_or(1.).clamp(0., 1.)
+    }
+
+    /// How opaque the terminal background is, from `0` for transparent to `255` for opaque
+    pub(crate) fn background_alpha(&self) -> u8 {
+        (self.background_opacity() * 255.).round() as u8
+    }
+
+    /// The stats to measure the render in, which are thrown away if the settings don't have any
+    pub(crate) fn stats(&self) -> Arc<crate::RenderStats> {
+        self.stats.clone().unwrap_or_default()
+    }
+
+    /// The time, in seconds, to show the last frame for before the gif loops
+    pub(crate) fn hold_last(&self) -> f64 {
+        self.hold_last
+            .unwrap_or(crate::hold_last::DEFAULT_HOLD_LAST)
+    }
+
+    /// The time, in seconds, to hold the end of the recording for before it fades out, which is
+    /// the end card's time if there is one, or else the last frame's
+    pub(crate) fn end_hold(&self) -> f64 {
+        match &self.end_card {
+            Some(end_card) => end_card.hold(),
+            None => self.hold_last(),
+        }
+    }
+
+    /// The time, in seconds, to show the final frame of the animation for, which is the last frame
+    /// of the fade-out if there is one, or else the end of the recording
+    pub(crate) fn final_hold(&self) -> f64 {
+        let fade = self.fade();
+        if fade.fades_out() {
+            fade.fade_out_frame_delay()
+        } else {
+            self.end_hold()
+        }
+    }
+
+    /// The fades at the start and end of the animation
+    pub(crate) fn fade(&self) -> crate::fade::Fade {
+        crate::fade::Fade::new(
+            self.fade_in.unwrap_or(0.),
+            self.fade_out.unwrap_or(0.),
+            self.background(),
+        )
+    }
+
+    /// The colors that the frames are rendered with, before they are redrawn in monochrome
+    pub(crate) fn profile(&self) -> crate::ColorProfile {
+        let profile = crate::ColorProfile::default();
+        if self.light {
+            crate::light::light_profile(&profile)
+        } else {
+            profile
+        }
+    }
+
+    /// The color of the terminal background in the rendered frames
+    pub(crate) fn background(&self) -> crate::color_profile::Rgb {
+        let background = self.profile().background;
+        match &self.monochrome {
+            Some(monochrome) => monochrome.rgb(background),
+            None => background,
+        }
+    }
+
+    /// The end card to show, in the colors of the rendered frames
+    pub(crate) fn end_card(&self) -> Option<crate::EndCard> {
+        let end_card = self
+            .end_card
+            .as_ref()?
+            .with_background(self.profile().background);
+        match &self.monochrome {
+            Some(monochrome) => Some(end_card.monochrome(monochrome)),
+            None => Some(end_card),
+        }
+    }
+
+    /// The number of frames at the end of the gif to cross-fade into the first frame
+    pub(crate) fn loop_crossfade(&self) -> usize {
+        match &self.end_card {
+            Some(_) => 0,
+            None => self.loop_crossfade.unwrap_or(0) as usize,
+        }
+    }
+
+    /// Whether or not the frames need to know the last marker before them
+    pub(crate) fn tracks_markers(&self) -> bool {
+        #[cfg(feature = "scripting")]
+        {
+            !self.scripts.is_empty()
+        }
+        #[cfg(not(feature = "scripting"))]
+        {
+            false
+        }
+    }
+}
+
+/// Settings for animated SVG output
+#[derive(Debug, Clone, Default)]
+pub struct SvgSettings {
+    /// Draw the terminal in a window with buttons, like svg-term's `--window`
+    ///
+    /// The window is also drawn when the conversion settings ask for a title bar.
+    pub window: bool,
+    /// The space around the terminal, in pixels
+    ///
+    /// If `None`, the terminal has 10 pixels of padding.
+    pub padding: Option<u16>,
+    /// Draw the cursor
+    pub cursor: bool,
+    /// The colors of the terminal
+    pub profile: ColorProfile,
+}
+
+impl SvgSettings {
+    /// The space around the terminal, in pixels
+    pub(crate) fn padding(&self) -> u16 {
+        self.padding.unwrap_or(crate::animated_svg::DEFAULT_PADDING)
+    }
+}
+
+/// Settings for mp4 video output
+#[derive(Debug, Clone, Default)]
+pub struct VideoSettings {
+    /// An audio file to add to the video, such as a narration of the demo
+    ///
+    /// It may be in any format that ffmpeg can read. The video is as long as the longer of the
+    /// audio and the recording.
+    pub audio: Option<PathBuf>,
+}
+
+/// Settings for png sequence output
+#[derive(Debug, Clone, Default)]
+pub struct PngSequenceSettings {
+    /// How hard to compress the pngs
+    pub compression: crate::PngCompression,
+    /// The template for the file names of the frames
+    pub file_name: crate::FrameNameTemplate,
+    /// The name of the cast, which replaces `{cast}` in the file names
+    ///
+    /// If `None`, `{cast}` is replaced by `cast`.
+    pub cast_name: Option<String>,
+    /// The manifest of the frames to write to the directory along with them, if any
+    pub manifest: Option<crate::ManifestFormat>,
+}
+
+/// How the frames of the gif are timed
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TimingMode {
+    /// Every change to the screen becomes exactly one frame, shown until the next change
+    #[default]
+    Events,
+    /// The screen is sampled at a fixed interval, in seconds, and every sample that differs from
+    /// the last one becomes a frame
+    Fixed(f64),
+}
+
+/// What to do with the time between the start of a recording and its first output
+///
+/// Parsed from `keep`, `trim`, or a number of seconds to cap the delay at. This doesn't apply when
+/// the recording is trimmed, because the gif then starts with the screen at the trim start.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum InitialDelay {
+    /// Show the screen as it was at the start of the recording until the first output
+    Keep,
+    /// Start the gif at the first output
+    #[default]
+    Trim,
+    /// Show the screen as it was at the start of the recording for at most this many seconds
+    Cap(f64),
+}
+
+impl FromStr for InitialDelay {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "keep" => Ok(InitialDelay::Keep),
+            "trim" => Ok(InitialDelay::Trim),
+            seconds => match seconds.parse::<f64>() {
+                Ok(max) if max >= 0. && max.is_finite() => Ok(InitialDelay::Cap(max)),
+                _ => Err(Error::Generic(format!(
+                    "Invalid initial delay `{}`, expected `keep`, `trim`, or a number of seconds",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
+impl fmt::Display for InitialDelay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InitialDelay::Keep => write!(f, "keep"),
+            InitialDelay::Trim => write!(f, "trim"),
+            InitialDelay::Cap(max) => write!(f, "{}", max),
+        }
+    }
+}
+
+/// A set of speed factors applied over time ranges of a recording
+///
+/// Parsed from a comma separated list of `start-end:speed` segments, with times in seconds of the
+/// original recording, such as `0-10:1.0,10-60:4.0`. A speed of `4.0` plays that part of the
+/// recording four times faster. Time outside of any segment plays at normal speed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpeedMap {
+    /// The segments, sorted by start time and non-overlapping
+    segments: Vec<SpeedSegment>,
+}
+
+/// A time range of a [`SpeedMap`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SpeedSegment {
+    start: f64,
+    end: f64,
+    speed: f64,
+}
+
+impl SpeedMap {
+    /// Map a time in the original recording to the time it is shown in the output
+    pub fn map_time(&self, time: f64) -> f64 {
+        let mut mapped = 0.;
+        let mut pos = 0.;
+        for segment in &self.segments {
+            if time <= segment.start {
+                break;
+            }
+
+            // Time between segments plays at normal speed
+            mapped += segment.start - pos;
+
+            let end = time.min(segment.end);
+            mapped += (end - segment.start) / segment.speed;
+            pos = end;
+        }
+
+        mapped + (time - pos).max(0.)
+    }
+
+    /// The slowest speed of any segment, if there are any
+    pub fn min_speed(&self) -> Option<f64> {
+        self.segments
+            .iter()
+            .map(|segment| segment.speed)
+            .min_by(|a, b| a.partial_cmp(b).expect("Speeds are never NaN"))
+    }
+}
+
+impl FromStr for SpeedMap {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |segment: &str| {
+            Error::Generic(format!(
+                "Invalid speed map segment `{}`, expected `start-end:speed`",
+                segment
+            ))
+        };
+
+        let mut segments = Vec::new();
+        for segment in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let mut range_speed = segment.splitn(2, ':');
+            let range = range_speed.next().ok_or_else(|| invalid(segment))?;
+            let speed = range_speed.next().ok_or_else(|| invalid(segment))?;
+            let mut start_end = range.splitn(2, '-');
+            let start = start_end.next().ok_or_else(|| invalid(segment))?;
+            let end = start_end.next().ok_or_else(|| invalid(segment))?;
+
+            let parse = |x: &str| x.trim().parse::<f64>().map_err(|_| invalid(segment));
+            let (start, end, speed) = (parse(start)?, parse(end)?, parse(speed)?);
+
+            if !(start >= 0. && end > start && speed > 0. && speed.is_finite()) {
+                return Err(invalid(segment));
+            }
+
+            segments.push(SpeedSegment { start, end, speed });
+        }
+
+        // Make sure segments don't overlap
+        segments.sort_by(|a, b| {
+            a.start
+                .partial_cmp(&b.start)
+                .expect("Checked for NaN above")
+        });
+        for pair in segments.windows(2) {
+            if pair[1].start < pair[0].end {
+                return Err(Error::Generic(format!(
+                    "Speed map segments {}-{} and {}-{} overlap",
+                    pair[0].start, pair[0].end, pair[1].start, pair[1].end
+                )));
+            }
+        }
+
+        Ok(SpeedMap { segments })
+    }
+}
+
+impl fmt::Display for SpeedMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}-{}:{}", segment.start, segment.end, segment.speed)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The part of a recording to render, in seconds of the original recording
+///
+/// Parsed from a `start-end` range, such as `2.5-30`. Either side may be left out to trim only the
+/// other end of the recording.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TrimSettings {
+    /// Where to start rendering, or `None` to start at the beginning of the recording
+    pub start: Option<f64>,
+    /// Where to stop rendering, or `None` to render until the end of the recording
+    pub end: Option<f64>,
+}
+
+impl FromStr for TrimSettings {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            Error::Generic(format!(
+                "Invalid trim `{}`, expected `start-end` in seconds",
+                s
+            ))
+        };
+
+        let mut start_end = s.splitn(2, '-');
+        let start = start_end.next().ok_or_else(invalid)?.trim();
+        let end = start_end.next().ok_or_else(invalid)?.trim();
+        let parse = |x: &str| -> Result<Option<f64>, Error> {
+            if x.is_empty() {
+                return Ok(None);
+            }
+            match x.parse::<f64>() {
+                Ok(x) if x >= 0. && x.is_finite() => Ok(Some(x)),
+                _ => Err(invalid()),
+            }
+        };
+        let trim = TrimSettings {
+            start: parse(start)?,
+            end: parse(end)?,
+        };
+
+        if let (Some(start), Some(end)) = (trim.start, trim.end) {
+            if end <= start {
+                return Err(invalid());
+            }
+        }
+
+        Ok(trim)
+    }
+}
+
+impl fmt::Display for TrimSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(start) = self.start {
+            write!(f, "{}", start)?;
+        }
+        write!(f, "-")?;
+        if let Some(end) = self.end {
+            write!(f, "{}", end)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The region of the terminal to render, in terminal cells
+#[derive(Debug, Clone, Copy, PartialEq, Hash)]
+pub struct CropSettings {
+    pub top: u16,
+    pub left: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl CropSettings {
+    /// Make sure that the crop is not empty and fits inside of a terminal of the given size
+    pub fn validate(&self, rows: u16, cols: u16) -> Result<(), crate::Error> {
+        if self.width == 0 || self.height == 0 {
+            return Err(crate::Error::Generic(format!(
+                "Crop width and height must be greater than 0, got: {}",
+                self
+            )));
+        }
+
+        let fits = self.left as u32 + self.width as u32 <= cols as u32
+            && self.top as u32 + self.height as u32 <= rows as u32;
+        if !fits {
+            return Err(crate::Error::Generic(format!(
+                "Crop {} does not fit inside of the terminal, which is {} columns wide and {} rows \
+                 tall",
+                self, cols, rows
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for CropSettings {
+    type Err = Error;
+
+    /// Parse a region such as `top=1,left=0,width=40,height=6`, which must have all four sides
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mut top, mut left, mut width, mut height) = (None, None, None, None);
+        for pair in s.split(',') {
+            let mut key_value = pair.splitn(2, '=');
+            let key = key_value.next().unwrap_or("");
+            let value = key_value
+                .next()
+                .and_then(|value| value.parse::<u16>().ok())
+                .ok_or_else(|| Error::Generic(format!("`{}` isn't a number of cells", pair)))?;
+            let side = match key {
+                "top" => &mut top,
+                "left" => &mut left,
+                "width" => &mut width,
+                "height" => &mut height,
+                _ => {
+                    return Err(Error::Generic(format!(
+                        "`{}` isn't top, left, width, or height",
+                        key
+                    )))
+                }
+            };
+            *side = Some(value);
+        }
+
+        match (top, left, width, height) {
+            (Some(top), Some(left), Some(width), Some(height)) => Ok(CropSettings {
+                top,
+                left,
+                width,
+                height,
+            }),
+            _ => Err(Error::Generic(
+                "the rectangle needs a top, left, width, and height".into(),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CropSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "top={},left={},width={},height={}",
+            self.top, self.left, self.width, self.height
+        )
+    }
+}
+
+/// The ratio of the width of a frame to its height, such as `16:9`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AspectRatio {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl AspectRatio {
+    /// Get the smallest (width, height) size in pixels that has this aspect ratio and that a
+    /// frame of the given size fits in
+    ///
+    /// The frame is only ever made wider or taller, so one side always stays the same.
+    pub(crate) fn fit(&self, (width, height): (usize, usize)) -> (usize, usize) {
+        let (ratio_width, ratio_height) = (self.width as f64, self.height as f64);
+        if width as f64 * ratio_height > height as f64 * ratio_width {
+            let padded_height = (width as f64 * ratio_height / ratio_width).round() as usize;
+            (width, padded_height.max(height))
+        } else {
+            let padded_width = (height as f64 * ratio_width / ratio_height).round() as usize;
+            (padded_width.max(width), height)
+        }
+    }
+}
+
+impl FromStr for AspectRatio {
+    type Err = Error;
+
+    /// Parse a ratio such as `16:9`, where both sides are greater than 0
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::Generic(format!("`{}` isn't a ratio like 16:9", s));
+        let (width, height) = s.split_once(':').ok_or_else(invalid)?;
+        let side = |side: &str| side.trim().parse::<u32>().ok().filter(|side| *side > 0);
+        match (side(width), side(height)) {
+            (Some(width), Some(height)) => Ok(AspectRatio { width, height }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for AspectRatio {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.width, self.height)
+    }
+}
+
 /// The progress of a cast render job
 #[derive(Default, Debug, Clone)]
 pub struct CastRenderProgress {
@@ -81,6 +1207,24 @@ pub struct CastRenderProgress {
     pub raster_progress: u64,
     /// The progress of the video sequencing
     pub sequence_progress: u64,
+    /// How far into the cast the frames have been parsed, in centiseconds
+    pub parse_progress: u64,
+    /// Whether or not all of the frames have been parsed, which makes `count` the final number of
+    /// frames
+    pub parsed: bool,
+}
+
+/// A summary of a cast from a quick pass over its events, which is made without emulating the
+/// terminal
+///
+/// The summary gives the totals that the progress of a render can be measured against before the
+/// frames are known.
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub struct CastSummary {
+    /// The number of events that are rendered
+    pub events: u64,
+    /// The time of the last event that is rendered, in centiseconds
+    pub duration: f64,
 }
 
 /// This types is used as a "command" to the progress thread to increment the progress
@@ -90,6 +1234,10 @@ pub(crate) enum ProgressCmd {
     IncrementCount,
     IncrementRasterProgress,
     IncrementSequenceProgress,
+    /// Frames have been parsed up to the given time, in centiseconds
+    ParsedUntil(u64),
+    /// All of the frames have been parsed
+    FinishParsing,
 }
 
 /// The trait for a progress handler
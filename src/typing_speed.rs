@@ -0,0 +1,148 @@
+//! Re-timing typed text to a steady typing speed
+//!
+//! Terminals echo typed characters back as tiny output events, one for each key press. Those
+//! events are moved so that the characters appear at a fixed number of characters per second,
+//! while the time between other output, such as the output of commands, is kept as it was
+//! recorded. The pause before the first key press of a run of typing is kept too, so that viewers
+//! still get the time to read the screen before the next command is typed.
+
+/// The longest output, in characters, that is taken for the echo of a single key press. This
+/// covers the short sequences that shells echo for backspace and the arrow keys, such as
+/// `\x08 \x08` and `\x1b[D`.
+const MAX_KEY_PRESS_LENGTH: usize = 8;
+
+/// Moves the events of a cast so that typed characters appear at a steady rate
+#[derive(Debug, Clone)]
+pub(crate) struct TypingSpeed {
+    /// The time between key presses, in seconds
+    interval: f64,
+    /// The time of the last event in the recording, in seconds
+    last_time: f64,
+    /// The time that the last event was moved to, in seconds
+    last_retimed: f64,
+    /// Whether or not the last output event was a key press
+    typing: bool,
+}
+
+impl TypingSpeed {
+    /// Re-time typing to the given number of characters per second
+    pub fn new(chars_per_second: f64) -> Self {
+        TypingSpeed {
+            interval: 1. / chars_per_second,
+            last_time: 0.,
+            last_retimed: 0.,
+            typing: false,
+        }
+    }
+
+    /// Get the time, in seconds, to move an event at the given time to
+    ///
+    /// Events must be given in order. Events at negative times, which come before the start of a
+    /// trimmed recording, are left where they are. Markers don't break a run of typing, and are
+    /// kept with the key press before them while typing.
+    pub fn retime(&mut self, time: f64, command: &str, output: &str) -> f64 {
+        if time < 0. {
+            return time;
+        }
+
+        let gap = (time - self.last_time).max(0.);
+        let retimed_gap = if command == "m" {
+            if self.typing {
+                0.
+            } else {
+                gap
+            }
+        } else {
+            let key_press = is_key_press(output);
+            let typed = key_press && self.typing;
+            self.typing = key_press;
+            if typed {
+                self.interval
+            } else {
+                gap
+            }
+        };
+
+        self.last_time = time;
+        self.last_retimed += retimed_gap;
+
+        self.last_retimed
+    }
+}
+
+/// Get whether or not an output event looks like the echo of a single key press
+///
+/// Key presses echo at most one printable character, not counting the characters of escape
+/// sequences, and don't start a new line. Prompts like `$ ` have more than one.
+fn is_key_press(output: &str) -> bool {
+    if output.is_empty()
+        || output.chars().count() > MAX_KEY_PRESS_LENGTH
+        || output.contains(['\r', '\n'])
+    {
+        return false;
+    }
+
+    let mut printable = 0;
+    let mut chars = output.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip the rest of the escape sequence, up to its final byte
+            if chars.next() == Some('[') {
+                chars.find(|c| ('@'..='~').contains(c));
+            }
+        } else if !c.is_control() {
+            printable += 1;
+        }
+    }
+
+    printable <= 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_key_presses() {
+        assert!(is_key_press("a"));
+        assert!(is_key_press("é"));
+        assert!(is_key_press("\x08 \x08"));
+        assert!(is_key_press("\x1b[D"));
+        assert!(!is_key_press(""));
+        assert!(!is_key_press("$ "));
+        assert!(!is_key_press("ls"));
+        assert!(!is_key_press("\r\n"));
+        assert!(!is_key_press("\x1b[1m\x1b[0mabcdef"));
+    }
+
+    #[test]
+    fn retimes_typing_to_a_steady_speed() {
+        let mut typing = TypingSpeed::new(10.);
+        let events = [
+            (-1., "o", "before the trim"),
+            (1., "o", "$ "),
+            // The pause before the first key press is kept
+            (2., "o", "l"),
+            (2.7, "o", "s"),
+            // Markers stay with the key press before them while typing
+            (2.8, "m", ""),
+            (3., "o", "\r\n"),
+            (3.5, "o", "file\r\n$ "),
+            (3.6, "m", ""),
+        ];
+        let retimed: Vec<f64> = events
+            .iter()
+            .map(|(time, command, output)| typing.retime(*time, command, output))
+            .collect();
+
+        let expected = [-1., 1., 2., 2.1, 2.1, 2.3, 2.8, 2.9];
+        for (retimed, expected) in retimed.iter().zip(&expected) {
+            assert!(
+                (retimed - expected).abs() < 1e-9,
+                "{:?} != {:?}",
+                retimed,
+                expected
+            );
+        }
+    }
+}
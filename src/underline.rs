@@ -0,0 +1,275 @@
+//! Underline styles and colors
+//!
+//! Editors with language servers mark errors and warnings with curly, dotted, and colored
+//! underlines, which `vt100` doesn't know about. It only keeps whether a cell is underlined, and
+//! it drops the whole SGR sequence when the style is set with colon separated parameters, like
+//! `4:3`, along with the colors and attributes around it.
+//!
+//! To keep them anyway, the output of the cast is rewritten before `vt100` gets it, so that it only
+//! sees parameters that it understands, and the underlines are emulated on a second terminal next
+//! to it. That terminal gets the same output, except that every SGR sequence is replaced by one
+//! that colors the text by the underline that it is printed with. Both terminals move the cursor,
+//! scroll, and erase in the same way, so the colors of the cells of the second one are the
+//! underlines of the cells of the first.
+
+use crate::types::{ScreenSnapshot, Underline, UnderlineStyle};
+
+/// The longest SGR sequence to keep when it is cut off at the end of an output, in bytes
+const MAX_SGR_LEN: usize = 256;
+
+/// The underlines of the cells of an emulated terminal
+pub(crate) struct Underlines {
+    /// The terminal that the underlines are kept on, with the style of each cell as the index of
+    /// its text color and the color of the line as its background color
+    layer: vt100::Parser,
+    /// The style of the line that text is printed with, if it is underlined
+    style: Option<UnderlineStyle>,
+    /// The color of the line that text is printed with
+    color: vt100::Color,
+    /// The start of an SGR sequence that was cut off at the end of the last output
+    partial: Vec<u8>,
+    /// Whether or not any text has been underlined yet
+    used: bool,
+}
+
+impl Underlines {
+    /// Keep track of the underlines of a terminal of the given size
+    pub fn new(rows: u16, cols: u16) -> Self {
+        Underlines {
+            layer: vt100::Parser::new(rows, cols, 0 /* scrollback */),
+            style: None,
+            color: vt100::Color::Default,
+            partial: Vec::new(),
+            used: false,
+        }
+    }
+
+    /// Keep track of the underlines that some output of the cast sets, returning the output with
+    /// its SGR sequences rewritten for `vt100`
+    pub fn process(&mut self, output: &[u8]) -> Vec<u8> {
+        let mut input = std::mem::take(&mut self.partial);
+        input.extend_from_slice(output);
+        let mut rewritten = Vec::with_capacity(input.len());
+        let mut layer = Vec::with_capacity(input.len());
+
+        let mut i = 0;
+        while i < input.len() {
+            let start = match input[i..].iter().position(|&b| b == 0x1b) {
+                Some(offset) => i + offset,
+                None => input.len(),
+            };
+            rewritten.extend_from_slice(&input[i..start]);
+            layer.extend_from_slice(&input[i..start]);
+            if start == input.len() {
+                break;
+            }
+
+            // Find the parameters of the sequence, if it is one that could be an SGR sequence
+            let params_start = (start + 2).min(input.len());
+            let params_end = input[params_start..]
+                .iter()
+                .position(|&b| !(b.is_ascii_digit() || b == b';' || b == b':'))
+                .map_or(input.len(), |offset| params_start + offset);
+            let end = if input.get(start + 1).is_some_and(|&b| b != b'[') {
+                start + 1
+            } else if params_end == input.len() && input.len() - start <= MAX_SGR_LEN {
+                // Wait for the rest of the sequence
+                self.partial = input[start..].to_vec();
+                break;
+            } else if input.get(params_end) != Some(&b'm') {
+                params_end
+            } else {
+                let params = String::from_utf8_lossy(&input[params_start..params_end]);
+                self.sgr(&params, &mut rewritten, &mut layer);
+                i = params_end + 1;
+                continue;
+            };
+            rewritten.extend_from_slice(&input[start..end]);
+            layer.extend_from_slice(&input[start..end]);
+            i = end;
+        }
+
+        self.layer.process(&layer);
+        rewritten
+    }
+
+    /// Apply an SGR sequence, writing the sequence for `vt100` to `rewritten` and the one that
+    /// colors text by its underline to `layer`
+    fn sgr(&mut self, params: &str, rewritten: &mut Vec<u8>, layer: &mut Vec<u8>) {
+        let mut kept: Vec<String> = Vec::new();
+        let mut groups = params.split(';');
+        while let Some(group) = groups.next() {
+            let subparams: Vec<&str> = group.split(':').collect();
+            if subparams.len() > 1 {
+                match subparams[0] {
+                    "4" => {
+                        self.style = style(subparams[1]);
+                        kept.push(if self.style.is_some() { "4" } else { "24" }.into());
+                    }
+                    "38" | "48" | "58" => match color(&subparams[1..]) {
+                        Some(color) if subparams[0] == "58" => self.color = color,
+                        Some(color) => {
+                            kept.push(format!("{};{}", subparams[0], color_params(color)))
+                        }
+                        None => (),
+                    },
+                    // `vt100` can't read any other attributes with colons, so leave them out
+                    _ => (),
+                }
+                continue;
+            }
+
+            match group {
+                "" | "0" => {
+                    self.style = None;
+                    self.color = vt100::Color::Default;
+                    kept.push("0".into());
+                }
+                "4" => {
+                    self.style = Some(UnderlineStyle::Single);
+                    kept.push("4".into());
+                }
+                "21" => {
+                    self.style = Some(UnderlineStyle::Double);
+                    kept.push("4".into());
+                }
+                "24" => {
+                    self.style = None;
+                    kept.push("24".into());
+                }
+                "38" | "48" | "58" => {
+                    let mode = groups.next().unwrap_or("");
+                    let color_params: Vec<&str> = match mode {
+                        "5" => groups.next().into_iter().collect(),
+                        "2" => groups.by_ref().take(3).collect(),
+                        _ => Vec::new(),
+                    };
+                    let mut subparams = vec![mode];
+                    subparams.extend(color_params);
+                    match color(&subparams) {
+                        Some(color) if group == "58" => self.color = color,
+                        Some(_) => kept.push(format!("{};{}", group, subparams.join(";"))),
+                        None => (),
+                    }
+                }
+                "59" => self.color = vt100::Color::Default,
+                other => kept.push(other.into()),
+            }
+        }
+        self.used |= self.style.is_some();
+
+        if !kept.is_empty() {
+            rewritten.extend_from_slice(format!("\x1b[{}m", kept.join(";")).as_bytes());
+        }
+        let mut layer_params = String::from("0");
+        if let Some(style) = self.style {
+            layer_params.push_str(&format!(";38;5;{}", style_index(style)));
+        }
+        if self.color != vt100::Color::Default {
+            layer_params.push_str(&format!(";48;{}", color_params(self.color)));
+        }
+        layer.extend_from_slice(format!("\x1b[{}m", layer_params).as_bytes());
+    }
+
+    /// Underline the cells of a snapshot of the terminal
+    ///
+    /// Only cells with text are underlined, because terminals don't underline the cells that are
+    /// erased while text is being underlined.
+    pub fn apply(&self, snapshot: &mut ScreenSnapshot) {
+        if !self.used {
+            return;
+        }
+
+        let screen = self.layer.screen();
+        let (rows, cols) = snapshot.size();
+        for row in 0..rows {
+            for col in 0..cols {
+                let cell = snapshot
+                    .cell_mut(row, col)
+                    .expect("The cell is on the screen");
+                if cell.contents.is_none() {
+                    continue;
+                }
+                cell.underline = screen.cell(row, col).and_then(|layer_cell| {
+                    let style = match layer_cell.fgcolor() {
+                        vt100::Color::Idx(index) => style_from_index(index)?,
+                        _ => return None,
+                    };
+
+                    Some(Underline {
+                        style,
+                        color: layer_cell.bgcolor(),
+                    })
+                });
+            }
+        }
+    }
+
+    /// Get the formatted contents of the terminal that the underlines are kept on, which changes
+    /// whenever the underlines do, or nothing if no text has been underlined
+    pub fn contents_formatted(&self) -> Option<Vec<u8>> {
+        Some(self.layer.screen().contents_formatted()).filter(|_| self.used)
+    }
+}
+
+/// Get the underline style set by the parameter after `4:`, or `None` if it turns underlines off
+fn style(param: &str) -> Option<UnderlineStyle> {
+    match param {
+        "0" => None,
+        "2" => Some(UnderlineStyle::Double),
+        "3" => Some(UnderlineStyle::Curly),
+        "4" => Some(UnderlineStyle::Dotted),
+        "5" => Some(UnderlineStyle::Dashed),
+        _ => Some(UnderlineStyle::Single),
+    }
+}
+
+/// Get the index of the color that stands for an underline style on the underline terminal
+fn style_index(style: UnderlineStyle) -> u8 {
+    match style {
+        UnderlineStyle::Single => 1,
+        UnderlineStyle::Double => 2,
+        UnderlineStyle::Curly => 3,
+        UnderlineStyle::Dotted => 4,
+        UnderlineStyle::Dashed => 5,
+    }
+}
+
+/// Get the underline style that a color index stands for on the underline terminal
+fn style_from_index(index: u8) -> Option<UnderlineStyle> {
+    match index {
+        1 => Some(UnderlineStyle::Single),
+        2 => Some(UnderlineStyle::Double),
+        3 => Some(UnderlineStyle::Curly),
+        4 => Some(UnderlineStyle::Dotted),
+        5 => Some(UnderlineStyle::Dashed),
+        _ => None,
+    }
+}
+
+/// Get the color of the parameters after `38`, `48`, or `58`, which are `5` and an index, or `2`
+/// and the red, green, and blue components, which may come after a color space id
+fn color(params: &[&str]) -> Option<vt100::Color> {
+    let number = |param: &str| param.parse::<u8>().ok();
+    match params.first() {
+        Some(&"5") => Some(vt100::Color::Idx(number(params.get(1)?)?)),
+        Some(&"2") if params.len() >= 4 => {
+            let rgb = &params[params.len() - 3..];
+            Some(vt100::Color::Rgb(
+                number(rgb[0])?,
+                number(rgb[1])?,
+                number(rgb[2])?,
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Get the semicolon separated parameters that set a color after `38`, `48`, or `58`
+fn color_params(color: vt100::Color) -> String {
+    match color {
+        vt100::Color::Default => String::new(),
+        vt100::Color::Idx(index) => format!("5;{}", index),
+        vt100::Color::Rgb(r, g, b) => format!("2;{};{};{}", r, g, b),
+    }
+}
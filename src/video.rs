@@ -0,0 +1,185 @@
+//! Encoding mp4 videos with ffmpeg
+//!
+//! Rendered frames are piped to an `ffmpeg` process as raw RGBA images at a fixed frame rate, with
+//! each frame repeated until the next one starts. The video that ffmpeg encodes is copied to the
+//! output as it is written.
+
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::types::RgbaFrame;
+use crate::Error;
+
+/// The frame rate of rendered videos
+pub(crate) const VIDEO_FPS: f64 = 30.;
+
+/// Encode frames to an mp4 video
+///
+/// `size` is the (width, height) of the frames in pixels, and the last frame is shown for
+/// `hold_last` seconds. The `audio` file, if any, is added to the video as its soundtrack.
+pub(crate) fn encode<F, W>(
+    frames: F,
+    (width, height): (usize, usize),
+    mut writer: W,
+    hold_last: f64,
+    audio: Option<&Path>,
+) -> Result<(), Error>
+where
+    F: Iterator<Item = Result<RgbaFrame, Error>>,
+    W: Write + Send,
+{
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-hide_banner", "-loglevel", "error"])
+        .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+        .arg("-s")
+        .arg(format!("{}x{}", width, height))
+        .arg("-r")
+        .arg(VIDEO_FPS.to_string())
+        .args(["-i", "-"]);
+    if let Some(audio) = audio {
+        command
+            .arg("-i")
+            .arg(audio)
+            .args(["-map", "0:v", "-map", "1:a", "-c:a", "aac"]);
+    }
+    command
+        .args(["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+        // H.264 needs the width and height to be even, and the frames are converted to YUV with
+        // the BT.709 matrix that the video is tagged with, so that players don't guess the colors
+        .args([
+            "-vf",
+            "pad=ceil(iw/2)*2:ceil(ih/2)*2,scale=out_color_matrix=bt709:out_range=tv",
+        ])
+        .args(["-colorspace", "bt709", "-color_primaries", "bt709"])
+        .args(["-color_trc", "iec61966-2-1", "-color_range", "tv"])
+        // The output is a pipe, so the index has to come before the video instead of after it
+        .args(["-movflags", "frag_keyframe+empty_moov", "-f", "mp4", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut ffmpeg = command.spawn().map_err(|e| {
+        Error::Generic(format!(
+            "Could not run ffmpeg, which is needed to render videos: {}",
+            e
+        ))
+    })?;
+    let stdin = ffmpeg.stdin.take().expect("Stdin is piped");
+    let mut stdout = ffmpeg.stdout.take().expect("Stdout is piped");
+    let mut stderr = ffmpeg.stderr.take().expect("Stderr is piped");
+
+    std::thread::scope(|scope| {
+        let output = scope.spawn(move || {
+            std::io::copy(&mut stdout, &mut writer)?;
+            writer.flush()
+        });
+        let messages = scope.spawn(move || {
+            let mut messages = String::new();
+            stderr.read_to_string(&mut messages).map(|_| messages)
+        });
+
+        // Closing stdin once the frames are written tells ffmpeg to finish the video
+        let written = write_frames(frames, (width, height), stdin, hold_last);
+        if written.is_err() {
+            ffmpeg.kill().ok();
+        }
+        let status = ffmpeg
+            .wait()
+            .map_err(|e| Error::Generic(format!("Could not wait for ffmpeg to finish: {}", e)))?;
+        let output = output.join().expect("Video output thread panicked");
+        let messages = messages
+            .join()
+            .expect("ffmpeg message thread panicked")
+            .unwrap_or_default();
+
+        // Frames that can't be rendered stop ffmpeg, so their error comes first
+        match written {
+            Err(WriteError::Frame(e)) => return Err(e),
+            _ if !status.success() => {
+                return Err(Error::Generic(format!(
+                    "ffmpeg could not encode the video: {}",
+                    messages.trim()
+                )))
+            }
+            Err(WriteError::Io(e)) => {
+                return Err(Error::Generic(format!(
+                    "Could not send frames to ffmpeg: {}",
+                    e
+                )))
+            }
+            Ok(()) => (),
+        }
+        output.map_err(|e| Error::Generic(format!("Could not write video: {}", e)))
+    })
+}
+
+/// An error while writing frames to ffmpeg
+enum WriteError {
+    /// A frame couldn't be rendered
+    Frame(Error),
+    /// A frame couldn't be sent to ffmpeg
+    Io(std::io::Error),
+}
+
+impl From<std::io::Error> for WriteError {
+    fn from(error: std::io::Error) -> Self {
+        WriteError::Io(error)
+    }
+}
+
+/// Write frames to ffmpeg, repeating each frame until the next one starts
+fn write_frames<F, W>(
+    frames: F,
+    (width, height): (usize, usize),
+    writer: W,
+    hold_last: f64,
+) -> Result<(), WriteError>
+where
+    F: Iterator<Item = Result<RgbaFrame, Error>>,
+    W: Write,
+{
+    let mut writer = BufWriter::new(writer);
+    let mut shown: Option<Vec<u8>> = None;
+    let mut written = 0;
+    for frame in frames {
+        let frame = frame.map_err(WriteError::Frame)?;
+        if (frame.image.width(), frame.image.height()) != (width, height) {
+            return Err(WriteError::Frame(Error::Generic(format!(
+                "Frame {} is {}x{} pixels instead of {}x{}",
+                frame.index,
+                frame.image.width(),
+                frame.image.height(),
+                width,
+                height
+            ))));
+        }
+
+        // Show the previous frame until this one starts. Frames that are shown for less than a
+        // frame of the video are dropped.
+        let start = (frame.timestamp() * VIDEO_FPS).round() as u64;
+        if let Some(previous) = shown.take() {
+            while written < start {
+                writer.write_all(&previous)?;
+                written += 1;
+            }
+        }
+        let pixels = frame
+            .image
+            .pixels()
+            .flat_map(|pixel| [pixel.r, pixel.g, pixel.b, pixel.a])
+            .collect();
+        shown = Some(pixels);
+    }
+
+    // The last frame is shown for at least one frame of the video
+    if let Some(last) = shown {
+        let hold = ((hold_last * VIDEO_FPS).round() as u64).max(1);
+        for _ in 0..hold {
+            writer.write_all(&last)?;
+        }
+    }
+    writer.flush()?;
+
+    Ok(())
+}